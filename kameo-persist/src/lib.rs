@@ -0,0 +1,485 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use clap::{Parser, Subcommand};
+use kameo_persistence::{
+    codec,
+    diff::{SnapshotVersion, diff_snapshots},
+    generation::{self, KeyCounters},
+    history, json,
+    migrate::{MigrateOptions, migrate},
+    redact::redacted,
+    registry, schema,
+    scrub::Scrubber,
+    stats::{GroupStats, storage_stats},
+    storage,
+};
+use url::Url;
+
+/// Inspect and manage snapshots stored by kameo-persistence.
+///
+/// Keys are URLs such as `file:///var/lib/app/actors/1`, or local paths. Only `file` keys are
+/// supported out of the box; a binary registering its own backends with `storage::set_backend`
+/// can run this same tool with `Cli::parse().run()`.
+#[derive(Debug, Parser)]
+#[command(name = "kameo-persist", version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// List the keys holding a snapshot under a root.
+    List { root: String },
+    /// Print the header of a snapshot and its payload, as JSON if the actor type is known.
+    Show { key: String },
+    /// Print the header of a snapshot.
+    Meta { key: String },
+    /// Print the snapshot kept in the history of a key which was in effect at a past moment,
+    /// and the messages recorded after it up to that moment.
+    StateAt {
+        key: String,
+        /// Moment as UTC time, such as `2026-10-15T14:32:00Z`, or as Unix seconds.
+        at: String,
+    },
+    /// Print the fields changed between two versions of a snapshot.
+    Diff {
+        key: String,
+        /// `current`, `previous`, or a moment as for `state-at`.
+        from: String,
+        /// `current`, `previous`, or a moment as for `state-at`.
+        #[arg(default_value = "current")]
+        to: String,
+    },
+    /// Print the storage used under a root by actor type and by key prefix.
+    Stats {
+        root: String,
+        /// Path segments below the root grouping keys by prefix.
+        #[arg(long, default_value_t = 1)]
+        depth: usize,
+        /// Largest keys to print per group.
+        #[arg(long, default_value_t = 3)]
+        top: usize,
+    },
+    /// Delete a key and every key below it.
+    Delete { key: String },
+    /// Copy every snapshot under a root to another root, possibly of another backend.
+    Copy { src: String, dst: String },
+    /// Migrate every snapshot under a root to another root, reporting progress.
+    Migrate {
+        src: String,
+        dst: String,
+        /// Rewrite snapshots in the current format, encrypted for their new keys.
+        #[arg(long)]
+        reencode: bool,
+        /// Type tag of snapshots without a header, when re-encoding.
+        #[arg(long)]
+        legacy_type_tag: Option<String>,
+        /// Skip keys already migrated by an interrupted run.
+        #[arg(long)]
+        resume: bool,
+    },
+    /// Rewrite every snapshot of an actor type under a prefix at its current schema version.
+    Upgrade { prefix: String, type_tag: String },
+    /// Check every snapshot under a root for corruption.
+    Scrub {
+        root: String,
+        /// Move corrupt snapshots aside.
+        #[arg(long)]
+        quarantine: bool,
+    },
+    /// Export a snapshot to canonical JSON.
+    Export {
+        key: String,
+        /// File to write instead of standard output.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Replace a snapshot with one imported from JSON, as written by `export`.
+    Import {
+        key: String,
+        /// File to read, or `-` for standard input.
+        input: PathBuf,
+    },
+}
+
+impl Cli {
+    pub async fn run(self) -> anyhow::Result<()> {
+        match self.command {
+            Command::List { root } => {
+                for key in storage::list(&parse_key(&root)?).await? {
+                    println!("{}", redacted(&key));
+                }
+            }
+            Command::Show { key } => {
+                let key = parse_key(&key)?;
+                let data = storage::read(&key).await?;
+                print_meta(&key, &data)?;
+                println!();
+
+                let (header, payload) = codec::split(&data)?;
+                match header.and_then(|header| registry::registration(&header.type_tag)) {
+                    Some(registration) => println!(
+                        "{}",
+                        serde_json::to_string_pretty(&(registration.to_json)(&data)?)?
+                    ),
+                    None => print_hex(payload),
+                }
+            }
+            Command::Meta { key } => {
+                let key = parse_key(&key)?;
+                print_meta(&key, &storage::read(&key).await?)?;
+
+                let counters = generation::counters(&key)?;
+                if counters != KeyCounters::default() {
+                    println!("written:  {} snapshots", counters.generation);
+                    println!(
+                        "restored: {} times, {} since the last write",
+                        counters.restores, counters.restores_since_write
+                    );
+                }
+            }
+            Command::StateAt { key, at } => {
+                let key = parse_key(&key)?;
+                let at = parse_time(&at)?;
+                let past = history::state_at(&key, at).await?.ok_or_else(|| {
+                    anyhow!(
+                        "No snapshot kept in the history of {} by {}",
+                        redacted(&key),
+                        format_time(at)
+                    )
+                })?;
+
+                print_meta(&key, &past.snapshot)?;
+                println!("saved at: {}", format_time(past.saved_at));
+                println!();
+
+                let (header, payload) = codec::split(&past.snapshot)?;
+                match header.and_then(|header| registry::registration(&header.type_tag)) {
+                    Some(registration) => println!(
+                        "{}",
+                        serde_json::to_string_pretty(&(registration.to_json)(&past.snapshot)?)?
+                    ),
+                    None => print_hex(payload),
+                }
+
+                if !past.messages.is_empty() {
+                    println!();
+                    println!("messages recorded since:");
+                    for msg in &past.messages {
+                        println!(
+                            "  {} {} {}",
+                            msg.seq,
+                            format_time(msg.recorded_at),
+                            msg.type_name
+                        );
+                    }
+                }
+            }
+            Command::Diff { key, from, to } => {
+                let key = parse_key(&key)?;
+                let changes =
+                    diff_snapshots(&key, parse_version(&from)?, parse_version(&to)?).await?;
+
+                for change in &changes {
+                    println!("{change}");
+                }
+                println!("{} fields changed", changes.len());
+            }
+            Command::Stats { root, depth, top } => {
+                let stats = storage_stats(&parse_key(&root)?, depth, top).await?;
+
+                println!("by type:");
+                for (type_tag, group) in &stats.by_type {
+                    print_group(type_tag, group);
+                }
+                println!("by prefix:");
+                for (prefix, group) in &stats.by_prefix {
+                    print_group(if prefix.is_empty() { "/" } else { prefix }, group);
+                }
+                println!(
+                    "{} snapshots, {} bytes",
+                    stats.total.snapshots, stats.total.total_bytes
+                );
+            }
+            Command::Delete { key } => {
+                let key = parse_key(&key)?;
+                let deleted = storage::list(&key).await?;
+                storage::delete(&key).await?;
+                for key in deleted {
+                    println!("deleted {}", redacted(&key));
+                }
+            }
+            Command::Copy { src, dst } => {
+                let copied = storage::copy(&parse_key(&src)?, &parse_key(&dst)?).await?;
+                for key in copied {
+                    println!("copied {}", redacted(&key));
+                }
+            }
+            Command::Migrate {
+                src,
+                dst,
+                reencode,
+                legacy_type_tag,
+                resume,
+            } => {
+                let mut options = MigrateOptions::new().on_progress(|progress| {
+                    let action = if progress.skipped {
+                        "skipped"
+                    } else {
+                        "migrated"
+                    };
+                    eprintln!(
+                        "[{}/{}] {action} {}",
+                        progress.done,
+                        progress.total,
+                        redacted(&progress.key)
+                    );
+                });
+                if reencode {
+                    options = options.reencode();
+                }
+                if let Some(type_tag) = legacy_type_tag {
+                    options = options.legacy_type_tag(type_tag);
+                }
+                if resume {
+                    options = options.resume();
+                }
+
+                let report = migrate(&parse_key(&src)?, &parse_key(&dst)?, options).await?;
+                println!(
+                    "migrated {}, skipped {}",
+                    report.migrated.len(),
+                    report.skipped.len()
+                );
+            }
+            Command::Upgrade { prefix, type_tag } => {
+                let report = schema::upgrade_type(&parse_key(&prefix)?, &type_tag).await?;
+                for key in &report.upgraded {
+                    println!("upgraded {}", redacted(key));
+                }
+                println!(
+                    "upgraded {}, already current {}",
+                    report.upgraded.len(),
+                    report.current.len()
+                );
+            }
+            Command::Scrub { root, quarantine } => {
+                let mut scrubber = Scrubber::new(parse_key(&root)?);
+                if quarantine {
+                    scrubber = scrubber.quarantine();
+                }
+
+                let report = scrubber.scrub().await?;
+                for (key, reason) in &report.corrupt {
+                    println!("corrupt {}: {reason}", redacted(key));
+                }
+                for (key, name) in &report.quarantined {
+                    println!("quarantined {} as {name}", redacted(key));
+                }
+                for (key, reason) in &report.unverified {
+                    println!("unverified {}: {reason}", redacted(key));
+                }
+                println!(
+                    "scanned {}, verified {}, corrupt {}, unverified {}",
+                    report.scanned.len(),
+                    report.verified.len(),
+                    report.corrupt.len(),
+                    report.unverified.len()
+                );
+            }
+            Command::Export { key, output } => {
+                let json = json::export(&parse_key(&key)?)
+                    .await?
+                    .to_canonical_string()?;
+                match output {
+                    Some(path) => std::fs::write(path, json + "\n")?,
+                    None => println!("{json}"),
+                }
+            }
+            Command::Import { key, input } => {
+                let key = parse_key(&key)?;
+                let json = if input.as_os_str() == "-" {
+                    std::io::read_to_string(std::io::stdin())?
+                } else {
+                    std::fs::read_to_string(input)?
+                };
+
+                json::import(&key, serde_json::from_str(&json)?).await?;
+                println!("imported {}", redacted(&key));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a key given as a URL or as a local path.
+pub fn parse_key(key: &str) -> anyhow::Result<Url> {
+    if let Ok(url) = Url::parse(key) {
+        return Ok(url);
+    }
+
+    let path = std::path::absolute(Path::new(key))?;
+    Url::from_directory_path(&path).map_err(|_| anyhow!("Invalid key: {key}"))
+}
+
+/// Parse a snapshot version given as `current`, `previous` or a time.
+pub fn parse_version(version: &str) -> anyhow::Result<SnapshotVersion> {
+    match version {
+        "current" => Ok(SnapshotVersion::Current),
+        "previous" => Ok(SnapshotVersion::Previous),
+        time => Ok(SnapshotVersion::At(parse_time(time)?)),
+    }
+}
+
+/// Parse a UTC time such as `2026-10-15T14:32:00Z`, seconds being optional, or Unix seconds.
+pub fn parse_time(time: &str) -> anyhow::Result<SystemTime> {
+    if let Ok(secs) = time.parse::<u64>() {
+        return Ok(UNIX_EPOCH + Duration::from_secs(secs));
+    }
+
+    let invalid = || anyhow!("Invalid time, expected YYYY-MM-DDTHH:MM[:SS]Z: {time}");
+    let (date, clock) = time
+        .strip_suffix('Z')
+        .and_then(|time| time.split_once('T'))
+        .ok_or_else(invalid)?;
+
+    let date = date
+        .split('-')
+        .map(|part| part.parse::<u64>().map_err(|_| invalid()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let clock = clock
+        .split(':')
+        .map(|part| part.parse::<u64>().map_err(|_| invalid()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let ([year, month, day], [hour, minute, rest @ ..]) = (date.as_slice(), clock.as_slice())
+    else {
+        return Err(invalid());
+    };
+    let second = match rest {
+        [] => 0,
+        [second] => *second,
+        _ => return Err(invalid()),
+    };
+    if !(1..=12).contains(month) || !(1..=31).contains(day) || *hour > 23 || *minute > 59 {
+        return Err(invalid());
+    }
+
+    let days = days_from_civil(*year, *month, *day).ok_or_else(invalid)?;
+    Ok(UNIX_EPOCH + Duration::from_secs(days * 86_400 + hour * 3600 + minute * 60 + second))
+}
+
+/// Format a time as UTC, such as `2026-10-15T14:32:00.250Z`.
+pub fn format_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days(secs / 86_400);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+// Days since the Unix epoch of a proleptic Gregorian date, from 1970
+fn days_from_civil(year: u64, month: u64, day: u64) -> Option<u64> {
+    let year = if month <= 2 {
+        year.checked_sub(1)?
+    } else {
+        year
+    };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    (era * 146_097 + day_of_era).checked_sub(719_468)
+}
+
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+
+    (year, month, day)
+}
+
+fn print_meta(key: &Url, data: &[u8]) -> anyhow::Result<()> {
+    let (header, payload) = codec::split(data)?;
+
+    println!("key:      {}", redacted(key));
+    match header {
+        Some(header) => {
+            if let Some(version) = codec::format_version(data) {
+                println!("format:   v{version}");
+            }
+            println!("type:     {}", header.type_tag);
+            println!("schema:   v{}", header.schema_version);
+            match header.subject {
+                Some(subject) => println!("subject:  {subject} (payload encrypted)"),
+                None => println!("subject:  none"),
+            }
+            match codec::verify(data) {
+                Ok(_) => println!("checksum: {:08x} (ok)", header.checksum),
+                Err(_) => println!("checksum: {:08x} (MISMATCH)", header.checksum),
+            }
+            for (name, value) in &header.metadata {
+                println!("meta:     {name}={value}");
+            }
+        }
+        None => println!("format:   legacy, no header"),
+    }
+    println!("size:     {} bytes", data.len());
+    println!("payload:  {} bytes", payload.len());
+
+    Ok(())
+}
+
+fn print_hex(payload: &[u8]) {
+    for (line, chunk) in payload.chunks(16).enumerate() {
+        let hex = chunk
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let text = chunk
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect::<String>();
+
+        println!("{:08x}  {hex:<47}  {text}", line * 16);
+    }
+}
+
+/// Print the storage used by a group of keys, with its largest keys.
+fn print_group(name: &str, group: &GroupStats) {
+    println!(
+        "  {name}: {} snapshots, {} bytes",
+        group.snapshots, group.total_bytes
+    );
+    for (key, bytes) in &group.largest {
+        println!("    {bytes} {}", redacted(key));
+    }
+}
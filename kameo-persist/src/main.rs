@@ -0,0 +1,7 @@
+use clap::Parser;
+use kameo_persist::Cli;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    Cli::parse().run().await
+}
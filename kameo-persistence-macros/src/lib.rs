@@ -1,14 +1,22 @@
 use heck::ToShoutySnakeCase;
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{parse_macro_input, DeriveInput};
+use syn::{DeriveInput, ItemStruct, parse_macro_input};
 
-#[proc_macro_derive(PersistentActor, attributes(snapshot))]
+#[proc_macro_derive(
+    PersistentActor,
+    attributes(persistent, snapshot, child, data_subject, schema_version, segments)
+)]
 pub fn derive_persistent_actor(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
 
     let name = &input.ident;
     let snapshot_type = find_snapshot_type(&input);
+    let persistent_children = impl_persistent_children(&input);
+    let data_subject = impl_data_subject(&input);
+    let schema_version = impl_schema_version(&input);
+    let segments = impl_segments(&input);
+    let type_tag = find_type_tag(&input);
 
     let regiestry_ident = syn::Ident::new(
         &format!("{}_REGISTRY", name.to_string().to_shouty_snake_case()),
@@ -24,11 +32,23 @@ pub fn derive_persistent_actor(input: TokenStream) -> TokenStream {
         impl ::kameo_persistence::PersistentActor for #name {
             type Snapshot = #snapshot_type;
 
+            fn type_tag() -> &'static str {
+                #type_tag
+            }
+
+            #persistent_children
+
+            #data_subject
+
+            #schema_version
+
+            #segments
 
             fn register_persistent(persistence_key: ::url::Url, actor_ref: &::kameo::prelude::ActorRef<Self>) -> ::anyhow::Result<()> {
                 let Ok(mut registry) = #regiestry_ident.write() else {
                     ::anyhow::bail!("Failed to acquire write lock on registry");
                 };
+                ::kameo_persistence::registry::register(persistence_key.clone(), actor_ref);
                 if let Some(old_pair) = registry.insert(persistence_key, actor_ref.downgrade()) {
                     #[cfg(feature = "tracing")]
                     ::tracing::warn!("Existing persistent actor reference for {old_pair:?} is replaced");
@@ -46,6 +66,60 @@ pub fn derive_persistent_actor(input: TokenStream) -> TokenStream {
                 registry
                     .get_right(persistence_key)
                     .and_then(|weak_ref| weak_ref.upgrade())
+                    .filter(|actor_ref| actor_ref.is_alive())
+            }
+
+            fn unregister_persistent(persistence_key: &::url::Url) {
+                ::kameo_persistence::registry::unregister(persistence_key);
+                #regiestry_ident.write().unwrap_or_else(|e| e.into_inner()).remove_left(persistence_key);
+            }
+
+            fn registry_counts() -> (usize, usize) {
+                ::kameo_persistence::registry::count_refs(&#regiestry_ident.read().unwrap_or_else(|e| e.into_inner()))
+            }
+
+            fn clear_registry() {
+                #regiestry_ident.write().unwrap_or_else(|e| e.into_inner()).clear();
+            }
+        }
+
+        ::kameo_persistence::inventory::submit! {
+            ::kameo_persistence::registry::TypeRegistration::new::<#name>()
+        }
+
+        impl ::kameo::prelude::Message<::kameo_persistence::autosave::Autosave> for #name {
+            type Reply = ();
+
+            async fn handle(
+                &mut self,
+                msg: ::kameo_persistence::autosave::Autosave,
+                ctx: &mut ::kameo::prelude::Context<Self, Self::Reply>,
+            ) -> Self::Reply {
+                ::kameo_persistence::PersistentActor::on_autosave(self, &ctx.actor_ref(), msg).await
+            }
+        }
+
+        impl ::kameo::prelude::Message<::kameo_persistence::rollback::Rollback> for #name {
+            type Reply = ::anyhow::Result<()>;
+
+            async fn handle(
+                &mut self,
+                _msg: ::kameo_persistence::rollback::Rollback,
+                ctx: &mut ::kameo::prelude::Context<Self, Self::Reply>,
+            ) -> Self::Reply {
+                ::kameo_persistence::PersistentActor::on_rollback(self, &ctx.actor_ref()).await
+            }
+        }
+
+        impl ::kameo::prelude::Message<::kameo_persistence::Checkpoint> for #name {
+            type Reply = ();
+
+            async fn handle(
+                &mut self,
+                msg: ::kameo_persistence::Checkpoint,
+                ctx: &mut ::kameo::prelude::Context<Self, Self::Reply>,
+            ) -> Self::Reply {
+                ::kameo_persistence::PersistentActor::on_checkpoint(self, &ctx.actor_ref(), msg).await
             }
         }
     };
@@ -65,3 +139,185 @@ fn find_snapshot_type(input: &DeriveInput) -> syn::Type {
 
     syn::parse_quote! { <Self as ::kameo::prelude::Actor>::Args }
 }
+
+fn find_type_tag(input: &DeriveInput) -> proc_macro2::TokenStream {
+    // Look for #[persistent(type_tag = "...")] attribute
+    let mut type_tag = None;
+    for attr in &input.attrs {
+        if attr.path().is_ident("persistent") {
+            let parsed = attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("type_tag") {
+                    type_tag = Some(meta.value()?.parse::<syn::LitStr>()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `type_tag = \"...\"`"))
+                }
+            });
+            if let Err(e) = parsed {
+                return e.to_compile_error();
+            }
+        }
+    }
+
+    match type_tag {
+        Some(type_tag) if type_tag.value().is_empty() => {
+            syn::Error::new(type_tag.span(), "type tag must not be empty").to_compile_error()
+        }
+        Some(type_tag) => quote! { #type_tag },
+        None => {
+            let name = &input.ident;
+            quote! { concat!(module_path!(), "::", stringify!(#name)) }
+        }
+    }
+}
+
+fn impl_schema_version(input: &DeriveInput) -> proc_macro2::TokenStream {
+    // Look for #[schema_version(N)] attribute
+    for attr in &input.attrs {
+        if attr.path().is_ident("schema_version") {
+            return match attr.parse_args::<syn::LitInt>() {
+                Ok(version) => quote! {
+                    fn schema_version() -> u32 {
+                        #version
+                    }
+                },
+                Err(e) => e.to_compile_error(),
+            };
+        }
+    }
+
+    quote! {}
+}
+
+fn impl_data_subject(input: &DeriveInput) -> proc_macro2::TokenStream {
+    // Look for #[data_subject(field)] attribute, naming a field of the snapshot
+    for attr in &input.attrs {
+        if attr.path().is_ident("data_subject") {
+            return match attr.parse_args::<syn::Member>() {
+                Ok(field) => quote! {
+                    fn data_subject(snapshot: &Self::Snapshot) -> Option<String> {
+                        Some(::std::string::ToString::to_string(&snapshot.#field))
+                    }
+                },
+                Err(e) => e.to_compile_error(),
+            };
+        }
+    }
+
+    quote! {}
+}
+
+fn impl_segments(input: &DeriveInput) -> proc_macro2::TokenStream {
+    // Look for #[segments(field, ...)] attribute, naming `Segment` fields of the snapshot
+    for attr in &input.attrs {
+        if attr.path().is_ident("segments") {
+            let fields = match attr.parse_args_with(
+                syn::punctuated::Punctuated::<syn::Ident, syn::Token![,]>::parse_terminated,
+            ) {
+                Ok(fields) => fields.into_iter().collect::<Vec<_>>(),
+                Err(e) => return e.to_compile_error(),
+            };
+            let names = fields
+                .iter()
+                .map(|field| field.to_string())
+                .collect::<Vec<_>>();
+
+            return quote! {
+                fn split_parts(snapshot: &mut Self::Snapshot) -> ::anyhow::Result<::kameo_persistence::parts::SnapshotParts> {
+                    let mut parts = ::kameo_persistence::parts::SnapshotParts::new();
+                    #(parts.insert_segment(#names, &mut snapshot.#fields)?;)*
+                    Ok(parts)
+                }
+
+                fn join_parts(snapshot: &mut Self::Snapshot, mut parts: ::kameo_persistence::parts::SnapshotParts) -> ::anyhow::Result<()> {
+                    #(parts.take_segment(#names, &mut snapshot.#fields)?;)*
+                    Ok(())
+                }
+            };
+        }
+    }
+
+    quote! {}
+}
+
+fn impl_persistent_children(input: &DeriveInput) -> proc_macro2::TokenStream {
+    // Look for fields marked with #[child]
+    let syn::Data::Struct(data) = &input.data else {
+        return quote! {};
+    };
+
+    let children = data
+        .fields
+        .iter()
+        .enumerate()
+        .filter(|(_, field)| field.attrs.iter().any(|attr| attr.path().is_ident("child")))
+        .map(|(index, field)| match &field.ident {
+            Some(ident) => quote! { #ident },
+            None => {
+                let index = syn::Index::from(index);
+                quote! { #index }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if children.is_empty() {
+        return quote! {};
+    }
+
+    quote! {
+        fn persistent_children(&self) -> Vec<::url::Url> {
+            let mut keys = Vec::new();
+            #(keys.extend(::kameo_persistence::hierarchy::PersistenceKeys::persistence_keys(&self.#children));)*
+            keys
+        }
+    }
+}
+
+/// Apply `#[persist(...)]` field attributes of a snapshot struct.
+///
+/// - `#[persist(redact)]` omits the field; it is restored as `Default::default()`.
+/// - `#[persist(hash)]` stores the SHA-256 of the field instead of its value.
+/// - `#[persist(encrypt)]` stores the field encrypted with the installed `FieldCipher`.
+///
+/// Must be placed above `#[derive(Serialize, Deserialize)]`.
+#[proc_macro_attribute]
+pub fn persist_fields(_args: TokenStream, item: TokenStream) -> TokenStream {
+    let mut item = parse_macro_input!(item as ItemStruct);
+
+    for field in item.fields.iter_mut() {
+        let mut attrs = Vec::with_capacity(field.attrs.len());
+
+        for attr in field.attrs.drain(..) {
+            if !attr.path().is_ident("persist") {
+                attrs.push(attr);
+                continue;
+            }
+
+            let mode = match attr.parse_args::<syn::Ident>() {
+                Ok(mode) => mode,
+                Err(e) => return e.to_compile_error().into(),
+            };
+
+            let serde_attr: syn::Attribute = match mode.to_string().as_str() {
+                "redact" => syn::parse_quote! { #[serde(skip)] },
+                "hash" => {
+                    syn::parse_quote! { #[serde(with = "::kameo_persistence::protect::hashed")] }
+                }
+                "encrypt" => {
+                    syn::parse_quote! { #[serde(with = "::kameo_persistence::protect::encrypted")] }
+                }
+                _ => {
+                    return syn::Error::new(mode.span(), "expected `redact`, `hash` or `encrypt`")
+                        .to_compile_error()
+                        .into();
+                }
+            };
+
+            attrs.push(serde_attr);
+        }
+
+        field.attrs = attrs;
+    }
+
+    TokenStream::from(quote! { #item })
+}
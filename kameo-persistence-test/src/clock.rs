@@ -0,0 +1,68 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use futures::future::BoxFuture;
+use kameo_persistence::clock::{self, Clock};
+use tokio::sync::watch;
+
+/// Clock which only moves when told to.
+pub struct ManualClock {
+    instant: Instant,
+    system_time: SystemTime,
+    elapsed: watch::Sender<Duration>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self {
+            instant: Instant::now(),
+            system_time: SystemTime::now(),
+            elapsed: watch::Sender::new(Duration::ZERO),
+        }
+    }
+}
+
+impl ManualClock {
+    /// Create a clock stopped at the current time and install it.
+    pub fn install() -> Arc<Self> {
+        let clock = Arc::new(Self::default());
+        clock::set_clock(clock.clone());
+        clock
+    }
+
+    /// Move time forward, completing every sleep whose deadline is reached.
+    pub fn advance(&self, duration: Duration) {
+        self.elapsed.send_modify(|elapsed| *elapsed += duration);
+    }
+
+    /// Time elapsed since the clock was created.
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.borrow()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.instant + self.elapsed()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.system_time + self.elapsed()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        let start = self.instant;
+        let mut elapsed = self.elapsed.subscribe();
+
+        Box::pin(async move {
+            while start + *elapsed.borrow_and_update() < deadline {
+                // The clock is gone, so time never reaches the deadline
+                if elapsed.changed().await.is_err() {
+                    std::future::pending::<()>().await;
+                }
+            }
+        })
+    }
+}
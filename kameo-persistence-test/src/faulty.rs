@@ -0,0 +1,285 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use kameo_persistence::storage::{self, Backend};
+use url::Url;
+
+/// Fault injected into a backend operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Delay the operation.
+    Latency(Duration),
+    /// Fail the operation without touching storage.
+    Error,
+    /// Write only the first half of the data, then fail.
+    PartialWrite,
+    /// Flip a byte of the data written or read, and report success.
+    Corrupt,
+}
+
+/// Backend operation, as recorded with the faults injected into it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Read,
+    Write,
+    Rename,
+    Remove,
+    Stat,
+    Delete,
+    List,
+}
+
+impl Operation {
+    fn is_write(self) -> bool {
+        matches!(self, Self::Write)
+    }
+
+    fn carries_data(self) -> bool {
+        matches!(self, Self::Read | Self::Write)
+    }
+}
+
+// SplitMix64, enough to make a schedule reproducible from its seed
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        if probability <= 0.0 {
+            return false;
+        }
+
+        let sample = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        sample < probability
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            0
+        } else {
+            self.next_u64() % bound
+        }
+    }
+}
+
+struct State {
+    rng: Rng,
+    scheduled: VecDeque<Option<Fault>>,
+    injected: Vec<(Operation, Fault)>,
+}
+
+/// Backend decorator injecting latency, errors, partial writes and corruption.
+///
+/// Faults are taken from the scripted schedule first, then drawn at the configured rates from a
+/// generator seeded with `seed`, so a failing test replays identically. Latency blocks the
+/// calling thread, as backend operations are synchronous.
+pub struct FaultyBackend {
+    inner: Arc<dyn Backend>,
+    error_rate: f64,
+    partial_write_rate: f64,
+    corruption_rate: f64,
+    max_latency: Duration,
+    state: Mutex<State>,
+}
+
+impl FaultyBackend {
+    /// Wrap `inner` without injecting any fault yet.
+    pub fn new(inner: Arc<dyn Backend>, seed: u64) -> Self {
+        Self {
+            inner,
+            error_rate: 0.0,
+            partial_write_rate: 0.0,
+            corruption_rate: 0.0,
+            max_latency: Duration::ZERO,
+            state: Mutex::new(State {
+                rng: Rng(seed),
+                scheduled: VecDeque::new(),
+                injected: Vec::new(),
+            }),
+        }
+    }
+
+    /// Fail this fraction of operations.
+    pub fn with_error_rate(mut self, rate: f64) -> Self {
+        self.error_rate = rate;
+        self
+    }
+
+    /// Cut this fraction of writes short.
+    pub fn with_partial_write_rate(mut self, rate: f64) -> Self {
+        self.partial_write_rate = rate;
+        self
+    }
+
+    /// Corrupt this fraction of the data written or read.
+    pub fn with_corruption_rate(mut self, rate: f64) -> Self {
+        self.corruption_rate = rate;
+        self
+    }
+
+    /// Delay every operation by up to `max`.
+    pub fn with_latency(mut self, max: Duration) -> Self {
+        self.max_latency = max;
+        self
+    }
+
+    /// Store keys of `scheme` through this backend.
+    pub fn install(self, scheme: &str) -> Arc<Self> {
+        let backend = Arc::new(self);
+        storage::set_backend(scheme, backend.clone());
+        backend
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Inject `fault` into the next operation not already scheduled, or none if `None`.
+    pub fn schedule(&self, fault: Option<Fault>) {
+        self.state().scheduled.push_back(fault);
+    }
+
+    /// Faults injected so far, in order.
+    pub fn injected(&self) -> Vec<(Operation, Fault)> {
+        self.state().injected.clone()
+    }
+
+    fn draw(&self, state: &mut State, operation: Operation) -> Option<Fault> {
+        if let Some(fault) = state.scheduled.pop_front() {
+            return fault;
+        }
+
+        let rng = &mut state.rng;
+        if rng.chance(self.error_rate) {
+            Some(Fault::Error)
+        } else if operation.is_write() && rng.chance(self.partial_write_rate) {
+            Some(Fault::PartialWrite)
+        } else if operation.carries_data() && rng.chance(self.corruption_rate) {
+            Some(Fault::Corrupt)
+        } else if !self.max_latency.is_zero() {
+            let nanos = rng.below(self.max_latency.as_nanos() as u64 + 1);
+            Some(Fault::Latency(Duration::from_nanos(nanos)))
+        } else {
+            None
+        }
+    }
+
+    /// Decide the fault of an operation, applying latency and errors right away.
+    fn begin(&self, operation: Operation) -> anyhow::Result<Option<(Fault, u64)>> {
+        let (fault, entropy) = {
+            let mut state = self.state();
+            let fault = self.draw(&mut state, operation);
+            if let Some(fault) = fault {
+                state.injected.push((operation, fault));
+            }
+            (fault, state.rng.next_u64())
+        };
+
+        match fault {
+            Some(Fault::Latency(delay)) => {
+                std::thread::sleep(delay);
+                Ok(None)
+            }
+            Some(Fault::Error) => anyhow::bail!("Injected {operation:?} failure"),
+            Some(fault) => Ok(Some((fault, entropy))),
+            None => Ok(None),
+        }
+    }
+}
+
+fn corrupt(data: &mut [u8], entropy: u64) {
+    if data.is_empty() {
+        return;
+    }
+    let index = (entropy % data.len() as u64) as usize;
+    data[index] ^= 1 << ((entropy >> 32) % 8);
+}
+
+impl Backend for FaultyBackend {
+    fn read_file(&self, key: &Url, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let fault = self.begin(Operation::Read)?;
+        let mut data = self.inner.read_file(key, name)?;
+
+        if let (Some((Fault::Corrupt, entropy)), Some(data)) = (fault, data.as_mut()) {
+            corrupt(data, entropy);
+        }
+
+        Ok(data)
+    }
+
+    fn write_file(&self, key: &Url, name: &str, data: &[u8]) -> anyhow::Result<()> {
+        match self.begin(Operation::Write)? {
+            Some((Fault::PartialWrite, _)) => {
+                self.inner.write_file(key, name, &data[..data.len() / 2])?;
+                anyhow::bail!("Injected partial write");
+            }
+            Some((Fault::Corrupt, entropy)) => {
+                let mut data = data.to_vec();
+                corrupt(&mut data, entropy);
+                self.inner.write_file(key, name, &data)
+            }
+            _ => self.inner.write_file(key, name, data),
+        }
+    }
+
+    fn rename_file(&self, key: &Url, from: &str, to: &str) -> anyhow::Result<()> {
+        self.begin(Operation::Rename)?;
+        self.inner.rename_file(key, from, to)
+    }
+
+    fn remove_file(&self, key: &Url, name: &str) -> anyhow::Result<()> {
+        self.begin(Operation::Remove)?;
+        self.inner.remove_file(key, name)
+    }
+
+    fn file_size(&self, key: &Url, name: &str) -> anyhow::Result<Option<u64>> {
+        self.begin(Operation::Stat)?;
+        self.inner.file_size(key, name)
+    }
+
+    fn exists(&self, key: &Url) -> anyhow::Result<bool> {
+        self.begin(Operation::Stat)?;
+        self.inner.exists(key)
+    }
+
+    fn delete(&self, key: &Url) -> anyhow::Result<()> {
+        self.begin(Operation::Delete)?;
+        self.inner.delete(key)
+    }
+
+    fn list(&self, root: &Url) -> anyhow::Result<Vec<Url>> {
+        self.begin(Operation::List)?;
+        self.inner.list(root)
+    }
+
+    fn list_files(&self, key: &Url) -> anyhow::Result<Vec<String>> {
+        self.begin(Operation::List)?;
+        self.inner.list_files(key)
+    }
+
+    fn file_version(&self, key: &Url, name: &str) -> anyhow::Result<Option<String>> {
+        self.begin(Operation::Stat)?;
+        self.inner.file_version(key, name)
+    }
+
+    fn write_file_if(
+        &self,
+        key: &Url,
+        name: &str,
+        expected: Option<&str>,
+        data: &[u8],
+    ) -> anyhow::Result<Option<String>> {
+        self.begin(Operation::Write)?;
+        self.inner.write_file_if(key, name, expected, data)
+    }
+}
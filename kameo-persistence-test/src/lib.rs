@@ -0,0 +1,43 @@
+pub mod clock;
+pub mod faulty;
+pub mod memory;
+pub mod round_trip;
+
+use std::fmt::Debug;
+
+use kameo_persistence::{PersistentActor, codec, registry, storage};
+use url::Url;
+
+pub use clock::ManualClock;
+pub use faulty::{Fault, FaultyBackend, Operation};
+pub use memory::MemoryBackend;
+pub use round_trip::assert_round_trip;
+
+/// Decode the snapshot of an actor type stored under a key.
+pub async fn read_snapshot<A: PersistentActor>(key: &Url) -> anyhow::Result<A::Snapshot> {
+    let data = storage::read(key).await?;
+    codec::decode::<A>(&data)
+}
+
+/// Panic unless the snapshot stored under `key` equals `expected`.
+pub async fn assert_snapshot<A>(key: &Url, expected: &A::Snapshot)
+where
+    A: PersistentActor,
+    A::Snapshot: PartialEq + Debug,
+{
+    match read_snapshot::<A>(key).await {
+        Ok(snapshot) => assert_eq!(
+            &snapshot, expected,
+            "snapshot stored under {key} differs from the expected one"
+        ),
+        Err(e) => panic!("no readable snapshot stored under {key}: {e:#}"),
+    }
+}
+
+/// Simulate a process restart: stop every persistent actor and clear every registry.
+///
+/// Storage is left untouched, so `lookup_persistent` finds nothing until actors are respawned
+/// from their snapshots.
+pub async fn restart() {
+    registry::reset().await;
+}
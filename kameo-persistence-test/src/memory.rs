@@ -0,0 +1,215 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use kameo_persistence::storage::{self, Backend};
+use url::Url;
+
+#[derive(Default)]
+struct State {
+    keys: BTreeMap<Url, BTreeMap<String, Vec<u8>>>,
+    fail_reads: bool,
+    fail_writes: bool,
+}
+
+/// Backend keeping every key in memory.
+///
+/// Clones share the same storage, so a test can inspect what the actors under test wrote.
+#[derive(Clone, Default)]
+pub struct MemoryBackend {
+    state: Arc<Mutex<State>>,
+}
+
+impl MemoryBackend {
+    /// Create an empty backend and store keys of `scheme` in it.
+    pub fn install(scheme: &str) -> Self {
+        let backend = Self::default();
+        storage::set_backend(scheme, Arc::new(backend.clone()));
+        backend
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Raw snapshot bytes stored under a key.
+    pub fn snapshot(&self, key: &Url) -> Option<Vec<u8>> {
+        self.state()
+            .keys
+            .get(key)?
+            .get(storage::snapshot_file())
+            .cloned()
+    }
+
+    /// Keys holding a snapshot.
+    pub fn keys(&self) -> Vec<Url> {
+        self.state()
+            .keys
+            .iter()
+            .filter(|(_, files)| files.contains_key(storage::snapshot_file()))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Forget everything stored.
+    pub fn clear(&self) {
+        self.state().keys.clear();
+    }
+
+    /// Make every read fail until turned off again.
+    pub fn fail_reads(&self, fail: bool) {
+        self.state().fail_reads = fail;
+    }
+
+    /// Make every write fail until turned off again.
+    pub fn fail_writes(&self, fail: bool) {
+        self.state().fail_writes = fail;
+    }
+
+    fn check_read(state: &State) -> anyhow::Result<()> {
+        if state.fail_reads {
+            anyhow::bail!("Injected read failure");
+        }
+        Ok(())
+    }
+
+    fn check_write(state: &State) -> anyhow::Result<()> {
+        if state.fail_writes {
+            anyhow::bail!("Injected write failure");
+        }
+        Ok(())
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn read_file(&self, key: &Url, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let state = self.state();
+        Self::check_read(&state)?;
+
+        Ok(state
+            .keys
+            .get(key)
+            .and_then(|files| files.get(name))
+            .cloned())
+    }
+
+    fn write_file(&self, key: &Url, name: &str, data: &[u8]) -> anyhow::Result<()> {
+        let mut state = self.state();
+        Self::check_write(&state)?;
+
+        state
+            .keys
+            .entry(key.clone())
+            .or_default()
+            .insert(name.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    fn rename_file(&self, key: &Url, from: &str, to: &str) -> anyhow::Result<()> {
+        let mut state = self.state();
+        Self::check_write(&state)?;
+
+        let files = state
+            .keys
+            .get_mut(key)
+            .ok_or_else(|| anyhow::anyhow!("No such key: {key}"))?;
+        let data = files
+            .remove(from)
+            .ok_or_else(|| anyhow::anyhow!("No such file: {from}"))?;
+        files.insert(to.to_string(), data);
+        Ok(())
+    }
+
+    fn remove_file(&self, key: &Url, name: &str) -> anyhow::Result<()> {
+        let mut state = self.state();
+        Self::check_write(&state)?;
+
+        if let Some(files) = state.keys.get_mut(key) {
+            files.remove(name);
+        }
+        Ok(())
+    }
+
+    fn file_size(&self, key: &Url, name: &str) -> anyhow::Result<Option<u64>> {
+        let state = self.state();
+        Self::check_read(&state)?;
+
+        Ok(state
+            .keys
+            .get(key)
+            .and_then(|files| files.get(name))
+            .map(|data| data.len() as u64))
+    }
+
+    fn exists(&self, key: &Url) -> anyhow::Result<bool> {
+        let state = self.state();
+        Self::check_read(&state)?;
+
+        Ok(state
+            .keys
+            .keys()
+            .any(|stored| storage::is_within(key, stored)))
+    }
+
+    fn delete(&self, key: &Url) -> anyhow::Result<()> {
+        let mut state = self.state();
+        Self::check_write(&state)?;
+
+        state
+            .keys
+            .retain(|stored, _| !storage::is_within(key, stored));
+        Ok(())
+    }
+
+    fn list(&self, root: &Url) -> anyhow::Result<Vec<Url>> {
+        let state = self.state();
+        Self::check_read(&state)?;
+
+        // Parents sort before their children
+        Ok(state
+            .keys
+            .keys()
+            .filter(|stored| storage::is_within(root, stored))
+            .cloned()
+            .collect())
+    }
+
+    fn list_files(&self, key: &Url) -> anyhow::Result<Vec<String>> {
+        let state = self.state();
+        Self::check_read(&state)?;
+
+        Ok(state
+            .keys
+            .get(key)
+            .map(|files| files.keys().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn write_file_if(
+        &self,
+        key: &Url,
+        name: &str,
+        expected: Option<&str>,
+        data: &[u8],
+    ) -> anyhow::Result<Option<String>> {
+        let mut state = self.state();
+        Self::check_write(&state)?;
+
+        let current = state
+            .keys
+            .get(key)
+            .and_then(|files| files.get(name))
+            .map(|data| storage::content_version(data));
+        if current.as_deref() != expected {
+            return Ok(None);
+        }
+
+        state
+            .keys
+            .entry(key.clone())
+            .or_default()
+            .insert(name.to_string(), data.to_vec());
+        Ok(Some(storage::content_version(data)))
+    }
+}
@@ -0,0 +1,64 @@
+use std::{fmt::Debug, sync::LazyLock, time::Duration};
+
+use kameo::prelude::*;
+use kameo_persistence::{Checkpoint, PersistentActor, checkpoint, codec};
+use url::Url;
+
+use crate::{MemoryBackend, read_snapshot};
+
+/// Scheme of the keys round trips are stored under.
+const SCHEME: &str = "kameo-round-trip";
+
+static BACKEND: LazyLock<MemoryBackend> = LazyLock::new(|| MemoryBackend::install(SCHEME));
+
+/// Assert that every generated actor survives persistence unchanged.
+///
+/// For each actor, its snapshot is encoded and decoded, turned into `Args`, spawned, and
+/// checkpointed again; every step must yield the original snapshot. Feed it actors generated by
+/// a property testing library to catch serialization asymmetries, such as fields skipped when
+/// saving or lost in `Args`.
+pub async fn assert_round_trip<A>(actors: impl IntoIterator<Item = A>)
+where
+    A: PersistentActor + Message<Checkpoint, Reply = ()>,
+    A::Snapshot: PartialEq + Debug,
+{
+    let backend = LazyLock::force(&BACKEND);
+
+    for (case, actor) in actors.into_iter().enumerate() {
+        let mut key = Url::parse(&format!("{SCHEME}://cases")).expect("valid round trip root");
+        key.path_segments_mut()
+            .expect("round trip root is a base")
+            .push(A::type_tag())
+            .push(&case.to_string());
+        let snapshot = A::Snapshot::from(&actor);
+
+        let data = codec::encode::<A>(&key, &snapshot)
+            .unwrap_or_else(|e| panic!("case {case}: failed to encode snapshot: {e:#}"));
+        let decoded = codec::decode::<A>(&data)
+            .unwrap_or_else(|e| panic!("case {case}: failed to decode snapshot: {e:#}"));
+        assert_eq!(
+            decoded, snapshot,
+            "case {case}: snapshot changed by encoding"
+        );
+
+        let actor_ref = A::spawn_persistent(key.clone(), decoded.into())
+            .await
+            .unwrap_or_else(|e| panic!("case {case}: failed to spawn from snapshot: {e:#}"));
+
+        checkpoint(&key, Duration::from_secs(5))
+            .await
+            .unwrap_or_else(|e| panic!("case {case}: failed to checkpoint respawned actor: {e:#}"));
+
+        let respawned = read_snapshot::<A>(&key)
+            .await
+            .unwrap_or_else(|e| panic!("case {case}: failed to read snapshot back: {e:#}"));
+        assert_eq!(
+            respawned, snapshot,
+            "case {case}: snapshot changed by respawning the actor"
+        );
+
+        let _ = actor_ref.stop_gracefully().await;
+        actor_ref.wait_for_shutdown().await;
+        backend.clear();
+    }
+}
@@ -1,7 +1,7 @@
 use kameo::prelude::*;
-use kameo_persistence::PersistentActor;
+use kameo_persistence::{PersistenceKeyExt, PersistentActor};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 use tracing::{debug, warn};
 use url::Url;
 use uuid::Uuid;
@@ -10,6 +10,7 @@ use uuid::Uuid;
 #[derive(Debug, Clone, PersistentActor)]
 pub struct ManagerActor {
     pub config: String,
+    #[child]
     pub sub_actors: HashMap<String, ActorRef<SubActor>>,
 }
 
@@ -80,7 +81,7 @@ impl Message<AddSubActor> for ManagerActor {
             return Ok(SubActor::spawn(SubActor { data: msg.data }));
         };
 
-        let sub_key = key.join("sub-actors")?.join(&Uuid::new_v4().to_string())?;
+        let sub_key = key.child::<SubActor>(&Uuid::new_v4().to_string())?;
 
         let Ok(sub_actor) = SubActor::spawn_persistent(
             sub_key.clone(),
@@ -125,7 +126,7 @@ async fn main() -> anyhow::Result<()> {
 
     // Try to restore or create new manager
     let manager = ManagerActor::try_respawn_persistent(
-        manager_key,
+        manager_key.clone(),
         ManagerArgs {
             config: "Default Config".to_string(),
             sub_actors: HashMap::new(),
@@ -145,5 +146,9 @@ async fn main() -> anyhow::Result<()> {
     let config = manager.ask(GetConfig).await?;
     println!("Manager config: {}", config);
 
+    // Save the manager and its sub-actors at a common barrier
+    let saved = kameo_persistence::checkpoint(&manager_key, Duration::from_secs(5)).await?;
+    println!("Checkpointed {} actors", saved.len());
+
     Ok(())
 }
@@ -0,0 +1,53 @@
+use kameo::prelude::*;
+use kameo_persistence::{
+    PersistentActor, PersistentSupervisor,
+    supervisor::{GetChild, ListChildren, SpawnChild, SupervisorSnapshot},
+};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+pub struct Worker {
+    pub data: String,
+}
+
+impl From<&Worker> for Worker {
+    fn from(actor: &Worker) -> Self {
+        actor.clone()
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let supervisor_key = Url::parse("file:///tmp/workers")?;
+
+    // Restores the supervisor along with every worker it spawned in previous runs
+    let supervisor = PersistentSupervisor::<Worker>::try_respawn_persistent(
+        supervisor_key,
+        SupervisorSnapshot::default(),
+    )
+    .await?;
+
+    if supervisor
+        .ask(GetChild {
+            name: "worker1".to_string(),
+        })
+        .await?
+        .is_none()
+    {
+        supervisor
+            .ask(SpawnChild {
+                name: "worker1".to_string(),
+                args: Worker {
+                    data: "Worker data".to_string(),
+                },
+            })
+            .await?;
+    }
+
+    for (name, key) in supervisor.ask(ListChildren).await? {
+        println!("{name}: {key}");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,283 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{checkpoint, codec, history, redact::redacted, registry, storage, write_stats};
+
+/// Checkpoint timeout of `POST /actors/{key}/save` unless `timeout_ms` is given.
+const DEFAULT_SAVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Live persistent actor, as listed by `GET /actors`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ActorInfo {
+    pub key: String,
+    pub type_tag: &'static str,
+}
+
+/// Metadata of a stored snapshot, as shown by `GET /actors/{key}/snapshot`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotInfo {
+    pub key: String,
+    /// Version of the header format, `None` for snapshots without a header.
+    pub format_version: Option<u8>,
+    pub type_tag: Option<String>,
+    pub schema_version: Option<u32>,
+    /// True if the payload is encrypted for a data subject, which is not disclosed.
+    pub encrypted: bool,
+    pub size: usize,
+}
+
+/// Snapshot in effect at a past moment, as shown by `GET /actors/{key}/state`.
+#[derive(Debug, Clone, Serialize)]
+pub struct PastStateInfo {
+    pub key: String,
+    /// Time the snapshot was written at, in milliseconds since the Unix epoch.
+    pub saved_at_ms: u64,
+    pub type_tag: Option<String>,
+    pub schema_version: Option<u32>,
+    pub size: usize,
+    /// Number of messages recorded between the snapshot and the moment.
+    pub messages_since: usize,
+    /// Snapshot as JSON, unless its payload is encrypted for a data subject.
+    #[cfg(feature = "json")]
+    pub state: Option<serde_json::Value>,
+}
+
+/// Snapshot write remembered in memory, as listed by `GET /actors/{key}/writes`.
+#[derive(Debug, Clone, Serialize)]
+pub struct WriteInfo {
+    /// Time of the write, in milliseconds since the Unix epoch.
+    pub at_ms: u64,
+    pub bytes: usize,
+}
+
+/// Key written most often recently, as listed by `GET /writes/busiest`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BusyKeyInfo {
+    pub key: String,
+    pub writes: usize,
+}
+
+/// Keys written by `POST /actors/{key}/save`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SaveResult {
+    pub saved: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SaveParams {
+    timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StateParams {
+    at_ms: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct BusiestParams {
+    window_ms: u64,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+struct AdminError(StatusCode, String);
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        (self.0, Json(ErrorBody { error: self.1 })).into_response()
+    }
+}
+
+impl From<anyhow::Error> for AdminError {
+    fn from(e: anyhow::Error) -> Self {
+        Self(StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}"))
+    }
+}
+
+/// Router exposing the persistence registry of this process:
+///
+/// - `GET /actors` lists the live persistent actors
+/// - `GET /actors/{key}` shows a live actor
+/// - `GET /actors/{key}/snapshot` shows the metadata of the snapshot stored under a key
+/// - `GET /actors/{key}/state?at_ms=` shows the snapshot kept in the history of a key which was
+///   in effect at a past moment, given in milliseconds since the Unix epoch
+/// - `GET /actors/{key}/writes` lists the last writes of a key, see
+///   `write_stats::set_write_tracking`
+/// - `GET /writes/busiest?window_ms=&limit=` lists the keys written most often within the
+///   window
+/// - `POST /actors/{key}/save` checkpoints an actor and its descendants, optionally with
+///   `?timeout_ms=`
+///
+/// Keys are percent-encoded URLs, and are redacted in responses. The router performs no
+/// authentication; nest it behind your own.
+pub fn router<S: Clone + Send + Sync + 'static>() -> Router<S> {
+    Router::new()
+        .route("/actors", get(list_actors))
+        .route("/actors/{key}", get(show_actor))
+        .route("/actors/{key}/snapshot", get(show_snapshot))
+        .route("/actors/{key}/state", get(show_state))
+        .route("/actors/{key}/writes", get(list_writes))
+        .route("/writes/busiest", get(list_busiest))
+        .route("/actors/{key}/save", post(save_actor))
+}
+
+fn parse_key(key: &str) -> Result<Url, AdminError> {
+    Url::parse(key).map_err(|e| AdminError(StatusCode::BAD_REQUEST, format!("Invalid key: {e}")))
+}
+
+fn not_found(key: &Url, what: &str) -> AdminError {
+    AdminError(
+        StatusCode::NOT_FOUND,
+        format!("No {what} for {}", redacted(key)),
+    )
+}
+
+async fn list_actors() -> Json<Vec<ActorInfo>> {
+    let actors = registry::live_keys()
+        .into_iter()
+        .filter_map(|key| {
+            let actor = registry::lookup(&key)?;
+            Some(ActorInfo {
+                key: redacted(&key).to_string(),
+                type_tag: actor.type_tag(),
+            })
+        })
+        .collect();
+
+    Json(actors)
+}
+
+async fn show_actor(Path(key): Path<String>) -> Result<Json<ActorInfo>, AdminError> {
+    let key = parse_key(&key)?;
+    let actor = registry::lookup(&key).ok_or_else(|| not_found(&key, "live persistent actor"))?;
+
+    Ok(Json(ActorInfo {
+        key: redacted(&key).to_string(),
+        type_tag: actor.type_tag(),
+    }))
+}
+
+async fn show_snapshot(Path(key): Path<String>) -> Result<Json<SnapshotInfo>, AdminError> {
+    let key = parse_key(&key)?;
+
+    if storage::backend(&key)?
+        .file_size(&key, storage::snapshot_file())?
+        .is_none()
+    {
+        return Err(not_found(&key, "snapshot"));
+    }
+
+    let data = storage::read(&key).await?;
+    let (header, _) = codec::split(&data)?;
+
+    Ok(Json(SnapshotInfo {
+        key: redacted(&key).to_string(),
+        format_version: codec::format_version(&data),
+        encrypted: header.as_ref().is_some_and(|h| h.subject.is_some()),
+        schema_version: header.as_ref().map(|h| h.schema_version),
+        type_tag: header.map(|h| h.type_tag),
+        size: data.len(),
+    }))
+}
+
+async fn show_state(
+    Path(key): Path<String>,
+    Query(params): Query<StateParams>,
+) -> Result<Json<PastStateInfo>, AdminError> {
+    let key = parse_key(&key)?;
+    let at = UNIX_EPOCH + Duration::from_millis(params.at_ms);
+
+    let past = history::state_at(&key, at)
+        .await?
+        .ok_or_else(|| not_found(&key, "snapshot history"))?;
+    let (header, _) = codec::split(&past.snapshot)?;
+
+    #[cfg(feature = "json")]
+    let state = match &header {
+        Some(h) if h.subject.is_none() => registry::registration(&h.type_tag)
+            .map(|registration| (registration.to_json)(&past.snapshot))
+            .transpose()?,
+        _ => None,
+    };
+
+    Ok(Json(PastStateInfo {
+        key: redacted(&key).to_string(),
+        saved_at_ms: unix_ms(past.saved_at),
+        schema_version: header.as_ref().map(|h| h.schema_version),
+        type_tag: header.map(|h| h.type_tag),
+        size: past.snapshot.len(),
+        messages_since: past.messages.len(),
+        #[cfg(feature = "json")]
+        state,
+    }))
+}
+
+async fn list_writes(Path(key): Path<String>) -> Result<Json<Vec<WriteInfo>>, AdminError> {
+    let key = parse_key(&key)?;
+
+    let writes = write_stats::recent_writes(&key)
+        .into_iter()
+        .map(|record| WriteInfo {
+            at_ms: unix_ms(record.at),
+            bytes: record.bytes,
+        })
+        .collect();
+
+    Ok(Json(writes))
+}
+
+async fn list_busiest(Query(params): Query<BusiestParams>) -> Json<Vec<BusyKeyInfo>> {
+    let busiest = write_stats::busiest(
+        Duration::from_millis(params.window_ms),
+        params.limit.unwrap_or(10),
+    );
+
+    Json(
+        busiest
+            .into_iter()
+            .map(|(key, writes)| BusyKeyInfo {
+                key: redacted(&key).to_string(),
+                writes,
+            })
+            .collect(),
+    )
+}
+
+async fn save_actor(
+    Path(key): Path<String>,
+    Query(params): Query<SaveParams>,
+) -> Result<Json<SaveResult>, AdminError> {
+    let key = parse_key(&key)?;
+
+    if registry::lookup(&key).is_none() {
+        return Err(not_found(&key, "live persistent actor"));
+    }
+
+    let timeout = params
+        .timeout_ms
+        .map_or(DEFAULT_SAVE_TIMEOUT, Duration::from_millis);
+    let saved = checkpoint(&key, timeout).await?;
+
+    Ok(Json(SaveResult {
+        saved: saved.iter().map(|key| redacted(key).to_string()).collect(),
+    }))
+}
+
+fn unix_ms(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
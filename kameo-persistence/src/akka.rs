@@ -0,0 +1,197 @@
+use std::marker::PhantomData;
+
+#[cfg(feature = "tracing")]
+use tracing::debug;
+use url::Url;
+
+#[cfg(feature = "tracing")]
+use crate::redact::redacted;
+use crate::{PersistentActor, tenant::push_segments};
+
+/// Latest snapshot of an entity of the akka-persistence-jdbc (or Pekko) `snapshot` table.
+pub const JDBC_SNAPSHOT_QUERY: &str = "SELECT persistence_id, sequence_number, created, \
+     snapshot_ser_id, snapshot_ser_manifest, snapshot_payload FROM snapshot \
+     WHERE persistence_id = ? ORDER BY sequence_number DESC LIMIT 1";
+
+/// Events of an entity from a sequence number on, of the akka-persistence-jdbc (or Pekko)
+/// `event_journal` table.
+pub const JDBC_EVENTS_QUERY: &str = "SELECT persistence_id, sequence_number, deleted, \
+     event_ser_id, event_ser_manifest, event_payload FROM event_journal \
+     WHERE persistence_id = ? AND sequence_number >= ? ORDER BY sequence_number";
+
+/// Latest snapshot of an entity of the akka-persistence-cassandra (or Pekko) `snapshots` table.
+pub const CASSANDRA_SNAPSHOT_QUERY: &str = "SELECT persistence_id, sequence_nr, timestamp, \
+     ser_id, ser_manifest, snapshot_data FROM snapshots \
+     WHERE persistence_id = ? ORDER BY sequence_nr DESC LIMIT 1";
+
+/// Events of one partition of an entity from a sequence number on, of the
+/// akka-persistence-cassandra (or Pekko) `messages` table, see `cassandra_partition`.
+pub const CASSANDRA_EVENTS_QUERY: &str = "SELECT persistence_id, sequence_nr, ser_id, \
+     ser_manifest, event FROM messages \
+     WHERE persistence_id = ? AND partition_nr = ? AND sequence_nr >= ?";
+
+/// Partition of the Cassandra `messages` table holding `sequence_nr`, for the journal's
+/// `target-partition-size` (500000 by default).
+pub fn cassandra_partition(sequence_nr: i64, partition_size: i64) -> i64 {
+    (sequence_nr - 1).max(0) / partition_size
+}
+
+/// Snapshot row of an Akka or Pekko persistence table.
+///
+/// The payload is serialized by the JVM serializer `ser_id`, such as Jackson JSON or protobuf,
+/// with `ser_manifest` naming the serialized class.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AkkaSnapshot {
+    pub persistence_id: String,
+    pub sequence_nr: i64,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: i64,
+    pub ser_id: i32,
+    pub ser_manifest: String,
+    pub payload: Vec<u8>,
+}
+
+/// Event row of an Akka or Pekko persistence journal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AkkaEvent {
+    pub persistence_id: String,
+    pub sequence_nr: i64,
+    /// Logically deleted, skipped when replaying; Cassandra journals have no such flag.
+    pub deleted: bool,
+    pub ser_id: i32,
+    pub ser_manifest: String,
+    pub payload: Vec<u8>,
+}
+
+/// Rows of Akka or Pekko persistence tables, implemented over a JDBC or Cassandra client of the
+/// application, such as with the queries of this module.
+pub trait AkkaJournal: Send + Sync {
+    /// Snapshot of the entity with the highest sequence number, if any.
+    fn latest_snapshot(
+        &self,
+        persistence_id: &str,
+    ) -> impl Future<Output = anyhow::Result<Option<AkkaSnapshot>>> + Send;
+
+    /// Events of the entity from `from_sequence_nr` on, in sequence order.
+    fn events(
+        &self,
+        persistence_id: &str,
+        from_sequence_nr: i64,
+    ) -> impl Future<Output = anyhow::Result<Vec<AkkaEvent>>> + Send;
+}
+
+type SnapshotDecoder<S> = Box<dyn Fn(&AkkaSnapshot) -> anyhow::Result<S> + Send + Sync>;
+
+type EventHandler<S> = Box<dyn Fn(&mut S, &AkkaEvent) -> anyhow::Result<()> + Send + Sync>;
+
+type Initial<S> = Box<dyn Fn() -> S + Send + Sync>;
+
+/// Restores the state of Akka or Pekko entities into snapshots of `A`.
+///
+/// The latest Akka snapshot is decoded, and the events after it are applied on top if an event
+/// handler is set, as the JVM entity would recover.
+pub struct AkkaImport<A: PersistentActor> {
+    decode_snapshot: SnapshotDecoder<A::Snapshot>,
+    apply_event: Option<EventHandler<A::Snapshot>>,
+    initial: Option<Initial<A::Snapshot>>,
+    _actor: PhantomData<fn() -> A>,
+}
+
+impl<A: PersistentActor> AkkaImport<A> {
+    /// Decode Akka snapshots with `decode_snapshot`, matching on their serializer and manifest.
+    pub fn new(
+        decode_snapshot: impl Fn(&AkkaSnapshot) -> anyhow::Result<A::Snapshot> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            decode_snapshot: Box::new(decode_snapshot),
+            apply_event: None,
+            initial: None,
+            _actor: PhantomData,
+        }
+    }
+
+    /// Replay the events after the latest snapshot with `apply_event`.
+    pub fn events(
+        mut self,
+        apply_event: impl Fn(&mut A::Snapshot, &AkkaEvent) -> anyhow::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.apply_event = Some(Box::new(apply_event));
+        self
+    }
+
+    /// State to replay the events of entities without an Akka snapshot on.
+    pub fn initial(mut self, initial: impl Fn() -> A::Snapshot + Send + Sync + 'static) -> Self {
+        self.initial = Some(Box::new(initial));
+        self
+    }
+
+    /// Recover the state of an entity, or `None` if the journal holds nothing for it.
+    pub async fn restore(
+        &self,
+        journal: &impl AkkaJournal,
+        persistence_id: &str,
+    ) -> anyhow::Result<Option<A::Snapshot>> {
+        let (mut state, from) = match journal.latest_snapshot(persistence_id).await? {
+            Some(row) => (Some((self.decode_snapshot)(&row)?), row.sequence_nr + 1),
+            None => (None, 1),
+        };
+
+        let Some(apply_event) = &self.apply_event else {
+            return Ok(state);
+        };
+
+        let events = journal.events(persistence_id, from).await?;
+        for event in events.iter().filter(|event| !event.deleted) {
+            let state = match &mut state {
+                Some(state) => state,
+                None => {
+                    let Some(initial) = &self.initial else {
+                        anyhow::bail!(
+                            "{persistence_id} has events but no snapshot, set an initial state"
+                        );
+                    };
+                    state.insert(initial())
+                }
+            };
+
+            apply_event(state, event)?;
+        }
+
+        Ok(state)
+    }
+
+    /// Recover the state of an entity and write it as the snapshot of `persistence_key`,
+    /// replacing any, to be respawned with `PersistentActor::respawn_persistent`.
+    ///
+    /// Returns false if the journal holds nothing for the entity.
+    pub async fn import(
+        &self,
+        journal: &impl AkkaJournal,
+        persistence_id: &str,
+        persistence_key: &Url,
+    ) -> anyhow::Result<bool> {
+        let Some(snapshot) = self.restore(journal, persistence_id).await? else {
+            return Ok(false);
+        };
+
+        A::try_write(persistence_key, snapshot).await?;
+
+        #[cfg(feature = "tracing")]
+        debug!(
+            "Imported Akka entity {persistence_id} into {}",
+            redacted(persistence_key)
+        );
+
+        Ok(true)
+    }
+}
+
+/// Persistence key of an Akka entity below `root`, with the parts of its persistence id as
+/// path segments, such as `<root>/Account/42` for the sharded entity `Account|42`.
+pub fn persistence_key(root: &Url, persistence_id: &str) -> anyhow::Result<Url> {
+    let mut key = root.clone();
+    for part in persistence_id.split('|') {
+        push_segments(&mut key, part)?;
+    }
+    Ok(key)
+}
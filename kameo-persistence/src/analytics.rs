@@ -0,0 +1,258 @@
+use std::{fs::File, path::Path, sync::Arc, time::UNIX_EPOCH};
+
+use arrow::{
+    array::{
+        ArrayRef, BinaryArray, BooleanArray, StringArray, TimestampMillisecondArray, UInt32Array,
+        UInt64Array,
+    },
+    datatypes::{DataType, Field, Schema, TimeUnit},
+    ipc::writer::FileWriter,
+    record_batch::RecordBatch,
+};
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use url::Url;
+
+use crate::{codec, recording, redact::redacted, storage};
+
+/// File format of an export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Parquet, Snappy-compressed.
+    Parquet,
+    /// Arrow IPC file, also known as Feather v2.
+    Arrow,
+}
+
+/// Rows written to a file at a time, bounding memory use.
+const BATCH_ROWS: usize = 8192;
+
+/// Outcome of an export.
+#[derive(Debug, Default)]
+pub struct ExportReport {
+    /// Rows written.
+    pub rows: usize,
+    /// Keys left out of the export, as they could not be read.
+    pub failed: Vec<(Url, anyhow::Error)>,
+}
+
+/// Export the metadata of every snapshot under `root` to a file, one row per key.
+///
+/// Columns are `key`, `type_tag`, `schema_version`, `format_version`, `encrypted`, `size` and
+/// `checksum_ok`, the latter null for snapshots without a header. Payloads are not exported.
+/// Keys whose snapshot cannot be read are left out and reported rather than failing the export.
+pub async fn export_snapshots(
+    root: &Url,
+    path: &Path,
+    format: ExportFormat,
+) -> anyhow::Result<ExportReport> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("key", DataType::Utf8, false),
+        Field::new("type_tag", DataType::Utf8, true),
+        Field::new("schema_version", DataType::UInt32, true),
+        Field::new("format_version", DataType::UInt32, true),
+        Field::new("encrypted", DataType::Boolean, false),
+        Field::new("size", DataType::UInt64, false),
+        Field::new("checksum_ok", DataType::Boolean, true),
+    ]));
+    let mut writer = BatchWriter::create(path, format, schema.clone())?;
+    let mut rows = SnapshotRows::default();
+    let mut report = ExportReport::default();
+
+    for key in storage::list(root).await? {
+        let data = match storage::backend(&key)?.read_file(&key, storage::snapshot_file()) {
+            Ok(Some(data)) => data,
+            Ok(None) => continue,
+            Err(e) => {
+                report.failed.push((key, e));
+                continue;
+            }
+        };
+        let header = match codec::split(&data) {
+            Ok((header, _)) => header,
+            Err(e) => {
+                report.failed.push((key, e));
+                continue;
+            }
+        };
+
+        rows.keys.push(redacted(&key).to_string());
+        rows.format_versions
+            .push(codec::format_version(&data).map(u32::from));
+        rows.encrypted
+            .push(header.as_ref().is_some_and(|h| h.subject.is_some()));
+        rows.checksums_ok
+            .push(header.as_ref().map(|_| codec::verify(&data).is_ok()));
+        rows.schema_versions
+            .push(header.as_ref().map(|h| h.schema_version));
+        rows.type_tags.push(header.map(|h| h.type_tag));
+        rows.sizes.push(data.len() as u64);
+
+        if rows.keys.len() == BATCH_ROWS {
+            report.rows += writer.write(rows.take(&schema)?)?;
+        }
+    }
+
+    report.rows += writer.write(rows.take(&schema)?)?;
+    writer.finish()?;
+    Ok(report)
+}
+
+/// Export the messages recorded for every key under `root` to a file, one row per message in
+/// recording order.
+///
+/// Columns are `key`, `seq`, `recorded_at` as a UTC timestamp in milliseconds, `type_name` and
+/// `data`, the message serialized with postcard. Keys whose recording cannot be read are left
+/// out and reported rather than failing the export.
+pub async fn export_messages(
+    root: &Url,
+    path: &Path,
+    format: ExportFormat,
+) -> anyhow::Result<ExportReport> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("key", DataType::Utf8, false),
+        Field::new("seq", DataType::UInt64, false),
+        Field::new(
+            "recorded_at",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("type_name", DataType::Utf8, false),
+        Field::new("data", DataType::Binary, false),
+    ]));
+    let mut writer = BatchWriter::create(path, format, schema.clone())?;
+    let mut rows = MessageRows::default();
+    let mut report = ExportReport::default();
+
+    for key in storage::list(root).await? {
+        let messages = match recording::recorded_messages(&key) {
+            Ok(messages) => messages,
+            Err(e) => {
+                report.failed.push((key, e));
+                continue;
+            }
+        };
+
+        for msg in messages {
+            rows.keys.push(redacted(&key).to_string());
+            rows.seqs.push(msg.seq);
+            rows.recorded_at.push(
+                msg.recorded_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as i64,
+            );
+            rows.type_names.push(msg.type_name);
+            rows.data.push(msg.data);
+
+            if rows.keys.len() == BATCH_ROWS {
+                report.rows += writer.write(rows.take(&schema)?)?;
+            }
+        }
+    }
+
+    report.rows += writer.write(rows.take(&schema)?)?;
+    writer.finish()?;
+    Ok(report)
+}
+
+#[derive(Default)]
+struct SnapshotRows {
+    keys: Vec<String>,
+    type_tags: Vec<Option<String>>,
+    schema_versions: Vec<Option<u32>>,
+    format_versions: Vec<Option<u32>>,
+    encrypted: Vec<bool>,
+    sizes: Vec<u64>,
+    checksums_ok: Vec<Option<bool>>,
+}
+
+impl SnapshotRows {
+    /// Move the rows gathered so far into a batch.
+    fn take(&mut self, schema: &Arc<Schema>) -> anyhow::Result<RecordBatch> {
+        let rows = std::mem::take(self);
+        Ok(RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(rows.keys)) as ArrayRef,
+                Arc::new(StringArray::from(rows.type_tags)),
+                Arc::new(UInt32Array::from(rows.schema_versions)),
+                Arc::new(UInt32Array::from(rows.format_versions)),
+                Arc::new(BooleanArray::from(rows.encrypted)),
+                Arc::new(UInt64Array::from(rows.sizes)),
+                Arc::new(BooleanArray::from(rows.checksums_ok)),
+            ],
+        )?)
+    }
+}
+
+#[derive(Default)]
+struct MessageRows {
+    keys: Vec<String>,
+    seqs: Vec<u64>,
+    recorded_at: Vec<i64>,
+    type_names: Vec<String>,
+    data: Vec<Vec<u8>>,
+}
+
+impl MessageRows {
+    /// Move the rows gathered so far into a batch.
+    fn take(&mut self, schema: &Arc<Schema>) -> anyhow::Result<RecordBatch> {
+        let rows = std::mem::take(self);
+        Ok(RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(rows.keys)) as ArrayRef,
+                Arc::new(UInt64Array::from(rows.seqs)),
+                Arc::new(TimestampMillisecondArray::from(rows.recorded_at).with_timezone("UTC")),
+                Arc::new(StringArray::from(rows.type_names)),
+                Arc::new(BinaryArray::from_iter_values(rows.data)),
+            ],
+        )?)
+    }
+}
+
+/// Writer of an export file, fed a batch of rows at a time.
+enum BatchWriter {
+    Parquet(ArrowWriter<File>),
+    Arrow(FileWriter<File>),
+}
+
+impl BatchWriter {
+    fn create(path: &Path, format: ExportFormat, schema: Arc<Schema>) -> anyhow::Result<Self> {
+        let file = File::create(path)?;
+
+        Ok(match format {
+            ExportFormat::Parquet => {
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+                Self::Parquet(ArrowWriter::try_new(file, schema, Some(properties))?)
+            }
+            ExportFormat::Arrow => Self::Arrow(FileWriter::try_new(file, &schema)?),
+        })
+    }
+
+    /// Write a batch, unless empty, returning its number of rows.
+    fn write(&mut self, batch: RecordBatch) -> anyhow::Result<usize> {
+        let rows = batch.num_rows();
+        if rows == 0 {
+            return Ok(0);
+        }
+
+        match self {
+            Self::Parquet(writer) => writer.write(&batch)?,
+            Self::Arrow(writer) => writer.write(&batch)?,
+        }
+        Ok(rows)
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        match self {
+            Self::Parquet(writer) => {
+                writer.close()?;
+            }
+            Self::Arrow(mut writer) => writer.finish()?,
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+#[cfg(feature = "tracing")]
+use tracing::debug;
+use url::Url;
+
+#[cfg(feature = "tracing")]
+use crate::redact::redacted;
+use crate::storage;
+
+/// Prefix of the files holding attachments, so they never collide with the crate's own files.
+const ATTACHMENT_PREFIX: &str = "attachment-";
+
+/// Suffix of an attachment being written, until it replaces the attachment.
+const TMP_SUFFIX: &str = ".tmp";
+
+/// Store a binary artifact, such as an image or a model, under a key next to its snapshot,
+/// replacing any attachment of that name atomically.
+///
+/// Attachments live and die with the key: they are listed and deleted with it, but are not part
+/// of the snapshot, so saving the actor does not rewrite them.
+pub async fn write_attachment(
+    persistence_key: &Url,
+    name: &str,
+    data: &[u8],
+) -> anyhow::Result<()> {
+    let file = file_name(name)?;
+    let tmp = format!("{file}{TMP_SUFFIX}");
+
+    let backend = storage::backend(persistence_key)?;
+    backend.write_file(persistence_key, &tmp, data)?;
+    backend.rename_file(persistence_key, &tmp, &file)?;
+
+    #[cfg(feature = "tracing")]
+    debug!(
+        "Wrote attachment {name} of {} bytes under {}",
+        data.len(),
+        redacted(persistence_key)
+    );
+
+    Ok(())
+}
+
+/// Read the attachment of that name stored under a key, if any.
+pub async fn read_attachment(persistence_key: &Url, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+    storage::backend(persistence_key)?.read_file(persistence_key, &file_name(name)?)
+}
+
+/// Remove the attachment of that name stored under a key, if any.
+pub async fn remove_attachment(persistence_key: &Url, name: &str) -> anyhow::Result<()> {
+    storage::backend(persistence_key)?.remove_file(persistence_key, &file_name(name)?)
+}
+
+/// Names of the attachments stored under a key, sorted.
+pub async fn list_attachments(persistence_key: &Url) -> anyhow::Result<Vec<String>> {
+    let mut names = storage::backend(persistence_key)?
+        .list_files(persistence_key)?
+        .into_iter()
+        .filter(|file| !file.ends_with(TMP_SUFFIX))
+        .filter_map(|file| file.strip_prefix(ATTACHMENT_PREFIX).map(str::to_string))
+        .collect::<Vec<_>>();
+
+    names.sort_unstable();
+    Ok(names)
+}
+
+/// File holding an attachment; names are single path components.
+fn file_name(name: &str) -> anyhow::Result<String> {
+    if name.is_empty()
+        || name.starts_with('.')
+        || name.ends_with(TMP_SUFFIX)
+        || name.contains(['/', '\\', '\0'])
+    {
+        anyhow::bail!("Invalid attachment name {name:?}");
+    }
+
+    Ok(format!("{ATTACHMENT_PREFIX}{name}"))
+}
@@ -0,0 +1,110 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{clock, redact};
+
+/// Persistence operation recorded in the audit log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOperation {
+    Save,
+    Restore,
+    Delete,
+}
+
+/// A single line of the audit log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    /// Who performed the operation, as given to `enable`.
+    pub principal: String,
+    pub operation: AuditOperation,
+    /// Key of the snapshot, with credentials redacted.
+    pub key: Url,
+    /// Size of the stored snapshot in bytes, if any.
+    pub size: Option<usize>,
+    /// CRC-32 of the stored snapshot, if any.
+    pub checksum: Option<u32>,
+}
+
+struct AuditLog {
+    principal: String,
+    file: File,
+}
+
+static AUDIT_LOG: Mutex<Option<AuditLog>> = Mutex::new(None);
+
+/// Append a record of every save, restore and delete to the file at `path`.
+///
+/// Records are written as JSON lines. Once enabled, an operation whose record cannot be
+/// appended fails, even though the storage itself may already have been changed.
+pub fn enable(path: impl AsRef<Path>, principal: impl Into<String>) -> anyhow::Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path.as_ref())?;
+
+    *AUDIT_LOG.lock().unwrap_or_else(|e| e.into_inner()) = Some(AuditLog {
+        principal: principal.into(),
+        file,
+    });
+
+    Ok(())
+}
+
+/// Stop appending to the audit log.
+pub fn disable() {
+    AUDIT_LOG.lock().unwrap_or_else(|e| e.into_inner()).take();
+}
+
+/// Read every record of an audit log.
+pub fn read_log(path: impl AsRef<Path>) -> anyhow::Result<Vec<AuditRecord>> {
+    let file = File::open(path.as_ref())?;
+
+    BufReader::new(file)
+        .lines()
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+/// Append a record for an operation on `persistence_key`, if the audit log is enabled.
+pub(crate) fn record(
+    operation: AuditOperation,
+    persistence_key: &Url,
+    data: Option<&[u8]>,
+) -> anyhow::Result<()> {
+    let mut audit_log = AUDIT_LOG.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(audit_log) = audit_log.as_mut() else {
+        return Ok(());
+    };
+
+    let record = AuditRecord {
+        timestamp_ms: clock::clock()
+            .system_time()
+            .duration_since(UNIX_EPOCH)?
+            .as_millis() as u64,
+        principal: audit_log.principal.clone(),
+        operation,
+        key: redact::redact(persistence_key),
+        size: data.map(<[u8]>::len),
+        checksum: data.map(crc32fast::hash),
+    };
+
+    let mut line = serde_json::to_vec(&record)?;
+    line.push(b'\n');
+
+    // A single write keeps concurrent appenders from interleaving lines
+    audit_log.file.write_all(&line)?;
+    audit_log.file.sync_data()?;
+
+    Ok(())
+}
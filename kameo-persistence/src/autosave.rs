@@ -0,0 +1,240 @@
+use std::{
+    hash::{BuildHasher, RandomState},
+    time::{Duration, Instant},
+};
+
+use kameo::prelude::*;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::{sync::oneshot, task::JoinHandle};
+#[cfg(feature = "tracing")]
+use tracing::warn;
+
+use crate::{PersistentActor, buffer, clock};
+
+/// Outcome of an autosave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutosaveOutcome {
+    /// The snapshot did not change since the previous autosave and was not written.
+    Unchanged,
+    /// The snapshot was written.
+    Saved {
+        /// Fingerprint of the snapshot, to tell whether it changed by the next autosave.
+        fingerprint: [u8; 32],
+        /// Size of the snapshot as stored.
+        bytes: u64,
+    },
+    /// The actor was backlogged and the autosave is retried later.
+    Deferred {
+        /// Time the autosave waited in the mailbox.
+        waited: Duration,
+    },
+}
+
+/// Message asking a persistent actor to save its snapshot if it changed since the previous
+/// autosave.
+pub struct Autosave {
+    last: Option<[u8; 32]>,
+    sent: Instant,
+    defer_after: Option<Duration>,
+    outcome: oneshot::Sender<anyhow::Result<AutosaveOutcome>>,
+}
+
+impl Autosave {
+    /// Fingerprint of the snapshot saved by the previous autosave, if any.
+    pub fn last_fingerprint(&self) -> Option<[u8; 32]> {
+        self.last
+    }
+
+    /// Time the autosave waited in the mailbox before being handled, if it exceeds the backlog
+    /// threshold of the policy and the autosave may still be deferred.
+    pub fn backlog(&self) -> Option<Duration> {
+        let waited = clock::clock().now().saturating_duration_since(self.sent);
+        self.defer_after
+            .filter(|threshold| waited > *threshold)
+            .map(|_| waited)
+    }
+
+    /// Report the outcome of the autosave.
+    pub fn report(self, result: anyhow::Result<AutosaveOutcome>) {
+        let _ = self.outcome.send(result);
+    }
+}
+
+/// Fingerprint of a snapshot, compared across autosaves to skip unchanged ones.
+pub fn fingerprint<S: Serialize>(snapshot: &S) -> anyhow::Result<[u8; 32]> {
+    let mut data = buffer::take();
+    *data = postcard::to_extend(snapshot, std::mem::take(&mut *data))?;
+    Ok(Sha256::digest(&*data).into())
+}
+
+/// Cadence of autosaves adapting to how often the state changes and how large it is.
+///
+/// Every change found by an autosave halves the interval, down to `min_interval` scaled up by
+/// the snapshot size relative to the reference size, so hot actors are saved often and huge ones
+/// less so. Every autosave finding no change doubles it, up to `max_interval`, so idle actors
+/// cost almost nothing. Each wait is randomly spread by the jitter fraction, and the first one
+/// is uniform over the whole interval, so thousands of actors restored together do not save in
+/// lockstep.
+#[derive(Debug, Clone, Copy)]
+pub struct AutosavePolicy {
+    min_interval: Duration,
+    max_interval: Duration,
+    jitter: f64,
+    reference_size: u64,
+    backlog: Option<Backlog>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Backlog {
+    threshold: Duration,
+    max_deferral: Duration,
+}
+
+impl AutosavePolicy {
+    /// Autosave between every `min_interval` and every `max_interval`, with 10% jitter and a
+    /// reference size of 64 KiB.
+    pub fn new(min_interval: Duration, max_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            max_interval: max_interval.max(min_interval),
+            jitter: 0.1,
+            reference_size: 64 * 1024,
+            backlog: None,
+        }
+    }
+
+    /// Spread each wait randomly by up to `fraction` of the interval either way.
+    pub fn jitter(mut self, fraction: f64) -> Self {
+        self.jitter = fraction.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Lengthen the shortest interval by `min_interval` for every `bytes` bytes of snapshot.
+    pub fn reference_size(mut self, bytes: u64) -> Self {
+        self.reference_size = bytes.max(1);
+        self
+    }
+
+    /// Defer autosaves which waited more than `threshold` in the actor's mailbox, so saving does
+    /// not add latency while the actor is backlogged.
+    ///
+    /// Deferred autosaves are retried every `threshold`, and no longer deferred once the first
+    /// one was deferred for `max_deferral`, so the state is still saved under sustained load.
+    pub fn defer_on_backlog(mut self, threshold: Duration, max_deferral: Duration) -> Self {
+        self.backlog = Some(Backlog {
+            threshold,
+            max_deferral,
+        });
+        self
+    }
+
+    /// Interval after an autosave with `outcome`.
+    pub fn adapt(&self, interval: Duration, outcome: &AutosaveOutcome) -> Duration {
+        match outcome {
+            AutosaveOutcome::Unchanged => interval.saturating_mul(2).min(self.max_interval),
+            AutosaveOutcome::Saved { bytes, .. } => {
+                let floor = self
+                    .min_interval
+                    .mul_f64(1.0 + *bytes as f64 / self.reference_size as f64);
+                (interval / 2).max(floor).min(self.max_interval)
+            }
+            AutosaveOutcome::Deferred { .. } => interval,
+        }
+    }
+
+    fn jittered(&self, interval: Duration) -> Duration {
+        interval.mul_f64(1.0 + self.jitter * (2.0 * random_unit() - 1.0))
+    }
+}
+
+/// Uniform random number in `[0, 1)`.
+fn random_unit() -> f64 {
+    // Every `RandomState` is seeded differently
+    (RandomState::new().hash_one(()) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Handle of an actor's autosaves; autosaving stops when it is dropped.
+pub struct AutosaveHandle {
+    task: JoinHandle<()>,
+}
+
+impl Drop for AutosaveHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Save the snapshot of a persistent actor periodically, at a cadence set by `policy`, as long
+/// as it is alive.
+///
+/// Autosaves are handled by the actor between its messages and write only if the snapshot
+/// changed since the previous autosave. With `AutosavePolicy::defer_on_backlog`, autosaves
+/// queued behind a backlog are deferred until it drains.
+pub fn autosave<A>(actor_ref: &ActorRef<A>, policy: AutosavePolicy) -> AutosaveHandle
+where
+    A: PersistentActor + Message<Autosave, Reply = ()>,
+{
+    let actor_ref = actor_ref.downgrade();
+
+    let task = tokio::spawn(async move {
+        let mut interval = policy.max_interval;
+        let mut wait = interval.mul_f64(random_unit());
+        let mut last = None;
+        let mut deferred_since: Option<Instant> = None;
+
+        loop {
+            let clock = clock::clock();
+            clock.sleep_until(clock.now() + wait).await;
+
+            let (outcome_tx, outcome_rx) = oneshot::channel();
+            let autosave = Autosave {
+                last,
+                sent: clock.now(),
+                defer_after: policy.backlog.and_then(|backlog| {
+                    let deferred_for = deferred_since
+                        .map(|since| clock.now().saturating_duration_since(since))
+                        .unwrap_or_default();
+                    (deferred_for < backlog.max_deferral).then_some(backlog.threshold)
+                }),
+                outcome: outcome_tx,
+            };
+
+            // Not holding a strong reference while waiting, so the actor can stop meanwhile
+            let sent = match actor_ref.upgrade() {
+                Some(live) => live.tell(autosave).await.is_ok(),
+                None => false,
+            };
+            if !sent {
+                return;
+            }
+
+            match outcome_rx.await {
+                Ok(Ok(AutosaveOutcome::Deferred { .. })) => {
+                    deferred_since.get_or_insert(clock.now());
+                    if let Some(backlog) = policy.backlog {
+                        wait = backlog.threshold;
+                        continue;
+                    }
+                }
+                Ok(Ok(outcome)) => {
+                    if let AutosaveOutcome::Saved { fingerprint, .. } = outcome {
+                        last = Some(fingerprint);
+                    }
+                    deferred_since = None;
+                    interval = policy.adapt(interval, &outcome);
+                }
+                Ok(Err(_e)) => {
+                    #[cfg(feature = "tracing")]
+                    warn!("Failed to autosave {}: {_e:#}", A::type_tag());
+                }
+                // The actor stopped before handling the autosave
+                Err(_) => return,
+            }
+
+            wait = policy.jittered(interval);
+        }
+    });
+
+    AutosaveHandle { task }
+}
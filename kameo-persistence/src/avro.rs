@@ -0,0 +1,195 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, RwLock},
+};
+
+use anyhow::anyhow;
+use apache_avro::{Schema, from_avro_datum, from_value, to_avro_datum, to_value};
+use kameo::prelude::*;
+use serde::{Serialize, de::DeserializeOwned};
+#[cfg(feature = "tracing")]
+use tracing::debug;
+use url::Url;
+
+use crate::{PersistentActor, RestoreContext, redact::redacted, storage};
+
+/// Name of the Avro encoding of a snapshot, next to the snapshot file.
+pub const AVRO_FILE: &str = "index.avro";
+
+/// Avro snapshot being written, until it replaces `AVRO_FILE`.
+const AVRO_TMP_FILE: &str = "index.avro.tmp";
+
+/// First byte of the Confluent wire format, followed by the schema id.
+const MAGIC_BYTE: u8 = 0;
+
+/// Schema registry, such as a Confluent Schema Registry, governing the Avro schemas of
+/// snapshots and events.
+///
+/// Bring your own client; registering a schema incompatible with the subject's previous ones
+/// should fail, so schemas only evolve as the registry allows.
+pub trait SchemaRegistry: Send + Sync {
+    /// Register a schema under a subject, returning its id, or the id it already has.
+    fn register(&self, subject: &str, schema: &str) -> anyhow::Result<u32>;
+
+    /// Schema registered with `id`.
+    fn schema(&self, id: u32) -> anyhow::Result<String>;
+}
+
+static SCHEMA_REGISTRY: RwLock<Option<Arc<dyn SchemaRegistry>>> = RwLock::new(None);
+
+// Ids of the schemas registered by this process, by subject and schema
+static REGISTERED: LazyLock<RwLock<HashMap<(String, String), u32>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+// Writer schemas fetched from the registry, by id
+static WRITER_SCHEMAS: LazyLock<RwLock<HashMap<u32, Arc<Schema>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Install the schema registry used to encode and decode Avro data.
+pub fn set_schema_registry(registry: impl SchemaRegistry + 'static) {
+    *SCHEMA_REGISTRY.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(registry));
+}
+
+fn schema_registry() -> anyhow::Result<Arc<dyn SchemaRegistry>> {
+    SCHEMA_REGISTRY
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .ok_or_else(|| anyhow!("No schema registry installed"))
+}
+
+/// Persistent actor whose snapshot can also be stored in Avro, with a schema governed by the
+/// schema registry.
+pub trait AvroSnapshot: PersistentActor {
+    /// Avro schema of the snapshot, as JSON.
+    fn avro_schema() -> &'static str;
+
+    /// Subject the schema is registered under, `<type tag>-value` by default.
+    fn avro_subject() -> String {
+        format!("{}-value", Self::type_tag())
+    }
+}
+
+/// Encode a value in Avro with `schema`, registered under `subject`, in the Confluent wire
+/// format: a zero byte, the schema id in big endian, then the datum.
+///
+/// Fails if the value does not match the schema, or if the registry rejects the schema.
+pub fn encode<T: Serialize>(subject: &str, schema: &str, value: &T) -> anyhow::Result<Vec<u8>> {
+    let id = register(subject, schema)?;
+    let parsed = writer_schema(id)?;
+
+    let value = to_value(value)?.resolve(&parsed)?;
+    let datum = to_avro_datum(&parsed, value)?;
+
+    let mut data = Vec::with_capacity(5 + datum.len());
+    data.push(MAGIC_BYTE);
+    data.extend_from_slice(&id.to_be_bytes());
+    data.extend_from_slice(&datum);
+    Ok(data)
+}
+
+/// Decode Avro data in the Confluent wire format, resolving the schema it was written with to
+/// `reader_schema` if given, so data written with older schemas is read with the current one.
+pub fn decode<T: DeserializeOwned>(data: &[u8], reader_schema: Option<&str>) -> anyhow::Result<T> {
+    let [MAGIC_BYTE, a, b, c, d, datum @ ..] = data else {
+        anyhow::bail!("Not Avro data in the Confluent wire format");
+    };
+    let writer = writer_schema(u32::from_be_bytes([*a, *b, *c, *d]))?;
+    let reader = reader_schema.map(Schema::parse_str).transpose()?;
+
+    let value = from_avro_datum(&writer, &mut &datum[..], reader.as_ref())?;
+    Ok(from_value(&value)?)
+}
+
+/// Write the Avro encoding of a snapshot under a key, replacing the previous one atomically.
+///
+/// Avro snapshots are kept next to the regular snapshot, for consumers governed by the schema
+/// registry; they are neither encrypted nor checksummed by the crate.
+pub async fn write_avro<A: AvroSnapshot>(
+    persistence_key: &Url,
+    snapshot: &A::Snapshot,
+) -> anyhow::Result<()> {
+    let data = encode(&A::avro_subject(), A::avro_schema(), snapshot)?;
+
+    let backend = storage::backend(persistence_key)?;
+    backend.write_file(persistence_key, AVRO_TMP_FILE, &data)?;
+    backend.rename_file(persistence_key, AVRO_TMP_FILE, AVRO_FILE)?;
+
+    #[cfg(feature = "tracing")]
+    debug!(
+        "Wrote {} bytes of Avro under {}",
+        data.len(),
+        redacted(persistence_key)
+    );
+
+    Ok(())
+}
+
+/// Read the Avro snapshot stored under a key with the actor's current schema.
+pub async fn read_avro<A: AvroSnapshot>(persistence_key: &Url) -> anyhow::Result<A::Snapshot> {
+    let data = storage::backend(persistence_key)?
+        .read_file(persistence_key, AVRO_FILE)?
+        .ok_or_else(|| {
+            anyhow!(
+                "No Avro snapshot stored under {}",
+                redacted(persistence_key)
+            )
+        })?;
+
+    decode(&data, Some(A::avro_schema()))
+}
+
+/// Respawn an actor from the Avro encoding of its snapshot, see `write_avro`.
+pub async fn respawn_avro<A: AvroSnapshot>(persistence_key: Url) -> anyhow::Result<ActorRef<A>> {
+    if let Some(actor_ref) = A::lookup_persistent(&persistence_key) {
+        return Ok(actor_ref);
+    }
+
+    let snapshot = read_avro::<A>(&persistence_key).await?;
+    A::spawn_persistent(
+        persistence_key,
+        A::restore_args(snapshot, &RestoreContext::global())?,
+    )
+    .await
+}
+
+fn register(subject: &str, schema: &str) -> anyhow::Result<u32> {
+    let key = (subject.to_string(), schema.to_string());
+    if let Some(id) = REGISTERED
+        .read()
+        .ok()
+        .and_then(|ids| ids.get(&key).copied())
+    {
+        return Ok(id);
+    }
+
+    // Invalid schemas fail here rather than in the registry
+    let parsed = Arc::new(Schema::parse_str(schema)?);
+    let id = schema_registry()?
+        .register(subject, schema)
+        .map_err(|e| e.context(format!("Failed to register the schema of {subject}")))?;
+
+    if let Ok(mut schemas) = WRITER_SCHEMAS.write() {
+        schemas.insert(id, parsed);
+    }
+    if let Ok(mut ids) = REGISTERED.write() {
+        ids.insert(key, id);
+    }
+    Ok(id)
+}
+
+fn writer_schema(id: u32) -> anyhow::Result<Arc<Schema>> {
+    if let Some(schema) = WRITER_SCHEMAS
+        .read()
+        .ok()
+        .and_then(|schemas| schemas.get(&id).cloned())
+    {
+        return Ok(schema);
+    }
+
+    let schema = Arc::new(Schema::parse_str(&schema_registry()?.schema(id)?)?);
+    if let Ok(mut schemas) = WRITER_SCHEMAS.write() {
+        schemas.insert(id, schema.clone());
+    }
+    Ok(schema)
+}
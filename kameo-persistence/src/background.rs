@@ -0,0 +1,133 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+};
+
+use tokio::task::JoinHandle;
+use url::Url;
+
+// Background saves of every key with one in progress
+static SAVES: LazyLock<Mutex<HashMap<Url, Arc<KeySaves>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Default)]
+struct KeySaves {
+    // Held while a save of the key is written
+    write: Arc<tokio::sync::Mutex<()>>,
+    // Sequence of the latest save started, older ones not written yet are skipped
+    latest: AtomicU64,
+    // Saves using the entry, only changed under the map lock
+    users: AtomicUsize,
+}
+
+/// Use of the entry of a key by one save, removing the entry once the last use ends.
+struct KeyUse {
+    persistence_key: Url,
+    saves: Arc<KeySaves>,
+}
+
+impl KeyUse {
+    /// Use the entry of a key, creating it if there is none.
+    fn acquire(persistence_key: &Url) -> Self {
+        let mut all = SAVES.lock().unwrap_or_else(|e| e.into_inner());
+        let saves = all.entry(persistence_key.clone()).or_default().clone();
+        Self::new(persistence_key, saves)
+    }
+
+    /// Use the entry of a key, if there is one.
+    fn existing(persistence_key: &Url) -> Option<Self> {
+        let all = SAVES.lock().unwrap_or_else(|e| e.into_inner());
+        let saves = all.get(persistence_key)?.clone();
+        Some(Self::new(persistence_key, saves))
+    }
+
+    fn new(persistence_key: &Url, saves: Arc<KeySaves>) -> Self {
+        saves.users.fetch_add(1, Ordering::Relaxed);
+        Self {
+            persistence_key: persistence_key.clone(),
+            saves,
+        }
+    }
+}
+
+impl Drop for KeyUse {
+    fn drop(&mut self) {
+        let mut all = SAVES.lock().unwrap_or_else(|e| e.into_inner());
+        if self.saves.users.fetch_sub(1, Ordering::Relaxed) == 1 {
+            all.remove(&self.persistence_key);
+        }
+    }
+}
+
+/// Save running on a background task, see `PersistentActor::save_snapshot_in_background`.
+///
+/// Dropping the handle does not cancel the save.
+pub struct BackgroundSave {
+    task: JoinHandle<anyhow::Result<()>>,
+}
+
+impl BackgroundSave {
+    /// Wait for the save to be written, or skipped for a newer save of the same key.
+    pub async fn wait(self) -> anyhow::Result<()> {
+        self.task
+            .await
+            .map_err(|e| anyhow::anyhow!("Background save panicked: {e}"))?
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+/// Run `save` on a background task, after the background saves of the key started before it,
+/// skipping it if a newer save of the key started meanwhile.
+pub(crate) fn spawn<F>(persistence_key: Url, save: F) -> BackgroundSave
+where
+    F: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    let key_use = KeyUse::acquire(&persistence_key);
+    let sequence = key_use.saves.latest.fetch_add(1, Ordering::SeqCst) + 1;
+
+    let task = tokio::spawn(async move {
+        let saves = &key_use.saves;
+        let _write = saves.write.lock().await;
+        if saves.latest.load(Ordering::SeqCst) == sequence {
+            save.await
+        } else {
+            Ok(())
+        }
+    });
+
+    BackgroundSave { task }
+}
+
+/// Wait for the background save of a key being written, before writing a snapshot captured
+/// after it; call `Settled::written` once written, to skip the background saves not started.
+///
+/// Background saves only wait for the foreground save while the returned guard lives, and are
+/// only skipped once it was written, so a foreground save failing or dropped loses none.
+pub(crate) async fn settle(persistence_key: &Url) -> Option<Settled> {
+    let key_use = KeyUse::existing(persistence_key)?;
+
+    let write = key_use.saves.write.clone().lock_owned().await;
+    Some(Settled {
+        _write: write,
+        key_use,
+    })
+}
+
+/// Foreground save holding back the background saves of its key, see `settle`.
+pub(crate) struct Settled {
+    _write: tokio::sync::OwnedMutexGuard<()>,
+    key_use: KeyUse,
+}
+
+impl Settled {
+    /// Skip the background saves of the key not started yet, older than the snapshot written.
+    pub(crate) fn written(self) {
+        self.key_use.saves.latest.fetch_add(1, Ordering::SeqCst);
+    }
+}
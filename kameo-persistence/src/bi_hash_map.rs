@@ -68,4 +68,21 @@ where
             None
         }
     }
+
+    pub fn len(&self) -> usize {
+        self.left_to_right.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.left_to_right.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&L, &R)> {
+        self.left_to_right.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.left_to_right.clear();
+        self.right_to_left.clear();
+    }
 }
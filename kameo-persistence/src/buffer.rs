@@ -0,0 +1,81 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+static POOL: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+static MAX_BUFFERS: AtomicUsize = AtomicUsize::new(64);
+static MAX_CAPACITY: AtomicUsize = AtomicUsize::new(1024 * 1024);
+
+/// Keep up to `max_buffers` buffers of at most `max_capacity` bytes for reuse, instead of 64
+/// buffers of up to 1 MiB.
+///
+/// Larger buffers are freed when dropped, so a rare huge snapshot does not stay allocated. Zero
+/// buffers disables pooling.
+pub fn set_pool_limits(max_buffers: usize, max_capacity: usize) {
+    MAX_BUFFERS.store(max_buffers, Ordering::Relaxed);
+    MAX_CAPACITY.store(max_capacity, Ordering::Relaxed);
+
+    let mut pool = POOL.lock().unwrap_or_else(|e| e.into_inner());
+    pool.retain(|buffer| buffer.capacity() <= max_capacity);
+    pool.truncate(max_buffers);
+}
+
+/// Take an empty buffer from the pool, or a new one if it is empty.
+pub fn take() -> PooledBuffer {
+    let buffer = POOL.lock().unwrap_or_else(|e| e.into_inner()).pop();
+
+    PooledBuffer {
+        buffer: buffer.unwrap_or_default(),
+    }
+}
+
+/// Number of buffers waiting in the pool.
+pub fn pooled() -> usize {
+    POOL.lock().unwrap_or_else(|e| e.into_inner()).len()
+}
+
+/// Buffer returned to the pool, emptied, when dropped.
+#[derive(Debug, Default)]
+pub struct PooledBuffer {
+    buffer: Vec<u8>,
+}
+
+impl PooledBuffer {
+    /// Take the buffer out of the pool for good.
+    pub fn into_inner(mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let capacity = self.buffer.capacity();
+        if capacity == 0 || capacity > MAX_CAPACITY.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut pool = POOL.lock().unwrap_or_else(|e| e.into_inner());
+        if pool.len() < MAX_BUFFERS.load(Ordering::Relaxed) {
+            self.buffer.clear();
+            pool.push(std::mem::take(&mut self.buffer));
+        }
+    }
+}
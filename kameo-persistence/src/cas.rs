@@ -0,0 +1,222 @@
+use std::{collections::HashSet, sync::Arc};
+
+use anyhow::anyhow;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::storage::{self, Backend};
+
+/// Marks a file holding the hash of its content instead of the content itself.
+const POINTER_MAGIC: &[u8; 4] = b"KPC\x01";
+
+/// Name of the content file inside a blob key.
+const BLOB_FILE: &str = "blob";
+
+/// Content file being written, until it is complete.
+const BLOB_TMP_FILE: &str = "blob.tmp";
+
+type Hash = [u8; 32];
+
+/// Backend decorator storing file contents by their SHA-256, so identical contents are stored once.
+///
+/// Every file written under a key becomes a small pointer to a blob kept below `blob_root`,
+/// which must lie outside the keys of actors. Identical states across many actors, common with
+/// templated entities, then share a single blob. Files written before the store was enabled are
+/// read as they are. Blobs are never deleted while writing; reclaim the unreferenced ones with
+/// `collect_blobs`.
+pub struct ContentAddressedBackend {
+    inner: Arc<dyn Backend>,
+    blob_root: Url,
+}
+
+impl ContentAddressedBackend {
+    /// Store contents in `inner`, with blobs below `blob_root`.
+    pub fn new(inner: Arc<dyn Backend>, blob_root: Url) -> Self {
+        Self { inner, blob_root }
+    }
+
+    /// Store keys of `scheme` through this backend.
+    pub fn install(self, scheme: &str) -> Arc<Self> {
+        let backend = Arc::new(self);
+        storage::set_backend(scheme, backend.clone());
+        backend
+    }
+
+    fn blob_key(&self, hash: &Hash) -> anyhow::Result<Url> {
+        let hex = hex(hash);
+
+        let mut key = self.blob_root.clone();
+        key.path_segments_mut()
+            .map_err(|_| anyhow!("Blob root cannot be a base"))?
+            .pop_if_empty()
+            .push(&hex[..2])
+            .push(&hex);
+
+        Ok(key)
+    }
+
+    fn pointer(&self, key: &Url, name: &str) -> anyhow::Result<Option<Hash>> {
+        Ok(self
+            .inner
+            .read_file(key, name)?
+            .as_deref()
+            .and_then(parse_pointer))
+    }
+
+    /// Store `data` as a blob, if not stored yet, and return the pointer to it.
+    fn write_blob(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let hash: Hash = Sha256::digest(data).into();
+        let blob_key = self.blob_key(&hash)?;
+
+        // Write then rename, so a blob is never seen incomplete and skipped by later writers
+        if self.inner.file_size(&blob_key, BLOB_FILE)?.is_none() {
+            self.inner.write_file(&blob_key, BLOB_TMP_FILE, data)?;
+            self.inner
+                .rename_file(&blob_key, BLOB_TMP_FILE, BLOB_FILE)?;
+        }
+
+        let mut pointer = POINTER_MAGIC.to_vec();
+        pointer.extend_from_slice(&hash);
+        Ok(pointer)
+    }
+
+    /// Delete the blobs not referenced by any file under `roots`, returning how many were deleted.
+    ///
+    /// Must not run concurrently with writes under `roots`, which may reference a blob between
+    /// the scan and the deletion.
+    pub fn collect_blobs(&self, roots: &[Url]) -> anyhow::Result<usize> {
+        let mut referenced = HashSet::new();
+
+        for root in roots {
+            for key in self.inner.list(root)? {
+                for name in self.inner.list_files(&key)? {
+                    if let Some(hash) = self.pointer(&key, &name)? {
+                        referenced.insert(self.blob_key(&hash)?);
+                    }
+                }
+            }
+        }
+
+        let mut deleted = 0;
+        for key in self.inner.list(&self.blob_root)? {
+            let is_blob = self.inner.file_size(&key, BLOB_FILE)?.is_some();
+            if is_blob && !referenced.contains(&key) {
+                self.inner.delete(&key)?;
+                deleted += 1;
+            }
+        }
+
+        Ok(deleted)
+    }
+}
+
+fn hex(hash: &Hash) -> String {
+    hash.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn parse_pointer(data: &[u8]) -> Option<Hash> {
+    data.strip_prefix(POINTER_MAGIC.as_slice())?.try_into().ok()
+}
+
+impl Backend for ContentAddressedBackend {
+    fn read_file(&self, key: &Url, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(data) = self.inner.read_file(key, name)? else {
+            return Ok(None);
+        };
+
+        let Some(hash) = parse_pointer(&data) else {
+            return Ok(Some(data));
+        };
+
+        let blob_key = self.blob_key(&hash)?;
+        self.inner
+            .read_file(&blob_key, BLOB_FILE)?
+            .ok_or_else(|| anyhow!("Missing blob {} for {key}", hex(&hash)))
+            .map(Some)
+    }
+
+    fn read_files(&self, keys: &[Url], name: &str) -> Vec<anyhow::Result<Option<Vec<u8>>>> {
+        let mut results = self.inner.read_files(keys, name);
+
+        // Blobs the pointers read refer to, read in a batch of their own
+        let mut pointers = Vec::new();
+        let mut blob_keys = Vec::new();
+        for (i, result) in results.iter_mut().enumerate() {
+            let Ok(Some(data)) = result else {
+                continue;
+            };
+            let Some(hash) = parse_pointer(data) else {
+                continue;
+            };
+
+            match self.blob_key(&hash) {
+                Ok(blob_key) => {
+                    pointers.push((i, hash));
+                    blob_keys.push(blob_key);
+                }
+                Err(e) => *result = Err(e),
+            }
+        }
+
+        let blobs = self.inner.read_files(&blob_keys, BLOB_FILE);
+        for ((i, hash), blob) in pointers.into_iter().zip(blobs) {
+            results[i] = blob.and_then(|blob| {
+                blob.ok_or_else(|| anyhow!("Missing blob {} for {}", hex(&hash), keys[i]))
+                    .map(Some)
+            });
+        }
+
+        results
+    }
+
+    fn write_file(&self, key: &Url, name: &str, data: &[u8]) -> anyhow::Result<()> {
+        let pointer = self.write_blob(data)?;
+        self.inner.write_file(key, name, &pointer)
+    }
+
+    fn rename_file(&self, key: &Url, from: &str, to: &str) -> anyhow::Result<()> {
+        self.inner.rename_file(key, from, to)
+    }
+
+    fn remove_file(&self, key: &Url, name: &str) -> anyhow::Result<()> {
+        self.inner.remove_file(key, name)
+    }
+
+    fn file_size(&self, key: &Url, name: &str) -> anyhow::Result<Option<u64>> {
+        match self.pointer(key, name)? {
+            Some(hash) => self.inner.file_size(&self.blob_key(&hash)?, BLOB_FILE),
+            None => self.inner.file_size(key, name),
+        }
+    }
+
+    fn exists(&self, key: &Url) -> anyhow::Result<bool> {
+        self.inner.exists(key)
+    }
+
+    fn delete(&self, key: &Url) -> anyhow::Result<()> {
+        self.inner.delete(key)
+    }
+
+    fn list(&self, root: &Url) -> anyhow::Result<Vec<Url>> {
+        self.inner.list(root)
+    }
+
+    fn list_files(&self, key: &Url) -> anyhow::Result<Vec<String>> {
+        self.inner.list_files(key)
+    }
+
+    fn file_version(&self, key: &Url, name: &str) -> anyhow::Result<Option<String>> {
+        self.inner.file_version(key, name)
+    }
+
+    fn write_file_if(
+        &self,
+        key: &Url,
+        name: &str,
+        expected: Option<&str>,
+        data: &[u8],
+    ) -> anyhow::Result<Option<String>> {
+        let pointer = self.write_blob(data)?;
+        self.inner.write_file_if(key, name, expected, &pointer)
+    }
+}
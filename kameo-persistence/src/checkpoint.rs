@@ -0,0 +1,295 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use futures::{StreamExt, stream};
+use kameo::prelude::*;
+use tokio::sync::{oneshot, watch};
+#[cfg(feature = "tracing")]
+use tracing::{debug, warn};
+use url::Url;
+
+#[cfg(feature = "tracing")]
+use crate::spans;
+use crate::{
+    PersistentActor,
+    background::{self, Settled},
+    clock, concurrency, hierarchy,
+    persistent_actor::report_save,
+    redact::redacted,
+    registry,
+    registry::ErasedPersistentActor,
+    transaction::SnapshotTransaction,
+};
+
+/// Snapshot captured by a checkpoint participant.
+pub struct Captured {
+    pub key: Url,
+    pub data: Vec<u8>,
+    pub children: Vec<Url>,
+}
+
+/// Message asking a persistent actor to capture its snapshot at a logical barrier.
+///
+/// The actor reports its encoded snapshot and persistent children, then holds its mailbox
+/// until every participant of the checkpoint has been captured and the snapshots are written.
+pub struct Checkpoint {
+    captured: oneshot::Sender<anyhow::Result<Captured>>,
+    release: watch::Receiver<bool>,
+}
+
+impl Checkpoint {
+    /// Return true if the checkpoint has already completed or been abandoned.
+    pub fn is_released(&self) -> bool {
+        *self.release.borrow()
+    }
+
+    /// Report the outcome of the snapshot and wait until the barrier is lifted.
+    pub async fn capture(mut self, result: anyhow::Result<Captured>) {
+        let _ = self.captured.send(result);
+
+        // Error means the coordinator is gone, which releases the barrier as well
+        let _ = self.release.wait_for(|released| *released).await;
+    }
+}
+
+/// Save a consistent snapshot of a persistent actor and all of its persistent descendants.
+///
+/// Each participant stops processing messages once its snapshot is captured, until the whole tree
+/// is saved, so no snapshot reflects a message handled after another participant's snapshot was
+/// taken. Descendants are discovered through `PersistentActor::persistent_children`. Snapshots
+/// are written as a single `SnapshotTransaction` rooted at `root_key`, so either the whole tree
+/// is saved or none of it. Returns the keys that were saved.
+///
+/// An actor waiting on another participant while handling a message cannot take part in the
+/// barrier, hence the `timeout` after which the checkpoint is abandoned and every participant
+/// released.
+pub async fn checkpoint(root_key: &Url, timeout: Duration) -> anyhow::Result<Vec<Url>> {
+    let deadline = clock::clock().now() + timeout;
+    let (release_tx, release_rx) = watch::channel(false);
+
+    let mut pending = VecDeque::from([root_key.clone()]);
+    let mut visited = HashSet::new();
+    let mut transaction = SnapshotTransaction::new(root_key.clone());
+    let mut references = Vec::new();
+
+    let result: anyhow::Result<Vec<Url>> = async {
+        while let Some(key) = pending.pop_front() {
+            if !visited.insert(key.clone()) {
+                continue;
+            }
+
+            let Some(actor) = registry::lookup(&key) else {
+                if key == *root_key {
+                    anyhow::bail!("No live persistent actor registered for {}", redacted(&key));
+                }
+
+                #[cfg(feature = "tracing")]
+                warn!(
+                    "Skipping checkpoint of {}: no live persistent actor registered",
+                    redacted(&key)
+                );
+                continue;
+            };
+
+            let (captured_tx, captured_rx) = oneshot::channel();
+
+            actor
+                .checkpoint(Checkpoint {
+                    captured: captured_tx,
+                    release: release_rx.clone(),
+                })
+                .await?;
+
+            let captured = clock::timeout_at(deadline, captured_rx)
+                .await
+                .ok_or_else(|| anyhow!("Timed out waiting for {} to checkpoint", redacted(&key)))?
+                .map_err(|_| anyhow!("Actor for {} dropped the checkpoint", redacted(&key)))??;
+
+            #[cfg(feature = "tracing")]
+            debug!(
+                "Captured {} with key {}, {} children",
+                actor.type_tag(),
+                redacted(&key),
+                captured.children.len()
+            );
+
+            transaction.stage_bytes_as(actor.type_tag(), captured.key.clone(), captured.data);
+            references.push((captured.key, captured.children.clone()));
+            pending.extend(captured.children);
+        }
+
+        let saved = transaction.keys().cloned().collect::<Vec<_>>();
+        let mut settled = Vec::new();
+        for key in &saved {
+            settled.extend(background::settle(key).await);
+        }
+        transaction.commit().await?;
+        settled.into_iter().for_each(Settled::written);
+
+        for (key, children) in references {
+            hierarchy::record_references(&key, &children)?;
+        }
+
+        Ok(saved)
+    }
+    .await;
+
+    let _ = release_tx.send(true);
+
+    result
+}
+
+/// Set of live persistent actors of any type, saved together with `save_group`.
+#[derive(Clone, Default)]
+pub struct ActorGroup {
+    members: Vec<(Option<Url>, Arc<dyn ErasedPersistentActor>)>,
+}
+
+impl ActorGroup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an actor to the group.
+    pub fn with<A>(mut self, actor_ref: &ActorRef<A>) -> Self
+    where
+        A: PersistentActor + Message<Checkpoint, Reply = ()>,
+    {
+        self.members.push((
+            A::persistence_key(actor_ref),
+            Arc::new(actor_ref.downgrade()),
+        ));
+        self
+    }
+
+    /// Add the live persistent actor registered under a key, failing if there is none.
+    pub fn with_key(mut self, persistence_key: &Url) -> anyhow::Result<Self> {
+        if !self.push_key(persistence_key) {
+            anyhow::bail!(
+                "No live persistent actor registered for {}",
+                redacted(persistence_key)
+            );
+        }
+        Ok(self)
+    }
+
+    /// Add the live persistent actor registered under a key, returning false if there is none.
+    pub(crate) fn push_key(&mut self, persistence_key: &Url) -> bool {
+        let Some(actor) = registry::lookup(persistence_key) else {
+            return false;
+        };
+        self.members.push((Some(persistence_key.clone()), actor));
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+/// Save the snapshots of a set of actors, at most `concurrency` at a time, returning the key
+/// saved or the error of every actor, in order.
+///
+/// Unlike `checkpoint`, every actor is saved on its own: an actor only holds its mailbox until
+/// its own snapshot is written, and a failing actor does not prevent the others from being
+/// saved. Actors not captured within `timeout` of the start of the batch fail.
+pub async fn save_all<A>(
+    actor_refs: &[ActorRef<A>],
+    concurrency: usize,
+    timeout: Duration,
+) -> Vec<anyhow::Result<Url>>
+where
+    A: PersistentActor + Message<Checkpoint, Reply = ()>,
+{
+    let group = actor_refs
+        .iter()
+        .fold(ActorGroup::new(), |group, actor_ref| group.with(actor_ref));
+
+    save_group(&group, concurrency, timeout).await
+}
+
+/// Save the snapshots of every actor of a group, as `save_all` does.
+pub async fn save_group(
+    group: &ActorGroup,
+    concurrency: usize,
+    timeout: Duration,
+) -> Vec<anyhow::Result<Url>> {
+    let deadline = clock::clock().now() + timeout;
+
+    let saves = group
+        .members
+        .iter()
+        .map(|(key, actor)| save_member(key.clone(), actor.clone(), deadline))
+        .collect::<Vec<_>>();
+
+    stream::iter(saves)
+        .buffered(concurrency.max(1))
+        .collect()
+        .await
+}
+
+async fn save_member(
+    persistence_key: Option<Url>,
+    actor: Arc<dyn ErasedPersistentActor>,
+    deadline: Instant,
+) -> anyhow::Result<Url> {
+    let Some(persistence_key) = &persistence_key else {
+        anyhow::bail!("Actor {} is not persistent", actor.type_tag());
+    };
+
+    let (release_tx, release_rx) = watch::channel(false);
+    let (captured_tx, captured_rx) = oneshot::channel();
+
+    let result = async {
+        actor
+            .checkpoint(Checkpoint {
+                captured: captured_tx,
+                release: release_rx,
+            })
+            .await?;
+
+        let captured = clock::timeout_at(deadline, captured_rx)
+            .await
+            .ok_or_else(|| {
+                anyhow!(
+                    "Timed out waiting for {} to capture its snapshot",
+                    redacted(persistence_key)
+                )
+            })?
+            .map_err(|_| anyhow!("Actor for {} dropped the save", redacted(persistence_key)))??;
+
+        let settled = background::settle(&captured.key).await;
+        report_save(actor.type_tag(), &captured.key, async {
+            #[cfg(feature = "tracing")]
+            spans::record_bytes(captured.data.len());
+
+            concurrency::write_as(actor.type_tag(), &captured.key, &captured.data).await?;
+            Ok(captured.data.len())
+        })
+        .await?;
+        if let Some(settled) = settled {
+            settled.written();
+        }
+        hierarchy::record_references(&captured.key, &captured.children)?;
+
+        Ok(captured.key)
+    }
+    .await;
+
+    let _ = release_tx.send(true);
+
+    #[cfg(feature = "tracing")]
+    if let Err(_e) = &result {
+        warn!("Failed to save {}: {_e:#}", redacted(persistence_key));
+    }
+
+    result
+}
@@ -0,0 +1,61 @@
+use std::{
+    sync::{Arc, LazyLock, RwLock},
+    time::{Instant, SystemTime},
+};
+
+use futures::future::{self, BoxFuture, Either};
+
+/// Source of time for deadlines, intervals and timestamps.
+///
+/// Replace the system clock with a manual one in tests to advance time without sleeping.
+pub trait Clock: Send + Sync {
+    /// Monotonic time, for deadlines and intervals.
+    fn now(&self) -> Instant;
+
+    /// Wall clock time, for timestamps.
+    fn system_time(&self) -> SystemTime;
+
+    /// Complete once `now()` reaches `deadline`.
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()>;
+}
+
+/// Clock backed by the operating system and the tokio timer.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep_until(deadline.into()))
+    }
+}
+
+static CLOCK: LazyLock<RwLock<Arc<dyn Clock>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(SystemClock)));
+
+/// Install the clock used by every time-based policy.
+pub fn set_clock(clock: Arc<dyn Clock>) {
+    *CLOCK.write().unwrap_or_else(|e| e.into_inner()) = clock;
+}
+
+/// Return the installed clock.
+pub fn clock() -> Arc<dyn Clock> {
+    CLOCK.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Run `fut` until `deadline` of the installed clock, returning `None` if it elapses first.
+pub async fn timeout_at<F: Future>(deadline: Instant, fut: F) -> Option<F::Output> {
+    let sleep = clock().sleep_until(deadline);
+
+    match future::select(std::pin::pin!(fut), sleep).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
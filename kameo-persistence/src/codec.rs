@@ -0,0 +1,223 @@
+use std::{borrow::Cow, collections::BTreeMap, sync::RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use url::Url;
+
+use crate::{
+    PersistenceError, PersistentActor, buffer, hooks, legacy, limits, parts, protect, schema,
+    tenant::{self, Tenant},
+};
+
+/// Marks snapshots stored with a `SnapshotHeader`.
+pub const MAGIC: &[u8; 4] = b"KPS\x01";
+
+/// Header stored in front of every snapshot payload.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotHeader {
+    /// `PersistentActor::type_tag` of the actor the snapshot belongs to.
+    pub type_tag: String,
+    /// Data subject whose key encrypts the payload, if any.
+    pub subject: Option<String>,
+    /// `PersistentActor::schema_version` of the payload.
+    pub schema_version: u32,
+    /// CRC-32 of the payload as stored.
+    pub checksum: u32,
+    /// Custom metadata attached when the snapshot was saved, see `set_metadata` and
+    /// `PersistentActor::metadata`.
+    pub metadata: BTreeMap<String, String>,
+}
+
+static METADATA: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+
+/// Attach `name` with `value` to the header of every snapshot saved from now on, such as the
+/// version of the application; `None` stops attaching it.
+///
+/// Entries of `PersistentActor::metadata` take precedence over these.
+pub fn set_metadata(name: &str, value: Option<&str>) {
+    let mut metadata = METADATA.write().unwrap_or_else(|e| e.into_inner());
+    match value {
+        Some(value) => metadata.insert(name.to_string(), value.to_string()),
+        None => metadata.remove(name),
+    };
+}
+
+/// Serialize a snapshot into the bytes written to storage under `persistence_key`.
+///
+/// The payload is encrypted for the actor's data subject, or else for the key's tenant if it is
+/// encrypted, then run through the installed `hooks::SnapshotHook`s. Fails if the snapshot
+/// exceeds a limit set in `limits`.
+pub fn encode<A: PersistentActor>(
+    persistence_key: &Url,
+    snapshot: &A::Snapshot,
+) -> anyhow::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    encode_into::<A>(persistence_key, snapshot, &mut data)?;
+    Ok(data)
+}
+
+/// Serialize a snapshot as `encode` does, replacing the content of `data`.
+///
+/// Reusing `data`, such as a `buffer::PooledBuffer`, avoids allocating for every save; only
+/// encrypted payloads and payloads run through a `hooks::SnapshotHook` still need an allocation.
+pub fn encode_into<A: PersistentActor>(
+    persistence_key: &Url,
+    snapshot: &A::Snapshot,
+    data: &mut Vec<u8>,
+) -> anyhow::Result<()> {
+    encode_parts_into::<A>(persistence_key, snapshot, &BTreeMap::new(), data)
+}
+
+/// Data subject whose key encrypts a snapshot stored under `persistence_key`, if any.
+pub(crate) fn subject<A: PersistentActor>(
+    persistence_key: &Url,
+    snapshot: &A::Snapshot,
+) -> Option<String> {
+    A::data_subject(snapshot).or_else(|| {
+        tenant::tenant_of(persistence_key)
+            .filter(Tenant::is_encrypted)
+            .map(|tenant| tenant.subject())
+    })
+}
+
+/// Serialize a snapshot as `encode_into` does, naming the files of the parts written for it by
+/// `parts::write` in its header.
+pub(crate) fn encode_parts_into<A: PersistentActor>(
+    persistence_key: &Url,
+    snapshot: &A::Snapshot,
+    part_files: &BTreeMap<String, String>,
+    data: &mut Vec<u8>,
+) -> anyhow::Result<()> {
+    let subject = subject::<A>(persistence_key, snapshot);
+
+    let mut plain = buffer::take();
+    *plain = postcard::to_extend(snapshot, std::mem::take(&mut *plain))?;
+
+    let payload = match &subject {
+        Some(subject) => Cow::Owned(protect::encrypt_for_subject(subject, &plain)?),
+        None => Cow::Borrowed(plain.as_slice()),
+    };
+    let (payload, hook_names) = hooks::before_write(payload)?;
+
+    let mut metadata = METADATA.read().unwrap_or_else(|e| e.into_inner()).clone();
+    metadata.extend(A::metadata(snapshot));
+    metadata.extend(part_files.clone());
+    metadata.remove(hooks::METADATA_KEY);
+    metadata.extend(hook_names.map(|names| (hooks::METADATA_KEY.to_string(), names)));
+
+    let header = SnapshotHeader {
+        type_tag: A::type_tag().to_string(),
+        subject,
+        schema_version: A::schema_version(),
+        checksum: crc32fast::hash(&payload),
+        metadata,
+    };
+
+    data.clear();
+    data.extend_from_slice(MAGIC);
+    *data = postcard::to_extend(&header, std::mem::take(data))?;
+    data.extend_from_slice(&payload);
+
+    limits::check::<A>(persistence_key, data.len())
+}
+
+/// Deserialize a snapshot from the bytes read from storage.
+///
+/// Payloads of an older schema version are upgraded with the migrations registered in `schema`.
+/// Bytes without a known header are decoded by the `legacy::LegacyDecoder` of `A`, if any.
+/// Fails with `PersistenceError::TypeMismatch` if the snapshot belongs to another actor type,
+/// and with `PersistenceError::ChecksumMismatch` if it was corrupted.
+pub fn decode<A: PersistentActor>(data: &[u8]) -> anyhow::Result<A::Snapshot> {
+    let (header, payload) = verify(data)?;
+
+    let Some(header) = header else {
+        if let Some(snapshot) = legacy::decode::<A>(data) {
+            return snapshot;
+        }
+
+        let payload = schema::upgrade_payload::<A>(0, Cow::Borrowed(payload))?;
+        return Ok(postcard::from_bytes(&payload)?);
+    };
+
+    if header.type_tag != A::type_tag() {
+        return Err(PersistenceError::TypeMismatch {
+            expected: A::type_tag().to_string(),
+            found: header.type_tag,
+        }
+        .into());
+    }
+
+    let payload = hooks::after_read(&header, Cow::Borrowed(payload))?;
+    let payload = match &header.subject {
+        Some(subject) => Cow::Owned(protect::decrypt_for_subject(subject, &payload)?),
+        None => payload,
+    };
+
+    let payload = schema::upgrade_payload::<A>(header.schema_version, payload)?;
+    Ok(postcard::from_bytes(&payload)?)
+}
+
+/// Deserialize a snapshot as `decode` does, then join the parts it was split into, read from
+/// under `persistence_key`, with `PersistentActor::join_parts`.
+pub fn decode_at<A: PersistentActor>(
+    persistence_key: &Url,
+    data: &[u8],
+) -> anyhow::Result<A::Snapshot> {
+    let mut snapshot = decode::<A>(data)?;
+
+    if let (Some(header), _) = split(data)?
+        && let Some(parts) = parts::read(persistence_key, &header)?
+    {
+        A::join_parts(&mut snapshot, parts)?;
+    }
+
+    Ok(snapshot)
+}
+
+/// Split stored bytes into header and payload.
+///
+/// Snapshots written before headers were introduced have no header. The payload is still
+/// encrypted if the header names a data subject.
+pub fn split(data: &[u8]) -> anyhow::Result<(Option<SnapshotHeader>, &[u8])> {
+    if let Some(rest) = data.strip_prefix(MAGIC.as_slice()) {
+        let (header, payload) = postcard::take_from_bytes(rest)?;
+        return Ok((Some(header), payload));
+    }
+
+    Ok((None, data))
+}
+
+/// Split stored bytes into header and payload, checking the payload against its checksum.
+///
+/// Snapshots without a header have no checksum and are not checked.
+pub fn verify(data: &[u8]) -> anyhow::Result<(Option<SnapshotHeader>, &[u8])> {
+    let (header, payload) = split(data)?;
+
+    if let Some(expected) = header.as_ref().map(|header| header.checksum) {
+        let found = crc32fast::hash(payload);
+        if found != expected {
+            return Err(PersistenceError::ChecksumMismatch { expected, found }.into());
+        }
+    }
+
+    Ok((header, payload))
+}
+
+/// Return true if decoding `data` failed with `error` because the stored bytes are corrupt.
+///
+/// A payload matching its checksum was stored as written, so failing to deserialize it, e.g.
+/// for a missing schema migration, is not corruption. Snapshots without a header are never
+/// considered corrupt, as they may belong to another actor type.
+pub fn is_corruption(error: &anyhow::Error, data: &[u8]) -> bool {
+    if let Some(PersistenceError::ChecksumMismatch { .. }) = error.downcast_ref() {
+        return true;
+    }
+
+    // Headers which cannot be parsed are corrupt
+    split(data).is_err()
+}
+
+/// Version of the header format of stored bytes, or `None` for snapshots without a header.
+pub fn format_version(data: &[u8]) -> Option<u8> {
+    data.starts_with(MAGIC).then_some(MAGIC[3])
+}
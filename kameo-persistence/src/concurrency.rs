@@ -0,0 +1,266 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, LazyLock, Mutex, RwLock},
+};
+
+#[cfg(feature = "tracing")]
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::{PersistenceError, PersistentActor, codec, redact::redacted, storage};
+
+/// Conflicts resolved in a row for one save before giving up.
+const MAX_RESOLVE_ATTEMPTS: usize = 8;
+
+static OPTIMISTIC: RwLock<Option<HashSet<&'static str>>> = RwLock::new(None);
+
+// Snapshot version each key was last read or written at by this process, `None` if it had none
+static VERSIONS: LazyLock<Mutex<HashMap<Url, Option<String>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// Resolves a conflict from the key, the local and the stored snapshot bytes into the bytes to
+// write, or `None` to keep the stored snapshot
+pub(crate) type Resolver =
+    Arc<dyn Fn(&Url, &[u8], &[u8]) -> anyhow::Result<Option<Vec<u8>>> + Send + Sync>;
+
+static RESOLVERS: RwLock<Option<HashMap<&'static str, Resolver>>> = RwLock::new(None);
+
+/// Outcome of a conflict resolver.
+#[derive(Debug, Clone)]
+pub enum Resolution<S> {
+    /// Keep the stored snapshot and drop the local one.
+    Theirs,
+    /// Replace the stored snapshot with the local one, last writer wins.
+    Ours,
+    /// Write the given merge of both snapshots.
+    Merged(S),
+}
+
+/// Check that nothing else replaced the snapshot of an actor of type `A` before saving it.
+///
+/// The version stored under a key is remembered when the actor is spawned or respawned, and
+/// every save is a compare-and-swap against it. A save fails with `PersistenceError::Conflict`
+/// if another writer, such as a second node sharing the storage, replaced the snapshot in
+/// between, instead of silently overwriting its update. The check is as atomic as the backend's
+/// `write_file_if`.
+pub fn set_optimistic<A: PersistentActor>(enabled: bool) {
+    let mut optimistic = OPTIMISTIC.write().unwrap_or_else(|e| e.into_inner());
+    let types = optimistic.get_or_insert_default();
+
+    if enabled {
+        types.insert(A::type_tag());
+    } else {
+        types.remove(A::type_tag());
+    }
+}
+
+/// Resolve conflicting saves of `A` with `resolve(local, stored)` instead of failing.
+///
+/// On a conflict the stored snapshot is read back and handed to the resolver with the one being
+/// saved, and the outcome is written with a new compare-and-swap, retried if yet another writer
+/// got in between. With `Theirs` or `Merged` the running actor keeps its own state; watch the
+/// key with `watch::watch` to receive the stored snapshot. A snapshot deleted in between is
+/// replaced by the local one.
+pub fn set_resolver<A: PersistentActor>(
+    resolve: impl Fn(&A::Snapshot, A::Snapshot) -> Resolution<A::Snapshot> + Send + Sync + 'static,
+) {
+    let resolver: Resolver = Arc::new(move |persistence_key, data, stored| {
+        let local = codec::decode::<A>(data)?;
+        let stored = codec::decode::<A>(stored)?;
+
+        match resolve(&local, stored) {
+            Resolution::Theirs => Ok(None),
+            Resolution::Ours => Ok(Some(data.to_vec())),
+            Resolution::Merged(merged) => Ok(Some(codec::encode::<A>(persistence_key, &merged)?)),
+        }
+    });
+
+    RESOLVERS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_default()
+        .insert(A::type_tag(), resolver);
+}
+
+/// Remove the conflict resolver of `A`, so its conflicting saves fail again.
+pub fn remove_resolver<A: PersistentActor>() {
+    if let Some(resolvers) = RESOLVERS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_mut()
+    {
+        resolvers.remove(A::type_tag());
+    }
+}
+
+/// Conflict resolver of the actor type tagged `type_tag`.
+pub(crate) fn resolver(type_tag: &str) -> Option<Resolver> {
+    RESOLVERS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .and_then(|resolvers| resolvers.get(type_tag).cloned())
+}
+
+/// Return true if saves of `A` are checked for conflicts.
+pub fn is_optimistic<A: PersistentActor>() -> bool {
+    is_optimistic_type(A::type_tag())
+}
+
+/// Return true if saves of the actor type tagged `type_tag` are checked for conflicts.
+pub(crate) fn is_optimistic_type(type_tag: &str) -> bool {
+    OPTIMISTIC
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .is_some_and(|types| types.contains(type_tag))
+}
+
+/// Version of the snapshot this process last read or wrote under a key.
+///
+/// Return `None` if the key is not tracked, and `Some(None)` if it had no snapshot.
+pub fn version(persistence_key: &Url) -> Option<Option<String>> {
+    VERSIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(persistence_key)
+        .cloned()
+}
+
+fn stored_version(persistence_key: &Url) -> anyhow::Result<Option<String>> {
+    storage::backend(persistence_key)?.file_version(persistence_key, storage::snapshot_file())
+}
+
+/// Remember the version stored under a key, before its snapshot is read.
+pub(crate) fn track(persistence_key: &Url) -> anyhow::Result<()> {
+    remember(persistence_key, stored_version(persistence_key)?);
+    Ok(())
+}
+
+/// Remember the version stored under a key, unless it is already tracked.
+pub(crate) fn track_if_untracked(persistence_key: &Url) -> anyhow::Result<()> {
+    if version(persistence_key).is_none() {
+        track(persistence_key)?;
+    }
+
+    Ok(())
+}
+
+/// Update the version of a tracked key after this process replaced its snapshot unchecked.
+pub(crate) fn note_write(persistence_key: &Url) -> anyhow::Result<()> {
+    if version(persistence_key).is_some() {
+        track(persistence_key)?;
+    }
+
+    Ok(())
+}
+
+/// Forget every tracked version.
+pub(crate) fn clear() {
+    VERSIONS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+fn conflict(type_tag: &str, persistence_key: &Url) -> anyhow::Error {
+    PersistenceError::Conflict {
+        type_tag: type_tag.to_string(),
+        key: redacted(persistence_key).to_string(),
+    }
+    .into()
+}
+
+/// Fail with `PersistenceError::Conflict` if the snapshot of the actor type tagged `type_tag`
+/// stored under a key is no longer the one last seen, for writes which cannot be conditional.
+pub(crate) fn check(type_tag: &str, persistence_key: &Url) -> anyhow::Result<()> {
+    if let Some(expected) = version(persistence_key)
+        && stored_version(persistence_key)? != expected
+    {
+        return Err(conflict(type_tag, persistence_key));
+    }
+
+    Ok(())
+}
+
+/// Write a snapshot of the actor type tagged `type_tag`, checked for conflicts if its saves
+/// are, see `set_optimistic`.
+pub(crate) async fn write_as(
+    type_tag: &str,
+    persistence_key: &Url,
+    data: &[u8],
+) -> anyhow::Result<()> {
+    if is_optimistic_type(type_tag) {
+        write(type_tag, persistence_key, data).await
+    } else {
+        storage::write(persistence_key, data).await
+    }
+}
+
+/// Write a snapshot of the actor type tagged `type_tag` if the stored version still is the one
+/// last seen under its key.
+pub(crate) async fn write(
+    type_tag: &str,
+    persistence_key: &Url,
+    data: &[u8],
+) -> anyhow::Result<()> {
+    let expected = match version(persistence_key) {
+        Some(expected) => expected,
+        None => stored_version(persistence_key)?,
+    };
+
+    if let Some(version) = storage::write_if(persistence_key, expected.as_deref(), data).await? {
+        remember(persistence_key, Some(version));
+        return Ok(());
+    }
+
+    #[cfg(feature = "tracing")]
+    warn!(
+        "Snapshot of {} was replaced by another writer",
+        redacted(persistence_key)
+    );
+
+    let Some(resolver) = resolver(type_tag) else {
+        return Err(conflict(type_tag, persistence_key));
+    };
+
+    let backend = storage::backend(persistence_key)?;
+    for _ in 0..MAX_RESOLVE_ATTEMPTS {
+        // Version first, so a snapshot replaced while reading fails the next write
+        let version = backend.file_version(persistence_key, storage::snapshot_file())?;
+        let stored = backend.read_file(persistence_key, storage::snapshot_file())?;
+
+        let resolved = match &stored {
+            Some(stored) => resolver(persistence_key, data, stored)?,
+            None => Some(data.to_vec()),
+        };
+
+        let Some(resolved) = resolved else {
+            #[cfg(feature = "tracing")]
+            debug!(
+                "Kept the stored snapshot of {} on conflict",
+                redacted(persistence_key)
+            );
+
+            remember(persistence_key, version);
+            return Ok(());
+        };
+
+        if let Some(version) =
+            storage::write_if(persistence_key, version.as_deref(), &resolved).await?
+        {
+            #[cfg(feature = "tracing")]
+            debug!("Resolved conflict on {}", redacted(persistence_key));
+
+            remember(persistence_key, Some(version));
+            return Ok(());
+        }
+    }
+
+    Err(conflict(type_tag, persistence_key))
+}
+
+/// Remember the version a key is known to be stored at.
+pub(crate) fn remember(persistence_key: &Url, version: Option<String>) {
+    VERSIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(persistence_key.clone(), version);
+}
@@ -0,0 +1,260 @@
+use std::sync::Arc;
+
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::sync::watch;
+#[cfg(feature = "tracing")]
+use tracing::debug;
+use url::Url;
+
+#[cfg(feature = "tracing")]
+use crate::redact::redacted;
+use crate::{Checkpoint, PersistentActor, codec, registry, watch::SnapshotChanged};
+
+/// Actor holding a configuration struct, saved on every change and pushed to subscribers.
+///
+/// Spawn it with `spawn_persistent` and the initial configuration, or respawn it from its
+/// snapshot. Changes are made with `Update`, or picked up from storage with `Reload`, such as
+/// after an operator edited the snapshot; combined with `watch::watch`, external edits are
+/// applied as they happen.
+pub struct PersistentConfig<T> {
+    config: Arc<T>,
+    changes: watch::Sender<Arc<T>>,
+}
+
+/// Snapshot and arguments of a `PersistentConfig`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConfigSnapshot<T> {
+    pub config: T,
+}
+
+impl<T> ConfigSnapshot<T> {
+    pub fn new(config: T) -> Self {
+        Self { config }
+    }
+}
+
+impl<T: Clone> From<&PersistentConfig<T>> for ConfigSnapshot<T> {
+    fn from(actor: &PersistentConfig<T>) -> Self {
+        Self::new((*actor.config).clone())
+    }
+}
+
+impl<T> From<ConfigSnapshot<T>> for PersistentConfig<T> {
+    fn from(snapshot: ConfigSnapshot<T>) -> Self {
+        let config = Arc::new(snapshot.config);
+        Self {
+            changes: watch::Sender::new(config.clone()),
+            config,
+        }
+    }
+}
+
+impl<T> PersistentConfig<T>
+where
+    T: Clone + PartialEq + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    /// Replace the configuration and notify subscribers, returning false if it is unchanged.
+    fn replace(&mut self, config: T) -> bool {
+        if *self.config == config {
+            return false;
+        }
+
+        self.config = Arc::new(config);
+        self.changes.send_replace(self.config.clone());
+        true
+    }
+
+    /// Save `config` and make it current, keeping the current one if it could not be saved.
+    async fn commit(&mut self, actor_ref: &ActorRef<Self>, config: T) -> anyhow::Result<()> {
+        if *self.config == config {
+            return Ok(());
+        }
+
+        let previous = std::mem::replace(&mut self.config, Arc::new(config));
+        if let Err(e) = self.save_snapshot(actor_ref).await {
+            self.config = previous;
+            return Err(e);
+        }
+
+        self.changes.send_replace(self.config.clone());
+        Ok(())
+    }
+}
+
+impl<T> Actor for PersistentConfig<T>
+where
+    T: Clone + PartialEq + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Args = ConfigSnapshot<T>;
+    type Error = anyhow::Error;
+
+    async fn on_start(args: Self::Args, _actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        Ok(args.into())
+    }
+}
+
+impl<T> PersistentActor for PersistentConfig<T>
+where
+    T: Clone + PartialEq + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Snapshot = ConfigSnapshot<T>;
+
+    fn register_persistent(persistence_key: Url, actor_ref: &ActorRef<Self>) -> anyhow::Result<()> {
+        let Ok(mut registry) = registry::typed_registry::<Self>().write() else {
+            anyhow::bail!("Failed to acquire write lock on registry");
+        };
+        registry::register(persistence_key.clone(), actor_ref);
+        registry.insert(persistence_key, actor_ref.downgrade());
+        Ok(())
+    }
+
+    fn persistence_key(actor_ref: &ActorRef<Self>) -> Option<Url> {
+        let registry = registry::typed_registry::<Self>().read().ok()?;
+        registry.get_left(&actor_ref.downgrade()).cloned()
+    }
+
+    fn lookup_persistent(persistence_key: &Url) -> Option<ActorRef<Self>> {
+        let registry = registry::typed_registry::<Self>().read().ok()?;
+        registry
+            .get_right(persistence_key)
+            .and_then(|weak_ref| weak_ref.upgrade())
+            .filter(|actor_ref| actor_ref.is_alive())
+    }
+
+    fn unregister_persistent(persistence_key: &Url) {
+        registry::unregister(persistence_key);
+        registry::typed_registry::<Self>()
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove_left(persistence_key);
+    }
+}
+
+impl<T> Message<Checkpoint> for PersistentConfig<T>
+where
+    T: Clone + PartialEq + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: Checkpoint,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.on_checkpoint(&ctx.actor_ref(), msg).await
+    }
+}
+
+/// Return the current configuration.
+pub struct Get;
+
+impl<T> Message<Get> for PersistentConfig<T>
+where
+    T: Clone + PartialEq + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Reply = Arc<T>;
+
+    async fn handle(&mut self, _msg: Get, _ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        self.config.clone()
+    }
+}
+
+/// Change the configuration with the closure, save it and notify subscribers, returning the new
+/// configuration.
+///
+/// The configuration is left as it was if the closure fails or the save does.
+pub struct Update<F>(pub F);
+
+impl<T, F> Message<Update<F>> for PersistentConfig<T>
+where
+    T: Clone + PartialEq + Send + Sync + Serialize + DeserializeOwned + 'static,
+    F: FnOnce(&mut T) -> anyhow::Result<()> + Send + 'static,
+{
+    type Reply = anyhow::Result<Arc<T>>;
+
+    async fn handle(
+        &mut self,
+        msg: Update<F>,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let mut config = (*self.config).clone();
+        (msg.0)(&mut config)?;
+
+        self.commit(&ctx.actor_ref(), config).await?;
+        Ok(self.config.clone())
+    }
+}
+
+/// Read the configuration back from storage, notifying subscribers if it changed there, and
+/// return true if it did.
+pub struct Reload;
+
+impl<T> Message<Reload> for PersistentConfig<T>
+where
+    T: Clone + PartialEq + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Reply = anyhow::Result<bool>;
+
+    async fn handle(&mut self, _msg: Reload, ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        let Some(key) = Self::persistence_key(&ctx.actor_ref()) else {
+            anyhow::bail!("Configuration actor is not persistent");
+        };
+
+        let data = Self::try_read(&key).await?;
+        let snapshot = codec::decode_at::<Self>(&key, &data)?;
+        let changed = self.replace(snapshot.config);
+
+        #[cfg(feature = "tracing")]
+        if changed {
+            debug!("Reloaded configuration {}", redacted(&key));
+        }
+
+        Ok(changed)
+    }
+}
+
+/// Subscribe to the configuration, which the receiver of the returned subscription holds,
+/// marked changed on every change.
+///
+/// The receiver is closed when the actor stops; subscribe again to its respawned actor.
+pub struct Subscribe;
+
+/// Receiver of the changes of a `PersistentConfig`, see `Subscribe`.
+#[derive(Reply)]
+pub struct ConfigSubscription<T: Send + Sync + 'static> {
+    pub changes: watch::Receiver<Arc<T>>,
+}
+
+impl<T> Message<Subscribe> for PersistentConfig<T>
+where
+    T: Clone + PartialEq + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Reply = ConfigSubscription<T>;
+
+    async fn handle(
+        &mut self,
+        _msg: Subscribe,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        ConfigSubscription {
+            changes: self.changes.subscribe(),
+        }
+    }
+}
+
+/// Applies changes picked up by `watch::watch` without saving them again.
+impl<T> Message<SnapshotChanged<ConfigSnapshot<T>>> for PersistentConfig<T>
+where
+    T: Clone + PartialEq + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: SnapshotChanged<ConfigSnapshot<T>>,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.replace(msg.0.config);
+    }
+}
@@ -0,0 +1,90 @@
+use std::{
+    any::{self, Any, TypeId},
+    collections::HashMap,
+    sync::{Arc, LazyLock, RwLock},
+};
+
+use anyhow::anyhow;
+
+// Dependencies every restore can use, see `provide`
+static GLOBAL: LazyLock<RwLock<RestoreContext>> =
+    LazyLock::new(|| RwLock::new(RestoreContext::new()));
+
+/// Runtime dependencies, such as database pools or HTTP clients, handed to
+/// `PersistentActor::restore_args` to rebuild an actor's `Args` from its snapshot.
+///
+/// Values are keyed by their type. Dependencies given to one respawn call take precedence over
+/// those registered for every restore with `provide`.
+#[derive(Clone, Default)]
+pub struct RestoreContext {
+    values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl RestoreContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Context with the dependencies registered with `provide`.
+    pub fn global() -> Self {
+        GLOBAL.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Add a dependency, replacing the one of the same type.
+    pub fn with<T: Any + Send + Sync>(mut self, value: T) -> Self {
+        self.insert(value);
+        self
+    }
+
+    /// Add a dependency, replacing the one of the same type.
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) {
+        self.values.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .cloned()
+            .and_then(|value| value.downcast().ok())
+    }
+
+    /// Return the dependency of type `T`, failing if none was provided.
+    pub fn require<T: Any + Send + Sync>(&self) -> anyhow::Result<Arc<T>> {
+        self.get()
+            .ok_or_else(|| anyhow!("No {} provided to restore with", any::type_name::<T>()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// This context with the dependencies of `fallback` it lacks.
+    pub(crate) fn or(mut self, fallback: Self) -> Self {
+        for (type_id, value) in fallback.values {
+            self.values.entry(type_id).or_insert(value);
+        }
+        self
+    }
+}
+
+/// Make a dependency available to every restore of this process, replacing the one of the same
+/// type.
+pub fn provide<T: Any + Send + Sync>(value: T) {
+    GLOBAL
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(value);
+}
+
+/// Stop providing the dependency of type `T` to restores.
+pub fn withdraw<T: Any + Send + Sync>() {
+    GLOBAL
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .values
+        .remove(&TypeId::of::<T>());
+}
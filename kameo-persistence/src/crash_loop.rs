@@ -0,0 +1,227 @@
+use std::{
+    sync::{Mutex, RwLock},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "tracing")]
+use tracing::warn;
+use url::Url;
+
+use crate::{clock, redact::redacted, storage};
+
+/// Name of the restart state inside a persistence key directory.
+pub const CRASHES_FILE: &str = "crashes.bin";
+
+/// Name of the record of a suspect snapshot inside a persistence key directory.
+pub const SUSPECT_FILE: &str = "suspect.bin";
+
+static POLICY: RwLock<Option<CrashLoopPolicy>> = RwLock::new(None);
+
+// Serializes read-modify-write cycles on restart states
+static STATE_LOCK: Mutex<()> = Mutex::new(());
+
+/// What to restore instead of a snapshot flagged as suspect.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SuspectFallback {
+    /// Fail the restore, so `try_respawn_persistent` spawns the actor from fresh `Args`.
+    #[default]
+    Fail,
+    /// Restore the previous snapshot, kept with `storage::set_keep_previous`, failing as
+    /// `Fail` does if none was kept.
+    Previous,
+}
+
+/// When a snapshot is suspected of crashing the actor restored from it, and what to do then.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrashLoopPolicy {
+    max_crashes: u32,
+    stable_after: Duration,
+    fallback: SuspectFallback,
+}
+
+impl CrashLoopPolicy {
+    /// Suspect the snapshot of a key once actors restored from it stopped `max_crashes` times
+    /// in a row before running for `stable_after`, failing the next restore.
+    ///
+    /// Actors stopped on purpose before `stable_after` count as crashed too, so keep it below
+    /// the lifetime of healthy actors.
+    pub fn new(max_crashes: u32, stable_after: Duration) -> Self {
+        Self {
+            max_crashes: max_crashes.max(1),
+            stable_after,
+            fallback: SuspectFallback::Fail,
+        }
+    }
+
+    /// Restore `fallback` instead of a suspect snapshot.
+    pub fn fallback(mut self, fallback: SuspectFallback) -> Self {
+        self.fallback = fallback;
+        self
+    }
+}
+
+/// Snapshot flagged as suspect of a crash loop.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Suspicion {
+    /// File the suspect snapshot was moved to under the key.
+    pub moved_to: String,
+    /// Crashes in a row shortly after restoring it.
+    pub crashes: u32,
+    /// Milliseconds since the Unix epoch when it was flagged.
+    pub flagged_at_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct RestartState {
+    /// Restores in a row whose actor did not become stable.
+    crashes: u32,
+    /// True from a restore until its actor ran for `stable_after`.
+    pending: bool,
+}
+
+/// Detect actors crashing shortly after every restore, breaking poison snapshot crash loops.
+///
+/// Every restore is tracked under its key until its actor has run for `stable_after`, so crash
+/// loops are detected across process restarts too; `None` stops detection.
+pub fn set_crash_loop_policy(policy: Option<CrashLoopPolicy>) {
+    *POLICY.write().unwrap_or_else(|e| e.into_inner()) = policy;
+}
+
+fn policy() -> Option<CrashLoopPolicy> {
+    *POLICY.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Return the suspicion of the snapshot of a key, if it was flagged.
+pub fn suspicion(persistence_key: &Url) -> anyhow::Result<Option<Suspicion>> {
+    match storage::backend(persistence_key)?.read_file(persistence_key, SUSPECT_FILE)? {
+        Some(data) => Ok(Some(postcard::from_bytes(&data)?)),
+        None => Ok(None),
+    }
+}
+
+/// Forget the crashes and suspicion of a key, e.g. once the bug was fixed. The suspect snapshot
+/// is kept under the file it was moved to.
+pub fn clear(persistence_key: &Url) -> anyhow::Result<()> {
+    let backend = storage::backend(persistence_key)?;
+    backend.remove_file(persistence_key, CRASHES_FILE)?;
+    backend.remove_file(persistence_key, SUSPECT_FILE)
+}
+
+/// Count a restore from snapshot bytes, returning the bytes to restore instead if the snapshot
+/// is suspect now.
+pub(crate) async fn check_restore(persistence_key: &Url, data: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    let Some(policy) = policy() else {
+        return Ok(data);
+    };
+
+    let crashes = update(persistence_key, |state| {
+        if state.pending {
+            state.crashes += 1;
+        }
+        state.pending = true;
+        state.crashes
+    })?;
+
+    if crashes < policy.max_crashes {
+        return Ok(data);
+    }
+
+    let now = clock::clock()
+        .system_time()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+
+    let backend = storage::backend(persistence_key)?;
+    let moved_to = format!("{}.suspect-{now}", storage::snapshot_file());
+    backend.rename_file(persistence_key, storage::snapshot_file(), &moved_to)?;
+
+    let suspicion = Suspicion {
+        moved_to,
+        crashes,
+        flagged_at_ms: now,
+    };
+    backend.write_file(
+        persistence_key,
+        SUSPECT_FILE,
+        &postcard::to_allocvec(&suspicion)?,
+    )?;
+
+    #[cfg(feature = "tracing")]
+    warn!(
+        "Snapshot of {} is suspect after {crashes} crashes shortly after restore, moved aside as {}",
+        redacted(persistence_key),
+        suspicion.moved_to
+    );
+
+    let previous = match policy.fallback {
+        SuspectFallback::Previous => storage::restore_previous(persistence_key).await?,
+        SuspectFallback::Fail => None,
+    };
+
+    // Only the previous snapshot is being restored; fresh actors are not watched
+    update(persistence_key, |state| {
+        *state = RestartState {
+            crashes: 0,
+            pending: previous.is_some(),
+        }
+    })?;
+
+    previous.ok_or_else(|| {
+        anyhow!(
+            "Snapshot of {} is suspect after {crashes} crashes shortly after restore",
+            redacted(persistence_key)
+        )
+    })
+}
+
+/// Clear the pending restore of a key once its actor ran for `stable_after`.
+pub(crate) fn watch_restore<A: Actor>(persistence_key: &Url, actor_ref: &ActorRef<A>) {
+    let Some(policy) = policy() else {
+        return;
+    };
+
+    let persistence_key = persistence_key.clone();
+    let actor_ref = actor_ref.downgrade();
+
+    tokio::spawn(async move {
+        let clock = clock::clock();
+        clock.sleep_until(clock.now() + policy.stable_after).await;
+
+        if !actor_ref
+            .upgrade()
+            .is_some_and(|actor_ref| actor_ref.is_alive())
+        {
+            return;
+        }
+
+        if let Err(_e) = update(&persistence_key, |state| *state = RestartState::default()) {
+            #[cfg(feature = "tracing")]
+            warn!(
+                "Failed to clear the restart state of {}: {_e:#}",
+                redacted(&persistence_key)
+            );
+        }
+    });
+}
+
+fn update<T>(persistence_key: &Url, f: impl FnOnce(&mut RestartState) -> T) -> anyhow::Result<T> {
+    let _guard = STATE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let backend = storage::backend(persistence_key)?;
+
+    let mut state = match backend.read_file(persistence_key, CRASHES_FILE)? {
+        Some(data) => postcard::from_bytes(&data)?,
+        None => RestartState::default(),
+    };
+    let result = f(&mut state);
+
+    backend.write_file(
+        persistence_key,
+        CRASHES_FILE,
+        &postcard::to_allocvec(&state)?,
+    )?;
+    Ok(result)
+}
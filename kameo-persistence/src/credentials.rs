@@ -0,0 +1,192 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::PathBuf,
+    sync::{Arc, LazyLock, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use url::Url;
+
+use crate::{clock, redact::redacted};
+
+/// Secret value, such as an access key or a password, hidden when debug printed.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    /// The value, to hand to the remote service.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(REDACTED)")
+    }
+}
+
+/// Source of the secrets remote backends need for a key, such as environment variables,
+/// mounted files, or a KMS.
+///
+/// Providers are consulted on every use, so secrets rotated at the source take effect without
+/// restarting; wrap slow providers in `Cached`. Closures taking the key and the secret name
+/// are providers too, e.g. to fetch secrets from a KMS.
+pub trait CredentialsProvider: Send + Sync {
+    /// Secret named `name`, such as `access_key`, for the key; `None` if the provider has none.
+    fn secret(&self, key: &Url, name: &str) -> anyhow::Result<Option<Secret>>;
+}
+
+impl<F> CredentialsProvider for F
+where
+    F: Fn(&Url, &str) -> anyhow::Result<Option<Secret>> + Send + Sync,
+{
+    fn secret(&self, key: &Url, name: &str) -> anyhow::Result<Option<Secret>> {
+        self(key, name)
+    }
+}
+
+// Providers, by URL scheme
+static PROVIDERS: LazyLock<RwLock<HashMap<String, Arc<dyn CredentialsProvider>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Consult `provider` for the secrets of keys of a URL scheme, replacing any provider of that
+/// scheme; installing a new provider rotates the secrets at runtime.
+pub fn set_credentials_provider(scheme: &str, provider: impl CredentialsProvider + 'static) {
+    PROVIDERS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(scheme.to_string(), Arc::new(provider));
+}
+
+/// Secret named `name` for a key, for remote backends to authenticate with.
+///
+/// Asks the provider of the key's scheme, then falls back to the query parameter of that name
+/// in the key, so keys embedding their secrets keep working.
+pub fn secret(key: &Url, name: &str) -> anyhow::Result<Secret> {
+    let provider = PROVIDERS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(key.scheme())
+        .cloned();
+
+    if let Some(provider) = provider
+        && let Some(secret) = provider.secret(key, name)?
+    {
+        return Ok(secret);
+    }
+
+    key.query_pairs()
+        .find(|(param, _)| param == name)
+        .map(|(_, value)| Secret::new(value))
+        .ok_or_else(|| anyhow!("No secret {name} for {}", redacted(key)))
+}
+
+/// Reads secrets from environment variables named after them, `access_key` being read from
+/// `<prefix>ACCESS_KEY`.
+#[derive(Debug, Clone)]
+pub struct EnvProvider {
+    prefix: String,
+}
+
+impl EnvProvider {
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+        }
+    }
+}
+
+impl CredentialsProvider for EnvProvider {
+    fn secret(&self, _key: &Url, name: &str) -> anyhow::Result<Option<Secret>> {
+        let var = format!("{}{}", self.prefix, name).to_ascii_uppercase();
+        Ok(std::env::var(var).ok().map(Secret::new))
+    }
+}
+
+/// Reads secrets from files named after them in a directory, such as mounted Kubernetes
+/// secrets, which are rotated by replacing the files.
+#[derive(Debug, Clone)]
+pub struct FileProvider {
+    dir: PathBuf,
+}
+
+impl FileProvider {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl CredentialsProvider for FileProvider {
+    fn secret(&self, _key: &Url, name: &str) -> anyhow::Result<Option<Secret>> {
+        if name.contains(['/', '\\']) || name.starts_with('.') {
+            anyhow::bail!("Invalid secret name {name:?}");
+        }
+
+        match std::fs::read_to_string(self.dir.join(name)) {
+            Ok(value) => Ok(Some(Secret::new(value.trim_end_matches(['\r', '\n'])))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// Secrets with the moment they expire, by key and name
+type CachedSecrets = HashMap<(Url, String), (Option<Secret>, Instant)>;
+
+/// Caches the secrets of another provider for a while, so a KMS is not asked on every use.
+pub struct Cached<P> {
+    provider: P,
+    ttl: Duration,
+    secrets: Mutex<CachedSecrets>,
+}
+
+impl<P: CredentialsProvider> Cached<P> {
+    /// Cache the secrets of `provider` for `ttl`, after which they are asked for again.
+    pub fn new(provider: P, ttl: Duration) -> Self {
+        Self {
+            provider,
+            ttl,
+            secrets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Forget every cached secret, e.g. once a secret was rotated.
+    pub fn invalidate(&self) {
+        self.secrets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+}
+
+impl<P: CredentialsProvider> CredentialsProvider for Cached<P> {
+    fn secret(&self, key: &Url, name: &str) -> anyhow::Result<Option<Secret>> {
+        let now = clock::clock().now();
+        let entry = (key.clone(), name.to_string());
+
+        if let Some((secret, expires_at)) = self
+            .secrets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&entry)
+            && now < *expires_at
+        {
+            return Ok(secret.clone());
+        }
+
+        // Not locked while asking, so a slow provider does not block other secrets
+        let secret = self.provider.secret(key, name)?;
+        self.secrets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(entry, (secret.clone(), now + self.ttl));
+        Ok(secret)
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use serde::{Serialize, de::DeserializeOwned};
+use url::Url;
+
+use crate::{clock, storage};
+
+/// Replies of recently processed requests, next to the snapshot of the actor.
+pub const DEDUP_FILE: &str = "dedup.bin";
+
+/// Cache being written, until it replaces `DEDUP_FILE`.
+const DEDUP_TMP_FILE: &str = "dedup.bin.tmp";
+
+/// Persistent cache of the replies to recently processed requests, by request ID.
+///
+/// An actor handling requests with side effects records each reply before returning it; a
+/// retried `ask`, such as one sent again after the actor crashed and was respawned, then gets
+/// the recorded reply instead of executing the side effects twice. The cache is stored under
+/// the actor's persistence key and rewritten on every record, keeping at most `capacity`
+/// replies, none older than `ttl`.
+pub struct DedupCache<R> {
+    persistence_key: Url,
+    capacity: usize,
+    ttl: Duration,
+    replies: HashMap<String, (R, u64)>,
+    /// Request IDs, oldest first.
+    order: VecDeque<String>,
+}
+
+impl<R: Clone + Serialize + DeserializeOwned> DedupCache<R> {
+    /// Load the cache stored under `persistence_key`, or start an empty one.
+    pub fn open(persistence_key: Url, capacity: usize, ttl: Duration) -> anyhow::Result<Self> {
+        let stored: Vec<(String, u64, R)> =
+            match storage::backend(&persistence_key)?.read_file(&persistence_key, DEDUP_FILE)? {
+                Some(data) => postcard::from_bytes(&data)?,
+                None => Vec::new(),
+            };
+
+        let mut cache = Self {
+            persistence_key,
+            capacity: capacity.max(1),
+            ttl,
+            replies: HashMap::with_capacity(stored.len()),
+            order: VecDeque::with_capacity(stored.len()),
+        };
+        for (request_id, recorded, reply) in stored {
+            cache.order.push_back(request_id.clone());
+            cache.replies.insert(request_id, (reply, recorded));
+        }
+        cache.evict();
+
+        Ok(cache)
+    }
+
+    /// Number of cached replies.
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Return true if no reply is cached.
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Return the reply recorded for a request, if it was processed within the TTL.
+    pub fn get(&mut self, request_id: &str) -> Option<R> {
+        self.evict();
+        self.replies.get(request_id).map(|(reply, _)| reply.clone())
+    }
+
+    /// Record the reply to a request and store the cache.
+    pub async fn record(&mut self, request_id: impl Into<String>, reply: R) -> anyhow::Result<()> {
+        let request_id = request_id.into();
+
+        if self
+            .replies
+            .insert(request_id.clone(), (reply, now_ms()))
+            .is_some()
+        {
+            self.order.retain(|id| *id != request_id);
+        }
+        self.order.push_back(request_id);
+        self.evict();
+
+        self.store()
+    }
+
+    /// Return the reply recorded for a request, or run `handle` and record its reply.
+    ///
+    /// Only successful replies are recorded, so failed requests can be retried.
+    pub async fn run<F, Fut>(&mut self, request_id: &str, handle: F) -> anyhow::Result<R>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<R>>,
+    {
+        if let Some(reply) = self.get(request_id) {
+            return Ok(reply);
+        }
+
+        let reply = handle().await?;
+        self.record(request_id, reply.clone()).await?;
+        Ok(reply)
+    }
+
+    /// Drop replies past the TTL or over the capacity, oldest first.
+    fn evict(&mut self) {
+        let expired_before = now_ms().saturating_sub(self.ttl.as_millis() as u64);
+
+        while let Some(oldest) = self.order.front() {
+            let expired = self
+                .replies
+                .get(oldest)
+                .is_none_or(|(_, recorded)| *recorded < expired_before);
+            if !expired && self.order.len() <= self.capacity {
+                break;
+            }
+
+            if let Some(oldest) = self.order.pop_front() {
+                self.replies.remove(&oldest);
+            }
+        }
+    }
+
+    fn store(&self) -> anyhow::Result<()> {
+        let stored = self
+            .order
+            .iter()
+            .filter_map(|id| {
+                let (reply, recorded) = self.replies.get(id)?;
+                Some((id, recorded, reply))
+            })
+            .collect::<Vec<_>>();
+
+        let backend = storage::backend(&self.persistence_key)?;
+        backend.write_file(
+            &self.persistence_key,
+            DEDUP_TMP_FILE,
+            &postcard::to_stdvec(&stored)?,
+        )?;
+        backend.rename_file(&self.persistence_key, DEDUP_TMP_FILE, DEDUP_FILE)
+    }
+}
+
+fn now_ms() -> u64 {
+    clock::clock()
+        .system_time()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
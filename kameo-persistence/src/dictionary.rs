@@ -0,0 +1,206 @@
+use std::{
+    collections::HashMap,
+    io::Read,
+    sync::{Arc, LazyLock, RwLock},
+};
+
+use anyhow::anyhow;
+#[cfg(feature = "tracing")]
+use tracing::debug;
+use url::Url;
+use zstd::{
+    bulk::Compressor,
+    dict::{DecoderDictionary, EncoderDictionary},
+    stream::Decoder,
+    zstd_safe,
+};
+
+#[cfg(feature = "tracing")]
+use crate::redact::redacted;
+use crate::storage;
+
+/// Largest dictionary trained by default, as by the `zstd` command line.
+pub const DEFAULT_MAX_SIZE: usize = 112_640;
+
+/// Prefix of the files holding dictionaries under a root.
+const DICT_PREFIX: &str = "dict-";
+
+/// Suffix of a dictionary being written, until it replaces the file.
+const TMP_SUFFIX: &str = ".tmp";
+
+/// Compression level, as for files compressed without a dictionary.
+const LEVEL: i32 = 0;
+
+struct Dictionary {
+    encoder: EncoderDictionary<'static>,
+    decoder: DecoderDictionary<'static>,
+}
+
+// Dictionaries to decompress files with, by id
+static DICTIONARIES: LazyLock<RwLock<HashMap<u32, Arc<Dictionary>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+// Dictionary files are compressed with, if any
+static ACTIVE: RwLock<Option<Arc<Dictionary>>> = RwLock::new(None);
+
+/// Train a zstd dictionary of up to `max_size` bytes on the snapshots stored under `root`,
+/// spread evenly over them if there are more than `max_samples`.
+///
+/// Dictionaries pay off for many small, similar snapshots, which zstd alone compresses
+/// poorly. Store the result with `store_dictionary` before compressing with it, as files
+/// compressed with a dictionary cannot be read without it.
+pub async fn train(root: &Url, max_samples: usize, max_size: usize) -> anyhow::Result<Vec<u8>> {
+    let keys = storage::list(root).await?;
+    let step = keys.len().div_ceil(max_samples.max(1)).max(1);
+
+    let mut samples = Vec::new();
+    for key in keys.iter().step_by(step) {
+        if let Some(data) = storage::backend(key)?.read_file(key, storage::snapshot_file())? {
+            samples.push(data);
+        }
+    }
+
+    if samples.is_empty() {
+        anyhow::bail!("No snapshots to train a dictionary on");
+    }
+
+    let dictionary = zstd::dict::from_samples(&samples, max_size).map_err(|e| {
+        anyhow!(
+            "Failed to train a dictionary on {} snapshots: {e}",
+            samples.len()
+        )
+    })?;
+
+    #[cfg(feature = "tracing")]
+    debug!(
+        "Trained a dictionary of {} bytes on {} snapshots under {}",
+        dictionary.len(),
+        samples.len(),
+        redacted(root)
+    );
+
+    Ok(dictionary)
+}
+
+/// Id of a dictionary, as recorded in the files compressed with it.
+pub fn dictionary_id(dictionary: &[u8]) -> anyhow::Result<u32> {
+    zstd_safe::get_dict_id_from_dict(dictionary)
+        .map(|id| id.get())
+        .ok_or_else(|| anyhow!("Not a zstd dictionary"))
+}
+
+/// Make a dictionary available to decompress files with, returning its id.
+pub fn add_dictionary(dictionary: &[u8]) -> anyhow::Result<u32> {
+    let id = dictionary_id(dictionary)?;
+    let dictionary = Arc::new(Dictionary {
+        encoder: EncoderDictionary::copy(dictionary, LEVEL),
+        decoder: DecoderDictionary::copy(dictionary),
+    });
+
+    DICTIONARIES
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(id, dictionary);
+    Ok(id)
+}
+
+/// Compress the files of keys with `compress=zstd` with the dictionary `id`, added with
+/// `add_dictionary`, or without one with `None`.
+///
+/// Files are decompressed with whichever dictionary they were compressed with, so switching
+/// dictionaries keeps older files readable as long as their dictionary stays added.
+pub fn use_dictionary(id: Option<u32>) -> anyhow::Result<()> {
+    let dictionary = match id {
+        Some(id) => Some(
+            DICTIONARIES
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(&id)
+                .cloned()
+                .ok_or_else(|| anyhow!("Dictionary {id} is not added"))?,
+        ),
+        None => None,
+    };
+
+    *ACTIVE.write().unwrap_or_else(|e| e.into_inner()) = dictionary;
+    Ok(())
+}
+
+/// Store a dictionary under `root`, returning its id; `load_dictionaries` adds it back.
+///
+/// Dictionaries are stored uncompressed whatever the options of `root`, as they are needed to
+/// decompress.
+pub fn store_dictionary(root: &Url, dictionary: &[u8]) -> anyhow::Result<u32> {
+    let id = dictionary_id(dictionary)?;
+    let root = plain(root);
+    let file = format!("{DICT_PREFIX}{id:08x}");
+    let tmp = format!("{file}{TMP_SUFFIX}");
+
+    let backend = storage::backend(&root)?;
+    backend.write_file(&root, &tmp, dictionary)?;
+    backend.rename_file(&root, &tmp, &file)?;
+
+    #[cfg(feature = "tracing")]
+    debug!("Stored dictionary {id} under {}", redacted(&root));
+
+    Ok(id)
+}
+
+/// Add every dictionary stored under `root` by `store_dictionary`, returning their ids.
+pub fn load_dictionaries(root: &Url) -> anyhow::Result<Vec<u32>> {
+    let root = plain(root);
+    let backend = storage::backend(&root)?;
+
+    let mut ids = Vec::new();
+    for file in backend.list_files(&root)? {
+        if !file.starts_with(DICT_PREFIX) || file.ends_with(TMP_SUFFIX) {
+            continue;
+        }
+
+        if let Some(data) = backend.read_file(&root, &file)? {
+            ids.push(add_dictionary(&data)?);
+        }
+    }
+
+    Ok(ids)
+}
+
+/// `root` without the options of its query string.
+fn plain(root: &Url) -> Url {
+    let mut root = root.clone();
+    root.set_query(None);
+    root
+}
+
+/// Compress a file with the dictionary in use, if any.
+pub(crate) fn compress(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let active = ACTIVE.read().unwrap_or_else(|e| e.into_inner()).clone();
+    match active {
+        Some(dictionary) => {
+            Ok(Compressor::with_prepared_dictionary(&dictionary.encoder)?.compress(data)?)
+        }
+        None => Ok(zstd::encode_all(data, LEVEL)?),
+    }
+}
+
+/// Decompress a file with the dictionary it was compressed with, if any.
+pub(crate) fn decompress(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let Some(id) = zstd_safe::get_dict_id_from_frame(data) else {
+        return Ok(zstd::decode_all(data)?);
+    };
+
+    let dictionary = DICTIONARIES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&id.get())
+        .cloned()
+        .ok_or_else(|| {
+            anyhow!(
+                "File is compressed with dictionary {id}, which is not added; see dictionary::load_dictionaries"
+            )
+        })?;
+
+    let mut decompressed = Vec::new();
+    Decoder::with_prepared_dictionary(data, &dictionary.decoder)?.read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
@@ -0,0 +1,182 @@
+use std::{fmt, time::SystemTime};
+
+use anyhow::anyhow;
+use serde::Serialize;
+use serde_json::Value;
+use url::Url;
+
+use crate::{
+    history, json,
+    redact::redacted,
+    storage::{self, PREVIOUS_FILE},
+};
+
+/// Stored version of a snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotVersion {
+    /// The snapshot stored now.
+    Current,
+    /// The snapshot it replaced, kept with `storage::set_keep_previous`.
+    Previous,
+    /// The snapshot kept in the history of the key which was in effect at the moment, see
+    /// `history::set_history`.
+    At(SystemTime),
+}
+
+/// Change of a field between two versions of a snapshot.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Change {
+    Added {
+        path: String,
+        new: Value,
+    },
+    Removed {
+        path: String,
+        old: Value,
+    },
+    Changed {
+        path: String,
+        old: Value,
+        new: Value,
+    },
+}
+
+impl Change {
+    /// JSON pointer of the field, such as `/orders/2/status`; empty for the whole snapshot.
+    pub fn path(&self) -> &str {
+        match self {
+            Change::Added { path, .. }
+            | Change::Removed { path, .. }
+            | Change::Changed { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { path, new } => write!(f, "+ {path}: {new}"),
+            Change::Removed { path, old } => write!(f, "- {path}: {old}"),
+            Change::Changed { path, old, new } => write!(f, "~ {path}: {old} -> {new}"),
+        }
+    }
+}
+
+/// Field-level differences between two stored versions of the snapshot under a key, from `v1`
+/// to `v2`, by path.
+///
+/// Both versions are decoded to JSON, so their actor type must be registered in this process.
+/// Object fields are compared by name and array items by position.
+pub async fn diff_snapshots(
+    persistence_key: &Url,
+    v1: SnapshotVersion,
+    v2: SnapshotVersion,
+) -> anyhow::Result<Vec<Change>> {
+    let old = load(persistence_key, v1).await?;
+    let new = load(persistence_key, v2).await?;
+
+    if old.type_tag != new.type_tag {
+        anyhow::bail!(
+            "Versions of {} belong to different actor types, {} and {}",
+            redacted(persistence_key),
+            old.type_tag,
+            new.type_tag
+        );
+    }
+
+    Ok(diff(&old.snapshot, &new.snapshot))
+}
+
+/// Field-level differences between two JSON values, as `diff_snapshots` computes them.
+pub fn diff(old: &Value, new: &Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_values(&mut String::new(), old, new, &mut changes);
+    changes
+}
+
+async fn load(
+    persistence_key: &Url,
+    version: SnapshotVersion,
+) -> anyhow::Result<json::JsonSnapshot> {
+    let data = match version {
+        SnapshotVersion::Current => storage::backend(persistence_key)?
+            .read_file(persistence_key, storage::snapshot_file())?,
+        SnapshotVersion::Previous => {
+            storage::backend(persistence_key)?.read_file(persistence_key, PREVIOUS_FILE)?
+        }
+        SnapshotVersion::At(at) => history::state_at(persistence_key, at)
+            .await?
+            .map(|past| past.snapshot),
+    };
+
+    let data = data.ok_or_else(|| {
+        anyhow!(
+            "No {version:?} snapshot stored under {}",
+            redacted(persistence_key)
+        )
+    })?;
+    json::export_bytes(persistence_key, &data)
+}
+
+fn diff_values(path: &mut String, old: &Value, new: &Value, changes: &mut Vec<Change>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (name, old_value) in old {
+                let len = push_segment(path, name);
+                match new.get(name) {
+                    Some(new_value) => diff_values(path, old_value, new_value, changes),
+                    None => changes.push(Change::Removed {
+                        path: path.clone(),
+                        old: old_value.clone(),
+                    }),
+                }
+                path.truncate(len);
+            }
+            for (name, new_value) in new {
+                if !old.contains_key(name) {
+                    let len = push_segment(path, name);
+                    changes.push(Change::Added {
+                        path: path.clone(),
+                        new: new_value.clone(),
+                    });
+                    path.truncate(len);
+                }
+            }
+        }
+        (Value::Array(old), Value::Array(new)) => {
+            for index in 0..old.len().max(new.len()) {
+                let len = push_segment(path, &index.to_string());
+                match (old.get(index), new.get(index)) {
+                    (Some(old_value), Some(new_value)) => {
+                        diff_values(path, old_value, new_value, changes)
+                    }
+                    (Some(old_value), None) => changes.push(Change::Removed {
+                        path: path.clone(),
+                        old: old_value.clone(),
+                    }),
+                    (None, Some(new_value)) => changes.push(Change::Added {
+                        path: path.clone(),
+                        new: new_value.clone(),
+                    }),
+                    (None, None) => {}
+                }
+                path.truncate(len);
+            }
+        }
+        (old, new) if old != new => changes.push(Change::Changed {
+            path: path.clone(),
+            old: old.clone(),
+            new: new.clone(),
+        }),
+        _ => {}
+    }
+}
+
+/// Append a JSON pointer segment to `path`, returning the length to truncate back to.
+fn push_segment(path: &mut String, segment: &str) -> usize {
+    let len = path.len();
+    path.push('/');
+    path.push_str(&segment.replace('~', "~0").replace('/', "~1"));
+    len
+}
@@ -0,0 +1,128 @@
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{LazyLock, RwLock},
+};
+
+#[cfg(feature = "tracing")]
+use tracing::warn;
+use url::Url;
+
+use crate::{PersistenceError, redact::redacted, tenant::push_segments};
+
+/// Environment variable naming the environment of the process, such as `prod`.
+pub const ENVIRONMENT_VAR: &str = "KAMEO_PERSISTENCE_ENV";
+
+/// Deployment environment whose name prefixes the keys built by `key` and `root`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Environment {
+    Dev,
+    Staging,
+    Prod,
+}
+
+impl Environment {
+    pub const ALL: [Environment; 3] = [Environment::Dev, Environment::Staging, Environment::Prod];
+
+    /// Path segment prefixing the keys of the environment.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Environment::Dev => "dev",
+            Environment::Staging => "staging",
+            Environment::Prod => "prod",
+        }
+    }
+}
+
+impl fmt::Display for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Environment {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "dev" | "development" | "local" => Ok(Environment::Dev),
+            "staging" | "stage" => Ok(Environment::Staging),
+            "prod" | "production" => Ok(Environment::Prod),
+            _ => anyhow::bail!("Unknown environment: {s:?}"),
+        }
+    }
+}
+
+// Set by `set_environment`, or else read from `ENVIRONMENT_VAR` on first use
+static ENVIRONMENT: LazyLock<RwLock<Option<Environment>>> =
+    LazyLock::new(|| RwLock::new(from_var()));
+
+fn from_var() -> Option<Environment> {
+    let value = std::env::var(ENVIRONMENT_VAR).ok()?;
+    value
+        .parse()
+        .inspect_err(|_e| {
+            #[cfg(feature = "tracing")]
+            warn!("Ignoring {ENVIRONMENT_VAR}: {_e}");
+        })
+        .ok()
+}
+
+/// Set the environment of the process, overriding `ENVIRONMENT_VAR`.
+pub fn set_environment(environment: Environment) {
+    *ENVIRONMENT.write().unwrap_or_else(|e| e.into_inner()) = Some(environment);
+}
+
+/// Environment of the process, `Dev` unless set with `set_environment` or `ENVIRONMENT_VAR`.
+pub fn environment() -> Environment {
+    configured().unwrap_or(Environment::Dev)
+}
+
+fn configured() -> Option<Environment> {
+    *ENVIRONMENT.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Root of the current environment below `base`, such as `file:///data/prod`.
+pub fn root(base: &Url) -> anyhow::Result<Url> {
+    let mut root = base.clone();
+    push_segments(&mut root, environment().as_str())?;
+    Ok(root)
+}
+
+/// Persistence key at `path` below the root of the current environment, such as
+/// `file:///data/prod/accounts/1` for `key(&base, "accounts/1")` in `Prod`.
+pub fn key(base: &Url, path: &str) -> anyhow::Result<Url> {
+    let mut key = root(base)?;
+    push_segments(&mut key, path)?;
+    Ok(key)
+}
+
+/// Refuse keys of another environment once the environment of the process is configured.
+///
+/// The first path segment naming an environment decides which one a key belongs to; keys
+/// without one are not checked.
+pub(crate) fn check(persistence_key: &Url) -> anyhow::Result<()> {
+    let Some(current) = configured() else {
+        return Ok(());
+    };
+
+    let found = persistence_key
+        .path_segments()
+        .into_iter()
+        .flatten()
+        .find_map(|segment| {
+            Environment::ALL
+                .into_iter()
+                .find(|environment| environment.as_str() == segment)
+        });
+
+    match found {
+        Some(environment) if environment != current => Err(PersistenceError::WrongEnvironment {
+            key: redacted(persistence_key).to_string(),
+            environment: environment.to_string(),
+            current: current.to_string(),
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
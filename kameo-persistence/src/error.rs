@@ -0,0 +1,115 @@
+use std::fmt;
+
+/// Errors raised by the persistence layer which callers may want to handle specifically.
+///
+/// Returned inside `anyhow::Error`; use `downcast_ref::<PersistenceError>()` to match on them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PersistenceError {
+    /// The snapshot was written by a different actor type than the one restoring it.
+    TypeMismatch { expected: String, found: String },
+    /// The key of the data subject the snapshot was encrypted for has been destroyed.
+    SubjectShredded { subject: String },
+    /// The encoded snapshot exceeds the size limit of its actor type.
+    SnapshotTooLarge {
+        type_tag: String,
+        size: u64,
+        limit: u64,
+    },
+    /// The write would take the tenant's storage usage over its quota.
+    QuotaExceeded {
+        tenant: String,
+        usage: u64,
+        quota: u64,
+    },
+    /// The snapshot was written with a newer schema version than the actor type supports.
+    SchemaTooNew {
+        type_tag: String,
+        version: u32,
+        current: u32,
+    },
+    /// Another writer replaced the snapshot since this process last read or wrote it.
+    Conflict { type_tag: String, key: String },
+    /// The stored payload does not match the checksum in its header.
+    ChecksumMismatch { expected: u32, found: u32 },
+    /// Another holder's lease of the key has not expired yet.
+    LeaseHeld { key: String, holder: String },
+    /// The lease this process wrote the key under was lost to a larger fencing token.
+    Fenced {
+        key: String,
+        token: u64,
+        current: u64,
+    },
+    /// The key belongs to another environment than the one of the process.
+    WrongEnvironment {
+        key: String,
+        environment: String,
+        current: String,
+    },
+}
+
+impl fmt::Display for PersistenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TypeMismatch { expected, found } => write!(
+                f,
+                "snapshot type mismatch: expected {expected}, found {found}"
+            ),
+            Self::SubjectShredded { subject } => {
+                write!(f, "snapshot data subject {subject} has been shredded")
+            }
+            Self::SnapshotTooLarge {
+                type_tag,
+                size,
+                limit,
+            } => write!(
+                f,
+                "snapshot of {type_tag} is {size} bytes, over the limit of {limit} bytes"
+            ),
+            Self::QuotaExceeded {
+                tenant,
+                usage,
+                quota,
+            } => write!(
+                f,
+                "tenant {tenant} would use {usage} bytes, over its quota of {quota} bytes"
+            ),
+            Self::SchemaTooNew {
+                type_tag,
+                version,
+                current,
+            } => write!(
+                f,
+                "snapshot of {type_tag} has schema version {version}, newer than the current {current}"
+            ),
+            Self::Conflict { type_tag, key } => write!(
+                f,
+                "snapshot of {type_tag} under {key} was replaced by another writer"
+            ),
+            Self::ChecksumMismatch { expected, found } => write!(
+                f,
+                "snapshot checksum mismatch: expected {expected:08x}, found {found:08x}"
+            ),
+            Self::LeaseHeld { key, holder } => {
+                write!(f, "lease of {key} is held by {holder}")
+            }
+            Self::Fenced {
+                key,
+                token,
+                current,
+            } => write!(
+                f,
+                "lease of {key} with token {token} was lost, the current token is {current}"
+            ),
+            Self::WrongEnvironment {
+                key,
+                environment,
+                current,
+            } => write!(
+                f,
+                "{key} belongs to the {environment} environment, not to {current}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PersistenceError {}
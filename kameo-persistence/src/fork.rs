@@ -0,0 +1,90 @@
+use kameo::prelude::*;
+#[cfg(feature = "tracing")]
+use tracing::debug;
+use url::Url;
+
+use crate::{PersistentActor, RestoreContext, codec, redact::redacted, storage};
+
+/// Marks a key designated as a template with `set_template`.
+pub const TEMPLATE_FILE: &str = "template";
+
+/// Copy the latest saved snapshot of `src_key` to `dst_key` and spawn an independent actor from
+/// it there, such as for what-if simulations or experiments on a copy of real data.
+///
+/// The snapshot is decoded and written again for `dst_key`, so it is encrypted for the tenant of
+/// the new key. Unsaved changes of a live source actor are not copied, and keys of persistent
+/// children are shared rather than forked. Fails if `dst_key` already holds a snapshot.
+pub async fn fork_persistent<A: PersistentActor>(
+    src_key: &Url,
+    dst_key: Url,
+) -> anyhow::Result<ActorRef<A>> {
+    let data = A::try_read(src_key).await?;
+    let snapshot = codec::decode_at::<A>(src_key, &data)?;
+
+    #[cfg(feature = "tracing")]
+    debug!("Forking {} into {}", redacted(src_key), redacted(&dst_key));
+
+    spawn_copy::<A>(dst_key, snapshot).await
+}
+
+/// Designate a key as a template for `spawn_from_template`, or no longer.
+///
+/// The template's snapshot is saved like any other, e.g. by spawning an actor under the key,
+/// saving it and stopping it.
+pub fn set_template(persistence_key: &Url, template: bool) -> anyhow::Result<()> {
+    let backend = storage::backend(persistence_key)?;
+    if template {
+        backend.write_file(persistence_key, TEMPLATE_FILE, &[])
+    } else {
+        backend.remove_file(persistence_key, TEMPLATE_FILE)
+    }
+}
+
+pub fn is_template(persistence_key: &Url) -> anyhow::Result<bool> {
+    Ok(storage::backend(persistence_key)?
+        .file_size(persistence_key, TEMPLATE_FILE)?
+        .is_some())
+}
+
+/// Spawn a new actor under `new_key` from the snapshot of the template `template_key`, changed
+/// by `overrides` first, such as to set its name or owner.
+///
+/// Fails if `template_key` was not designated with `set_template`, or if `new_key` already
+/// holds a snapshot.
+pub async fn spawn_from_template<A: PersistentActor>(
+    template_key: &Url,
+    new_key: Url,
+    overrides: impl FnOnce(&mut A::Snapshot),
+) -> anyhow::Result<ActorRef<A>> {
+    anyhow::ensure!(
+        is_template(template_key)?,
+        "{} is not a template",
+        redacted(template_key)
+    );
+
+    let data = A::try_read(template_key).await?;
+    let mut snapshot = codec::decode_at::<A>(template_key, &data)?;
+    overrides(&mut snapshot);
+
+    spawn_copy::<A>(new_key, snapshot).await
+}
+
+/// Write `snapshot` under a new key and spawn the actor from it.
+async fn spawn_copy<A: PersistentActor>(
+    dst_key: Url,
+    snapshot: A::Snapshot,
+) -> anyhow::Result<ActorRef<A>> {
+    anyhow::ensure!(
+        A::lookup_persistent(&dst_key).is_none()
+            && storage::backend(&dst_key)?
+                .file_size(&dst_key, storage::snapshot_file())?
+                .is_none(),
+        "{} already holds a persistent actor",
+        redacted(&dst_key)
+    );
+
+    A::try_write(&dst_key, snapshot.clone()).await?;
+
+    let args = A::restore_args(snapshot, &RestoreContext::global())?;
+    A::spawn_persistent(dst_key, args).await
+}
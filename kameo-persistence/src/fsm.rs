@@ -0,0 +1,212 @@
+use std::marker::PhantomData;
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+#[cfg(feature = "tracing")]
+use tracing::debug;
+use url::Url;
+
+use crate::{redact::redacted, storage};
+
+/// Prefix of the journaled events, followed by their zero-padded sequence number.
+const EVENT_PREFIX: &str = "event-";
+
+/// Event being written, until it is renamed into the journal.
+const EVENT_TMP_FILE: &str = "event.tmp";
+
+#[derive(Serialize, Deserialize)]
+struct FsmSnapshot<S> {
+    state: S,
+    /// Number of events applied to reach `state`.
+    seq: u64,
+}
+
+/// Finite state machine whose transitions are journaled under a key and replayed on open.
+///
+/// Every event accepted by the transition function is written to the journal before the new
+/// state is returned, so a protocol or session actor keeping its state here loses no transition
+/// on crash. The state is snapshotted every `snapshot_every` events, after which the events it
+/// covers are removed from the journal, keeping replays short.
+pub struct PersistentFsm<S, E> {
+    persistence_key: Url,
+    state: S,
+    seq: u64,
+    snapshot_seq: u64,
+    snapshot_every: u64,
+    transition: fn(&S, &E) -> Option<S>,
+    _event: PhantomData<fn(E)>,
+}
+
+impl<S, E> PersistentFsm<S, E>
+where
+    S: Serialize + DeserializeOwned,
+    E: Serialize + DeserializeOwned,
+{
+    /// Open the state machine stored under `persistence_key`, replaying its journal, or start
+    /// it in `initial` if nothing is stored.
+    ///
+    /// `transition` returns the state an event leads to, or `None` if the event is not allowed
+    /// in the current state.
+    pub async fn open(
+        persistence_key: Url,
+        initial: S,
+        transition: fn(&S, &E) -> Option<S>,
+    ) -> anyhow::Result<Self> {
+        let backend = storage::backend(&persistence_key)?;
+
+        let (state, seq) = match backend.read_file(&persistence_key, storage::snapshot_file())? {
+            Some(data) => {
+                let snapshot: FsmSnapshot<S> = postcard::from_bytes(&data)?;
+                (snapshot.state, snapshot.seq)
+            }
+            None => (initial, 0),
+        };
+
+        let mut fsm = Self {
+            persistence_key,
+            state,
+            seq,
+            snapshot_seq: seq,
+            snapshot_every: 100,
+            transition,
+            _event: PhantomData,
+        };
+
+        for (event_seq, name) in fsm.journal()? {
+            if event_seq <= fsm.seq {
+                continue;
+            }
+            if event_seq != fsm.seq + 1 {
+                anyhow::bail!(
+                    "Journal of {} is missing event {}",
+                    redacted(&fsm.persistence_key),
+                    fsm.seq + 1
+                );
+            }
+
+            let data = backend
+                .read_file(&fsm.persistence_key, &name)?
+                .ok_or_else(|| anyhow!("Event {name} vanished while replaying"))?;
+            let event: E = postcard::from_bytes(&data)?;
+
+            fsm.state = (fsm.transition)(&fsm.state, &event).ok_or_else(|| {
+                anyhow!(
+                    "Journaled event {event_seq} of {} is not allowed in its state",
+                    redacted(&fsm.persistence_key)
+                )
+            })?;
+            fsm.seq = event_seq;
+        }
+
+        #[cfg(feature = "tracing")]
+        debug!(
+            "Opened state machine {} at event {}, {} replayed",
+            redacted(&fsm.persistence_key),
+            fsm.seq,
+            fsm.seq - fsm.snapshot_seq
+        );
+
+        Ok(fsm)
+    }
+
+    /// Snapshot the state every `events` events, instead of every 100.
+    pub fn snapshot_every(mut self, events: u64) -> Self {
+        self.snapshot_every = events.max(1);
+        self
+    }
+
+    /// Current state.
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    /// Number of events applied since the state machine was started.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Apply an event, journaling it before returning the new state.
+    ///
+    /// Fails without changing the state if the event is not allowed in the current state.
+    pub async fn fire(&mut self, event: E) -> anyhow::Result<&S> {
+        let state = (self.transition)(&self.state, &event).ok_or_else(|| {
+            anyhow!(
+                "Event not allowed in the current state of {}",
+                redacted(&self.persistence_key)
+            )
+        })?;
+
+        let backend = storage::backend(&self.persistence_key)?;
+        backend.write_file(
+            &self.persistence_key,
+            EVENT_TMP_FILE,
+            &postcard::to_stdvec(&event)?,
+        )?;
+        backend.rename_file(
+            &self.persistence_key,
+            EVENT_TMP_FILE,
+            &event_file(self.seq + 1),
+        )?;
+
+        self.state = state;
+        self.seq += 1;
+
+        if self.seq - self.snapshot_seq >= self.snapshot_every {
+            self.snapshot().await?;
+        }
+
+        Ok(&self.state)
+    }
+
+    /// Events journaled since the last snapshot, in order.
+    pub fn events(&self) -> anyhow::Result<Vec<E>> {
+        let backend = storage::backend(&self.persistence_key)?;
+
+        let mut events = Vec::new();
+        for (_, name) in self.journal()? {
+            if let Some(data) = backend.read_file(&self.persistence_key, &name)? {
+                events.push(postcard::from_bytes(&data)?);
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Snapshot the current state and remove the journaled events it covers.
+    pub async fn snapshot(&mut self) -> anyhow::Result<()> {
+        let snapshot = FsmSnapshot {
+            state: &self.state,
+            seq: self.seq,
+        };
+        storage::write_atomic(&self.persistence_key, &postcard::to_stdvec(&snapshot)?).await?;
+        self.snapshot_seq = self.seq;
+
+        let backend = storage::backend(&self.persistence_key)?;
+        for (event_seq, name) in self.journal()? {
+            if event_seq <= self.seq {
+                backend.remove_file(&self.persistence_key, &name)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sequence numbers and file names of the journaled events, in order.
+    fn journal(&self) -> anyhow::Result<Vec<(u64, String)>> {
+        let mut journal = storage::backend(&self.persistence_key)?
+            .list_files(&self.persistence_key)?
+            .into_iter()
+            .filter_map(|name| {
+                let seq = name.strip_prefix(EVENT_PREFIX)?.parse().ok()?;
+                Some((seq, name))
+            })
+            .collect::<Vec<_>>();
+
+        journal.sort_unstable();
+        Ok(journal)
+    }
+}
+
+fn event_file(seq: u64) -> String {
+    format!("{EVENT_PREFIX}{seq:020}")
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    time::{Duration, UNIX_EPOCH},
+};
+
+#[cfg(feature = "tracing")]
+use tracing::{debug, info};
+use url::Url;
+
+#[cfg(feature = "tracing")]
+use crate::redact::redacted;
+use crate::{
+    clock,
+    hierarchy::{self, CHILDREN_FILE, ChildManifest},
+    registry, storage,
+};
+
+/// Outcome of a garbage collection pass.
+#[derive(Debug, Clone, Default)]
+pub struct GcReport {
+    /// Persistence keys found under the root.
+    pub scanned: Vec<Url>,
+    /// Keys no longer referenced by their parent for the minimum age nor alive in this process.
+    pub orphans: Vec<Url>,
+    /// Orphans whose storage was deleted.
+    pub deleted: Vec<Url>,
+}
+
+/// Time a child must have been left out of its parent's snapshots before it is collected, by
+/// default.
+pub const DEFAULT_MIN_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Find, and unless `dry_run` delete, orphaned snapshots under `root`, once unreferenced for
+/// `DEFAULT_MIN_AGE`.
+pub async fn collect_garbage(root: &Url, dry_run: bool) -> anyhow::Result<GcReport> {
+    collect_garbage_with(root, DEFAULT_MIN_AGE, dry_run).await
+}
+
+/// Find, and unless `dry_run` delete, orphaned snapshots under `root`.
+///
+/// A key is orphaned when it was derived with `PersistenceKeyExt::child`, the snapshots of its
+/// parent saved for at least `min_age` no longer reference it, and no live actor of this
+/// process holds it. Descendants of orphans are orphaned as well. Keys which never were
+/// recorded as a child are roots and always kept, as are children derived since their parent's
+/// latest snapshot.
+///
+/// Actors of other processes are not seen, so `min_age` must exceed the time a parent may run
+/// holding a child without saving, for instance while the child is handed over between
+/// processes.
+pub async fn collect_garbage_with(
+    root: &Url,
+    min_age: Duration,
+    dry_run: bool,
+) -> anyhow::Result<GcReport> {
+    let backend = storage::backend(root)?;
+
+    let mut manifests = BTreeMap::new();
+    let mut report = GcReport::default();
+
+    for key in backend.list(root)? {
+        if backend.file_size(&key, storage::snapshot_file())?.is_some() {
+            report.scanned.push(key.clone());
+        }
+        if backend.file_size(&key, CHILDREN_FILE)?.is_some() {
+            manifests.insert(key.clone(), hierarchy::read_manifest(&key)?);
+        }
+    }
+
+    let cutoff = clock::clock()
+        .system_time()
+        .checked_sub(min_age)
+        .and_then(|cutoff| cutoff.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |cutoff| cutoff.as_millis() as u64);
+    let orphans = find_orphans(&manifests, cutoff);
+
+    for (parent_key, key) in orphans {
+        report.orphans.push(key.clone());
+
+        if dry_run {
+            continue;
+        }
+
+        storage::delete(&key).await?;
+        // The parent may have been deleted as an orphan itself
+        if backend.exists(&parent_key)? {
+            hierarchy::forget_child(&parent_key, &key)?;
+        }
+
+        #[cfg(feature = "tracing")]
+        debug!("Deleted orphaned snapshot {}", redacted(&key));
+
+        report.deleted.push(key);
+    }
+
+    #[cfg(feature = "tracing")]
+    info!(
+        "Garbage collection under {}: {} keys, {} orphans, {} deleted",
+        redacted(root),
+        report.scanned.len(),
+        report.orphans.len(),
+        report.deleted.len()
+    );
+
+    Ok(report)
+}
+
+/// Return orphaned keys along with their parent key, parents first, for children unreferenced
+/// since `cutoff` milliseconds since the Unix epoch or earlier.
+fn find_orphans(manifests: &BTreeMap<Url, ChildManifest>, cutoff: u64) -> Vec<(Url, Url)> {
+    let mut pending = manifests
+        .iter()
+        .flat_map(|(parent_key, manifest)| {
+            manifest
+                .unreferenced()
+                .filter(|(_, entry)| entry.unreferenced_since_ms <= Some(cutoff))
+                .map(move |(key, _)| (parent_key.clone(), key.clone()))
+        })
+        .collect::<VecDeque<_>>();
+
+    let mut seen = BTreeSet::new();
+    let mut orphans = Vec::new();
+
+    // Breadth first, so every orphan comes before what it owns
+    while let Some((parent_key, key)) = pending.pop_front() {
+        if registry::lookup(&key).is_some() || !seen.insert(key.clone()) {
+            continue;
+        }
+
+        // Whatever an orphan owns is unreachable as well
+        if let Some(manifest) = manifests.get(&key) {
+            pending.extend(
+                manifest
+                    .children
+                    .keys()
+                    .map(|child_key| (key.clone(), child_key.clone())),
+            );
+        }
+
+        orphans.push((parent_key, key));
+    }
+
+    orphans
+}
@@ -0,0 +1,143 @@
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    clock,
+    codec::{self, SnapshotHeader},
+    redact::redacted,
+    storage,
+};
+
+/// Name of the counters inside a persistence key directory.
+pub const COUNTERS_FILE: &str = "counters.bin";
+
+static TRACK_COUNTERS: AtomicBool = AtomicBool::new(false);
+
+// Serializes read-modify-write cycles on counters
+static COUNTERS_LOCK: Mutex<()> = Mutex::new(());
+
+/// Counters of the writes and restores of a key, kept when enabled with `set_counters`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyCounters {
+    /// Snapshots written under the key; writing the snapshot already stored again, as when
+    /// retrying a failed save, does not count.
+    pub generation: u64,
+    /// Times an actor was restored from the key.
+    pub restores: u64,
+    /// Restores since the last snapshot was written; an actor crashing and restoring in a loop
+    /// without ever saving drives it up.
+    pub restores_since_write: u64,
+    /// Milliseconds since the Unix epoch of the last write, zero if none.
+    pub last_written_ms: u64,
+    /// Milliseconds since the Unix epoch of the last restore, zero if none.
+    pub last_restored_ms: u64,
+    /// CRC-32 of the last snapshot counted, zero if none.
+    pub last_written_checksum: u32,
+}
+
+/// Snapshot stored under a key with its header and counters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotMetadata {
+    /// `None` for snapshots written before headers were introduced; holds the custom
+    /// `metadata` attached when the snapshot was saved.
+    pub header: Option<SnapshotHeader>,
+    /// Size of the snapshot in bytes.
+    pub size: usize,
+    pub generation: u64,
+    pub restores: u64,
+    pub restores_since_write: u64,
+    pub last_written: Option<SystemTime>,
+    pub last_restored: Option<SystemTime>,
+}
+
+/// Count the snapshots written under every key and the restores from it, in `COUNTERS_FILE`.
+///
+/// Costs an extra read and write per save and restore. Keys written before counting was
+/// enabled start from zero.
+pub fn set_counters(enabled: bool) {
+    TRACK_COUNTERS.store(enabled, Ordering::Relaxed);
+}
+
+/// Read the counters of a key, all zero if none were kept.
+pub fn counters(persistence_key: &Url) -> anyhow::Result<KeyCounters> {
+    match storage::backend(persistence_key)?.read_file(persistence_key, COUNTERS_FILE)? {
+        Some(data) => Ok(postcard::from_bytes(&data)?),
+        None => Ok(KeyCounters::default()),
+    }
+}
+
+/// Read the header of the snapshot stored under a key, with its write and restore counters.
+pub async fn snapshot_metadata(persistence_key: &Url) -> anyhow::Result<SnapshotMetadata> {
+    let data = storage::backend(persistence_key)?
+        .read_file(persistence_key, storage::snapshot_file())?
+        .ok_or_else(|| anyhow!("No snapshot stored under {}", redacted(persistence_key)))?;
+    let (header, _) = codec::split(&data)?;
+    let counters = counters(persistence_key)?;
+
+    Ok(SnapshotMetadata {
+        header,
+        size: data.len(),
+        generation: counters.generation,
+        restores: counters.restores,
+        restores_since_write: counters.restores_since_write,
+        last_written: time(counters.last_written_ms),
+        last_restored: time(counters.last_restored_ms),
+    })
+}
+
+/// Count a snapshot written under a key, if enabled.
+pub(crate) fn note_write(persistence_key: &Url, data: &[u8]) -> anyhow::Result<()> {
+    let checksum = crc32fast::hash(data);
+    update(persistence_key, |counters, now| {
+        if counters.generation == 0 || counters.last_written_checksum != checksum {
+            counters.generation += 1;
+            counters.last_written_checksum = checksum;
+        }
+        counters.restores_since_write = 0;
+        counters.last_written_ms = now;
+    })
+}
+
+/// Count a restore from a key, if enabled.
+pub(crate) fn note_restore(persistence_key: &Url) -> anyhow::Result<()> {
+    update(persistence_key, |counters, now| {
+        counters.restores += 1;
+        counters.restores_since_write += 1;
+        counters.last_restored_ms = now;
+    })
+}
+
+fn update(persistence_key: &Url, f: impl FnOnce(&mut KeyCounters, u64)) -> anyhow::Result<()> {
+    if !TRACK_COUNTERS.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    let now = clock::clock()
+        .system_time()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0);
+
+    let _guard = COUNTERS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut counters = counters(persistence_key)?;
+    f(&mut counters, now);
+
+    storage::backend(persistence_key)?.write_file(
+        persistence_key,
+        COUNTERS_FILE,
+        &postcard::to_allocvec(&counters)?,
+    )
+}
+
+fn time(ms: u64) -> Option<SystemTime> {
+    (ms > 0).then(|| UNIX_EPOCH + Duration::from_millis(ms))
+}
@@ -0,0 +1,125 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use tokio::task::JoinHandle;
+#[cfg(feature = "tracing")]
+use tracing::warn;
+use url::Url;
+
+#[cfg(feature = "metrics")]
+use crate::metrics;
+#[cfg(feature = "tracing")]
+use crate::redact::redacted;
+use crate::{clock, storage};
+
+/// Outcome of a health check of the backend serving a probe key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackendHealth {
+    /// Scheme of the probe key, selecting the backend.
+    pub scheme: String,
+    pub healthy: bool,
+    /// Time the check took, or the timeout if it did not complete.
+    pub latency: Duration,
+    /// Why the check failed, if it did.
+    pub error: Option<String>,
+    pub checked_at: SystemTime,
+}
+
+/// Check the backend serving `probe_key`, failing the check if it takes longer than `timeout`.
+///
+/// The check runs `Backend::health_check` on the blocking pool, so a hung backend does not
+/// stall the caller past the timeout.
+pub async fn health_check(probe_key: &Url, timeout: Duration) -> BackendHealth {
+    let clock = clock::clock();
+    let started = clock.now();
+    let checked_at = clock.system_time();
+
+    let result = match storage::backend(probe_key) {
+        Ok(backend) => {
+            let key = probe_key.clone();
+            let check = tokio::task::spawn_blocking(move || backend.health_check(&key));
+
+            match clock::timeout_at(started + timeout, check).await {
+                Some(Ok(result)) => result,
+                Some(Err(e)) => Err(anyhow::anyhow!("Health check panicked: {e}")),
+                None => Err(anyhow::anyhow!("Health check timed out after {timeout:?}")),
+            }
+        }
+        Err(e) => Err(e),
+    };
+
+    let health = BackendHealth {
+        scheme: probe_key.scheme().to_string(),
+        healthy: result.is_ok(),
+        latency: clock.now().saturating_duration_since(started).min(timeout),
+        error: result.err().map(|e| format!("{e:#}")),
+        checked_at,
+    };
+
+    #[cfg(feature = "tracing")]
+    if let Some(_e) = &health.error {
+        warn!("Backend of {} is unhealthy: {_e}", redacted(probe_key));
+    }
+
+    #[cfg(feature = "metrics")]
+    metrics::record_health(&health.scheme, health.healthy, health.latency);
+
+    health
+}
+
+/// Handle of a periodic health checker; checking stops when it is dropped.
+pub struct HealthCheckHandle {
+    report: Arc<RwLock<Vec<BackendHealth>>>,
+    task: JoinHandle<()>,
+}
+
+impl HealthCheckHandle {
+    /// Outcome of the latest check of every probe key, empty until the first round completed.
+    pub fn report(&self) -> Vec<BackendHealth> {
+        self.report
+            .read()
+            .map(|report| report.clone())
+            .unwrap_or_default()
+    }
+
+    /// Return true once every backend passed its latest check, for readiness probes.
+    pub fn is_healthy(&self) -> bool {
+        let report = self.report();
+        !report.is_empty() && report.iter().all(|health| health.healthy)
+    }
+}
+
+impl Drop for HealthCheckHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Check the backends serving `probe_keys`, one per scheme to watch, every `interval`.
+///
+/// Probe keys should be dedicated to health checks, as checks write under them.
+pub fn start(probe_keys: Vec<Url>, interval: Duration, timeout: Duration) -> HealthCheckHandle {
+    let report = Arc::new(RwLock::new(Vec::new()));
+
+    let task = tokio::spawn({
+        let report = report.clone();
+        async move {
+            loop {
+                let mut round = Vec::with_capacity(probe_keys.len());
+                for probe_key in &probe_keys {
+                    round.push(health_check(probe_key, timeout).await);
+                }
+                if let Ok(mut report) = report.write() {
+                    *report = round;
+                }
+
+                let clock = clock::clock();
+                clock.sleep_until(clock.now() + interval).await;
+            }
+        }
+    });
+
+    HealthCheckHandle { report, task }
+}
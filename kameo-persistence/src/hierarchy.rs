@@ -0,0 +1,274 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+use anyhow::anyhow;
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{PersistentActor, clock, redact::redacted, registry, storage};
+
+/// Name of the child manifest inside a parent key directory.
+pub const CHILDREN_FILE: &str = "children.bin";
+
+// Serializes read-modify-write cycles on child manifests within the process
+static MANIFEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// Times a manifest update is retried when other processes keep replacing the manifest.
+const MAX_UPDATE_ATTEMPTS: usize = 16;
+
+/// Child derived from a parent persistence key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChildEntry {
+    pub name: String,
+    pub type_tag: String,
+    /// Milliseconds since the Unix epoch of the first snapshot of the parent saved without the
+    /// child since it was last referenced, `None` while referenced or if no snapshot of the
+    /// parent was saved since the child was derived.
+    pub unreferenced_since_ms: Option<u64>,
+}
+
+/// Children derived from a parent key, and the ones its latest snapshot references.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChildManifest {
+    pub children: BTreeMap<Url, ChildEntry>,
+    pub referenced: BTreeSet<Url>,
+}
+
+impl ChildManifest {
+    /// Children left out of a snapshot of the parent saved since they were derived.
+    ///
+    /// Children derived since the parent's latest snapshot are not included, as the parent has
+    /// not had the chance to reference them yet.
+    pub fn unreferenced(&self) -> impl Iterator<Item = (&Url, &ChildEntry)> {
+        self.children
+            .iter()
+            .filter(|(_, entry)| entry.unreferenced_since_ms.is_some())
+    }
+}
+
+/// Derivation of child persistence keys.
+pub trait PersistenceKeyExt {
+    /// Derive the key of a child actor named `name`, and record it in this key's child manifest.
+    fn child<A: PersistentActor>(&self, name: &str) -> anyhow::Result<Url>;
+}
+
+impl PersistenceKeyExt for Url {
+    fn child<A: PersistentActor>(&self, name: &str) -> anyhow::Result<Url> {
+        if name.is_empty() || name == "." || name == ".." {
+            anyhow::bail!("Invalid child name: {name:?}");
+        }
+
+        let mut child_key = self.clone();
+        child_key
+            .path_segments_mut()
+            .map_err(|_| anyhow!("Persistence key cannot be a base: {}", redacted(self)))?
+            .pop_if_empty()
+            .push(name);
+
+        update_manifest(self, |manifest| {
+            let entry = manifest
+                .children
+                .entry(child_key.clone())
+                .or_insert_with(|| ChildEntry {
+                    name: name.to_string(),
+                    type_tag: A::type_tag().to_string(),
+                    unreferenced_since_ms: None,
+                });
+            entry.type_tag = A::type_tag().to_string();
+        })?;
+
+        Ok(child_key)
+    }
+}
+
+/// Read the child manifest of a parent key, empty if none was recorded.
+pub fn read_manifest(parent_key: &Url) -> anyhow::Result<ChildManifest> {
+    match storage::backend(parent_key)?.read_file(parent_key, CHILDREN_FILE)? {
+        Some(bytes) => Ok(postcard::from_bytes(&bytes)?),
+        None => Ok(ChildManifest::default()),
+    }
+}
+
+/// Record the children referenced by the latest snapshot of a parent key.
+pub fn record_references(parent_key: &Url, children: &[Url]) -> anyhow::Result<()> {
+    // Nothing to track for actors which never had children
+    if children.is_empty()
+        && storage::backend(parent_key)?
+            .file_size(parent_key, CHILDREN_FILE)?
+            .is_none()
+    {
+        return Ok(());
+    }
+
+    let now = clock::clock()
+        .system_time()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0);
+
+    update_manifest(parent_key, |manifest| {
+        manifest.referenced = children.iter().cloned().collect();
+        for (key, entry) in &mut manifest.children {
+            entry.unreferenced_since_ms = if manifest.referenced.contains(key) {
+                None
+            } else {
+                entry.unreferenced_since_ms.or(Some(now))
+            };
+        }
+    })
+}
+
+/// Remove a child from the manifest of its parent key, once its storage is gone.
+pub fn forget_child(parent_key: &Url, child_key: &Url) -> anyhow::Result<()> {
+    update_manifest(parent_key, |manifest| {
+        manifest.children.remove(child_key);
+        manifest.referenced.remove(child_key);
+    })
+}
+
+/// Apply `update` to the child manifest of a parent key, writing it only if it changed.
+///
+/// The manifest is replaced only if no other writer replaced it since it was read, as parents
+/// and their children may be saved by different processes; `update` is applied again to the
+/// manifest they wrote otherwise.
+fn update_manifest(parent_key: &Url, update: impl Fn(&mut ChildManifest)) -> anyhow::Result<()> {
+    let _guard = MANIFEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let backend = storage::backend(parent_key)?;
+
+    for _ in 0..MAX_UPDATE_ATTEMPTS {
+        let version = backend.file_version(parent_key, CHILDREN_FILE)?;
+        let current = read_manifest(parent_key)?;
+
+        let mut manifest = current.clone();
+        update(&mut manifest);
+        if manifest == current {
+            return Ok(());
+        }
+
+        let data = postcard::to_stdvec(&manifest)?;
+        if backend
+            .write_file_if(parent_key, CHILDREN_FILE, version.as_deref(), &data)?
+            .is_some()
+        {
+            return Ok(());
+        }
+    }
+
+    Err(anyhow!(
+        "Child manifest of {} kept changing while being updated",
+        redacted(parent_key)
+    ))
+}
+
+pub(crate) fn write_manifest(parent_key: &Url, manifest: &ChildManifest) -> anyhow::Result<()> {
+    storage::backend(parent_key)?.write_file(
+        parent_key,
+        CHILDREN_FILE,
+        &postcard::to_stdvec(manifest)?,
+    )
+}
+
+/// Actor tree restored by `respawn_tree`.
+pub struct RecoveredTree<A: PersistentActor> {
+    /// The root actor.
+    pub root: ActorRef<A>,
+    /// Descendants respawned by the recovery, deepest first.
+    pub restored: Vec<Url>,
+    /// Descendants which could not be respawned.
+    pub failed: Vec<(Url, anyhow::Error)>,
+}
+
+/// Respawn a persistent actor and every descendant referenced by the child manifests under it.
+///
+/// Descendants are respawned deepest first, so a parent respawning its children in `on_start`
+/// finds them already running. Descendant types are resolved by type tag, see
+/// `registry::respawner`. A descendant failing to respawn does not prevent
+/// the rest of the tree from being restored.
+pub async fn respawn_tree<A: PersistentActor>(root_key: Url) -> anyhow::Result<RecoveredTree<A>> {
+    let mut descendants = Vec::new();
+    let mut pending = vec![root_key.clone()];
+    let mut seen = BTreeSet::from([root_key.clone()]);
+
+    while let Some(parent_key) = pending.pop() {
+        let manifest = read_manifest(&parent_key)?;
+
+        for key in &manifest.referenced {
+            let Some(entry) = manifest.children.get(key) else {
+                continue;
+            };
+            if seen.insert(key.clone()) {
+                descendants.push((key.clone(), entry.type_tag.clone()));
+                pending.push(key.clone());
+            }
+        }
+    }
+
+    let mut restored = Vec::new();
+    let mut failed = Vec::new();
+
+    for (key, type_tag) in descendants.into_iter().rev() {
+        if registry::lookup(&key).is_some() {
+            continue;
+        }
+
+        let result = match registry::respawner(&type_tag) {
+            Some(respawn) => respawn(key.clone()).await,
+            None => Err(anyhow!("No respawner registered for actor type {type_tag}")),
+        };
+
+        match result {
+            Ok(()) => restored.push(key),
+            Err(e) => failed.push((key, e)),
+        }
+    }
+
+    let root = A::respawn_persistent(root_key).await?;
+
+    Ok(RecoveredTree {
+        root,
+        restored,
+        failed,
+    })
+}
+
+/// Persistent actor references whose persistence keys can be listed.
+///
+/// Fields marked `#[child]` on a derived `PersistentActor` must implement this trait.
+pub trait PersistenceKeys {
+    /// Return the persistence keys of the persistent actors referenced.
+    fn persistence_keys(&self) -> Vec<Url>;
+}
+
+impl<A: PersistentActor> PersistenceKeys for ActorRef<A> {
+    fn persistence_keys(&self) -> Vec<Url> {
+        A::persistence_key(self).into_iter().collect()
+    }
+}
+
+impl<T: PersistenceKeys> PersistenceKeys for Option<T> {
+    fn persistence_keys(&self) -> Vec<Url> {
+        self.iter().flat_map(T::persistence_keys).collect()
+    }
+}
+
+impl<T: PersistenceKeys> PersistenceKeys for Vec<T> {
+    fn persistence_keys(&self) -> Vec<Url> {
+        self.iter().flat_map(T::persistence_keys).collect()
+    }
+}
+
+impl<K, T: PersistenceKeys, S> PersistenceKeys for HashMap<K, T, S> {
+    fn persistence_keys(&self) -> Vec<Url> {
+        self.values().flat_map(T::persistence_keys).collect()
+    }
+}
+
+impl<K, T: PersistenceKeys> PersistenceKeys for BTreeMap<K, T> {
+    fn persistence_keys(&self) -> Vec<Url> {
+        self.values().flat_map(T::persistence_keys).collect()
+    }
+}
@@ -0,0 +1,288 @@
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::task::JoinHandle;
+#[cfg(feature = "tracing")]
+use tracing::{info, warn};
+use url::Url;
+
+#[cfg(feature = "tracing")]
+use crate::redact::redacted;
+use crate::{
+    clock,
+    recording::{self, RecordedMessage},
+    storage,
+};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Prefix of the kept snapshot versions, followed by the zero-padded time they were written at,
+/// in milliseconds since the Unix epoch.
+const HISTORY_PREFIX: &str = "history-";
+
+static MAX_VERSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Keep the last `max_versions` snapshots written under every key, for `state_at`; 0 keeps
+/// none, which is the default.
+///
+/// Costs an extra write per save, and the storage of the kept versions. Set a generous limit
+/// when thinning the history out with a `HistoryCompactor` instead.
+pub fn set_history(max_versions: usize) {
+    MAX_VERSIONS.store(max_versions, Ordering::Relaxed);
+}
+
+/// State of an actor at a past moment, as returned by `state_at`.
+#[derive(Debug, Clone)]
+pub struct PastState {
+    /// Time the snapshot was written at.
+    pub saved_at: SystemTime,
+    /// Raw bytes of the snapshot in effect at the moment.
+    pub snapshot: Vec<u8>,
+    /// Messages recorded after the snapshot was written, up to the moment, in order.
+    ///
+    /// Replaying them with `recording::Replay` against an actor restored from `snapshot`
+    /// reconstructs the exact state at the moment.
+    pub messages: Vec<RecordedMessage>,
+}
+
+/// Add a snapshot just written under a key to its history, if enabled, dropping the oldest
+/// versions beyond the limit.
+///
+/// A snapshot equal to the last version kept is not added again.
+pub(crate) fn note_write(persistence_key: &Url, data: &[u8]) -> anyhow::Result<()> {
+    let max_versions = MAX_VERSIONS.load(Ordering::Relaxed);
+    if max_versions == 0 {
+        return Ok(());
+    }
+
+    let backend = storage::backend(persistence_key)?;
+    if let Some((_, last)) = history_files(persistence_key)?.last()
+        && backend.read_file(persistence_key, last)?.as_deref() == Some(data)
+    {
+        return Ok(());
+    }
+
+    backend.write_file(
+        persistence_key,
+        &version_file(clock::clock().system_time()),
+        data,
+    )?;
+
+    let versions = history_files(persistence_key)?;
+    for (_, name) in &versions[..versions.len().saturating_sub(max_versions)] {
+        backend.remove_file(persistence_key, name)?;
+    }
+
+    Ok(())
+}
+
+/// Add the snapshot a transaction just moved into place under a key to its history, if enabled.
+pub(crate) fn note_applied(persistence_key: &Url) -> anyhow::Result<()> {
+    if MAX_VERSIONS.load(Ordering::Relaxed) == 0 {
+        return Ok(());
+    }
+
+    match storage::backend(persistence_key)?.read_file(persistence_key, storage::snapshot_file())? {
+        Some(data) => note_write(persistence_key, &data),
+        None => Ok(()),
+    }
+}
+
+/// Times the kept snapshots of a key were written at, oldest first.
+pub fn versions(persistence_key: &Url) -> anyhow::Result<Vec<SystemTime>> {
+    Ok(history_files(persistence_key)?
+        .into_iter()
+        .map(|(at, _)| at)
+        .collect())
+}
+
+/// State of the actor stored under a key at the moment `at`, or `None` if no snapshot kept in
+/// its history was written by then.
+///
+/// Pairs the last snapshot written by then with the messages recorded after it, so the state
+/// is exact for actors being recorded, and as of their last save otherwise.
+pub async fn state_at(persistence_key: &Url, at: SystemTime) -> anyhow::Result<Option<PastState>> {
+    let Some((saved_at, name)) = history_files(persistence_key)?
+        .into_iter()
+        .rfind(|(saved_at, _)| *saved_at <= at)
+    else {
+        return Ok(None);
+    };
+
+    let Some(snapshot) = storage::backend(persistence_key)?.read_file(persistence_key, &name)?
+    else {
+        return Ok(None);
+    };
+
+    let messages = recording::recorded_messages(persistence_key)?
+        .into_iter()
+        .filter(|msg| msg.recorded_at > saved_at && msg.recorded_at <= at)
+        .collect();
+
+    Ok(Some(PastState {
+        saved_at,
+        snapshot,
+        messages,
+    }))
+}
+
+/// Outcome of a history compaction pass.
+#[derive(Debug, Clone, Default)]
+pub struct CompactionReport {
+    /// Keys whose history was checked.
+    pub scanned: Vec<Url>,
+    /// Kept versions removed, by key.
+    pub removed: Vec<(Url, usize)>,
+}
+
+/// Periodic thinning of the kept snapshot versions under a root, such as in object storage,
+/// following per-key retention rather than bucket lifecycle rules, which cannot tell versions
+/// of one key from another's.
+///
+/// Keeps the most recent versions of every key, then the last version of each UTC day for a
+/// number of days, and removes the rest; 10 versions and 30 days by default.
+pub struct HistoryCompactor {
+    root: Url,
+    recent: usize,
+    daily_for: u32,
+}
+
+/// Handle of a periodic compaction; compacting stops when it is dropped.
+pub struct CompactionHandle {
+    task: JoinHandle<()>,
+}
+
+impl Drop for CompactionHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl HistoryCompactor {
+    pub fn new(root: Url) -> Self {
+        Self {
+            root,
+            recent: 10,
+            daily_for: 30,
+        }
+    }
+
+    /// Keep the `recent` most recent versions of every key, whatever their age.
+    pub fn keep_recent(mut self, recent: usize) -> Self {
+        self.recent = recent;
+        self
+    }
+
+    /// Keep the last version of each UTC day of the past `days` days.
+    pub fn keep_daily(mut self, days: u32) -> Self {
+        self.daily_for = days;
+        self
+    }
+
+    /// Compact every `interval` until the returned handle is dropped.
+    pub fn spawn(self, interval: Duration) -> CompactionHandle {
+        let task = tokio::spawn(async move {
+            loop {
+                let clock = clock::clock();
+                clock.sleep_until(clock.now() + interval).await;
+
+                if let Err(_e) = self.compact().await {
+                    #[cfg(feature = "tracing")]
+                    warn!(
+                        "Failed to compact the history of {}: {_e:#}",
+                        redacted(&self.root)
+                    );
+                }
+            }
+        });
+
+        CompactionHandle { task }
+    }
+
+    /// Run a single compaction pass.
+    pub async fn compact(&self) -> anyhow::Result<CompactionReport> {
+        let backend = storage::backend(&self.root)?;
+        let now = clock::clock().system_time();
+        let mut report = CompactionReport::default();
+
+        for key in storage::list(&self.root).await? {
+            let versions = history_files(&key)?;
+            report.scanned.push(key.clone());
+
+            let expired = self.expired(&versions, now);
+            for name in &expired {
+                backend.remove_file(&key, name)?;
+            }
+            if !expired.is_empty() {
+                report.removed.push((key, expired.len()));
+            }
+
+            // Yields between keys, so large roots do not hold up other tasks
+            tokio::task::yield_now().await;
+        }
+
+        #[cfg(feature = "tracing")]
+        info!(
+            "Compacted the history of {}: {} keys, {} versions removed",
+            redacted(&self.root),
+            report.scanned.len(),
+            report
+                .removed
+                .iter()
+                .map(|(_, removed)| removed)
+                .sum::<usize>()
+        );
+
+        Ok(report)
+    }
+
+    /// File names of the versions, oldest first, not retained at `now`.
+    fn expired<'a>(&self, versions: &'a [(SystemTime, String)], now: SystemTime) -> Vec<&'a str> {
+        let recent = versions.len().saturating_sub(self.recent);
+        let horizon = now.checked_sub(DAY * self.daily_for).unwrap_or(UNIX_EPOCH);
+
+        // Last version of each day within the horizon
+        let mut daily = BTreeMap::new();
+        for (index, (at, _)) in versions.iter().enumerate() {
+            if *at >= horizon {
+                let day =
+                    at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / DAY.as_secs();
+                daily.insert(day, index);
+            }
+        }
+        let daily = daily.into_values().collect::<Vec<_>>();
+
+        versions
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index < recent && !daily.contains(index))
+            .map(|(_, (_, name))| name.as_str())
+            .collect()
+    }
+}
+
+/// Write times and file names of the kept snapshots of a key, oldest first.
+fn history_files(persistence_key: &Url) -> anyhow::Result<Vec<(SystemTime, String)>> {
+    let mut files = storage::backend(persistence_key)?
+        .list_files(persistence_key)?
+        .into_iter()
+        .filter_map(|name| {
+            let ms = name.strip_prefix(HISTORY_PREFIX)?.parse().ok()?;
+            Some((UNIX_EPOCH + Duration::from_millis(ms), name))
+        })
+        .collect::<Vec<_>>();
+
+    files.sort_unstable();
+    Ok(files)
+}
+
+fn version_file(at: SystemTime) -> String {
+    let ms = at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    format!("{HISTORY_PREFIX}{ms:020}")
+}
@@ -0,0 +1,113 @@
+use std::{
+    borrow::Cow,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::anyhow;
+
+use crate::codec::SnapshotHeader;
+
+/// Header metadata entry naming the hooks a payload went through, in order.
+pub(crate) const METADATA_KEY: &str = "hooks";
+
+/// Transform of stored bytes, such as proprietary encryption or dedup fingerprinting, run on
+/// every snapshot and part on top of the crate's own encoding.
+///
+/// `before_write` receives the payload as it would be stored, after any data subject
+/// encryption, and `after_read` must undo it. The header stays readable and its checksum covers
+/// the transformed bytes.
+pub trait SnapshotHook: Send + Sync + 'static {
+    /// Name stored in the header of snapshots written through the hook, so they are read back
+    /// through it; ASCII letters, digits, `-` and `_`.
+    fn name(&self) -> &str;
+
+    fn before_write(&self, data: Vec<u8>) -> anyhow::Result<Vec<u8>>;
+
+    fn after_read(&self, data: Vec<u8>) -> anyhow::Result<Vec<u8>>;
+}
+
+static HOOKS: RwLock<Vec<Arc<dyn SnapshotHook>>> = RwLock::new(Vec::new());
+
+/// Install a hook for the whole process, run after the hooks installed before it on write.
+///
+/// Snapshots written before are still read, without the hook; keep a hook installed as long as
+/// snapshots written through it are stored. Fails if the name is invalid or already installed.
+pub fn add_snapshot_hook(hook: impl SnapshotHook) -> anyhow::Result<()> {
+    let name = hook.name();
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        anyhow::bail!("Invalid snapshot hook name: {name:?}");
+    }
+
+    let mut hooks = HOOKS.write().unwrap_or_else(|e| e.into_inner());
+    if hooks.iter().any(|installed| installed.name() == name) {
+        anyhow::bail!("Snapshot hook {name} is already installed");
+    }
+
+    hooks.push(Arc::new(hook));
+    Ok(())
+}
+
+/// Remove every installed hook; snapshots written through them can no longer be read.
+pub fn clear_snapshot_hooks() {
+    HOOKS.write().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+fn hooks() -> Vec<Arc<dyn SnapshotHook>> {
+    HOOKS.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Names of the installed hooks as stored in the header, or `None` if there are none.
+pub(crate) fn names() -> Option<String> {
+    let hooks = hooks();
+    (!hooks.is_empty()).then(|| join_names(&hooks))
+}
+
+fn join_names(hooks: &[Arc<dyn SnapshotHook>]) -> String {
+    hooks
+        .iter()
+        .map(|hook| hook.name())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Run `data` through every installed hook, returning it with the header metadata entry naming
+/// them, if any.
+pub(crate) fn before_write(data: Cow<'_, [u8]>) -> anyhow::Result<(Cow<'_, [u8]>, Option<String>)> {
+    let hooks = hooks();
+    if hooks.is_empty() {
+        return Ok((data, None));
+    }
+
+    let mut data = data.into_owned();
+    for hook in &hooks {
+        data = hook.before_write(data)?;
+    }
+
+    Ok((Cow::Owned(data), Some(join_names(&hooks))))
+}
+
+/// Undo the hooks the header of `data` names, in reverse order.
+pub(crate) fn after_read<'a>(
+    header: &SnapshotHeader,
+    data: Cow<'a, [u8]>,
+) -> anyhow::Result<Cow<'a, [u8]>> {
+    let Some(names) = header.metadata.get(METADATA_KEY) else {
+        return Ok(data);
+    };
+
+    let hooks = hooks();
+    let mut data = data.into_owned();
+    for name in names.split(',').rev() {
+        let hook = hooks
+            .iter()
+            .find(|hook| hook.name() == name)
+            .ok_or_else(|| anyhow!("Snapshot hook {name} is not installed"))?;
+        data = hook.after_read(data)?;
+    }
+
+    Ok(Cow::Owned(data))
+}
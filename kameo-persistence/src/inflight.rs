@@ -0,0 +1,72 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    ops::Deref,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use kameo::prelude::*;
+use tokio::sync::OnceCell;
+use url::Url;
+
+type Cell<A> = OnceCell<ActorRef<A>>;
+
+/// `Cell` of any actor type.
+type AnyCell = Arc<dyn Any + Send + Sync>;
+
+// Respawns in progress, by actor type and key
+static IN_FLIGHT: LazyLock<Mutex<HashMap<(TypeId, Url), AnyCell>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Cell the respawns of a key in progress share, initialized by the first of them.
+///
+/// If the first respawn fails or is dropped, the next waiting respawn tries in turn. The cell is
+/// forgotten once the actor is registered, or once every respawn using it gave up.
+pub(crate) fn cell<A: Actor>(persistence_key: &Url) -> InFlight<A> {
+    let mut in_flight = IN_FLIGHT.lock().unwrap_or_else(|e| e.into_inner());
+    let entry = in_flight
+        .entry((TypeId::of::<A>(), persistence_key.clone()))
+        .or_insert_with(|| Arc::new(Cell::<A>::new()));
+
+    let cell = entry
+        .clone()
+        .downcast()
+        .unwrap_or_else(|_| unreachable!("Cells are keyed by their actor type"));
+
+    InFlight {
+        persistence_key: persistence_key.clone(),
+        cell,
+    }
+}
+
+/// Respawn sharing the cell of its key, see `cell`.
+pub(crate) struct InFlight<A: Actor> {
+    persistence_key: Url,
+    cell: Arc<Cell<A>>,
+}
+
+impl<A: Actor> Deref for InFlight<A> {
+    type Target = Cell<A>;
+
+    fn deref(&self) -> &Cell<A> {
+        &self.cell
+    }
+}
+
+impl<A: Actor> Drop for InFlight<A> {
+    fn drop(&mut self) {
+        let mut in_flight = IN_FLIGHT.lock().unwrap_or_else(|e| e.into_inner());
+        let key = (TypeId::of::<A>(), self.persistence_key.clone());
+
+        // Once the actor is registered, where later respawns find it, or once no other respawn
+        // waits on the cell
+        let done = self.cell.initialized() || Arc::strong_count(&self.cell) == 2;
+        if done
+            && in_flight
+                .get(&key)
+                .is_some_and(|entry| std::ptr::addr_eq(Arc::as_ptr(entry), Arc::as_ptr(&self.cell)))
+        {
+            in_flight.remove(&key);
+        }
+    }
+}
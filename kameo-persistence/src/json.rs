@@ -0,0 +1,81 @@
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{PersistentActor, codec, redact::redacted, registry, storage};
+
+/// Snapshot exported to JSON, tagged with the type of its actor.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonSnapshot {
+    /// `PersistentActor::type_tag` of the actor the snapshot belongs to.
+    pub type_tag: String,
+    pub snapshot: serde_json::Value,
+}
+
+impl JsonSnapshot {
+    /// Render as canonical JSON: pretty printed, with object keys sorted.
+    pub fn to_canonical_string(&self) -> anyhow::Result<String> {
+        // `Value` objects are sorted maps, so converting sorts every key
+        Ok(serde_json::to_string_pretty(&serde_json::to_value(self)?)?)
+    }
+}
+
+/// Decode stored snapshot bytes of an actor type into JSON.
+pub fn to_json<A: PersistentActor>(data: &[u8]) -> anyhow::Result<serde_json::Value> {
+    Ok(serde_json::to_value(codec::decode::<A>(data)?)?)
+}
+
+/// Encode JSON into the snapshot bytes of an actor type to store under a key.
+pub fn from_json<A: PersistentActor>(
+    persistence_key: &Url,
+    value: serde_json::Value,
+) -> anyhow::Result<Vec<u8>> {
+    let snapshot: A::Snapshot = serde_json::from_value(value)?;
+    codec::encode::<A>(persistence_key, &snapshot)
+}
+
+/// Export the snapshot stored under a key to JSON, whatever its type.
+///
+/// The type is read from the snapshot header and must be registered in this process, by
+/// deriving `PersistentActor` or with `registry::register_type`.
+pub async fn export(persistence_key: &Url) -> anyhow::Result<JsonSnapshot> {
+    let data = storage::read(persistence_key).await?;
+    export_bytes(persistence_key, &data)
+}
+
+/// Export snapshot bytes stored under a key to JSON, whatever their type, as `export` does.
+pub fn export_bytes(persistence_key: &Url, data: &[u8]) -> anyhow::Result<JsonSnapshot> {
+    let (Some(header), _) = codec::split(data)? else {
+        anyhow::bail!(
+            "Snapshot stored under {} has no type tag",
+            redacted(persistence_key)
+        );
+    };
+
+    let registration = registry::registration(&header.type_tag)
+        .ok_or_else(|| anyhow!("Actor type {} is not registered", header.type_tag))?;
+
+    Ok(JsonSnapshot {
+        snapshot: (registration.to_json)(data)?,
+        type_tag: header.type_tag,
+    })
+}
+
+/// Replace the snapshot stored under a key with one imported from JSON.
+///
+/// Fails while an actor of this process is running under the key, as its next save would
+/// overwrite the import; stop it first and respawn it afterwards.
+pub async fn import(persistence_key: &Url, snapshot: JsonSnapshot) -> anyhow::Result<()> {
+    if registry::lookup(persistence_key).is_some() {
+        anyhow::bail!(
+            "Cannot import under {} while its actor is running",
+            redacted(persistence_key)
+        );
+    }
+
+    let registration = registry::registration(&snapshot.type_tag)
+        .ok_or_else(|| anyhow!("Actor type {} is not registered", snapshot.type_tag))?;
+
+    let data = (registration.from_json)(persistence_key, snapshot.snapshot)?;
+    storage::write(persistence_key, &data).await
+}
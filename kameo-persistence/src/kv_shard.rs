@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+
+use anyhow::anyhow;
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+#[cfg(feature = "tracing")]
+use tracing::debug;
+use url::Url;
+
+use crate::{Checkpoint, PersistentActor, redact::redacted, registry, storage};
+
+/// Prefix of the files holding entries, so they never collide with the crate's own files.
+const ENTRY_PREFIX: &str = "entry-";
+
+/// Suffix of an entry being written, until it replaces the entry.
+const TMP_SUFFIX: &str = ".tmp";
+
+/// Actor storing a map with every entry in a file of its own under its key, so a change writes
+/// only that entry rather than the whole map.
+///
+/// Entries are loaded on the first message after a spawn or respawn. Like attachments, they
+/// are not part of the snapshot, so history, rollback and forks do not cover them. Without a
+/// persistence key the map is only kept in memory.
+pub struct PersistentKvShard<K, V> {
+    entries: BTreeMap<K, V>,
+    loaded: bool,
+}
+
+/// Snapshot and arguments of a `PersistentKvShard`, holding nothing as entries are stored on
+/// their own.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KvShardSnapshot;
+
+impl<K, V> From<&PersistentKvShard<K, V>> for KvShardSnapshot {
+    fn from(_shard: &PersistentKvShard<K, V>) -> Self {
+        Self
+    }
+}
+
+impl<K, V> PersistentKvShard<K, V>
+where
+    K: Ord + Send + Sync + Serialize + DeserializeOwned + 'static,
+    V: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    /// Load the entries stored under the key on first use, saving the snapshot if there is
+    /// none yet so the shard can be respawned.
+    async fn load(&mut self, actor_ref: &ActorRef<Self>) -> anyhow::Result<Option<Url>> {
+        let key = Self::persistence_key(actor_ref);
+        if self.loaded {
+            return Ok(key);
+        }
+
+        let Some(key) = key else {
+            self.loaded = true;
+            return Ok(None);
+        };
+
+        let backend = storage::backend(&key)?;
+        for file in backend.list_files(&key)? {
+            if !file.starts_with(ENTRY_PREFIX) || file.ends_with(TMP_SUFFIX) {
+                continue;
+            }
+
+            let Some(data) = backend.read_file(&key, &file)? else {
+                continue;
+            };
+            let (entry_key, value) = postcard::from_bytes::<(K, V)>(&data)
+                .map_err(|e| anyhow!("Entry {file} of {} is corrupt: {e}", redacted(&key)))?;
+            self.entries.insert(entry_key, value);
+        }
+
+        if backend.file_size(&key, storage::snapshot_file())?.is_none() {
+            self.save_snapshot(actor_ref).await?;
+        }
+
+        #[cfg(feature = "tracing")]
+        debug!(
+            "Loaded {} entries of {}",
+            self.entries.len(),
+            redacted(&key)
+        );
+
+        self.loaded = true;
+        Ok(Some(key))
+    }
+}
+
+/// File holding the entry of `entry_key`, named after a digest of the serialized key.
+fn entry_file<K: Serialize>(entry_key: &K) -> anyhow::Result<String> {
+    let digest = Sha256::digest(postcard::to_allocvec(entry_key)?);
+    let hex = digest[..16]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    Ok(format!("{ENTRY_PREFIX}{hex}"))
+}
+
+impl<K, V> Actor for PersistentKvShard<K, V>
+where
+    K: Ord + Send + Sync + Serialize + DeserializeOwned + 'static,
+    V: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Args = KvShardSnapshot;
+    type Error = anyhow::Error;
+
+    async fn on_start(_args: Self::Args, _actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            entries: BTreeMap::new(),
+            loaded: false,
+        })
+    }
+}
+
+impl<K, V> PersistentActor for PersistentKvShard<K, V>
+where
+    K: Ord + Send + Sync + Serialize + DeserializeOwned + 'static,
+    V: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Snapshot = KvShardSnapshot;
+
+    fn register_persistent(persistence_key: Url, actor_ref: &ActorRef<Self>) -> anyhow::Result<()> {
+        let Ok(mut registry) = registry::typed_registry::<Self>().write() else {
+            anyhow::bail!("Failed to acquire write lock on registry");
+        };
+        registry::register(persistence_key.clone(), actor_ref);
+        registry.insert(persistence_key, actor_ref.downgrade());
+        Ok(())
+    }
+
+    fn persistence_key(actor_ref: &ActorRef<Self>) -> Option<Url> {
+        let registry = registry::typed_registry::<Self>().read().ok()?;
+        registry.get_left(&actor_ref.downgrade()).cloned()
+    }
+
+    fn lookup_persistent(persistence_key: &Url) -> Option<ActorRef<Self>> {
+        let registry = registry::typed_registry::<Self>().read().ok()?;
+        registry
+            .get_right(persistence_key)
+            .and_then(|weak_ref| weak_ref.upgrade())
+            .filter(|actor_ref| actor_ref.is_alive())
+    }
+
+    fn unregister_persistent(persistence_key: &Url) {
+        registry::unregister(persistence_key);
+        registry::typed_registry::<Self>()
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove_left(persistence_key);
+    }
+}
+
+impl<K, V> Message<Checkpoint> for PersistentKvShard<K, V>
+where
+    K: Ord + Send + Sync + Serialize + DeserializeOwned + 'static,
+    V: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: Checkpoint,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.on_checkpoint(&ctx.actor_ref(), msg).await
+    }
+}
+
+/// Return the value of a key, if any.
+pub struct Get<K>(pub K);
+
+impl<K, V> Message<Get<K>> for PersistentKvShard<K, V>
+where
+    K: Ord + Send + Sync + Serialize + DeserializeOwned + 'static,
+    V: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Reply = anyhow::Result<Option<V>>;
+
+    async fn handle(&mut self, msg: Get<K>, ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        self.load(&ctx.actor_ref()).await?;
+        Ok(self.entries.get(&msg.0).cloned())
+    }
+}
+
+/// Write the value of a key, replacing and returning any previous one.
+///
+/// The entry is written before the map changes, so a failed write leaves both as they were.
+pub struct Insert<K, V>(pub K, pub V);
+
+impl<K, V> Message<Insert<K, V>> for PersistentKvShard<K, V>
+where
+    K: Ord + Send + Sync + Serialize + DeserializeOwned + 'static,
+    V: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Reply = anyhow::Result<Option<V>>;
+
+    async fn handle(
+        &mut self,
+        msg: Insert<K, V>,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let Insert(entry_key, value) = msg;
+
+        if let Some(key) = self.load(&ctx.actor_ref()).await? {
+            let file = entry_file(&entry_key)?;
+            let tmp = format!("{file}{TMP_SUFFIX}");
+
+            let backend = storage::backend(&key)?;
+            backend.write_file(&key, &tmp, &postcard::to_allocvec(&(&entry_key, &value))?)?;
+            backend.rename_file(&key, &tmp, &file)?;
+        }
+
+        Ok(self.entries.insert(entry_key, value))
+    }
+}
+
+/// Remove a key, returning its value if any.
+pub struct Remove<K>(pub K);
+
+impl<K, V> Message<Remove<K>> for PersistentKvShard<K, V>
+where
+    K: Ord + Send + Sync + Serialize + DeserializeOwned + 'static,
+    V: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Reply = anyhow::Result<Option<V>>;
+
+    async fn handle(
+        &mut self,
+        msg: Remove<K>,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        if let Some(key) = self.load(&ctx.actor_ref()).await?
+            && self.entries.contains_key(&msg.0)
+        {
+            storage::backend(&key)?.remove_file(&key, &entry_file(&msg.0)?)?;
+        }
+
+        Ok(self.entries.remove(&msg.0))
+    }
+}
+
+/// Return the number of entries.
+pub struct Len;
+
+impl<K, V> Message<Len> for PersistentKvShard<K, V>
+where
+    K: Ord + Send + Sync + Serialize + DeserializeOwned + 'static,
+    V: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Reply = anyhow::Result<usize>;
+
+    async fn handle(&mut self, _msg: Len, ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        self.load(&ctx.actor_ref()).await?;
+        Ok(self.entries.len())
+    }
+}
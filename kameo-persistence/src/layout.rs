@@ -0,0 +1,224 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::anyhow;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::{
+    options,
+    storage::{self, Backend},
+};
+
+/// File in every key directory holding the key it stores, as hashed directory names do not.
+const KEY_FILE: &str = "key.url";
+
+/// How keys map to directories of the local file system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// `<root>/<hash of key>/`: no nesting, however deep the keys.
+    Hashed,
+    /// `<root>/ab/cd/<hash of key>/` with `levels` shard directories named after the leading
+    /// bytes of the hash, so no directory holds more than 256 entries per level above the keys.
+    Sharded { levels: usize },
+}
+
+/// Stores keys in directories of the local file system arranged by a `Layout`, instead of
+/// mirroring the key path as `FileBackend` does.
+///
+/// Millions of sibling directories under one root degrade badly on ext4 and NTFS; sharding
+/// spreads them out. Keys are hashed, so `list` and `delete` scan the roots, and `exists`
+/// only looks at the key itself, not at keys below it.
+#[derive(Debug, Clone)]
+pub struct LayoutBackend {
+    root: PathBuf,
+    layout: Layout,
+    /// Roots of the keys whose first path segment names an actor type.
+    type_roots: BTreeMap<String, PathBuf>,
+}
+
+impl LayoutBackend {
+    /// Store keys under the directory `root`, arranged by `layout`.
+    pub fn new(root: impl Into<PathBuf>, layout: Layout) -> Self {
+        Self {
+            root: root.into(),
+            layout,
+            type_roots: BTreeMap::new(),
+        }
+    }
+
+    /// Store keys whose first path segment is `segment`, such as `accounts` in
+    /// `scheme:///accounts/1`, under their own root directory.
+    pub fn type_root(mut self, segment: &str, root: impl Into<PathBuf>) -> Self {
+        self.type_roots.insert(segment.to_string(), root.into());
+        self
+    }
+
+    /// Store keys of `scheme` in this backend.
+    pub fn install(self, scheme: &str) -> Self {
+        storage::set_backend(scheme, Arc::new(self.clone()));
+        self
+    }
+
+    fn root_of(&self, key: &Url) -> &Path {
+        key.path_segments()
+            .and_then(|mut segments| segments.find(|segment| !segment.is_empty()))
+            .and_then(|segment| self.type_roots.get(segment))
+            .unwrap_or(&self.root)
+    }
+
+    fn key_dir(&self, key: &Url) -> PathBuf {
+        let hash = hex(&Sha256::digest(canonical(key).as_bytes())[..16]);
+
+        let mut dir = self.root_of(key).to_path_buf();
+        if let Layout::Sharded { levels } = self.layout {
+            for level in 0..levels.min(hash.len() / 2) {
+                dir.push(&hash[level * 2..level * 2 + 2]);
+            }
+        }
+        dir.join(hash)
+    }
+
+    fn create_key_dir(&self, key: &Url) -> anyhow::Result<PathBuf> {
+        let dir = self.key_dir(key);
+
+        if !dir.join(KEY_FILE).exists() {
+            std::fs::create_dir_all(&dir)?;
+            std::fs::write(dir.join(KEY_FILE), canonical(key))?;
+        }
+
+        Ok(dir)
+    }
+
+    /// Every key stored in the roots, found by their `KEY_FILE`.
+    fn all_keys(&self) -> anyhow::Result<Vec<Url>> {
+        let depth = match self.layout {
+            Layout::Hashed => 0,
+            Layout::Sharded { levels } => levels,
+        };
+
+        let mut keys = Vec::new();
+        for root in std::iter::once(&self.root).chain(self.type_roots.values()) {
+            collect_keys(root, depth, &mut keys)?;
+        }
+        Ok(keys)
+    }
+}
+
+impl Backend for LayoutBackend {
+    fn read_file(&self, key: &Url, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match std::fs::read(self.key_dir(key).join(name)) {
+            Ok(data) => Ok(Some(options::decode_file(key, name, data)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write_file(&self, key: &Url, name: &str, data: &[u8]) -> anyhow::Result<()> {
+        let dir = self.create_key_dir(key)?;
+        storage::write_synced(
+            key,
+            &dir.join(name),
+            &options::encode_file(key, name, data)?,
+        )
+    }
+
+    fn rename_file(&self, key: &Url, from: &str, to: &str) -> anyhow::Result<()> {
+        let dir = self.key_dir(key);
+        std::fs::rename(dir.join(from), dir.join(to))?;
+        storage::sync_dir(key, &dir)
+    }
+
+    fn remove_file(&self, key: &Url, name: &str) -> anyhow::Result<()> {
+        match std::fs::remove_file(self.key_dir(key).join(name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn file_size(&self, key: &Url, name: &str) -> anyhow::Result<Option<u64>> {
+        match std::fs::metadata(self.key_dir(key).join(name)) {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn exists(&self, key: &Url) -> anyhow::Result<bool> {
+        Ok(self.key_dir(key).exists())
+    }
+
+    fn delete(&self, key: &Url) -> anyhow::Result<()> {
+        for key in self.list(key)? {
+            let dir = self.key_dir(&key);
+            if dir.exists() {
+                std::fs::remove_dir_all(dir)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn list(&self, root: &Url) -> anyhow::Result<Vec<Url>> {
+        let mut keys = self
+            .all_keys()?
+            .into_iter()
+            .filter(|key| storage::is_within(root, key))
+            .collect::<Vec<_>>();
+
+        // Parents sort before the keys below them
+        keys.sort_unstable_by(|a, b| canonical(a).cmp(canonical(b)));
+        Ok(keys)
+    }
+
+    fn list_files(&self, key: &Url) -> anyhow::Result<Vec<String>> {
+        let dir = self.key_dir(key);
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type()?.is_file() && name != KEY_FILE {
+                names.push(name);
+            }
+        }
+
+        Ok(names)
+    }
+}
+
+/// Key as hashed, without the trailing slash of directory-like URLs.
+fn canonical(key: &Url) -> &str {
+    key.as_str().trim_end_matches('/')
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Collect the keys of the key directories `depth` shard levels below `dir`.
+fn collect_keys(dir: &Path, depth: usize, keys: &mut Vec<Url>) -> anyhow::Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+
+        if depth > 0 {
+            collect_keys(&path, depth - 1, keys)?;
+        } else if let Ok(key) = std::fs::read_to_string(path.join(KEY_FILE)) {
+            keys.push(Url::parse(&key).map_err(|e| anyhow!("Invalid key in {path:?}: {e}"))?);
+        }
+    }
+
+    Ok(())
+}
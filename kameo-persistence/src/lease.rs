@@ -0,0 +1,327 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, RwLock},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+#[cfg(feature = "tracing")]
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::{PersistenceError, PersistentActor, clock, redact::redacted, storage};
+
+/// Name of the lease inside a persistence key directory.
+pub const LEASE_FILE: &str = "lease.bin";
+
+// Fencing token this process writes each fenced key with
+static FENCES: LazyLock<RwLock<HashMap<Url, u64>>> = LazyLock::new(|| RwLock::new(HashMap::new()));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredLease {
+    holder: String,
+    token: u64,
+    ttl_ms: u64,
+    expires_at_ms: u64,
+}
+
+/// Exclusive right to write a key until it expires, with the fencing token it was granted.
+///
+/// Leases are stored under the key and acquired with the backend's `write_file_if`, so the
+/// guarantee is as strong as its compare-and-swap. Tokens grow with every new holder.
+#[derive(Debug, Clone)]
+pub struct Lease {
+    persistence_key: Url,
+    holder: String,
+    token: u64,
+    ttl: Duration,
+    version: String,
+}
+
+impl Lease {
+    pub fn persistence_key(&self) -> &Url {
+        &self.persistence_key
+    }
+
+    pub fn holder(&self) -> &str {
+        &self.holder
+    }
+
+    /// Fencing token of the lease, larger than the token of every previous holder.
+    pub fn token(&self) -> u64 {
+        self.token
+    }
+
+    /// Extend the lease by its ttl, failing with `PersistenceError::Fenced` if it was lost.
+    pub fn renew(&mut self) -> anyhow::Result<()> {
+        let backend = storage::backend(&self.persistence_key)?;
+        let stored = stored(&self.persistence_key)?;
+        let now = now_ms();
+
+        let current = stored.as_ref().map_or(0, |(lease, _)| lease.token);
+        let valid = stored.as_ref().is_some_and(|(lease, version)| {
+            lease.token == self.token && lease.expires_at_ms > now && *version == self.version
+        });
+        if !valid {
+            return Err(self.fenced(current));
+        }
+
+        let lease = StoredLease {
+            holder: self.holder.clone(),
+            token: self.token,
+            ttl_ms: self.ttl.as_millis() as u64,
+            expires_at_ms: now + self.ttl.as_millis() as u64,
+        };
+        self.version = backend
+            .write_file_if(
+                &self.persistence_key,
+                LEASE_FILE,
+                Some(&self.version),
+                &postcard::to_allocvec(&lease)?,
+            )?
+            .ok_or_else(|| self.fenced(current))?;
+
+        Ok(())
+    }
+
+    /// Give the lease up, letting another holder acquire it right away.
+    pub fn release(self) -> anyhow::Result<()> {
+        unfence(&self.persistence_key);
+
+        // Expire rather than remove the lease, so the next holder still gets a larger token
+        let lease = StoredLease {
+            holder: self.holder.clone(),
+            token: self.token,
+            ttl_ms: 0,
+            expires_at_ms: 0,
+        };
+        storage::backend(&self.persistence_key)?.write_file_if(
+            &self.persistence_key,
+            LEASE_FILE,
+            Some(&self.version),
+            &postcard::to_allocvec(&lease)?,
+        )?;
+
+        Ok(())
+    }
+
+    fn fenced(&self, current: u64) -> anyhow::Error {
+        PersistenceError::Fenced {
+            key: redacted(&self.persistence_key).to_string(),
+            token: self.token,
+            current,
+        }
+        .into()
+    }
+}
+
+/// Acquire the lease of a key for `ttl` as `holder`, such as a node id, failing with
+/// `PersistenceError::LeaseHeld` while another holder's lease has not expired.
+///
+/// A holder acquiring its own unexpired lease again, e.g. after restarting, is granted a new
+/// token, fencing out its previous incarnation.
+pub fn acquire(persistence_key: &Url, holder: &str, ttl: Duration) -> anyhow::Result<Lease> {
+    let stored = stored(persistence_key)?;
+    let now = now_ms();
+
+    if let Some((lease, _)) = &stored
+        && lease.holder != holder
+        && lease.expires_at_ms > now
+    {
+        return Err(PersistenceError::LeaseHeld {
+            key: redacted(persistence_key).to_string(),
+            holder: lease.holder.clone(),
+        }
+        .into());
+    }
+
+    let token = stored.as_ref().map_or(0, |(lease, _)| lease.token) + 1;
+    let lease = StoredLease {
+        holder: holder.to_string(),
+        token,
+        ttl_ms: ttl.as_millis() as u64,
+        expires_at_ms: now + ttl.as_millis() as u64,
+    };
+
+    let version = storage::backend(persistence_key)?
+        .write_file_if(
+            persistence_key,
+            LEASE_FILE,
+            stored.as_ref().map(|(_, version)| version.as_str()),
+            &postcard::to_allocvec(&lease)?,
+        )?
+        .ok_or_else(|| PersistenceError::LeaseHeld {
+            key: redacted(persistence_key).to_string(),
+            holder: "a concurrent holder".to_string(),
+        })?;
+
+    #[cfg(feature = "tracing")]
+    debug!(
+        "{holder} acquired the lease of {} with token {token}",
+        redacted(persistence_key)
+    );
+
+    Ok(Lease {
+        persistence_key: persistence_key.clone(),
+        holder: holder.to_string(),
+        token,
+        ttl,
+        version,
+    })
+}
+
+/// Make every snapshot write of the lease's key from this process check the lease first.
+///
+/// A write fails with `PersistenceError::Fenced` once a larger token was granted, or once less
+/// than a quarter of the ttl is left, leaving that margin for writes already past the check and
+/// for clock drift between nodes.
+pub fn fence(lease: &Lease) {
+    FENCES
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(lease.persistence_key.clone(), lease.token);
+}
+
+/// Stop checking the lease of a key on writes.
+pub fn unfence(persistence_key: &Url) {
+    FENCES
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(persistence_key);
+}
+
+/// Fail if the key is fenced and this process no longer holds its lease.
+pub(crate) fn check(persistence_key: &Url) -> anyhow::Result<()> {
+    let Some(token) = FENCES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(persistence_key)
+        .copied()
+    else {
+        return Ok(());
+    };
+
+    let stored = stored(persistence_key)?;
+    let current = stored.as_ref().map_or(0, |(lease, _)| lease.token);
+    let valid = stored.is_some_and(|(lease, _)| {
+        lease.token == token && lease.expires_at_ms > now_ms() + lease.ttl_ms / 4
+    });
+
+    if !valid {
+        return Err(PersistenceError::Fenced {
+            key: redacted(persistence_key).to_string(),
+            token,
+            current,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Handle of a single-writer actor's lease; renewal stops when it is dropped.
+pub struct LeaseKeeper {
+    task: JoinHandle<()>,
+}
+
+impl Drop for LeaseKeeper {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Respawn an actor in single-writer mode: acquire the lease of its key as `holder`, fence the
+/// key, respawn the actor and renew the lease every third of `ttl` while it is alive.
+///
+/// If a renewal fails, such as when this node was partitioned from the storage for longer than
+/// the ttl and another node took over, the actor is killed and its writes already fail fenced,
+/// so at most one live actor in the cluster writes the key. Combine with
+/// `remote::lookup_or_respawn` so other nodes reach the holder instead of contending for the
+/// lease.
+pub async fn respawn_single_writer<A: PersistentActor>(
+    persistence_key: Url,
+    holder: &str,
+    ttl: Duration,
+) -> anyhow::Result<(ActorRef<A>, LeaseKeeper)> {
+    let lease = Unkept::fence(acquire(&persistence_key, holder, ttl)?);
+    let actor_ref = A::respawn_persistent(persistence_key.clone()).await?;
+    let keeper = keep(lease.into_inner(), &actor_ref);
+    Ok((actor_ref, keeper))
+}
+
+/// Fenced lease released when dropped, unless handed to `keep`, so a failed or dropped spawn
+/// under it does not keep the key fenced.
+pub(crate) struct Unkept(Option<Lease>);
+
+impl Unkept {
+    pub(crate) fn fence(lease: Lease) -> Self {
+        fence(&lease);
+        Self(Some(lease))
+    }
+
+    pub(crate) fn into_inner(mut self) -> Lease {
+        self.0
+            .take()
+            .expect("Unkept leases hold their lease until taken")
+    }
+}
+
+impl Drop for Unkept {
+    fn drop(&mut self) {
+        if let Some(lease) = self.0.take() {
+            let _ = lease.release();
+        }
+    }
+}
+
+/// Renew a fenced lease every third of its ttl while the actor is alive, killing the actor if a
+/// renewal fails and releasing the lease once it stopped.
+pub(crate) fn keep<A: Actor>(mut lease: Lease, actor_ref: &ActorRef<A>) -> LeaseKeeper {
+    let weak_ref = actor_ref.downgrade();
+    let task = tokio::spawn(async move {
+        loop {
+            let clock = clock::clock();
+            clock.sleep_until(clock.now() + lease.ttl / 3).await;
+
+            let Some(actor_ref) = weak_ref.upgrade().filter(|actor_ref| actor_ref.is_alive())
+            else {
+                let _ = lease.release();
+                return;
+            };
+
+            if let Err(_e) = lease.renew() {
+                #[cfg(feature = "tracing")]
+                warn!(
+                    "Lost the lease of {}, stopping its actor: {_e:#}",
+                    redacted(&lease.persistence_key)
+                );
+
+                actor_ref.kill();
+                return;
+            }
+        }
+    });
+
+    LeaseKeeper { task }
+}
+
+fn stored(persistence_key: &Url) -> anyhow::Result<Option<(StoredLease, String)>> {
+    let backend = storage::backend(persistence_key)?;
+    let Some(version) = backend.file_version(persistence_key, LEASE_FILE)? else {
+        return Ok(None);
+    };
+    let Some(data) = backend.read_file(persistence_key, LEASE_FILE)? else {
+        return Ok(None);
+    };
+
+    Ok(Some((postcard::from_bytes(&data)?, version)))
+}
+
+fn now_ms() -> u64 {
+    clock::clock()
+        .system_time()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
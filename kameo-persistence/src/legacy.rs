@@ -0,0 +1,63 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use crate::PersistentActor;
+
+/// Decoder of snapshots stored before adopting this crate, such as files of plain bincode.
+///
+/// Set with `set_legacy_decoder`, it is invoked for stored bytes without a header this crate
+/// understands, instead of reading them as a postcard payload of schema version zero. The
+/// snapshot is saved with a header from the next save on.
+pub trait LegacyDecoder<A: PersistentActor>: Send + Sync + 'static {
+    fn decode(&self, data: &[u8]) -> anyhow::Result<A::Snapshot>;
+}
+
+impl<A, F> LegacyDecoder<A> for F
+where
+    A: PersistentActor,
+    F: Fn(&[u8]) -> anyhow::Result<A::Snapshot> + Send + Sync + 'static,
+{
+    fn decode(&self, data: &[u8]) -> anyhow::Result<A::Snapshot> {
+        self(data)
+    }
+}
+
+/// Decoder returning the snapshot as a postcard payload of the current schema version.
+type Decoder = Arc<dyn Fn(&[u8]) -> anyhow::Result<Vec<u8>> + Send + Sync>;
+
+// Legacy decoders by type tag
+static DECODERS: RwLock<Option<HashMap<&'static str, Decoder>>> = RwLock::new(None);
+
+/// Decode snapshots of `A` stored without a known header with `decoder`.
+pub fn set_legacy_decoder<A: PersistentActor>(decoder: impl LegacyDecoder<A>) {
+    let decoder: Decoder = Arc::new(move |data| {
+        let snapshot = decoder.decode(data)?;
+        Ok(postcard::to_allocvec(&snapshot)?)
+    });
+
+    DECODERS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_default()
+        .insert(A::type_tag(), decoder);
+}
+
+/// Stop decoding legacy snapshots of `A`, once all of them were saved again.
+pub fn clear_legacy_decoder<A: PersistentActor>() {
+    if let Some(decoders) = DECODERS.write().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        decoders.remove(A::type_tag());
+    }
+}
+
+/// Decode headerless `data` with the legacy decoder of `A`, or `None` if it has none.
+pub(crate) fn decode<A: PersistentActor>(data: &[u8]) -> Option<anyhow::Result<A::Snapshot>> {
+    let decoder = DECODERS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .and_then(|decoders| decoders.get(A::type_tag()).cloned())?;
+
+    Some(decoder(data).and_then(|payload| Ok(postcard::from_bytes(&payload)?)))
+}
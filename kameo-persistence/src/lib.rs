@@ -1,12 +1,123 @@
+#[cfg(feature = "admin")]
+pub mod admin;
+#[cfg(feature = "akka")]
+pub mod akka;
+#[cfg(feature = "parquet")]
+pub mod analytics;
+pub mod attachment;
+#[cfg(feature = "audit")]
+pub mod audit;
+pub mod autosave;
+#[cfg(feature = "avro")]
+pub mod avro;
+pub mod background;
 pub mod bi_hash_map;
+pub mod buffer;
+pub mod cas;
+pub mod checkpoint;
+pub mod clock;
+pub mod codec;
+pub mod concurrency;
+pub mod config;
+pub mod context;
+pub mod crash_loop;
+pub mod credentials;
+pub mod dedup;
+#[cfg(feature = "zstd")]
+pub mod dictionary;
+#[cfg(feature = "json")]
+pub mod diff;
+pub mod environment;
+pub mod error;
+pub mod fork;
+pub mod fsm;
+pub mod gc;
+pub mod generation;
+pub mod health;
+pub mod hierarchy;
+pub mod history;
+pub mod hooks;
+mod inflight;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod kv_shard;
+pub mod layout;
+pub mod lease;
+pub mod legacy;
+pub mod limits;
+pub mod live_keys;
+pub mod log_store;
+#[cfg(feature = "tracing")]
+pub mod logging;
+#[cfg(feature = "json")]
+pub mod manifest;
+pub mod merge;
+#[cfg(feature = "metrics")]
+mod metrics;
+pub mod migrate;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+pub mod observer;
+pub mod options;
+pub mod parts;
 pub mod persistent_actor;
+pub mod poison;
+pub mod protect;
+pub mod rate_limit;
+pub mod reconcile;
+pub mod recording;
+pub mod recovery;
+pub mod redact;
+pub mod registry;
+#[cfg(feature = "remote")]
+pub mod remote;
+pub mod rollback;
+pub mod router;
+pub mod saga;
+pub mod schema;
+pub mod scrub;
+pub mod segment;
+pub mod sequence;
+pub mod shutdown;
+pub mod singleton;
+#[cfg(feature = "tracing")]
+mod spans;
+pub mod standby;
+pub mod stats;
+pub mod storage;
+pub mod supervisor;
+pub mod sync;
+pub mod tenant;
+pub mod topic;
+pub mod transaction;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub mod uring;
+pub mod watch;
+pub mod write_stats;
 
 // Re-export local modules
 pub use bi_hash_map::BiHashMap;
+pub use checkpoint::{ActorGroup, Checkpoint, checkpoint, save_all, save_group};
+pub use context::RestoreContext;
+pub use error::PersistenceError;
+pub use fork::{fork_persistent, spawn_from_template};
+pub use hierarchy::{PersistenceKeyExt, respawn_tree};
+pub use observer::{PersistenceObserver, add_observer};
 pub use persistent_actor::PersistentActor;
+pub use protect::{FieldCipher, SubjectKeys, set_cipher, set_subject_keys, shred_subject};
+pub use registry::respawn_any;
+pub use rollback::RollbackExt;
+pub use singleton::PersistentSingleton;
+pub use supervisor::PersistentSupervisor;
+pub use tenant::Tenant;
+pub use transaction::SnapshotTransaction;
 
 // Re-export macros
-pub use kameo_persistence_macros::PersistentActor;
+pub use kameo_persistence_macros::{PersistentActor, persist_fields};
+
+// Used by the derive macro
+#[doc(hidden)]
+pub use inventory;
 
 // Test module
 #[cfg(test)]
@@ -17,5 +128,27 @@ mod tests {
         let t = trybuild::TestCases::new();
         t.pass("tests/derive_persistent_actor.rs");
         t.pass("tests/derive_persistent_actor_with_custom_snapshot.rs");
+        t.pass("tests/derive_persistent_actor_with_children.rs");
+        t.pass("tests/persist_fields.rs");
+        t.pass("tests/schema_version.rs");
+        t.pass("tests/single_writer.rs");
+        t.pass("tests/cancellation.rs");
+        t.pass("tests/segments.rs");
+        t.pass("tests/json_hooks.rs");
+        t.pass("tests/type_tag.rs");
+        t.pass("tests/log_store.rs");
+        t.pass("tests/gc.rs");
+        t.pass("tests/concurrency.rs");
+        t.pass("tests/content_addressed.rs");
+        t.pass("tests/transaction.rs");
+        t.pass("tests/checkpoint.rs");
+        t.pass("tests/concurrent_respawn.rs");
+        t.pass("tests/router.rs");
+        t.pass("tests/crash_loop.rs");
+        t.pass("tests/poison.rs");
+        t.pass("tests/background.rs");
+        t.pass("tests/watch.rs");
+        t.pass("tests/shred.rs");
+        t.pass("tests/quota.rs");
     }
 }
@@ -0,0 +1,120 @@
+use std::{collections::HashMap, sync::RwLock};
+
+use url::Url;
+
+use crate::{PersistenceError, PersistentActor, storage, tenant};
+
+/// What to do with a write exceeding a limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitAction {
+    /// Fail the write with a `PersistenceError`.
+    Reject,
+    /// Log a warning and write anyway.
+    Warn,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limit {
+    bytes: u64,
+    action: LimitAction,
+}
+
+static SNAPSHOT_LIMITS: RwLock<Option<HashMap<&'static str, Limit>>> = RwLock::new(None);
+static TENANT_QUOTAS: RwLock<Option<HashMap<String, Limit>>> = RwLock::new(None);
+
+/// Limit the encoded size of snapshots of an actor type.
+pub fn set_max_snapshot_size<A: PersistentActor>(bytes: u64, action: LimitAction) {
+    SNAPSHOT_LIMITS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_default()
+        .insert(A::type_tag(), Limit { bytes, action });
+}
+
+/// Limit the total size of the snapshots stored by a tenant.
+///
+/// Usage is measured by scanning the tenant's storage on every write, so prefer limiting
+/// snapshot sizes for tenants with many actors.
+pub fn set_tenant_quota(tenant_id: &str, bytes: u64, action: LimitAction) {
+    TENANT_QUOTAS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_default()
+        .insert(tenant_id.to_string(), Limit { bytes, action });
+}
+
+/// Remove every limit and quota.
+pub fn clear_limits() {
+    SNAPSHOT_LIMITS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .take();
+    TENANT_QUOTAS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .take();
+}
+
+fn enforce(limit: Limit, error: PersistenceError) -> anyhow::Result<()> {
+    match limit.action {
+        LimitAction::Reject => Err(error.into()),
+        LimitAction::Warn => {
+            #[cfg(feature = "tracing")]
+            tracing::warn!("{error}");
+            Ok(())
+        }
+    }
+}
+
+/// Check a snapshot of `size` bytes about to be written under `persistence_key`.
+pub(crate) fn check<A: PersistentActor>(persistence_key: &Url, size: usize) -> anyhow::Result<()> {
+    let snapshot_limit = SNAPSHOT_LIMITS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .and_then(|limits| limits.get(A::type_tag()).copied());
+
+    if let Some(limit) = snapshot_limit
+        && size as u64 > limit.bytes
+    {
+        enforce(
+            limit,
+            PersistenceError::SnapshotTooLarge {
+                type_tag: A::type_tag().to_string(),
+                size: size as u64,
+                limit: limit.bytes,
+            },
+        )?;
+    }
+
+    let Some(tenant) = tenant::tenant_of(persistence_key) else {
+        return Ok(());
+    };
+
+    let quota = TENANT_QUOTAS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .and_then(|quotas| quotas.get(tenant.id()).copied());
+
+    let Some(quota) = quota else {
+        return Ok(());
+    };
+
+    // The snapshot replaces the one currently stored under the key
+    let replaced = storage::snapshot_size(persistence_key)?;
+    let usage = tenant.usage()?.saturating_sub(replaced) + size as u64;
+
+    if usage > quota.bytes {
+        enforce(
+            quota,
+            PersistenceError::QuotaExceeded {
+                tenant: tenant.id().to_string(),
+                usage,
+                quota: quota.bytes,
+            },
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,169 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{LazyLock, Mutex, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+#[cfg(feature = "tracing")]
+use tracing::warn;
+use url::Url;
+
+#[cfg(feature = "tracing")]
+use crate::redact::redacted;
+use crate::{PersistentActor, storage};
+
+/// Prefix of the files holding the live keys of a type under the store root.
+const LIVE_PREFIX: &str = "live-";
+
+/// Suffix of a file being written, until it replaces the file.
+const TMP_SUFFIX: &str = ".tmp";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LiveKeys {
+    type_tag: String,
+    keys: BTreeSet<Url>,
+}
+
+// Root the live keys are stored under, if enabled
+static ROOT: RwLock<Option<Url>> = RwLock::new(None);
+
+// Keys last stored, by type tag, read on the first change of each type
+static STORED: LazyLock<Mutex<HashMap<String, BTreeSet<Url>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Store the keys of the persistent actors registered in this process under `root`, a file per
+/// actor type, rewritten whenever a key is registered for the first time or unregistered;
+/// `None` stops storing them.
+///
+/// After a restart, `Recovery::live_keys` respawns them, or read them with `stored`. Keys stay
+/// stored when their actor stops without being unregistered, such as on a crash or
+/// `shutdown::shutdown_all`, so they are respawned; `shutdown::stop_persistent` removes them.
+pub fn set_live_key_store(root: Option<Url>) {
+    *ROOT.write().unwrap_or_else(|e| e.into_inner()) = root;
+    clear();
+}
+
+fn root() -> Option<Url> {
+    ROOT.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Every stored live key with the type tag of its actor.
+pub fn stored() -> anyhow::Result<Vec<(String, Url)>> {
+    let Some(root) = root() else {
+        return Ok(Vec::new());
+    };
+
+    let backend = storage::backend(&root)?;
+    let mut stored = Vec::new();
+    for file in backend.list_files(&root)? {
+        if !file.starts_with(LIVE_PREFIX) || file.ends_with(TMP_SUFFIX) {
+            continue;
+        }
+
+        if let Some(data) = backend.read_file(&root, &file)? {
+            let live = postcard::from_bytes::<LiveKeys>(&data)?;
+            stored.extend(
+                live.keys
+                    .into_iter()
+                    .map(|key| (live.type_tag.clone(), key)),
+            );
+        }
+    }
+
+    Ok(stored)
+}
+
+/// Stored live keys of actors of type `A`.
+pub fn stored_of<A: PersistentActor>() -> anyhow::Result<Vec<Url>> {
+    let Some(root) = root() else {
+        return Ok(Vec::new());
+    };
+
+    Ok(read(&root, A::type_tag())?.into_iter().collect())
+}
+
+/// Forget the stored live key of an actor of type `A`, such as one whose snapshot was deleted.
+pub fn forget<A: PersistentActor>(persistence_key: &Url) {
+    note_unregister(A::type_tag(), persistence_key);
+}
+
+fn file_name(type_tag: &str) -> String {
+    let digest = Sha256::digest(type_tag.as_bytes());
+    let hex = digest[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    format!("{LIVE_PREFIX}{hex}")
+}
+
+fn read(root: &Url, type_tag: &str) -> anyhow::Result<BTreeSet<Url>> {
+    let data = storage::backend(root)?.read_file(root, &file_name(type_tag))?;
+    match data {
+        Some(data) => Ok(postcard::from_bytes::<LiveKeys>(&data)?.keys),
+        None => Ok(BTreeSet::new()),
+    }
+}
+
+fn write(root: &Url, type_tag: &str, keys: &BTreeSet<Url>) -> anyhow::Result<()> {
+    let file = file_name(type_tag);
+    let tmp = format!("{file}{TMP_SUFFIX}");
+    let data = postcard::to_allocvec(&LiveKeys {
+        type_tag: type_tag.to_string(),
+        keys: keys.clone(),
+    })?;
+
+    let backend = storage::backend(root)?;
+    backend.write_file(root, &tmp, &data)?;
+    backend.rename_file(root, &tmp, &file)
+}
+
+/// Apply `change` to the stored keys of a type, rewriting them if it returns true.
+fn update(type_tag: &str, change: impl FnOnce(&mut BTreeSet<Url>) -> bool) {
+    let Some(root) = root() else {
+        return;
+    };
+
+    // Held while writing, so concurrent changes of a type are stored in order
+    let mut stored = STORED.lock().unwrap_or_else(|e| e.into_inner());
+    let keys = match stored.get_mut(type_tag) {
+        Some(keys) => keys,
+        None => match read(&root, type_tag) {
+            Ok(keys) => stored.entry(type_tag.to_string()).or_insert(keys),
+            Err(_e) => {
+                #[cfg(feature = "tracing")]
+                warn!("Failed to read the live keys of {type_tag}: {_e:#}");
+                return;
+            }
+        },
+    };
+
+    if !change(keys) {
+        return;
+    }
+
+    if let Err(_e) = write(&root, type_tag, keys) {
+        #[cfg(feature = "tracing")]
+        warn!(
+            "Failed to store the live keys of {type_tag} under {}: {_e:#}",
+            redacted(&root)
+        );
+        // Read again on the next change
+        stored.remove(type_tag);
+    }
+}
+
+/// Store the key of an actor just registered, unless it is stored already.
+pub(crate) fn note_register(type_tag: &str, persistence_key: &Url) {
+    update(type_tag, |keys| keys.insert(persistence_key.clone()));
+}
+
+/// Remove the key of an actor just unregistered.
+pub(crate) fn note_unregister(type_tag: &str, persistence_key: &Url) {
+    update(type_tag, |keys| keys.remove(persistence_key));
+}
+
+/// Forget the keys read so far, to read them from storage again.
+pub(crate) fn clear() {
+    STORED.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
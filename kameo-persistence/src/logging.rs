@@ -0,0 +1,134 @@
+use std::{
+    io::{self, Write},
+    sync::{
+        RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use serde::Serialize;
+use tracing::{Level, trace};
+
+/// Logging of snapshot contents on save, at trace level within the `persist.save` span; off
+/// unless set with `set_snapshot_logging`.
+///
+/// Snapshots are rendered as JSON with the `json` feature, or else as the hex of their
+/// postcard encoding, and rendering stops at `max_bytes`, so multi-megabyte snapshots cost no
+/// more than small ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotLogging {
+    /// Longest rendering logged; longer ones are cut.
+    pub max_bytes: usize,
+    /// Log one save in this many, counted across the process.
+    pub sample_every: u64,
+}
+
+impl Default for SnapshotLogging {
+    fn default() -> Self {
+        Self {
+            max_bytes: 4096,
+            sample_every: 1,
+        }
+    }
+}
+
+static LOGGING: RwLock<Option<SnapshotLogging>> = RwLock::new(None);
+
+// Saves seen while logging is on, for sampling
+static SAVES: AtomicU64 = AtomicU64::new(0);
+
+/// Log snapshot contents on save as `logging` says, or stop with `None`.
+pub fn set_snapshot_logging(logging: Option<SnapshotLogging>) {
+    *LOGGING.write().unwrap_or_else(|e| e.into_inner()) = logging;
+}
+
+/// Log a snapshot being saved, if enabled, sampled in and trace level is on.
+pub(crate) fn log_snapshot<S: Serialize>(snapshot: &S) {
+    let Some(logging) = *LOGGING.read().unwrap_or_else(|e| e.into_inner()) else {
+        return;
+    };
+
+    if !tracing::enabled!(Level::TRACE)
+        || !SAVES
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(logging.sample_every.max(1))
+    {
+        return;
+    }
+
+    let (rendered, cut) = render(snapshot, logging.max_bytes);
+    if cut {
+        trace!(snapshot = %rendered, "Saving snapshot, cut at {} bytes", logging.max_bytes);
+    } else {
+        trace!(snapshot = %rendered, "Saving snapshot");
+    }
+}
+
+/// Writer keeping the first `limit` bytes and failing past them, to stop serializing.
+struct Limited {
+    data: Vec<u8>,
+    limit: usize,
+    cut: bool,
+}
+
+impl Write for Limited {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let room = self.limit - self.data.len();
+        if buf.len() > room {
+            self.data.extend_from_slice(&buf[..room]);
+            self.cut = true;
+            return Err(io::Error::other("snapshot rendering limit reached"));
+        }
+
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Render `snapshot` up to `max_bytes`, returning true if it was cut.
+#[cfg(feature = "json")]
+fn render<S: Serialize>(snapshot: &S, max_bytes: usize) -> (String, bool) {
+    let mut writer = Limited {
+        data: Vec::new(),
+        limit: max_bytes,
+        cut: false,
+    };
+
+    if let Err(e) = serde_json::to_writer(&mut writer, snapshot)
+        && !writer.cut
+    {
+        return (format!("<unrenderable: {e}>"), false);
+    }
+
+    (
+        String::from_utf8_lossy(&writer.data).into_owned(),
+        writer.cut,
+    )
+}
+
+#[cfg(not(feature = "json"))]
+fn render<S: Serialize>(snapshot: &S, max_bytes: usize) -> (String, bool) {
+    // Two hex digits per byte
+    let mut writer = Limited {
+        data: Vec::new(),
+        limit: max_bytes / 2,
+        cut: false,
+    };
+
+    if let Err(e) = postcard::to_io(snapshot, &mut writer)
+        && !writer.cut
+    {
+        return (format!("<unrenderable: {e}>"), false);
+    }
+
+    let hex = writer
+        .data
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    (hex, writer.cut)
+}
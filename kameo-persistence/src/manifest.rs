@@ -0,0 +1,118 @@
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicBool, Ordering},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    clock, codec, hierarchy,
+    options::{Compression, Format, KeyOptions},
+    storage,
+};
+
+/// Name of the manifest inside a persistence key directory.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+static WRITE_MANIFEST: AtomicBool = AtomicBool::new(false);
+
+/// Self-describing summary of the snapshot stored under a key, for tooling and humans.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// `PersistentActor::type_tag` of the actor, `None` for snapshots without a header.
+    pub type_tag: Option<String>,
+    pub schema_version: Option<u32>,
+    /// Version of the snapshot header, see `codec::format_version`.
+    pub format_version: Option<u8>,
+    /// Encoding the snapshot is stored in, `postcard` or `json`.
+    pub format: String,
+    /// Compression the snapshot is stored with, if any.
+    pub compression: Option<String>,
+    /// True if the payload is encrypted for a data subject.
+    pub encrypted: bool,
+    /// CRC-32 of the payload, from the header.
+    pub checksum: Option<u32>,
+    /// SHA-256 of the snapshot bytes, see `storage::content_version`.
+    pub content_version: String,
+    /// Size of the snapshot in bytes, before any compression.
+    pub size: usize,
+    /// Keys of the children derived from this key.
+    pub children: Vec<Url>,
+    /// Milliseconds since the Unix epoch when the first snapshot was written.
+    pub created_at_ms: u64,
+    /// Milliseconds since the Unix epoch when the snapshot was last written.
+    pub updated_at_ms: u64,
+    /// Custom metadata from the header, see `codec::set_metadata`.
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Write `MANIFEST_FILE` next to the snapshot on every save when enabled.
+pub fn set_manifest(enabled: bool) {
+    WRITE_MANIFEST.store(enabled, Ordering::Relaxed);
+}
+
+/// Read the manifest stored under a key, if any.
+pub fn read(persistence_key: &Url) -> anyhow::Result<Option<Manifest>> {
+    match storage::backend(persistence_key)?.read_file(persistence_key, MANIFEST_FILE)? {
+        Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+        None => Ok(None),
+    }
+}
+
+/// Describe the snapshot bytes just written under a key, if manifests are enabled.
+pub(crate) fn note_write(persistence_key: &Url, data: &[u8]) -> anyhow::Result<()> {
+    if !WRITE_MANIFEST.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    let now = clock::clock()
+        .system_time()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0);
+
+    // An unreadable manifest is replaced rather than failing the save
+    let created_at_ms = read(persistence_key)
+        .ok()
+        .flatten()
+        .map_or(now, |manifest| manifest.created_at_ms);
+
+    let (header, _) = codec::split(data)?;
+    let options = KeyOptions::parse(persistence_key)?;
+
+    let manifest = Manifest {
+        type_tag: header.as_ref().map(|header| header.type_tag.clone()),
+        schema_version: header.as_ref().map(|header| header.schema_version),
+        format_version: codec::format_version(data),
+        format: match options.format {
+            Format::Postcard => "postcard",
+            Format::Json => "json",
+        }
+        .to_string(),
+        compression: options.compress.map(|compression| match compression {
+            Compression::Zstd => "zstd".to_string(),
+        }),
+        encrypted: header
+            .as_ref()
+            .is_some_and(|header| header.subject.is_some()),
+        checksum: header.as_ref().map(|header| header.checksum),
+        content_version: storage::content_version(data),
+        size: data.len(),
+        children: hierarchy::read_manifest(persistence_key)?
+            .children
+            .into_keys()
+            .collect(),
+        created_at_ms,
+        updated_at_ms: now,
+        metadata: header.map(|header| header.metadata).unwrap_or_default(),
+    };
+
+    storage::backend(persistence_key)?.write_file(
+        persistence_key,
+        MANIFEST_FILE,
+        &serde_json::to_vec_pretty(&manifest)?,
+    )
+}
@@ -0,0 +1,161 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    hash::Hash,
+};
+
+#[cfg(feature = "tracing")]
+use tracing::debug;
+use url::Url;
+
+use crate::{
+    PersistenceError, PersistentActor, codec,
+    concurrency::{self, Resolution},
+    redact::redacted,
+    storage, transaction,
+};
+
+/// Rounds of reading and writing replicas before `reconcile` gives up on concurrent writers.
+const MAX_RECONCILE_ATTEMPTS: usize = 8;
+
+/// Snapshot with CRDT semantics, so diverged copies can be combined without losing updates.
+///
+/// `merge` must be commutative, associative and idempotent; replicas then converge to the same
+/// state whatever order they are merged in.
+pub trait Merge {
+    /// Merge `other` into `self`.
+    fn merge(&mut self, other: Self);
+}
+
+impl<T: Ord> Merge for BTreeSet<T> {
+    fn merge(&mut self, other: Self) {
+        self.extend(other);
+    }
+}
+
+impl<T: Eq + Hash> Merge for HashSet<T> {
+    fn merge(&mut self, other: Self) {
+        self.extend(other);
+    }
+}
+
+impl<K: Ord, V: Merge> Merge for BTreeMap<K, V> {
+    fn merge(&mut self, other: Self) {
+        for (key, value) in other {
+            match self.get_mut(&key) {
+                Some(existing) => existing.merge(value),
+                None => {
+                    self.insert(key, value);
+                }
+            }
+        }
+    }
+}
+
+impl<K: Eq + Hash, V: Merge> Merge for HashMap<K, V> {
+    fn merge(&mut self, other: Self) {
+        for (key, value) in other {
+            match self.get_mut(&key) {
+                Some(existing) => existing.merge(value),
+                None => {
+                    self.insert(key, value);
+                }
+            }
+        }
+    }
+}
+
+impl<T: Merge> Merge for Option<T> {
+    fn merge(&mut self, other: Self) {
+        match (self.as_mut(), other) {
+            (Some(existing), Some(other)) => existing.merge(other),
+            (None, other) => *self = other,
+            (Some(_), None) => {}
+        }
+    }
+}
+
+/// Resolve conflicting saves of `A` by merging the local and the stored snapshot.
+///
+/// Requires optimistic saves, see `concurrency::set_optimistic`.
+pub fn set_merge_resolver<A>()
+where
+    A: PersistentActor,
+    A::Snapshot: Merge,
+{
+    concurrency::set_resolver::<A>(|local, mut stored| {
+        stored.merge(local.clone());
+        Resolution::Merged(stored)
+    });
+}
+
+/// Merge the snapshots of `A` stored under every replica key and write the result to all of them.
+///
+/// Replicas are copies of the same actor kept under different keys, such as a local and a
+/// central store. Missing replicas are created; each write is a compare-and-swap, and the round
+/// is repeated if another writer got in between. Return the merged snapshot, or `None` if no
+/// replica holds one. Actors running under the keys keep their state; with a merge resolver
+/// their next save merges into the reconciled snapshot.
+pub async fn reconcile<A>(replicas: &[Url]) -> anyhow::Result<Option<A::Snapshot>>
+where
+    A: PersistentActor,
+    A::Snapshot: Merge,
+{
+    for _ in 0..MAX_RECONCILE_ATTEMPTS {
+        let mut stored = Vec::with_capacity(replicas.len());
+        for key in replicas {
+            let backend = storage::backend(key)?;
+            transaction::recover(key)?;
+
+            // Version first, so a snapshot replaced while reading fails the write
+            let version = backend.file_version(key, storage::snapshot_file())?;
+            let data = backend.read_file(key, storage::snapshot_file())?;
+            stored.push((key, version, data));
+        }
+
+        let mut merged: Option<A::Snapshot> = None;
+        for (_, _, data) in &stored {
+            if let Some(data) = data {
+                merged.merge(Some(codec::decode::<A>(data)?));
+            }
+        }
+
+        let Some(merged) = merged else {
+            return Ok(None);
+        };
+
+        // Nothing diverged
+        let first = &stored[0].2;
+        if first.is_some() && stored.iter().all(|(_, _, data)| data == first) {
+            return Ok(Some(merged));
+        }
+
+        // Versions tracked for live actors are left as they are, so their next save conflicts
+        let mut conflicted = false;
+        for (key, version, _) in &stored {
+            let data = codec::encode::<A>(key, &merged)?;
+            conflicted |= storage::write_if(key, version.as_deref(), &data)
+                .await?
+                .is_none();
+        }
+
+        if !conflicted {
+            #[cfg(feature = "tracing")]
+            debug!(
+                "Reconciled {} replicas of {}",
+                replicas.len(),
+                redacted(stored[0].0)
+            );
+
+            return Ok(Some(merged));
+        }
+    }
+
+    Err(PersistenceError::Conflict {
+        type_tag: A::type_tag().to_string(),
+        key: replicas
+            .first()
+            .map(|key| redacted(key).to_string())
+            .unwrap_or_default(),
+    }
+    .into())
+}
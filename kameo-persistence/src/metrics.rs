@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use metrics::{counter, gauge, histogram};
+
+fn outcome(ok: bool) -> &'static str {
+    if ok { "ok" } else { "error" }
+}
+
+/// Record a snapshot save of an actor type.
+pub(crate) fn record_save(actor_type: &'static str, bytes: Option<usize>, elapsed: Duration) {
+    counter!(
+        "kameo_persistence_saves_total",
+        "actor_type" => actor_type,
+        "outcome" => outcome(bytes.is_some())
+    )
+    .increment(1);
+
+    histogram!("kameo_persistence_save_duration_seconds", "actor_type" => actor_type)
+        .record(elapsed.as_secs_f64());
+
+    if let Some(bytes) = bytes {
+        counter!("kameo_persistence_bytes_written_total", "actor_type" => actor_type)
+            .increment(bytes as u64);
+    }
+}
+
+/// Record a restore of an actor type from its snapshot.
+pub(crate) fn record_restore(actor_type: &'static str, ok: bool, elapsed: Duration) {
+    counter!(
+        "kameo_persistence_restores_total",
+        "actor_type" => actor_type,
+        "outcome" => outcome(ok)
+    )
+    .increment(1);
+
+    histogram!("kameo_persistence_restore_duration_seconds", "actor_type" => actor_type)
+        .record(elapsed.as_secs_f64());
+}
+
+/// Record the number of persistence keys registered for an actor type.
+pub(crate) fn record_registry_size(actor_type: &'static str, size: usize) {
+    gauge!("kameo_persistence_registered_actors", "actor_type" => actor_type).set(size as f64);
+}
+
+/// Record the outcome of a snapshot transaction commit.
+pub(crate) fn record_transaction(snapshots: usize, ok: bool, elapsed: Duration) {
+    counter!("kameo_persistence_transactions_total", "outcome" => outcome(ok)).increment(1);
+
+    histogram!("kameo_persistence_transaction_snapshots").record(snapshots as f64);
+    histogram!("kameo_persistence_transaction_duration_seconds").record(elapsed.as_secs_f64());
+}
+
+/// Record the outcome of a health check of the backend of a scheme.
+pub(crate) fn record_health(scheme: &str, healthy: bool, latency: Duration) {
+    gauge!("kameo_persistence_backend_healthy", "scheme" => scheme.to_string()).set(if healthy {
+        1.0
+    } else {
+        0.0
+    });
+
+    histogram!("kameo_persistence_health_check_duration_seconds", "scheme" => scheme.to_string())
+        .record(latency.as_secs_f64());
+}
@@ -0,0 +1,198 @@
+use anyhow::anyhow;
+#[cfg(feature = "tracing")]
+use tracing::{debug, info};
+use url::Url;
+
+use crate::{
+    codec,
+    hierarchy::{self, CHILDREN_FILE, ChildManifest},
+    redact::redacted,
+    registry, storage,
+};
+
+/// Progress of a migration, reported after every key.
+#[derive(Debug, Clone)]
+pub struct MigrateProgress {
+    /// Key just handled, under the source root.
+    pub key: Url,
+    /// Keys handled so far, including `key`.
+    pub done: usize,
+    /// Keys holding a snapshot under the source root.
+    pub total: usize,
+    /// True if `key` was skipped as already migrated.
+    pub skipped: bool,
+}
+
+type ProgressFn = Box<dyn Fn(&MigrateProgress) + Send + Sync>;
+
+/// How `migrate` writes snapshots to the destination.
+#[derive(Default)]
+pub struct MigrateOptions {
+    reencode: bool,
+    legacy_type_tag: Option<String>,
+    resume: bool,
+    on_progress: Option<ProgressFn>,
+}
+
+impl MigrateOptions {
+    /// Copy snapshots as raw bytes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode every snapshot and encode it again for its destination key.
+    ///
+    /// Snapshots are then written in the current format, and encrypted for the subject or
+    /// tenant of their destination key. Their actor types must be registered in this process.
+    pub fn reencode(mut self) -> Self {
+        self.reencode = true;
+        self
+    }
+
+    /// Decode snapshots without a header, written before type tags were stored, as this type.
+    pub fn legacy_type_tag(mut self, type_tag: impl Into<String>) -> Self {
+        self.legacy_type_tag = Some(type_tag.into());
+        self
+    }
+
+    /// Skip keys which already hold a snapshot at the destination.
+    ///
+    /// Snapshots are written atomically, so an interrupted migration can be resumed this way as
+    /// long as the source has not changed in between.
+    pub fn resume(mut self) -> Self {
+        self.resume = true;
+        self
+    }
+
+    /// Call `on_progress` after every key.
+    pub fn on_progress(
+        mut self,
+        on_progress: impl Fn(&MigrateProgress) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_progress = Some(Box::new(on_progress));
+        self
+    }
+}
+
+/// Outcome of a migration.
+#[derive(Debug, Clone, Default)]
+pub struct MigrateReport {
+    /// Destination keys written.
+    pub migrated: Vec<Url>,
+    /// Destination keys skipped as already migrated.
+    pub skipped: Vec<Url>,
+}
+
+/// Copy every snapshot stored under `src_root` to the same relative key below `dst_root`.
+///
+/// The roots may be stored by different backends, e.g. local files and an object store.
+/// Snapshots are read and written one at a time, and child manifests follow their parents with
+/// the child keys below `src_root` rebased. Keys stored inside snapshots are not rewritten.
+/// The source is left untouched.
+pub async fn migrate(
+    src_root: &Url,
+    dst_root: &Url,
+    options: MigrateOptions,
+) -> anyhow::Result<MigrateReport> {
+    let keys = storage::list(src_root).await?;
+    let mut report = MigrateReport::default();
+
+    #[cfg(feature = "tracing")]
+    info!(
+        "Migrating {} snapshots from {} to {}",
+        keys.len(),
+        redacted(src_root),
+        redacted(dst_root)
+    );
+
+    for (index, key) in keys.iter().enumerate() {
+        let target = rebase(key, src_root, dst_root)?;
+
+        let skipped = options.resume
+            && storage::backend(&target)?
+                .file_size(&target, storage::snapshot_file())?
+                .is_some();
+
+        if skipped {
+            report.skipped.push(target);
+        } else {
+            migrate_key(key, &target, src_root, dst_root, &options).await?;
+
+            #[cfg(feature = "tracing")]
+            debug!("Migrated {} to {}", redacted(key), redacted(&target));
+
+            report.migrated.push(target);
+        }
+
+        if let Some(on_progress) = &options.on_progress {
+            on_progress(&MigrateProgress {
+                key: key.clone(),
+                done: index + 1,
+                total: keys.len(),
+                skipped,
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+async fn migrate_key(
+    key: &Url,
+    target: &Url,
+    src_root: &Url,
+    dst_root: &Url,
+    options: &MigrateOptions,
+) -> anyhow::Result<()> {
+    let data = storage::read(key).await?;
+
+    let data = if options.reencode {
+        let type_tag = match codec::split(&data)?.0 {
+            Some(header) => header.type_tag,
+            None => options.legacy_type_tag.clone().ok_or_else(|| {
+                anyhow!("Snapshot stored under {} has no type tag", redacted(key))
+            })?,
+        };
+
+        let registration = registry::registration(&type_tag)
+            .ok_or_else(|| anyhow!("Actor type {type_tag} is not registered"))?;
+
+        (registration.reencode)(target, &data)?
+    } else {
+        data
+    };
+
+    // Children first, so a resumed migration never finds a snapshot without its manifest
+    let backend = storage::backend(key)?;
+    if backend.file_size(key, CHILDREN_FILE)?.is_some() {
+        let manifest = hierarchy::read_manifest(key)?;
+        let rebased = |child: &Url| {
+            storage::rebase(child, src_root, dst_root).unwrap_or_else(|| child.clone())
+        };
+
+        hierarchy::write_manifest(
+            target,
+            &ChildManifest {
+                children: manifest
+                    .children
+                    .iter()
+                    .map(|(child, entry)| (rebased(child), entry.clone()))
+                    .collect(),
+                referenced: manifest.referenced.iter().map(rebased).collect(),
+            },
+        )?;
+    }
+
+    storage::write_atomic(target, &data).await
+}
+
+fn rebase(key: &Url, src_root: &Url, dst_root: &Url) -> anyhow::Result<Url> {
+    storage::rebase(key, src_root, dst_root).ok_or_else(|| {
+        anyhow!(
+            "Cannot rebase {} from {} to {}",
+            redacted(key),
+            redacted(src_root),
+            redacted(dst_root)
+        )
+    })
+}
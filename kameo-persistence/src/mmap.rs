@@ -0,0 +1,175 @@
+use std::{fs::File, io, ops::Deref};
+
+use anyhow::anyhow;
+use kameo::prelude::*;
+use memmap2::Mmap;
+use rkyv::{
+    Archive, Portable,
+    api::high::{HighSerializer, HighValidator},
+    bytecheck::CheckBytes,
+    de::Pool,
+    rancor::{self, Strategy},
+    ser::allocator::ArenaHandle,
+    util::AlignedVec,
+};
+#[cfg(feature = "tracing")]
+use tracing::debug;
+use url::Url;
+
+#[cfg(feature = "audit")]
+use crate::audit::{self, AuditOperation};
+use crate::{
+    PersistentActor, RestoreContext, codec, concurrency,
+    redact::redacted,
+    storage::{self, FileBackend},
+    transaction,
+};
+
+/// Name of the rkyv archive of a snapshot, next to the snapshot file.
+pub const ARCHIVE_FILE: &str = "index.rkyv";
+
+/// Archive being written, until it replaces `ARCHIVE_FILE`.
+const ARCHIVE_TMP_FILE: &str = "index.rkyv.tmp";
+
+/// File of a `file` key mapped into memory, read by the kernel on access instead of copied.
+pub struct MappedFile {
+    map: Mmap,
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+/// Map a file stored under a `file` key, or return `None` if it does not exist.
+///
+/// The file must not be truncated while mapped; snapshots are replaced by renames, which leave
+/// mapped files intact.
+pub fn map(persistence_key: &Url, name: &str) -> anyhow::Result<Option<MappedFile>> {
+    if persistence_key.scheme() != "file" {
+        anyhow::bail!(
+            "Only file keys can be mapped, not {}",
+            redacted(persistence_key)
+        );
+    }
+
+    let file = match File::open(FileBackend::key_dir(persistence_key)?.join(name)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    // Safety: files under keys are only replaced by renames, never modified in place
+    let map = unsafe { Mmap::map(&file)? };
+    Ok(Some(MappedFile { map }))
+}
+
+/// Map the snapshot stored under a `file` key, as `storage::read` reads it.
+pub async fn read_mapped(persistence_key: &Url) -> anyhow::Result<MappedFile> {
+    transaction::recover(persistence_key)?;
+
+    let data = map(persistence_key, storage::snapshot_file())?
+        .ok_or_else(|| anyhow!("No snapshot stored under {}", redacted(persistence_key)))?;
+
+    #[cfg(feature = "audit")]
+    audit::record(AuditOperation::Restore, persistence_key, Some(&data))?;
+
+    Ok(data)
+}
+
+/// Respawn an actor from its snapshot mapped into memory, without copying it first.
+///
+/// Snapshots neither encrypted for a data subject nor migrated from an older schema are
+/// deserialized straight out of the map.
+pub async fn respawn_mapped<A: PersistentActor>(
+    persistence_key: Url,
+) -> anyhow::Result<ActorRef<A>> {
+    if let Some(actor_ref) = A::lookup_persistent(&persistence_key) {
+        return Ok(actor_ref);
+    }
+
+    if concurrency::is_optimistic::<A>() {
+        concurrency::track(&persistence_key)?;
+    }
+
+    let data = read_mapped(&persistence_key).await?;
+    let snapshot = match codec::decode_at::<A>(&persistence_key, &data) {
+        Ok(snapshot) => snapshot,
+        Err(e) if codec::is_corruption(&e, &data) => {
+            drop(data);
+            A::fall_back(&persistence_key, e).await?
+        }
+        Err(e) => return Err(e),
+    };
+
+    A::spawn_persistent(
+        persistence_key,
+        A::restore_args(snapshot, &RestoreContext::global())?,
+    )
+    .await
+}
+
+/// Write the rkyv archive of a snapshot under a key, replacing the previous one atomically.
+///
+/// Archives are kept next to the regular snapshot and are neither encrypted, checksummed by
+/// the crate nor migrated; rkyv validates them when accessed.
+pub async fn write_archived<T>(persistence_key: &Url, value: &T) -> anyhow::Result<()>
+where
+    T: for<'a> rkyv::Serialize<HighSerializer<AlignedVec, ArenaHandle<'a>, rancor::Error>>,
+{
+    let bytes = rkyv::to_bytes::<rancor::Error>(value)?;
+
+    let backend = storage::backend(persistence_key)?;
+    backend.write_file(persistence_key, ARCHIVE_TMP_FILE, &bytes)?;
+    backend.rename_file(persistence_key, ARCHIVE_TMP_FILE, ARCHIVE_FILE)?;
+
+    #[cfg(feature = "tracing")]
+    debug!(
+        "Archived {} bytes under {}",
+        bytes.len(),
+        redacted(persistence_key)
+    );
+
+    Ok(())
+}
+
+/// Validate and access the archive in a mapped file in place.
+pub fn access_archived<T>(file: &MappedFile) -> anyhow::Result<&T::Archived>
+where
+    T: Archive,
+    T::Archived: Portable + for<'a> CheckBytes<HighValidator<'a, rancor::Error>>,
+{
+    Ok(rkyv::access::<T::Archived, rancor::Error>(file)?)
+}
+
+/// Respawn an actor from the rkyv archive of its snapshot, see `write_archived`.
+///
+/// The archive is mapped, validated and deserialized straight into the snapshot.
+pub async fn respawn_archived<A>(persistence_key: Url) -> anyhow::Result<ActorRef<A>>
+where
+    A: PersistentActor,
+    A::Snapshot: Archive,
+    <A::Snapshot as Archive>::Archived: Portable
+        + for<'a> CheckBytes<HighValidator<'a, rancor::Error>>
+        + rkyv::Deserialize<A::Snapshot, Strategy<Pool, rancor::Error>>,
+{
+    if let Some(actor_ref) = A::lookup_persistent(&persistence_key) {
+        return Ok(actor_ref);
+    }
+
+    let file = map(&persistence_key, ARCHIVE_FILE)?
+        .ok_or_else(|| anyhow!("No archive stored under {}", redacted(&persistence_key)))?;
+
+    let archived = access_archived::<A::Snapshot>(&file)?;
+    let snapshot = rkyv::deserialize::<A::Snapshot, rancor::Error>(archived)?;
+    drop(file);
+
+    A::spawn_persistent(
+        persistence_key,
+        A::restore_args(snapshot, &RestoreContext::global())?,
+    )
+    .await
+}
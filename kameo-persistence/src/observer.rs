@@ -0,0 +1,72 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use url::Url;
+
+/// Hooks notified of persistence operations, for custom logging, auditing or alerting.
+///
+/// Every method defaults to doing nothing. Hooks run inline with the operation, so they should
+/// return quickly.
+pub trait PersistenceObserver: Send + Sync + 'static {
+    /// A snapshot save started.
+    fn on_save_start(&self, _actor_type: &'static str, _key: &Url) {}
+
+    /// A snapshot of `bytes` bytes was saved.
+    fn on_save_ok(&self, _actor_type: &'static str, _key: &Url, _bytes: usize, _elapsed: Duration) {
+    }
+
+    /// A snapshot save failed.
+    fn on_save_err(&self, _actor_type: &'static str, _key: &Url, _error: &anyhow::Error) {}
+
+    /// An actor was restored from its snapshot.
+    fn on_restore_ok(&self, _actor_type: &'static str, _key: &Url, _elapsed: Duration) {}
+
+    /// An actor could not be restored from its snapshot.
+    fn on_restore_err(&self, _actor_type: &'static str, _key: &Url, _error: &anyhow::Error) {}
+
+    /// An actor was registered under a persistence key.
+    fn on_register(&self, _actor_type: &'static str, _key: &Url) {}
+}
+
+static OBSERVERS: RwLock<Vec<Arc<dyn PersistenceObserver>>> = RwLock::new(Vec::new());
+
+/// Install an observer for the whole process.
+pub fn add_observer(observer: impl PersistenceObserver) {
+    OBSERVERS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(Arc::new(observer));
+}
+
+/// Remove every installed observer.
+pub fn clear_observers() {
+    OBSERVERS.write().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Save reported to observers as failed if dropped before it finished, so every
+/// `on_save_start` is followed by `on_save_ok` or `on_save_err` even when the save is cancelled.
+pub(crate) struct SaveReport<'a> {
+    pub actor_type: &'static str,
+    pub key: &'a Url,
+    pub finished: bool,
+}
+
+impl Drop for SaveReport<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let e = anyhow::anyhow!("Save was cancelled");
+            notify(|o| o.on_save_err(self.actor_type, self.key, &e));
+        }
+    }
+}
+
+/// Call `f` on every installed observer.
+pub(crate) fn notify(f: impl Fn(&dyn PersistenceObserver)) {
+    let observers = OBSERVERS.read().unwrap_or_else(|e| e.into_inner());
+
+    for observer in observers.iter() {
+        f(observer.as_ref());
+    }
+}
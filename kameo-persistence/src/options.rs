@@ -0,0 +1,162 @@
+use std::borrow::Cow;
+
+#[cfg(feature = "json")]
+use anyhow::anyhow;
+use url::Url;
+
+#[cfg(feature = "zstd")]
+use crate::dictionary;
+use crate::storage;
+#[cfg(feature = "json")]
+use crate::{codec, hooks, json::JsonSnapshot, redact::redacted, registry};
+
+/// First bytes of a zstd frame.
+#[cfg(feature = "zstd")]
+const ZSTD_MAGIC: &[u8; 4] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Encoding of the snapshots stored under a key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// The binary encoding of `codec`.
+    #[default]
+    Postcard,
+    /// The canonical JSON of `json::export`, readable without the crate. Requires the `json`
+    /// feature; snapshots encrypted by `protect` or run through a `hooks::SnapshotHook` stay
+    /// binary.
+    Json,
+}
+
+/// Compression of the snapshots stored under a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Requires the `zstd` feature; compressed with the dictionary of
+    /// `dictionary::use_dictionary`, if any.
+    Zstd,
+}
+
+/// Options of the storage of a key, from its query string, such as
+/// `file:///data/actor?fsync=true&format=json&compress=zstd`.
+///
+/// Options are honored by the file system backends, and apply to the snapshot files only;
+/// unknown parameters are ignored, as other backends may read their own. Keys differing only in
+/// their query are distinct keys to the registry, but share their storage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyOptions {
+    /// Flush files to disk before a write returns; `fsync=true`.
+    pub fsync: bool,
+    /// `format=postcard` or `format=json`.
+    pub format: Format,
+    /// `compress=zstd`.
+    pub compress: Option<Compression>,
+}
+
+impl KeyOptions {
+    /// Parse the options out of the query string of a key.
+    pub fn parse(key: &Url) -> anyhow::Result<Self> {
+        let mut options = Self::default();
+        if key.query().is_none() {
+            return Ok(options);
+        }
+
+        for (name, value) in key.query_pairs() {
+            match &*name {
+                "fsync" => options.fsync = parse_bool(&name, &value)?,
+                "format" => {
+                    options.format = match &*value {
+                        "postcard" => Format::Postcard,
+                        #[cfg(feature = "json")]
+                        "json" => Format::Json,
+                        #[cfg(not(feature = "json"))]
+                        "json" => anyhow::bail!("format=json requires the json feature"),
+                        _ => anyhow::bail!("Unknown format {value:?}"),
+                    }
+                }
+                "compress" => {
+                    options.compress = match &*value {
+                        "none" => None,
+                        #[cfg(feature = "zstd")]
+                        "zstd" => Some(Compression::Zstd),
+                        #[cfg(not(feature = "zstd"))]
+                        "zstd" => anyhow::bail!("compress=zstd requires the zstd feature"),
+                        _ => anyhow::bail!("Unknown compression {value:?}"),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse_bool(name: &str, value: &str) -> anyhow::Result<bool> {
+    match value {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => anyhow::bail!("Invalid value {value:?} of {name}, expected true or false"),
+    }
+}
+
+/// Encode a file to store under a key as its options ask.
+///
+/// Only snapshot files are encoded, see `storage::is_snapshot_file`; other files, such as
+/// attachments, are stored as they are.
+pub(crate) fn encode_file<'a>(
+    key: &Url,
+    name: &str,
+    data: &'a [u8],
+) -> anyhow::Result<Cow<'a, [u8]>> {
+    if !storage::is_snapshot_file(name) {
+        return Ok(Cow::Borrowed(data));
+    }
+
+    let options = KeyOptions::parse(key)?;
+    #[cfg_attr(not(feature = "json"), allow(unused_mut))]
+    let mut data = Cow::Borrowed(data);
+
+    #[cfg(feature = "json")]
+    if options.format == Format::Json && data.starts_with(codec::MAGIC) {
+        // Payloads encrypted, or transformed by a hook, must not be stored in the clear
+        if let (Some(header), _) = codec::split(&data)?
+            && header.subject.is_none()
+            && !header.metadata.contains_key(hooks::METADATA_KEY)
+        {
+            let snapshot = crate::json::export_bytes(key, &data)?;
+            data = Cow::Owned(snapshot.to_canonical_string()?.into_bytes());
+        }
+    }
+
+    match options.compress {
+        #[cfg(feature = "zstd")]
+        Some(Compression::Zstd) => Ok(Cow::Owned(dictionary::compress(&data)?)),
+        _ => Ok(data),
+    }
+}
+
+/// Decode a file read from under a key as its options say it was stored.
+///
+/// Snapshots stored before an option was set on the key, still uncompressed or binary, are
+/// read as they are; other files are never decoded.
+pub(crate) fn decode_file(key: &Url, name: &str, data: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    if !storage::is_snapshot_file(name) {
+        return Ok(data);
+    }
+
+    let options = KeyOptions::parse(key)?;
+    let data = match options.compress {
+        #[cfg(feature = "zstd")]
+        Some(Compression::Zstd) if data.starts_with(ZSTD_MAGIC) => dictionary::decompress(&data)?,
+        _ => data,
+    };
+
+    #[cfg(feature = "json")]
+    if options.format == Format::Json && data.first() == Some(&b'{') {
+        let snapshot = serde_json::from_slice::<JsonSnapshot>(&data)
+            .map_err(|e| anyhow!("JSON snapshot of {} is corrupt: {e}", redacted(key)))?;
+        let registration = registry::registration(&snapshot.type_tag)
+            .ok_or_else(|| anyhow!("Actor type {} is not registered", snapshot.type_tag))?;
+        return (registration.from_json)(key, snapshot.snapshot);
+    }
+
+    Ok(data)
+}
@@ -0,0 +1,289 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use anyhow::anyhow;
+use serde::{Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::{
+    codec::{self, SnapshotHeader},
+    hooks, protect,
+    redact::redacted,
+    segment::Segment,
+    storage::{self, PREVIOUS_FILE},
+};
+
+/// Prefix of the files holding parts, so they never collide with the crate's own files.
+const PART_PREFIX: &str = "part-";
+
+/// Prefix of the header metadata entries naming the file of each part.
+const METADATA_PREFIX: &str = "part:";
+
+/// Suffix of a part being written, until it replaces the part.
+const TMP_SUFFIX: &str = ".tmp";
+
+/// Named parts split off a snapshot by `PersistentActor::split_parts`, such as a large map,
+/// each stored in a file of its own next to the snapshot file.
+///
+/// Part files are named after their content, so parts unchanged since the last save are not
+/// written again, and the snapshot header names the files of its parts, so a snapshot is always
+/// read with the parts it was written with.
+#[derive(Default)]
+pub struct SnapshotParts {
+    parts: BTreeMap<String, Part>,
+    // Files the parts were read from, by name
+    files: BTreeMap<String, String>,
+}
+
+enum Part {
+    Data(Vec<u8>),
+    /// Serialized only if changed since it was stored.
+    Segment(Box<dyn SegmentPart>),
+}
+
+/// `Segment` split off a snapshot, see `SnapshotParts::insert_segment`.
+pub(crate) trait SegmentPart: Send + Sync {
+    /// File the segment is stored in, unless changed since.
+    fn stored(&self) -> Option<String>;
+
+    fn encode(&self) -> anyhow::Result<Vec<u8>>;
+
+    /// Record the file the segment was written to.
+    fn stored_in(&self, file: &str);
+}
+
+impl SnapshotParts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store `value` as the part `name`, replacing any; names are ASCII letters, digits and
+    /// underscores.
+    pub fn insert<T: Serialize>(&mut self, name: &str, value: &T) -> anyhow::Result<()> {
+        check_name(name)?;
+        self.parts
+            .insert(name.to_string(), Part::Data(postcard::to_allocvec(value)?));
+        Ok(())
+    }
+
+    /// Store `segment` as the part `name`, serialized only if it changed since it was last
+    /// written or restored, and leave it out of the snapshot it belongs to.
+    pub fn insert_segment<T>(&mut self, name: &str, segment: &mut Segment<T>) -> anyhow::Result<()>
+    where
+        T: Serialize + Send + Sync + 'static,
+    {
+        check_name(name)?;
+        self.parts
+            .insert(name.to_string(), Part::Segment(Box::new(segment.detach())));
+        Ok(())
+    }
+
+    /// Remove the part `name` and deserialize it, if any.
+    pub fn take<T: DeserializeOwned>(&mut self, name: &str) -> anyhow::Result<Option<T>> {
+        let data = match self.parts.remove(name) {
+            Some(Part::Data(data)) => data,
+            Some(Part::Segment(segment)) => segment.encode()?,
+            None => return Ok(None),
+        };
+        Ok(Some(postcard::from_bytes(&data)?))
+    }
+
+    /// Put the part `name` back into `segment`, if any, remembering the file it was read from
+    /// so it is not written again until changed.
+    pub fn take_segment<T: DeserializeOwned>(
+        &mut self,
+        name: &str,
+        segment: &mut Segment<T>,
+    ) -> anyhow::Result<()> {
+        let Some(value) = self.take(name)? else {
+            return Ok(());
+        };
+
+        *segment = Segment::restored(value, self.files.get(name).cloned());
+        Ok(())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.parts.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.parts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parts.is_empty()
+    }
+}
+
+impl fmt::Debug for SnapshotParts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
+
+fn check_name(name: &str) -> anyhow::Result<()> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        anyhow::bail!("Invalid snapshot part name: {name:?}");
+    }
+    Ok(())
+}
+
+/// Write the parts not stored under a key yet, encrypted for `subject` if any, and return the
+/// header metadata entries naming the files of every part.
+pub(crate) fn write(
+    persistence_key: &Url,
+    subject: Option<&str>,
+    parts: &SnapshotParts,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut files = BTreeMap::new();
+    if parts.is_empty() {
+        return Ok(files);
+    }
+
+    let backend = storage::backend(persistence_key)?;
+    let tag = hooks::names().map(|names| hooks_tag(&names));
+    let store = |name: &str, data: &[u8]| -> anyhow::Result<String> {
+        let file = file_name(name, data, tag.as_deref());
+
+        if backend.file_size(persistence_key, &file)?.is_none() {
+            let data = match subject {
+                Some(subject) => Cow::Owned(protect::encrypt_for_subject(subject, data)?),
+                None => Cow::Borrowed(data),
+            };
+            let (data, _) = hooks::before_write(data)?;
+
+            let tmp = format!("{file}{TMP_SUFFIX}");
+            backend.write_file(persistence_key, &tmp, &data)?;
+            backend.rename_file(persistence_key, &tmp, &file)?;
+        }
+
+        Ok(file)
+    };
+
+    for (name, part) in &parts.parts {
+        let file = match part {
+            Part::Data(data) => store(name, data)?,
+            Part::Segment(segment) => {
+                // Also written again if stored under another key, such as for a fork, or through
+                // other hooks
+                let stored = segment.stored().filter(|file| {
+                    file_tag(file) == tag.as_deref()
+                        && backend
+                            .file_size(persistence_key, file)
+                            .is_ok_and(|size| size.is_some())
+                });
+
+                match stored {
+                    Some(file) => file,
+                    None => {
+                        let file = store(name, &segment.encode()?)?;
+                        segment.stored_in(&file);
+                        file
+                    }
+                }
+            }
+        };
+
+        files.insert(format!("{METADATA_PREFIX}{name}"), file);
+    }
+
+    Ok(files)
+}
+
+/// Read the parts the header of a snapshot stored under a key names, or `None` if it names
+/// none.
+pub(crate) fn read(
+    persistence_key: &Url,
+    header: &SnapshotHeader,
+) -> anyhow::Result<Option<SnapshotParts>> {
+    let mut files = part_files(header).peekable();
+    if files.peek().is_none() {
+        return Ok(None);
+    }
+
+    let backend = storage::backend(persistence_key)?;
+    let tag = header
+        .metadata
+        .get(hooks::METADATA_KEY)
+        .map(|names| hooks_tag(names));
+    let mut parts = SnapshotParts::new();
+    for (name, file) in files {
+        let data = backend
+            .read_file(persistence_key, file)?
+            .ok_or_else(|| anyhow!("Part {name} of {} is missing", redacted(persistence_key)))?;
+
+        let data = hooks::after_read(header, Cow::Owned(data))?.into_owned();
+        let data = match &header.subject {
+            Some(subject) => protect::decrypt_for_subject(subject, &data)?,
+            None => data,
+        };
+
+        if file != file_name(name, &data, tag.as_deref()) {
+            anyhow::bail!("Part {name} of {} is corrupt", redacted(persistence_key));
+        }
+
+        parts.parts.insert(name.to_string(), Part::Data(data));
+        parts.files.insert(name.to_string(), file.to_string());
+    }
+
+    Ok(Some(parts))
+}
+
+/// Remove the part files under a key that neither `files`, the parts of the snapshot just
+/// written, nor the previous snapshot name.
+pub(crate) fn prune(persistence_key: &Url, files: &BTreeMap<String, String>) -> anyhow::Result<()> {
+    let backend = storage::backend(persistence_key)?;
+
+    let mut keep = files.values().cloned().collect::<BTreeSet<_>>();
+    if let Some(previous) = backend.read_file(persistence_key, PREVIOUS_FILE)?
+        && let Ok((Some(header), _)) = codec::split(&previous)
+    {
+        keep.extend(part_files(&header).map(|(_, file)| file.to_string()));
+    }
+
+    for file in backend.list_files(persistence_key)? {
+        if file.starts_with(PART_PREFIX) && !keep.contains(&file) {
+            backend.remove_file(persistence_key, &file)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Names and files of the parts a snapshot header names.
+fn part_files(header: &SnapshotHeader) -> impl Iterator<Item = (&str, &str)> {
+    header.metadata.iter().filter_map(|(entry, file)| {
+        entry
+            .strip_prefix(METADATA_PREFIX)
+            .map(|name| (name, file.as_str()))
+    })
+}
+
+/// File of a part, named after its content and the hooks it is written through, if any.
+fn file_name(name: &str, data: &[u8], tag: Option<&str>) -> String {
+    match tag {
+        Some(tag) => format!("{PART_PREFIX}{name}-{}-{tag}", digest(data)),
+        None => format!("{PART_PREFIX}{name}-{}", digest(data)),
+    }
+}
+
+/// Tag of the hooks a part file was written through, if any; names and digests hold no `-`.
+fn file_tag(file: &str) -> Option<&str> {
+    file.splitn(4, '-').nth(3)
+}
+
+fn hooks_tag(names: &str) -> String {
+    digest(names.as_bytes())[..8].to_string()
+}
+
+fn digest(data: &[u8]) -> String {
+    Sha256::digest(data)[..16]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
@@ -3,26 +3,33 @@ use kameo::prelude::*;
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "tracing")]
 use std::any;
+use std::collections::BTreeMap;
+use std::time::Instant;
 #[cfg(feature = "tracing")]
-use std::fmt::Debug;
-#[cfg(feature = "tracing")]
-use tracing::{debug, trace, warn};
+use tracing::{Instrument, debug, trace, warn};
 use url::Url;
 
+#[cfg(feature = "metrics")]
+use crate::metrics;
+use crate::{
+    PersistenceError,
+    autosave::{self, Autosave, AutosaveOutcome},
+    background::{self, BackgroundSave},
+    buffer,
+    checkpoint::{Captured, Checkpoint},
+    codec, concurrency,
+    context::RestoreContext,
+    crash_loop, generation, hierarchy, inflight,
+    observer::{self, SaveReport},
+    parts::{self, SnapshotParts},
+    redact::redacted,
+    registry, storage,
+};
+#[cfg(feature = "tracing")]
+use crate::{logging, spans};
+
 // todo Make deriving macro for this trait
 pub trait PersistentActor: Actor {
-    #[cfg(feature = "tracing")]
-    type Snapshot: Debug
-        + Clone
-        + Send
-        + Sync
-        + Serialize
-        + for<'a> Deserialize<'a>
-        + Into<<Self as Actor>::Args>
-        + for<'a> From<&'a Self>;
-    // + for<'a> TryFrom<&'a Url>; // Usually Self::Args
-
-    #[cfg(not(feature = "tracing"))]
     type Snapshot: Clone
         + Send
         + Sync
@@ -30,6 +37,7 @@ pub trait PersistentActor: Actor {
         + for<'a> Deserialize<'a>
         + Into<<Self as Actor>::Args>
         + for<'a> From<&'a Self>;
+    // + for<'a> TryFrom<&'a Url>; // Usually Self::Args
 
     /// Per "Actor" unique key for persistent storage
     // One could use other kind of permanent storage, but it should be directory like structure
@@ -51,13 +59,169 @@ pub trait PersistentActor: Actor {
     /// Return an existing persistent actor reference if it exists.
     fn lookup_persistent(persistence_key: &Url) -> Option<ActorRef<Self>>;
 
+    /// Forget the actor registered under a key, so it is no longer looked up.
+    ///
+    /// The derive macro removes it from its registry; the default only from the process-wide one.
+    fn unregister_persistent(persistence_key: &Url) {
+        registry::unregister(persistence_key);
+    }
+
+    /// Forget every registered actor of this type.
+    ///
+    /// Used to simulate a process restart in tests; the derive macro clears its registry.
+    fn clear_registry() {}
+
+    /// Number of live and dead actors in the registry of this type, see
+    /// `registry::registry_stats`.
+    ///
+    /// The derive macro counts its registry; the default counts the process-wide registry.
+    fn registry_counts() -> (usize, usize) {
+        registry::process_counts(Self::type_tag())
+    }
+
+    /// Stable name of the actor type, stored with its snapshots.
+    ///
+    /// Part of the storage format: snapshots are only restored as the type with the tag they
+    /// were saved with, others failing with `PersistenceError::TypeMismatch`. The derive macro
+    /// tags types with their module path and name, so moving or renaming one needs its former
+    /// tag kept with `#[persistent(type_tag = "...")]`.
+    fn type_tag() -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Version of the snapshot schema, stored with every snapshot.
+    ///
+    /// Bump it when the snapshot type changes incompatibly, and register the step from the
+    /// previous version with `schema::register_migration`.
+    fn schema_version() -> u32 {
+        0
+    }
+
+    /// Data subject whose key encrypts the snapshot, for crypto-shredding.
+    ///
+    /// Requires a key store installed with `protect::set_subject_keys`.
+    fn data_subject(_snapshot: &Self::Snapshot) -> Option<String> {
+        None
+    }
+
+    /// Custom metadata stored in the header of the snapshot, such as a migration marker or an
+    /// operator note, readable with `generation::snapshot_metadata` without decoding it.
+    fn metadata(_snapshot: &Self::Snapshot) -> BTreeMap<String, String> {
+        BTreeMap::new()
+    }
+
+    /// Move large fields out of the snapshot into named parts, each stored in a file of its own
+    /// and only written when changed, such as `parts.insert("large_map", &snapshot.large_map)`
+    /// after taking the map out.
+    ///
+    /// Parts are split off by `try_write` only; other saves store the whole snapshot.
+    fn split_parts(_snapshot: &mut Self::Snapshot) -> anyhow::Result<SnapshotParts> {
+        Ok(SnapshotParts::new())
+    }
+
+    /// Put the parts split off by `split_parts` back into the snapshot, when it is restored.
+    fn join_parts(_snapshot: &mut Self::Snapshot, _parts: SnapshotParts) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Persistence keys of the persistent actors owned by this actor.
+    ///
+    /// Used to discover descendants when checkpointing a hierarchy.
+    fn persistent_children(&self) -> Vec<Url> {
+        Vec::new()
+    }
+
+    /// Save the snapshot for an autosave, unless it did not change since the previous one or the
+    /// actor is backlogged.
+    fn on_autosave(
+        &self,
+        actor_ref: &ActorRef<Self>,
+        autosave: Autosave,
+    ) -> impl Future<Output = ()> + Send {
+        // Checked before capturing the state, so a backlogged actor is not held up
+        let captured = match autosave.backlog() {
+            Some(waited) => Err(waited),
+            None => Ok((Self::Snapshot::from(self), self.persistent_children())),
+        };
+        let persistence_key = Self::persistence_key(actor_ref);
+
+        Box::pin(async move {
+            let result = async {
+                let key = persistence_key.ok_or_else(|| {
+                    anyhow!("Actor {} is not persistent", std::any::type_name::<Self>())
+                })?;
+
+                let (snapshot, children) = match captured {
+                    Ok(captured) => captured,
+                    Err(waited) => return Ok(AutosaveOutcome::Deferred { waited }),
+                };
+
+                let fingerprint = autosave::fingerprint(&snapshot)?;
+                if autosave.last_fingerprint() == Some(fingerprint) {
+                    return Ok(AutosaveOutcome::Unchanged);
+                }
+
+                let settled = background::settle(&key).await;
+                Self::try_write(&key, snapshot).await?;
+                if let Some(settled) = settled {
+                    settled.written();
+                }
+                hierarchy::record_references(&key, &children)?;
+
+                let bytes = storage::backend(&key)?
+                    .file_size(&key, storage::snapshot_file())?
+                    .unwrap_or(0);
+                Ok(AutosaveOutcome::Saved { fingerprint, bytes })
+            }
+            .await;
+
+            autosave.report(result);
+        })
+    }
+
+    /// Capture the snapshot for a checkpoint and hold until the checkpoint is released.
+    fn on_checkpoint(
+        &self,
+        actor_ref: &ActorRef<Self>,
+        checkpoint: Checkpoint,
+    ) -> impl Future<Output = ()> + Send {
+        let snapshot = Self::Snapshot::from(self);
+        let children = self.persistent_children();
+        let persistence_key = Self::persistence_key(actor_ref);
+
+        Box::pin(async move {
+            // The checkpoint was abandoned before reaching this actor
+            if checkpoint.is_released() {
+                return;
+            }
+
+            let result = match persistence_key {
+                Some(key) => codec::encode::<Self>(&key, &snapshot).map(|data| Captured {
+                    key,
+                    data,
+                    children,
+                }),
+                None => Err(anyhow!(
+                    "Actor {} is not persistent",
+                    std::any::type_name::<Self>()
+                )),
+            };
+
+            checkpoint.capture(result).await;
+        })
+    }
+
     /// Save the current state of the actor to the persistent storage.
     fn save_snapshot(
         &self,
         actor_ref: &ActorRef<Self>,
-    ) -> impl Future<Output = anyhow::Result<()>> {
+    ) -> impl Future<Output = anyhow::Result<()>> + Send {
+        let persistence_key = Self::persistence_key(actor_ref);
+        let snapshot = Self::Snapshot::from(self);
+        let children = self.persistent_children();
+
         Box::pin(async move {
-            let Some(key) = Self::persistence_key(actor_ref) else {
+            let Some(key) = persistence_key else {
                 #[cfg(feature = "tracing")]
                 trace!(
                     "Actor {} is not persistent, skipping snapshot save.",
@@ -66,48 +230,154 @@ pub trait PersistentActor: Actor {
                 return Ok(());
             };
 
-            let snapshot = Self::Snapshot::from(self);
+            let settled = background::settle(&key).await;
+            Self::try_write(&key, snapshot).await?;
+            if let Some(settled) = settled {
+                settled.written();
+            }
+
+            hierarchy::record_references(&key, &children)?;
+
+            Ok(())
+        })
+    }
+
+    /// Capture the snapshot and save it on a background task, so encoding and writing a large
+    /// snapshot do not hold up the actor's messages.
+    ///
+    /// Background saves of a key are written in the order they were captured, skipping those a
+    /// newer save superseded before they started; foreground saves wait for the one being
+    /// written. Wait on the returned handle for the outcome.
+    fn save_snapshot_in_background(
+        &self,
+        actor_ref: &ActorRef<Self>,
+    ) -> anyhow::Result<BackgroundSave> {
+        let key = Self::persistence_key(actor_ref)
+            .ok_or_else(|| anyhow!("Actor {} is not persistent", std::any::type_name::<Self>()))?;
+        let snapshot = Self::Snapshot::from(self);
+        let children = self.persistent_children();
 
+        Ok(background::spawn(key.clone(), async move {
             Self::try_write(&key, snapshot).await?;
+            hierarchy::record_references(&key, &children)
+        }))
+    }
+
+    /// Replace the state of the running actor with its latest saved snapshot, rebuilding it with
+    /// `on_start` from the snapshot's `Args`, see `RollbackExt::rollback_to_snapshot`.
+    ///
+    /// The actor is left unchanged if the snapshot cannot be read or the actor rebuilt.
+    fn on_rollback(
+        &mut self,
+        actor_ref: &ActorRef<Self>,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send {
+        let persistence_key = Self::persistence_key(actor_ref);
+        let actor_ref = actor_ref.clone();
+
+        Box::pin(async move {
+            let key = persistence_key.ok_or_else(|| {
+                anyhow!("Actor {} is not persistent", std::any::type_name::<Self>())
+            })?;
+
+            // Before reading, so a snapshot replaced in between makes the next save conflict
+            if concurrency::is_optimistic::<Self>() {
+                concurrency::track(&key)?;
+            }
+
+            let data = Self::try_read(&key).await?;
+            let snapshot = codec::decode_at::<Self>(&key, &data)?;
+            let args = Self::restore_args(snapshot, &RestoreContext::global())?;
+
+            *self = Self::on_start(args, actor_ref).await.map_err(|e| {
+                anyhow!(
+                    "Failed to rebuild {} from its snapshot: {e:?}",
+                    redacted(&key)
+                )
+            })?;
+
+            #[cfg(feature = "tracing")]
+            debug!("Rolled {} back to its snapshot", redacted(&key));
 
             Ok(())
         })
     }
 
     /// Spawn a new persistent actor with the given arguments.
+    ///
+    /// The actor is spawned and registered without yielding in between, so a dropped call never
+    /// leaves an unregistered actor running; it is killed if it cannot be registered.
     fn spawn_persistent(
         persistence_key: Url,
         args: <Self as Actor>::Args,
-    ) -> impl Future<Output = anyhow::Result<ActorRef<Self>>> {
+    ) -> impl Future<Output = anyhow::Result<ActorRef<Self>>> + Send {
         Box::pin(async move {
+            if concurrency::is_optimistic::<Self>() {
+                concurrency::track_if_untracked(&persistence_key)?;
+            }
+
             let actor_ref = Self::spawn(args);
 
-            Self::register_persistent(persistence_key, &actor_ref)?;
+            if let Err(e) = Self::register_persistent(persistence_key, &actor_ref) {
+                actor_ref.kill();
+                return Err(e);
+            }
 
             Ok(actor_ref)
         })
     }
 
+    /// Build the `Args` to restore an actor with from its snapshot and the runtime dependencies
+    /// it cannot store, such as database pools; defaults to `Into`.
+    fn restore_args(
+        snapshot: Self::Snapshot,
+        _context: &RestoreContext,
+    ) -> anyhow::Result<<Self as Actor>::Args> {
+        Ok(snapshot.into())
+    }
+
     /// Respawn a persistent actor from the persistent storage.
+    ///
+    /// Cancellation safe: dropping the call before it returns leaves no actor registered, or
+    /// one registered and fully restored that the next call returns.
     fn respawn_persistent(
         persistence_key: Url,
-    ) -> impl Future<Output = anyhow::Result<ActorRef<Self>>> {
+    ) -> impl Future<Output = anyhow::Result<ActorRef<Self>>> + Send {
+        Self::respawn_persistent_with(persistence_key, RestoreContext::new())
+    }
+
+    /// Respawn a persistent actor from the persistent storage, passing `context` on top of the
+    /// dependencies registered with `context::provide` to `restore_args`.
+    fn respawn_persistent_with(
+        persistence_key: Url,
+        context: RestoreContext,
+    ) -> impl Future<Output = anyhow::Result<ActorRef<Self>>> + Send {
+        respawn_from::<Self>(persistence_key, context, None)
+    }
+
+    /// Move a corrupt snapshot aside and decode the previous one instead, if it was kept.
+    ///
+    /// Fails with `error` if no previous snapshot was kept, see `storage::set_keep_previous`.
+    fn fall_back(
+        persistence_key: &Url,
+        error: anyhow::Error,
+    ) -> impl Future<Output = anyhow::Result<Self::Snapshot>> + Send {
         Box::pin(async move {
-            if let Some(actor_ref) = Self::lookup_persistent(&persistence_key) {
-                #[cfg(feature = "tracing")]
-                trace!(
-                    "Found existing persistent actor {} with key {persistence_key:?}.",
-                    any::type_name::<Self>(),
-                );
-                return Ok(actor_ref);
-            }
+            let _quarantined = storage::quarantine(persistence_key)?;
 
-            let data = Self::try_read(&persistence_key).await?;
-            let snapshot: Self::Snapshot = postcard::from_bytes(&data)?;
+            let Some(previous) = storage::restore_previous(persistence_key).await? else {
+                return Err(error.context(format!(
+                    "Snapshot of {} is corrupt and no previous snapshot was kept",
+                    redacted(persistence_key)
+                )));
+            };
 
-            let actor_ref = Self::spawn_persistent(persistence_key, snapshot.into()).await?;
+            #[cfg(feature = "tracing")]
+            warn!(
+                "Snapshot of {} is corrupt ({error:#}), moved aside as {_quarantined}; restoring the previous one",
+                redacted(persistence_key),
+            );
 
-            Ok(actor_ref)
+            codec::decode_at::<Self>(persistence_key, &previous)
         })
     }
 
@@ -115,15 +385,23 @@ pub trait PersistentActor: Actor {
     fn try_respawn_persistent(
         persistence_key: Url,
         args: <Self as Actor>::Args,
-    ) -> impl Future<Output = anyhow::Result<ActorRef<Self>>> {
+    ) -> impl Future<Output = anyhow::Result<ActorRef<Self>>> + Send {
         Box::pin(async move {
             match Self::respawn_persistent(persistence_key.clone()).await {
                 Ok(actor_ref) => Ok(actor_ref),
+                // Never replace another actor type's snapshot with a fresh instance
+                Err(e)
+                    if e.downcast_ref::<PersistenceError>()
+                        .is_some_and(|e| matches!(e, PersistenceError::TypeMismatch { .. })) =>
+                {
+                    Err(e)
+                }
                 Err(_e) => {
                     #[cfg(feature = "tracing")]
                     warn!(
-                        "Failed to respawn persistent actor {} with key {persistence_key:?}: {_e}. Creating a new instance.",
+                        "Failed to respawn persistent actor {} with key {}: {_e}. Creating a new instance.",
                         any::type_name::<Self>(),
+                        redacted(&persistence_key),
                     );
                     Self::spawn_persistent(persistence_key, args).await
                 }
@@ -132,65 +410,196 @@ pub trait PersistentActor: Actor {
     }
 
     /// Try to read the persistent actor's snapshot from the persistent storage.
-    fn try_read(persistence_key: &Url) -> impl Future<Output = anyhow::Result<Vec<u8>>> {
-        Box::pin(async move {
-            match persistence_key.scheme() {
-                "file" => {
-                    let path = persistence_key
-                        .to_file_path()
-                        .map_err(|_| anyhow!("Failed to convert Url to file path"))?;
-
-                    if !path.exists() {
-                        anyhow::bail!("persistence key does not exist: {path:?}");
-                    }
-
-                    Ok(std::fs::read(&path.join("index.bin"))?)
-                }
-                // todo Support http(s), Ws(s), S3, etc.
-                _ => Err(anyhow!(
-                    "Unsupported scheme for persistence key: {}",
-                    persistence_key.scheme()
-                )),
-            }
-        })
+    fn try_read(persistence_key: &Url) -> impl Future<Output = anyhow::Result<Vec<u8>>> + Send {
+        Box::pin(async move { storage::read(persistence_key).await })
     }
 
     /// Try to write the persistent actor's snapshot to the persistent storage.
+    ///
+    /// Cancellation safe: storage is only written after the last point the future can be
+    /// dropped at, so a dropped save writes either the whole snapshot or nothing, and observers
+    /// see it fail.
     fn try_write(
         persistence_key: &Url,
         snapshot: Self::Snapshot,
-    ) -> impl Future<Output = anyhow::Result<()>> {
+    ) -> impl Future<Output = anyhow::Result<()>> + Send {
         Box::pin(async move {
+            let save = async {
+                let mut snapshot = snapshot;
+                let parts = Self::split_parts(&mut snapshot)?;
+                #[cfg(feature = "tracing")]
+                logging::log_snapshot(&snapshot);
+                let part_files = parts::write(
+                    persistence_key,
+                    codec::subject::<Self>(persistence_key, &snapshot).as_deref(),
+                    &parts,
+                )?;
+
+                let mut data = buffer::take();
+                codec::encode_parts_into::<Self>(
+                    persistence_key,
+                    &snapshot,
+                    &part_files,
+                    &mut data,
+                )?;
+
+                #[cfg(feature = "tracing")]
+                spans::record_bytes(data.len());
+
+                concurrency::write_as(Self::type_tag(), persistence_key, &data).await?;
+
+                // The snapshot is written, so failing to clean up does not fail the save
+                if !part_files.is_empty()
+                    && let Err(_e) = parts::prune(persistence_key, &part_files)
+                {
+                    #[cfg(feature = "tracing")]
+                    warn!(
+                        "Failed to remove stale parts of {}: {_e:#}",
+                        redacted(persistence_key)
+                    );
+                }
+
+                Ok(data.len())
+            };
+
+            report_save(Self::type_tag(), persistence_key, save)
+                .await
+                .map(|_| ())
+        })
+    }
+}
+
+/// Await `save`, which writes the snapshot of `persistence_key` and returns its size, reporting it
+/// to observers, metrics and spans.
+pub(crate) async fn report_save(
+    type_tag: &'static str,
+    persistence_key: &Url,
+    save: impl Future<Output = anyhow::Result<usize>>,
+) -> anyhow::Result<usize> {
+    let started = Instant::now();
+    observer::notify(|o| o.on_save_start(type_tag, persistence_key));
+    let mut report = SaveReport {
+        actor_type: type_tag,
+        key: persistence_key,
+        finished: false,
+    };
+
+    #[cfg(feature = "tracing")]
+    let span = spans::save(type_tag, persistence_key);
+    #[cfg(feature = "tracing")]
+    let save = save.instrument(span.clone());
+
+    let result = save.await;
+
+    #[cfg(feature = "tracing")]
+    spans::finish(&span, result.is_ok(), started.elapsed());
+
+    #[cfg(feature = "metrics")]
+    metrics::record_save(type_tag, result.as_ref().ok().copied(), started.elapsed());
+
+    report.finished = true;
+    match &result {
+        Ok(bytes) => {
+            observer::notify(|o| o.on_save_ok(type_tag, persistence_key, *bytes, started.elapsed()))
+        }
+        Err(e) => observer::notify(|o| o.on_save_err(type_tag, persistence_key, e)),
+    }
+
+    result
+}
+
+/// Respawn as `respawn_persistent_with` does, from `data` if the snapshot was read already, such
+/// as by `storage::read_many`, rather than reading it with `try_read`.
+pub(crate) fn respawn_from<A: PersistentActor>(
+    persistence_key: Url,
+    context: RestoreContext,
+    data: Option<Vec<u8>>,
+) -> impl Future<Output = anyhow::Result<ActorRef<A>>> + Send {
+    Box::pin(async move {
+        if let Some(actor_ref) = A::lookup_persistent(&persistence_key) {
             #[cfg(feature = "tracing")]
-            debug!(
-                "Saving snapshot {snapshot:#?} for actor: {:?} with key: {persistence_key:?}",
-                any::type_name::<Self>(),
+            trace!(
+                "Found existing persistent actor {} with key {}.",
+                any::type_name::<A>(),
+                redacted(&persistence_key),
             );
+            return Ok(actor_ref);
+        }
+
+        // Concurrent respawns of the key wait for the first one and share its actor
+        let cell = inflight::cell::<A>(&persistence_key);
+        cell.get_or_try_init(|| async {
+            // Registered by a respawn finishing between the lookup above and taking the cell
+            if let Some(actor_ref) = A::lookup_persistent(&persistence_key) {
+                return Ok(actor_ref);
+            }
 
-            let data = postcard::to_stdvec(&snapshot)?;
+            let started = Instant::now();
 
-            match persistence_key.scheme() {
-                "file" => {
-                    let path = persistence_key
-                        .to_file_path()
-                        .map_err(|_| anyhow!("Failed to convert Url to file path"))?;
+            let restore = async {
+                // Before reading, so a snapshot replaced in between makes the next
+                // save conflict
+                if concurrency::is_optimistic::<A>() {
+                    concurrency::track(&persistence_key)?;
+                }
 
-                    if !path.exists() {
-                        std::fs::create_dir_all(&path)?;
-                    } else if !path.is_dir() {
-                        anyhow::bail!("persistence key exists but is not a directory: {:?}", path);
+                let data = match data {
+                    // Read after tracking instead, so the tracked version is the one restored
+                    Some(data) if !concurrency::is_optimistic::<A>() => data,
+                    _ => A::try_read(&persistence_key).await?,
+                };
+                let data = crash_loop::check_restore(&persistence_key, data).await?;
+                #[cfg(feature = "tracing")]
+                spans::record_bytes(data.len());
+                let snapshot = match codec::decode_at::<A>(&persistence_key, &data) {
+                    Ok(snapshot) => snapshot,
+                    Err(e) if codec::is_corruption(&e, &data) => {
+                        A::fall_back(&persistence_key, e).await?
                     }
+                    Err(e) => return Err(e),
+                };
+
+                let args = A::restore_args(snapshot, &context.or(RestoreContext::global()))?;
+                let actor_ref = A::spawn_persistent(persistence_key.clone(), args).await?;
+                // The actor is live and registered from here, so bookkeeping failures
+                // do not fail the restore
+                if let Err(_e) = generation::note_restore(&persistence_key) {
+                    #[cfg(feature = "tracing")]
+                    warn!(
+                        "Failed to count the restore of {}: {_e:#}",
+                        redacted(&persistence_key)
+                    );
+                }
+                crash_loop::watch_restore(&persistence_key, &actor_ref);
+
+                Ok(actor_ref)
+            };
+
+            #[cfg(feature = "tracing")]
+            let span = spans::restore(A::type_tag(), &persistence_key);
+            #[cfg(feature = "tracing")]
+            let restore = restore.instrument(span.clone());
+
+            let result = restore.await;
 
-                    std::fs::write(&path.join("index.bin"), data)?;
+            #[cfg(feature = "tracing")]
+            spans::finish(&span, result.is_ok(), started.elapsed());
+
+            #[cfg(feature = "metrics")]
+            metrics::record_restore(A::type_tag(), result.is_ok(), started.elapsed());
 
-                    Ok(())
+            match &result {
+                Ok(_) => observer::notify(|o| {
+                    o.on_restore_ok(A::type_tag(), &persistence_key, started.elapsed())
+                }),
+                Err(e) => {
+                    observer::notify(|o| o.on_restore_err(A::type_tag(), &persistence_key, e))
                 }
-                // todo Support http(s), Ws(s), S3, etc.
-                _ => Err(anyhow!(
-                    "Unsupported scheme for persistencekey: {}",
-                    persistence_key.scheme()
-                )),
             }
+
+            result
         })
-    }
+        .await
+        .cloned()
+    })
 }
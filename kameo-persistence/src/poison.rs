@@ -0,0 +1,267 @@
+use std::{
+    any,
+    collections::HashMap,
+    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+#[cfg(feature = "tracing")]
+use tracing::warn;
+use url::Url;
+
+#[cfg(feature = "tracing")]
+use crate::redact::redacted;
+use crate::{
+    PersistentActor, clock,
+    recording::{RecordedMessage, Replay},
+    storage,
+};
+
+/// Prefix of the quarantined messages, followed by their zero-padded id.
+const POISON_PREFIX: &str = "poison-";
+
+/// Message being handled, until its handler returns.
+const IN_FLIGHT_FILE: &str = "in-flight.bin";
+
+/// Crashes of the handlers of messages, by fingerprint.
+const ATTEMPTS_FILE: &str = "attempts.bin";
+
+static MAX_ATTEMPTS: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Serialize, Deserialize)]
+struct InFlight {
+    fingerprint: String,
+    type_name: String,
+    data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredPoison {
+    quarantined_at_ms: u64,
+    actor_type: String,
+    attempts: u32,
+    type_name: String,
+    data: Vec<u8>,
+}
+
+/// Message quarantined after crashing its handler too often.
+#[derive(Debug, Clone)]
+pub struct PoisonMessage {
+    /// The message, whose `seq` is its id in the quarantine and `recorded_at` the moment it
+    /// was quarantined.
+    pub message: RecordedMessage,
+    /// `PersistentActor::type_tag` of the actor whose handler crashed.
+    pub actor_type: String,
+    /// Crashes of the handler before the message was quarantined.
+    pub attempts: u32,
+}
+
+/// Quarantine messages once their handler crashed `attempts` times, zero disabling quarantine,
+/// the default.
+///
+/// Handlers wrapped in `guard` mark the message they handle under the actor's key, so a crash
+/// is detected when the respawned actor is handed the next message, such as the same message
+/// redelivered by a durable mailbox or a retrying sender. Marking costs a write per message.
+pub fn set_max_attempts(attempts: u32) {
+    MAX_ATTEMPTS.store(attempts, Ordering::Relaxed);
+}
+
+/// Run the handler of a message, unless the message crashed it too often before, in which case
+/// it is quarantined under the actor's key and skipped, returning `None`.
+///
+/// Call at the start of a handler, wrapping the rest of it.
+pub async fn guard<A, M, F, Fut, R>(
+    actor_ref: &ActorRef<A>,
+    msg: &M,
+    handle: F,
+) -> anyhow::Result<Option<R>>
+where
+    A: PersistentActor,
+    M: Serialize,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = R>,
+{
+    let max_attempts = MAX_ATTEMPTS.load(Ordering::Relaxed);
+    let Some(persistence_key) = A::persistence_key(actor_ref).filter(|_| max_attempts > 0) else {
+        return Ok(Some(handle().await));
+    };
+    let backend = storage::backend(&persistence_key)?;
+
+    let type_name = any::type_name::<M>();
+    let data = postcard::to_stdvec(msg)?;
+    let in_flight = InFlight {
+        fingerprint: fingerprint(type_name, &data),
+        type_name: type_name.to_string(),
+        data,
+    };
+
+    let mut attempts = read_attempts(&persistence_key)?;
+
+    // A message still marked in flight crashed the previous incarnation of the actor
+    if let Some(data) = backend.read_file(&persistence_key, IN_FLIGHT_FILE)? {
+        let crashed: InFlight = postcard::from_bytes(&data)?;
+        *attempts.entry(crashed.fingerprint).or_default() += 1;
+        write_attempts(&persistence_key, &attempts)?;
+        backend.remove_file(&persistence_key, IN_FLIGHT_FILE)?;
+    }
+
+    let crashes = attempts.get(&in_flight.fingerprint).copied().unwrap_or(0);
+    if crashes >= max_attempts {
+        quarantine::<A>(&persistence_key, &in_flight, crashes)?;
+        attempts.remove(&in_flight.fingerprint);
+        write_attempts(&persistence_key, &attempts)?;
+        return Ok(None);
+    }
+
+    backend.write_file(
+        &persistence_key,
+        IN_FLIGHT_FILE,
+        &postcard::to_stdvec(&in_flight)?,
+    )?;
+    let reply = handle().await;
+    backend.remove_file(&persistence_key, IN_FLIGHT_FILE)?;
+
+    if attempts.remove(&in_flight.fingerprint).is_some() {
+        write_attempts(&persistence_key, &attempts)?;
+    }
+
+    Ok(Some(reply))
+}
+
+/// Messages quarantined under the key of an actor, in order.
+pub fn quarantined(persistence_key: &Url) -> anyhow::Result<Vec<PoisonMessage>> {
+    let backend = storage::backend(persistence_key)?;
+
+    let mut messages = Vec::new();
+    for (id, name) in poison_files(persistence_key)? {
+        let Some(data) = backend.read_file(persistence_key, &name)? else {
+            continue;
+        };
+        let stored: StoredPoison = postcard::from_bytes(&data)?;
+
+        messages.push(PoisonMessage {
+            message: RecordedMessage {
+                seq: id,
+                recorded_at: UNIX_EPOCH + Duration::from_millis(stored.quarantined_at_ms),
+                type_name: stored.type_name,
+                data: stored.data,
+            },
+            actor_type: stored.actor_type,
+            attempts: stored.attempts,
+        });
+    }
+
+    Ok(messages)
+}
+
+/// Send the messages quarantined under the key of an actor to it again, such as after deploying
+/// a fix, removing each once sent; return how many were sent.
+///
+/// A message crashing its handler again is quarantined again once it exhausted its attempts.
+pub async fn reprocess<A: Actor>(
+    persistence_key: &Url,
+    replay: &Replay<A>,
+    actor_ref: &ActorRef<A>,
+) -> anyhow::Result<usize> {
+    let backend = storage::backend(persistence_key)?;
+
+    let messages = quarantined(persistence_key)?;
+    for poison in &messages {
+        replay
+            .run(std::slice::from_ref(&poison.message), actor_ref)
+            .await?;
+        backend.remove_file(persistence_key, &poison_file(poison.message.seq))?;
+    }
+
+    Ok(messages.len())
+}
+
+/// Remove a quarantined message for good.
+pub fn discard(persistence_key: &Url, id: u64) -> anyhow::Result<()> {
+    storage::backend(persistence_key)?.remove_file(persistence_key, &poison_file(id))
+}
+
+fn quarantine<A: PersistentActor>(
+    persistence_key: &Url,
+    in_flight: &InFlight,
+    attempts: u32,
+) -> anyhow::Result<()> {
+    let id = poison_files(persistence_key)?
+        .last()
+        .map_or(1, |(id, _)| id + 1);
+
+    let stored = StoredPoison {
+        quarantined_at_ms: clock::clock()
+            .system_time()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+        actor_type: A::type_tag().to_string(),
+        attempts,
+        type_name: in_flight.type_name.clone(),
+        data: in_flight.data.clone(),
+    };
+    storage::backend(persistence_key)?.write_file(
+        persistence_key,
+        &poison_file(id),
+        &postcard::to_stdvec(&stored)?,
+    )?;
+
+    #[cfg(feature = "tracing")]
+    warn!(
+        "Quarantined message {} to {} as {id} after {attempts} crashes",
+        in_flight.type_name,
+        redacted(persistence_key)
+    );
+
+    Ok(())
+}
+
+fn fingerprint(type_name: &str, data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(type_name.as_bytes());
+    hasher.update([0]);
+    hasher.update(data);
+    format!("{:x}", hasher.finalize())
+}
+
+fn read_attempts(persistence_key: &Url) -> anyhow::Result<HashMap<String, u32>> {
+    match storage::backend(persistence_key)?.read_file(persistence_key, ATTEMPTS_FILE)? {
+        Some(data) => Ok(postcard::from_bytes(&data)?),
+        None => Ok(HashMap::new()),
+    }
+}
+
+fn write_attempts(persistence_key: &Url, attempts: &HashMap<String, u32>) -> anyhow::Result<()> {
+    let backend = storage::backend(persistence_key)?;
+    match attempts.is_empty() {
+        true => backend.remove_file(persistence_key, ATTEMPTS_FILE),
+        false => backend.write_file(
+            persistence_key,
+            ATTEMPTS_FILE,
+            &postcard::to_stdvec(attempts)?,
+        ),
+    }
+}
+
+/// Ids and file names of the quarantined messages, in order.
+fn poison_files(persistence_key: &Url) -> anyhow::Result<Vec<(u64, String)>> {
+    let mut files = storage::backend(persistence_key)?
+        .list_files(persistence_key)?
+        .into_iter()
+        .filter_map(|name| {
+            let id = name.strip_prefix(POISON_PREFIX)?.parse().ok()?;
+            Some((id, name))
+        })
+        .collect::<Vec<_>>();
+
+    files.sort_unstable();
+    Ok(files)
+}
+
+fn poison_file(id: u64) -> String {
+    format!("{POISON_PREFIX}{id:020}")
+}
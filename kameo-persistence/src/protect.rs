@@ -0,0 +1,138 @@
+use std::sync::{Arc, RwLock};
+
+use crate::PersistenceError;
+
+/// Encrypts snapshot fields marked with `#[persist(encrypt)]`.
+///
+/// Bring your own AEAD or KMS client; the ciphertext must carry whatever it needs to be
+/// decrypted, such as its nonce and key id.
+pub trait FieldCipher: Send + Sync {
+    fn encrypt(&self, plaintext: &[u8]) -> anyhow::Result<Vec<u8>>;
+
+    fn decrypt(&self, ciphertext: &[u8]) -> anyhow::Result<Vec<u8>>;
+}
+
+static CIPHER: RwLock<Option<Arc<dyn FieldCipher>>> = RwLock::new(None);
+
+/// Install the cipher used for fields marked with `#[persist(encrypt)]`.
+///
+/// Without a cipher, saving a snapshot with encrypted fields fails instead of storing plaintext.
+pub fn set_cipher(cipher: impl FieldCipher + 'static) {
+    *CIPHER.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(cipher));
+}
+
+fn cipher() -> Option<Arc<dyn FieldCipher>> {
+    CIPHER.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Per data subject encryption keys, for crypto-shredding.
+///
+/// Snapshots of actors with a `PersistentActor::data_subject` are encrypted with the key of that
+/// subject. Destroying the key makes every copy of those snapshots unreadable, including backups.
+pub trait SubjectKeys: Send + Sync {
+    /// Encrypt with the key of `subject`, creating the key if needed.
+    fn encrypt(&self, subject: &str, plaintext: &[u8]) -> anyhow::Result<Vec<u8>>;
+
+    /// Decrypt with the key of `subject`, or return `None` if the key has been destroyed.
+    fn decrypt(&self, subject: &str, ciphertext: &[u8]) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Destroy the key of `subject`.
+    fn destroy(&self, subject: &str) -> anyhow::Result<()>;
+}
+
+static SUBJECT_KEYS: RwLock<Option<Arc<dyn SubjectKeys>>> = RwLock::new(None);
+
+/// Install the key store used to encrypt snapshots per data subject.
+pub fn set_subject_keys(keys: impl SubjectKeys + 'static) {
+    *SUBJECT_KEYS.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(keys));
+}
+
+fn subject_keys() -> anyhow::Result<Arc<dyn SubjectKeys>> {
+    SUBJECT_KEYS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("No subject key store installed"))
+}
+
+/// Forget a data subject by destroying its key.
+///
+/// Every snapshot encrypted for the subject fails to restore with
+/// `PersistenceError::SubjectShredded` afterwards.
+pub fn shred_subject(subject: &str) -> anyhow::Result<()> {
+    subject_keys()?.destroy(subject)
+}
+
+pub(crate) fn encrypt_for_subject(subject: &str, plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+    subject_keys()?.encrypt(subject, plaintext)
+}
+
+pub(crate) fn decrypt_for_subject(subject: &str, ciphertext: &[u8]) -> anyhow::Result<Vec<u8>> {
+    subject_keys()?
+        .decrypt(subject, ciphertext)?
+        .ok_or_else(|| {
+            PersistenceError::SubjectShredded {
+                subject: subject.to_string(),
+            }
+            .into()
+        })
+}
+
+/// Serde adapter storing a field encrypted with the installed `FieldCipher`.
+pub mod encrypted {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer, de, ser};
+
+    pub fn serialize<T: Serialize, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let cipher = super::cipher()
+            .ok_or_else(|| ser::Error::custom("No field cipher installed for encrypted field"))?;
+
+        let plaintext = postcard::to_allocvec(value).map_err(ser::Error::custom)?;
+        let ciphertext = cipher.encrypt(&plaintext).map_err(ser::Error::custom)?;
+
+        ciphertext.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, T: for<'a> Deserialize<'a>, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        let ciphertext = Vec::<u8>::deserialize(deserializer)?;
+
+        let cipher = super::cipher()
+            .ok_or_else(|| de::Error::custom("No field cipher installed for encrypted field"))?;
+
+        let plaintext = cipher.decrypt(&ciphertext).map_err(de::Error::custom)?;
+
+        postcard::from_bytes(&plaintext).map_err(de::Error::custom)
+    }
+}
+
+/// Serde adapter storing the SHA-256 of a field instead of its value.
+///
+/// The hex digest is restored in place of the value, so the field type must be `From<String>`.
+pub mod hashed {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer, ser};
+    use sha2::{Digest, Sha256};
+
+    pub fn serialize<T: Serialize, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let bytes = postcard::to_allocvec(value).map_err(ser::Error::custom)?;
+
+        let digest = Sha256::digest(&bytes)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+
+        serializer.serialize_str(&digest)
+    }
+
+    pub fn deserialize<'de, T: From<String>, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        String::deserialize(deserializer).map(T::from)
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "tracing")]
+use tracing::trace;
+
+use crate::clock;
+
+struct Bucket {
+    per_second: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+static BUCKET: Mutex<Option<Bucket>> = Mutex::new(None);
+
+/// Limit snapshot writes of the whole process to `per_second` on average, allowing bursts of
+/// up to `burst` writes.
+///
+/// The limit is shared by every actor type and backend, protecting backends with request or
+/// connection limits from save stampedes, such as every actor saving right after a mass
+/// restore. Writes over the limit wait for their turn, in the order they arrived.
+pub fn set_save_rate(per_second: f64, burst: u32) {
+    let burst = f64::from(burst.max(1));
+
+    *BUCKET.lock().unwrap_or_else(|e| e.into_inner()) = Some(Bucket {
+        per_second: per_second.max(f64::MIN_POSITIVE),
+        burst,
+        tokens: burst,
+        updated: clock::clock().now(),
+    });
+}
+
+/// Remove the save rate limit.
+pub fn clear_save_rate() {
+    BUCKET.lock().unwrap_or_else(|e| e.into_inner()).take();
+}
+
+/// Wait until `writes` snapshot writes are allowed by the save rate limit.
+///
+/// Cancellation safe: tokens taken by a wait dropped before its end are given back.
+pub(crate) async fn acquire(writes: usize) {
+    let clock = clock::clock();
+
+    let wait = {
+        let mut bucket = BUCKET.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(bucket) = bucket.as_mut() else {
+            return;
+        };
+
+        let now = clock.now();
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * bucket.per_second).min(bucket.burst);
+        bucket.updated = now;
+
+        // Tokens are taken even when running short, queueing later writes behind this one
+        bucket.tokens -= writes as f64;
+        if bucket.tokens >= 0.0 {
+            return;
+        }
+        Duration::from_secs_f64(-bucket.tokens / bucket.per_second)
+    };
+
+    #[cfg(feature = "tracing")]
+    trace!("Save rate limit reached, waiting {wait:?}");
+
+    let refund = Refund(writes);
+    clock.sleep_until(clock.now() + wait).await;
+    std::mem::forget(refund);
+}
+
+/// Tokens to give back to the bucket if a wait is dropped.
+struct Refund(usize);
+
+impl Drop for Refund {
+    fn drop(&mut self) {
+        if let Some(bucket) = BUCKET.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            bucket.tokens = (bucket.tokens + self.0 as f64).min(bucket.burst);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+#[cfg(feature = "tracing")]
+use tracing::info;
+use url::Url;
+
+use crate::{codec, live_keys, registry, storage};
+
+/// Where the actor type a snapshot was expected to have comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypeSource {
+    /// The key as stored by `live_keys::set_live_key_store`.
+    LiveKeys,
+    /// The actor alive under the key in this process.
+    LiveActor,
+    /// The manifest next to the snapshot, see `manifest::set_manifest`.
+    Manifest,
+}
+
+/// Snapshot belonging to another actor type than expected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeMismatch {
+    pub key: Url,
+    /// Type tag in the snapshot header.
+    pub found: String,
+    pub expected: String,
+    pub source: TypeSource,
+}
+
+/// Outcome of comparing the stored live keys, the snapshots in storage and the live actors.
+#[derive(Debug, Clone, Default)]
+pub struct ReconcileReport {
+    /// Keys holding a snapshot under the roots.
+    pub scanned: Vec<Url>,
+    /// Keys stored as live without a snapshot.
+    pub missing: Vec<Url>,
+    /// Snapshots under the roots neither stored as live nor alive in this process.
+    pub orphans: Vec<Url>,
+    pub type_mismatches: Vec<TypeMismatch>,
+    /// Snapshots of actor types not registered in this process, with the type tag; they cannot
+    /// be respawned.
+    pub unknown_types: Vec<(Url, String)>,
+    /// Keys stored as live whose actor is not alive in this process.
+    pub not_live: Vec<Url>,
+}
+
+impl ReconcileReport {
+    /// Return true if nothing but actors not respawned yet was found.
+    pub fn is_consistent(&self) -> bool {
+        self.missing.is_empty()
+            && self.orphans.is_empty()
+            && self.type_mismatches.is_empty()
+            && self.unknown_types.is_empty()
+    }
+}
+
+/// Compare the snapshots under `roots` with the live keys stored by
+/// `live_keys::set_live_key_store`, the manifests and the actors alive in this process, such
+/// as after an infrastructure incident.
+///
+/// Stored live keys are checked wherever they are stored. Without a live key store, every
+/// snapshot of an actor not alive is reported as an orphan. Nothing is changed.
+pub async fn reconcile(roots: &[Url]) -> anyhow::Result<ReconcileReport> {
+    let stored = live_keys::stored()?
+        .into_iter()
+        .map(|(type_tag, key)| (key, type_tag))
+        .collect::<BTreeMap<_, _>>();
+
+    let live = registry::live_keys()
+        .into_iter()
+        .filter_map(|key| {
+            let actor = registry::lookup(&key)?;
+            Some((key, actor.type_tag()))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let mut report = ReconcileReport::default();
+    for root in roots {
+        report.scanned.extend(storage::list(root).await?);
+    }
+
+    let scanned = report.scanned.iter().cloned().collect::<BTreeSet<_>>();
+    let mut keys = scanned.clone();
+    keys.extend(stored.keys().cloned());
+    keys.extend(live.keys().cloned());
+
+    for key in keys {
+        let is_stored = stored.contains_key(&key);
+        let is_live = live.contains_key(&key);
+
+        if is_stored && !is_live {
+            report.not_live.push(key.clone());
+        }
+
+        let backend = storage::backend(&key)?;
+        let Some(data) = backend.read_file(&key, storage::snapshot_file())? else {
+            if is_stored {
+                report.missing.push(key);
+            }
+            continue;
+        };
+
+        if scanned.contains(&key) && !is_stored && !is_live {
+            report.orphans.push(key.clone());
+        }
+
+        // Snapshots without a header, or too corrupt to read one, have no type to compare
+        let Ok((Some(header), _)) = codec::split(&data) else {
+            continue;
+        };
+        let found = header.type_tag;
+
+        if registry::registration(&found).is_none() {
+            report.unknown_types.push((key.clone(), found.clone()));
+        }
+
+        let mut expected = Vec::new();
+        if let Some(type_tag) = stored.get(&key) {
+            expected.push((type_tag.clone(), TypeSource::LiveKeys));
+        }
+        if let Some(type_tag) = live.get(&key) {
+            expected.push((type_tag.to_string(), TypeSource::LiveActor));
+        }
+        #[cfg(feature = "json")]
+        if let Ok(Some(manifest)) = crate::manifest::read(&key)
+            && let Some(type_tag) = manifest.type_tag
+        {
+            expected.push((type_tag, TypeSource::Manifest));
+        }
+
+        for (type_tag, source) in expected {
+            if type_tag != found {
+                report.type_mismatches.push(TypeMismatch {
+                    key: key.clone(),
+                    found: found.clone(),
+                    expected: type_tag,
+                    source,
+                });
+            }
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    info!(
+        "Reconciled {} keys: {} missing, {} orphans, {} type mismatches, {} unknown types, {} not live",
+        report.scanned.len(),
+        report.missing.len(),
+        report.orphans.len(),
+        report.type_mismatches.len(),
+        report.unknown_types.len(),
+        report.not_live.len()
+    );
+
+    Ok(report)
+}
@@ -0,0 +1,300 @@
+use std::{
+    any,
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use futures::future::BoxFuture;
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+#[cfg(feature = "tracing")]
+use tracing::debug;
+use url::Url;
+
+#[cfg(feature = "tracing")]
+use crate::redact::redacted;
+use crate::{PersistentActor, clock, storage};
+
+/// Prefix of the recorded messages, followed by their zero-padded sequence number.
+const MESSAGE_PREFIX: &str = "message-";
+
+/// Message being recorded, until it is renamed into the recording.
+const MESSAGE_TMP_FILE: &str = "message.tmp";
+
+// Sequence number of the next recorded message, per key being recorded
+static RECORDING: Mutex<Option<HashMap<Url, u64>>> = Mutex::new(None);
+
+#[derive(Serialize, Deserialize)]
+struct StoredMessage {
+    recorded_at_ms: u64,
+    type_name: String,
+    data: Vec<u8>,
+}
+
+/// Message recorded for an actor.
+#[derive(Debug, Clone)]
+pub struct RecordedMessage {
+    /// Position of the message in the recording, from 1.
+    pub seq: u64,
+    pub recorded_at: SystemTime,
+    /// Type name of the message, as given by `std::any::type_name`.
+    pub type_name: String,
+    /// Message serialized with postcard.
+    pub data: Vec<u8>,
+}
+
+impl RecordedMessage {
+    /// Return true if the message is of type `M`.
+    pub fn is<M>(&self) -> bool {
+        self.type_name == any::type_name::<M>()
+    }
+
+    /// Deserialize the message as `M`.
+    pub fn decode<M: DeserializeOwned>(&self) -> anyhow::Result<M> {
+        Ok(postcard::from_bytes(&self.data)?)
+    }
+}
+
+/// Start recording the messages passed to `record` by the actor stored under `persistence_key`,
+/// appending to its existing recording if any.
+pub fn start_recording(persistence_key: Url) -> anyhow::Result<()> {
+    let next = recording_files(&persistence_key)?
+        .last()
+        .map_or(1, |(seq, _)| seq + 1);
+
+    let Ok(mut recording) = RECORDING.lock() else {
+        anyhow::bail!("Failed to acquire lock on recordings");
+    };
+    recording
+        .get_or_insert_with(HashMap::new)
+        .insert(persistence_key, next);
+    Ok(())
+}
+
+/// Stop recording the messages of the actor stored under `persistence_key`, keeping what was
+/// recorded.
+pub fn stop_recording(persistence_key: &Url) {
+    if let Ok(mut recording) = RECORDING.lock()
+        && let Some(recording) = recording.as_mut()
+    {
+        recording.remove(persistence_key);
+    }
+}
+
+/// Return true if the messages of the actor stored under `persistence_key` are being recorded.
+pub fn is_recording(persistence_key: &Url) -> bool {
+    RECORDING.lock().is_ok_and(|recording| {
+        recording
+            .as_ref()
+            .is_some_and(|recording| recording.contains_key(persistence_key))
+    })
+}
+
+/// Record a message received by a persistent actor, if its messages are being recorded.
+///
+/// Called at the start of the actor's handlers, so the recording holds every message in the
+/// order the actor handled them. Does nothing for actors not being recorded.
+pub async fn record<A, M>(actor_ref: &ActorRef<A>, msg: &M) -> anyhow::Result<()>
+where
+    A: PersistentActor,
+    M: Serialize,
+{
+    // Nothing is recorded in production unless asked for, so stay cheap
+    let recording_any = RECORDING
+        .lock()
+        .is_ok_and(|recording| recording.as_ref().is_some_and(|r| !r.is_empty()));
+    if !recording_any {
+        return Ok(());
+    }
+
+    let Some(persistence_key) = A::persistence_key(actor_ref) else {
+        return Ok(());
+    };
+
+    let seq = {
+        let Ok(mut recording) = RECORDING.lock() else {
+            anyhow::bail!("Failed to acquire lock on recordings");
+        };
+        let Some(next) = recording
+            .as_mut()
+            .and_then(|recording| recording.get_mut(&persistence_key))
+        else {
+            return Ok(());
+        };
+        *next += 1;
+        *next - 1
+    };
+
+    let stored = StoredMessage {
+        recorded_at_ms: clock::clock()
+            .system_time()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+        type_name: any::type_name::<M>().to_string(),
+        data: postcard::to_stdvec(msg)?,
+    };
+
+    let backend = storage::backend(&persistence_key)?;
+    backend.write_file(
+        &persistence_key,
+        MESSAGE_TMP_FILE,
+        &postcard::to_stdvec(&stored)?,
+    )?;
+    backend.rename_file(&persistence_key, MESSAGE_TMP_FILE, &message_file(seq))
+}
+
+/// Messages recorded for the actor stored under `persistence_key`, in order.
+pub fn recorded_messages(persistence_key: &Url) -> anyhow::Result<Vec<RecordedMessage>> {
+    let backend = storage::backend(persistence_key)?;
+
+    let mut messages = Vec::new();
+    for (seq, name) in recording_files(persistence_key)? {
+        let Some(data) = backend.read_file(persistence_key, &name)? else {
+            continue;
+        };
+        let stored: StoredMessage = postcard::from_bytes(&data)?;
+
+        messages.push(RecordedMessage {
+            seq,
+            recorded_at: UNIX_EPOCH + Duration::from_millis(stored.recorded_at_ms),
+            type_name: stored.type_name,
+            data: stored.data,
+        });
+    }
+
+    Ok(messages)
+}
+
+/// Remove the messages recorded for the actor stored under `persistence_key`.
+pub fn clear_recording(persistence_key: &Url) -> anyhow::Result<()> {
+    let backend = storage::backend(persistence_key)?;
+    for (_, name) in recording_files(persistence_key)? {
+        backend.remove_file(persistence_key, &name)?;
+    }
+
+    if let Ok(mut recording) = RECORDING.lock()
+        && let Some(next) = recording
+            .as_mut()
+            .and_then(|recording| recording.get_mut(persistence_key))
+    {
+        *next = 1;
+    }
+    Ok(())
+}
+
+type Dispatch<A> = Box<
+    dyn Fn(&RecordedMessage, ActorRef<A>) -> BoxFuture<'static, anyhow::Result<()>> + Send + Sync,
+>;
+
+/// Replays recorded messages against an actor, such as a fresh one spawned in a test, to
+/// reproduce a production bug deterministically.
+///
+/// Every message type in the recording must be registered with `message`.
+pub struct Replay<A: Actor> {
+    dispatchers: HashMap<&'static str, Dispatch<A>>,
+}
+
+impl<A: Actor> Default for Replay<A> {
+    fn default() -> Self {
+        Self {
+            dispatchers: HashMap::new(),
+        }
+    }
+}
+
+impl<A: Actor> Replay<A> {
+    /// Create a replay without message types.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replay recorded messages of type `M`.
+    pub fn message<M>(mut self) -> Self
+    where
+        A: Message<M>,
+        M: DeserializeOwned + Send + 'static,
+    {
+        self.dispatchers.insert(
+            any::type_name::<M>(),
+            Box::new(|recorded, actor_ref| {
+                let msg = recorded.decode::<M>();
+                let seq = recorded.seq;
+
+                Box::pin(async move {
+                    if actor_ref.tell(msg?).await.is_err() {
+                        anyhow::bail!("Actor stopped before message {seq} was replayed");
+                    }
+                    Ok(())
+                })
+            }),
+        );
+        self
+    }
+
+    /// Send the messages to the actor in order, returning how many were sent.
+    ///
+    /// The actor handles them in the same order, so asking it anything afterwards observes the
+    /// state left by the whole replay.
+    pub async fn run(
+        &self,
+        messages: &[RecordedMessage],
+        actor_ref: &ActorRef<A>,
+    ) -> anyhow::Result<usize> {
+        for recorded in messages {
+            let dispatch = self
+                .dispatchers
+                .get(recorded.type_name.as_str())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Message {} of type {} is not registered for replay",
+                        recorded.seq,
+                        recorded.type_name
+                    )
+                })?;
+
+            dispatch(recorded, actor_ref.clone()).await?;
+        }
+
+        Ok(messages.len())
+    }
+
+    /// Replay the messages recorded for the actor stored under `persistence_key`.
+    pub async fn run_recorded(
+        &self,
+        persistence_key: &Url,
+        actor_ref: &ActorRef<A>,
+    ) -> anyhow::Result<usize> {
+        let messages = recorded_messages(persistence_key)?;
+
+        #[cfg(feature = "tracing")]
+        debug!(
+            "Replaying {} messages recorded for {}",
+            messages.len(),
+            redacted(persistence_key)
+        );
+
+        self.run(&messages, actor_ref).await
+    }
+}
+
+/// Sequence numbers and file names of the recorded messages, in order.
+fn recording_files(persistence_key: &Url) -> anyhow::Result<Vec<(u64, String)>> {
+    let mut files = storage::backend(persistence_key)?
+        .list_files(persistence_key)?
+        .into_iter()
+        .filter_map(|name| {
+            let seq = name.strip_prefix(MESSAGE_PREFIX)?.parse().ok()?;
+            Some((seq, name))
+        })
+        .collect::<Vec<_>>();
+
+    files.sort_unstable();
+    Ok(files)
+}
+
+fn message_file(seq: u64) -> String {
+    format!("{MESSAGE_PREFIX}{seq:020}")
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::{HashMap, HashSet},
+    pin::pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{StreamExt, stream};
+use tokio::sync::{Semaphore, watch};
+#[cfg(feature = "tracing")]
+use tracing::{info, warn};
+use url::Url;
+
+#[cfg(feature = "tracing")]
+use crate::redact::redacted;
+use crate::{PersistentActor, clock, live_keys, registry, storage};
+
+/// Progress of a recovery, reported after every key.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryProgress {
+    /// Actors respawned or found alive so far.
+    pub restored: usize,
+    /// Keys which failed to respawn so far.
+    pub failed: usize,
+    /// Keys holding a snapshot under the roots.
+    pub total: usize,
+    pub elapsed: Duration,
+    /// Estimated time left, from the pace so far; `None` before the first key.
+    pub eta: Option<Duration>,
+}
+
+impl RecoveryProgress {
+    /// Return true once every key was handled.
+    pub fn is_complete(&self) -> bool {
+        self.restored + self.failed == self.total
+    }
+}
+
+/// Outcome of a recovery.
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryReport {
+    /// Keys whose actor was respawned or found alive.
+    pub restored: Vec<Url>,
+    /// Keys which failed to respawn, with the error.
+    pub failed: Vec<(Url, String)>,
+}
+
+type ProgressFn = Box<dyn Fn(&RecoveryProgress) + Send + Sync>;
+
+/// Respawns the persistent actors stored under configured roots at boot, reporting progress.
+///
+/// Every key holding a snapshot under a root is respawned as the type named in its header, so
+/// the types must be registered in this process. Progress is reported to `on_progress` and to
+/// the receivers from `progress`, so services can gate readiness on recovery completion.
+pub struct Recovery {
+    roots: Vec<Url>,
+    live_keys: bool,
+    concurrency: usize,
+    read_batch: usize,
+    // Actors of a type allowed to start at a time, by type tag
+    max_starting: HashMap<&'static str, usize>,
+    max_starting_per_type: Option<usize>,
+    on_progress: Option<ProgressFn>,
+    progress: watch::Sender<RecoveryProgress>,
+}
+
+impl Default for Recovery {
+    fn default() -> Self {
+        Self {
+            roots: Vec::new(),
+            live_keys: false,
+            concurrency: 16,
+            read_batch: 64,
+            max_starting: HashMap::new(),
+            max_starting_per_type: None,
+            on_progress: None,
+            progress: watch::Sender::new(RecoveryProgress::default()),
+        }
+    }
+}
+
+impl Recovery {
+    /// Recover nothing yet, respawning up to 16 actors at a time from snapshots read 64 at a
+    /// time.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Respawn every actor stored under `root`.
+    pub fn root(mut self, root: Url) -> Self {
+        self.roots.push(root);
+        self
+    }
+
+    /// Also respawn the actors live before the restart, stored with
+    /// `live_keys::set_live_key_store`, wherever they are stored.
+    pub fn live_keys(mut self) -> Self {
+        self.live_keys = true;
+        self
+    }
+
+    /// Respawn up to `actors` actors at a time.
+    pub fn concurrency(mut self, actors: usize) -> Self {
+        self.concurrency = actors.max(1);
+        self
+    }
+
+    /// Read the snapshots of up to `keys` actors at a time with `storage::read_many`, ahead of
+    /// respawning them, so remote backends batching reads cut the round trips of a cold start.
+    ///
+    /// Snapshots read ahead are restored as read, even if replaced before their respawn, and
+    /// not through `PersistentActor::try_read`; with 1, every snapshot is read by its respawn
+    /// instead. Types with optimistic concurrency always read their snapshot again.
+    pub fn read_batch(mut self, keys: usize) -> Self {
+        self.read_batch = keys.max(1);
+        self
+    }
+
+    /// Let at most `actors` actors of type `A` run `on_start` at a time, to protect the
+    /// databases or APIs their constructors touch.
+    ///
+    /// An actor counts as starting until its `on_start` returned; other types keep respawning
+    /// meanwhile, up to the `concurrency`.
+    pub fn max_starting<A: PersistentActor>(mut self, actors: usize) -> Self {
+        self.max_starting.insert(A::type_tag(), actors.max(1));
+        self
+    }
+
+    /// Let at most `actors` actors of each type without a limit of its own run `on_start` at a
+    /// time, see `max_starting`.
+    pub fn max_starting_per_type(mut self, actors: usize) -> Self {
+        self.max_starting_per_type = Some(actors.max(1));
+        self
+    }
+
+    /// Call `on_progress` after every key.
+    pub fn on_progress(
+        mut self,
+        on_progress: impl Fn(&RecoveryProgress) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_progress = Some(Box::new(on_progress));
+        self
+    }
+
+    /// Receiver of the progress, updated after every key, e.g. for a readiness probe.
+    pub fn progress(&self) -> watch::Receiver<RecoveryProgress> {
+        self.progress.subscribe()
+    }
+
+    /// Respawn every actor under the roots, returning once all were handled.
+    ///
+    /// Failing keys are reported rather than aborting the recovery. Actors are respawned in
+    /// listing order, parents first; if parents respawn their own children, use a concurrency
+    /// of 1 so no child is respawned concurrently with its parent.
+    pub async fn run(self) -> anyhow::Result<RecoveryReport> {
+        let clock = clock::clock();
+        let started = clock.now();
+
+        let mut keys = Vec::new();
+        for root in &self.roots {
+            keys.extend(storage::list(root).await?);
+        }
+        if self.live_keys {
+            keys.extend(live_keys::stored()?.into_iter().map(|(_, key)| key));
+        }
+
+        // Live keys are usually under a root as well
+        let mut seen = HashSet::new();
+        keys.retain(|key| seen.insert(key.clone()));
+
+        let mut progress = RecoveryProgress {
+            total: keys.len(),
+            ..Default::default()
+        };
+        self.progress.send_replace(progress.clone());
+
+        #[cfg(feature = "tracing")]
+        info!(
+            "Recovering {} actors under {} roots",
+            keys.len(),
+            self.roots.len()
+        );
+
+        // Created on the first key of each limited type
+        let starting = Mutex::new(HashMap::<String, Arc<Semaphore>>::new());
+        let admit = |type_tag: &str| {
+            let limit = self
+                .max_starting
+                .get(type_tag)
+                .copied()
+                .or(self.max_starting_per_type);
+            let semaphore = limit.map(|limit| {
+                starting
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .entry(type_tag.to_string())
+                    .or_insert_with(|| Arc::new(Semaphore::new(limit)))
+                    .clone()
+            });
+
+            async move {
+                match semaphore {
+                    Some(semaphore) => semaphore.acquire_owned().await.ok(),
+                    None => None,
+                }
+            }
+        };
+
+        let mut report = RecoveryReport::default();
+        let batches = keys
+            .chunks(self.read_batch)
+            .map(<[Url]>::to_vec)
+            .collect::<Vec<_>>();
+        let read_ahead = self.read_batch > 1;
+        let respawned = stream::iter(batches)
+            .then(|batch| async move {
+                let data = match read_ahead {
+                    true => storage::read_many(&batch)
+                        .await
+                        .into_iter()
+                        .map(|data| data.map(Some))
+                        .collect(),
+                    false => batch.iter().map(|_| Ok(None)).collect::<Vec<_>>(),
+                };
+                stream::iter(batch.into_iter().zip(data))
+            })
+            .flatten()
+            .map(|(key, data)| async {
+                let result = match data {
+                    Ok(data) => registry::respawn_any_admitted(key.clone(), data, admit).await,
+                    // Actors alive already need no snapshot
+                    Err(e) => registry::lookup(&key).ok_or(e),
+                };
+                (key, result)
+            })
+            .buffered(self.concurrency);
+        let mut respawned = pin!(respawned);
+
+        while let Some((key, result)) = respawned.next().await {
+            match result {
+                Ok(_) => {
+                    progress.restored += 1;
+                    report.restored.push(key);
+                }
+                Err(e) => {
+                    #[cfg(feature = "tracing")]
+                    warn!("Failed to recover {}: {e:#}", redacted(&key));
+
+                    progress.failed += 1;
+                    report.failed.push((key, format!("{e:#}")));
+                }
+            }
+
+            let done = progress.restored + progress.failed;
+            progress.elapsed = clock.now().saturating_duration_since(started);
+            progress.eta = Some(
+                progress
+                    .elapsed
+                    .mul_f64((progress.total - done) as f64 / done as f64),
+            );
+
+            if let Some(on_progress) = &self.on_progress {
+                on_progress(&progress);
+            }
+            self.progress.send_replace(progress.clone());
+        }
+
+        #[cfg(feature = "tracing")]
+        info!(
+            "Recovered {} actors in {:?}, {} failed",
+            progress.restored, progress.elapsed, progress.failed
+        );
+
+        Ok(report)
+    }
+}
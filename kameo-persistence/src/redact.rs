@@ -0,0 +1,81 @@
+use std::fmt;
+
+use url::Url;
+
+/// Query parameters whose values are hidden when a persistence key is displayed.
+const SECRET_PARAMS: &[&str] = &[
+    "access_token",
+    "api_key",
+    "apikey",
+    "auth",
+    "credential",
+    "key",
+    "passwd",
+    "password",
+    "secret",
+    "sig",
+    "signature",
+    "token",
+    "x-amz-credential",
+    "x-amz-security-token",
+    "x-amz-signature",
+];
+
+const REDACTED: &str = "REDACTED";
+
+fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SECRET_PARAMS.contains(&name.as_str())
+        || name.ends_with("_token")
+        || name.ends_with("_secret")
+        || name.ends_with("_password")
+}
+
+/// Return a copy of a persistence key with credentials replaced by `REDACTED`.
+///
+/// Hides the password of the userinfo and the values of query parameters which look like secrets.
+pub fn redact(persistence_key: &Url) -> Url {
+    let mut key = persistence_key.clone();
+
+    if key.password().is_some() {
+        let _ = key.set_password(Some(REDACTED));
+    }
+
+    if key.query_pairs().any(|(name, _)| is_secret(&name)) {
+        let pairs = key
+            .query_pairs()
+            .map(|(name, value)| {
+                let value = if is_secret(&name) {
+                    REDACTED.into()
+                } else {
+                    value
+                };
+                (name.into_owned(), value.into_owned())
+            })
+            .collect::<Vec<_>>();
+
+        key.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+
+    key
+}
+
+/// Displays a persistence key with its credentials redacted.
+pub struct Redacted<'a>(pub &'a Url);
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&redact(self.0), f)
+    }
+}
+
+impl fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Wrap a persistence key so it is redacted whenever it is logged or formatted.
+pub fn redacted(persistence_key: &Url) -> Redacted<'_> {
+    Redacted(persistence_key)
+}
@@ -0,0 +1,518 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    sync::{Arc, LazyLock, RwLock},
+    time::SystemTime,
+};
+
+use anyhow::anyhow;
+use futures::future::BoxFuture;
+use kameo::prelude::*;
+use tokio::sync::OwnedSemaphorePermit;
+use url::Url;
+
+use crate::{
+    BiHashMap, PersistentActor, RestoreContext, checkpoint::Checkpoint, clock, codec, concurrency,
+    live_keys, observer, persistent_actor, redact::redacted, storage,
+};
+
+/// Registry of persistence keys for a single actor type.
+pub type TypedRegistry<A> = RwLock<BiHashMap<Url, WeakActorRef<A>>>;
+
+/// Type-erased handle to a registered persistent actor.
+pub trait ErasedPersistentActor: Send + Sync {
+    /// Type tag of the actor behind this handle.
+    fn type_tag(&self) -> &'static str;
+
+    /// Return true if the actor is still running.
+    fn is_alive(&self) -> bool;
+
+    /// Deliver a checkpoint request to the actor.
+    fn checkpoint(&self, checkpoint: Checkpoint) -> BoxFuture<'static, anyhow::Result<()>>;
+
+    /// Stop the actor gracefully and wait until it has shut down.
+    fn stop(&self) -> BoxFuture<'static, ()>;
+
+    /// Wait until the actor has finished `on_start`.
+    fn wait_for_startup(&self) -> BoxFuture<'static, ()>;
+}
+
+impl<A> ErasedPersistentActor for WeakActorRef<A>
+where
+    A: PersistentActor + Message<Checkpoint, Reply = ()>,
+{
+    fn type_tag(&self) -> &'static str {
+        A::type_tag()
+    }
+
+    fn is_alive(&self) -> bool {
+        self.upgrade().is_some_and(|actor_ref| actor_ref.is_alive())
+    }
+
+    fn checkpoint(&self, checkpoint: Checkpoint) -> BoxFuture<'static, anyhow::Result<()>> {
+        let actor_ref = self.upgrade();
+
+        Box::pin(async move {
+            let Some(actor_ref) = actor_ref else {
+                anyhow::bail!("Actor {} is no longer running", std::any::type_name::<A>());
+            };
+
+            actor_ref
+                .tell(checkpoint)
+                .await
+                .map_err(|e| anyhow!("Failed to deliver checkpoint: {e}"))
+        })
+    }
+
+    fn stop(&self) -> BoxFuture<'static, ()> {
+        let actor_ref = self.upgrade();
+
+        Box::pin(async move {
+            let Some(actor_ref) = actor_ref else {
+                return;
+            };
+
+            // Already stopping if the stop signal cannot be delivered
+            let _ = actor_ref.stop_gracefully().await;
+            actor_ref.wait_for_shutdown().await;
+        })
+    }
+
+    fn wait_for_startup(&self) -> BoxFuture<'static, ()> {
+        let actor_ref = self.upgrade();
+
+        Box::pin(async move {
+            if let Some(actor_ref) = actor_ref {
+                actor_ref.wait_for_startup().await;
+            }
+        })
+    }
+}
+
+// Process-wide view over every persistent actor, regardless of its type
+static REGISTRY: LazyLock<RwLock<HashMap<Url, Arc<dyn ErasedPersistentActor>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+// Moment an actor of each type was last registered, by type tag
+static LAST_REGISTERED: LazyLock<RwLock<HashMap<&'static str, SystemTime>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Registry of an actor type at a moment, to debug leaks of actor references.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistryStats {
+    pub type_tag: &'static str,
+    /// Registered actors still running.
+    pub live: usize,
+    /// Registered actors which stopped, whose weak references are still kept.
+    pub dead: usize,
+    /// When an actor of this type was last registered in this process, if ever.
+    pub last_registered: Option<SystemTime>,
+}
+
+/// Statistics of the registry of an actor type.
+pub fn registry_stats<A: PersistentActor>() -> RegistryStats {
+    let (live, dead) = A::registry_counts();
+
+    RegistryStats {
+        type_tag: A::type_tag(),
+        live,
+        dead,
+        last_registered: LAST_REGISTERED
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(A::type_tag())
+            .copied(),
+    }
+}
+
+/// Statistics of the registries of every registered actor type, sorted by type tag.
+pub fn registry_dump() -> Vec<RegistryStats> {
+    let mut stats = inventory::iter::<TypeRegistration>
+        .into_iter()
+        .map(|registration| (registration.stats)())
+        .collect::<Vec<_>>();
+
+    for registration in REGISTERED_TYPES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .values()
+    {
+        if !stats
+            .iter()
+            .any(|stats| stats.type_tag == (registration.type_tag)())
+        {
+            stats.push((registration.stats)());
+        }
+    }
+
+    stats.sort_unstable_by_key(|stats| stats.type_tag);
+    stats
+}
+
+/// Count the live and dead actors of a typed registry, for `PersistentActor::registry_counts`.
+pub fn count_refs<A: Actor>(registry: &BiHashMap<Url, WeakActorRef<A>>) -> (usize, usize) {
+    let live = registry
+        .iter()
+        .filter(|(_, actor)| {
+            actor
+                .upgrade()
+                .is_some_and(|actor_ref| actor_ref.is_alive())
+        })
+        .count();
+    (live, registry.len() - live)
+}
+
+/// Count the live and dead actors of a type in the process-wide registry.
+pub fn process_counts(type_tag: &str) -> (usize, usize) {
+    let registry = REGISTRY.read().unwrap_or_else(|e| e.into_inner());
+
+    let (mut live, mut dead) = (0, 0);
+    for actor in registry
+        .values()
+        .filter(|actor| actor.type_tag() == type_tag)
+    {
+        match actor.is_alive() {
+            true => live += 1,
+            false => dead += 1,
+        }
+    }
+    (live, dead)
+}
+
+/// Register a persistent actor in the process-wide registry.
+///
+/// Called by the derived `register_persistent`; manual implementations may call it as well.
+pub fn register<A>(persistence_key: Url, actor_ref: &ActorRef<A>)
+where
+    A: PersistentActor + Message<Checkpoint, Reply = ()>,
+{
+    let mut registry = REGISTRY.write().unwrap_or_else(|e| e.into_inner());
+
+    registry.insert(persistence_key.clone(), Arc::new(actor_ref.downgrade()));
+    LAST_REGISTERED
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(A::type_tag(), clock::clock().system_time());
+
+    observer::notify(|o| o.on_register(A::type_tag(), &persistence_key));
+
+    #[cfg(feature = "metrics")]
+    crate::metrics::record_registry_size(
+        A::type_tag(),
+        registry
+            .values()
+            .filter(|actor| actor.type_tag() == A::type_tag())
+            .count(),
+    );
+
+    drop(registry);
+    live_keys::note_register(A::type_tag(), &persistence_key);
+}
+
+/// Return a handle to a live persistent actor of any type.
+pub fn lookup(persistence_key: &Url) -> Option<Arc<dyn ErasedPersistentActor>> {
+    let registry = REGISTRY.read().unwrap_or_else(|e| e.into_inner());
+
+    registry
+        .get(persistence_key)
+        .filter(|actor| actor.is_alive())
+        .cloned()
+}
+
+/// Forget the actor registered under a key in the process-wide registry, without stopping it.
+///
+/// Called by the derived `unregister_persistent`; manual implementations may call it as well.
+pub fn unregister(persistence_key: &Url) {
+    let removed = REGISTRY
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(persistence_key);
+
+    if let Some(actor) = removed {
+        live_keys::note_unregister(actor.type_tag(), persistence_key);
+    }
+}
+
+/// Return the persistence keys of every live persistent actor.
+pub fn live_keys() -> Vec<Url> {
+    let registry = REGISTRY.read().unwrap_or_else(|e| e.into_inner());
+
+    registry
+        .iter()
+        .filter(|(_, actor)| actor.is_alive())
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+/// Stop every live persistent actor and forget all registrations, as if the process restarted.
+///
+/// Intended for tests. Actors are stopped gracefully, so their `on_stop` hooks still run.
+pub async fn stop_all() {
+    let actors = REGISTRY
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .drain()
+        .map(|(_, actor)| actor)
+        .collect::<Vec<_>>();
+
+    futures::future::join_all(actors.iter().map(|actor| actor.stop())).await;
+}
+
+/// Stop every persistent actor and clear every registry, including those of each actor type.
+///
+/// Intended for tests simulating a process restart within one binary: afterwards actors can
+/// only be respawned from storage. Configuration such as backends and registered types is kept.
+pub async fn reset() {
+    stop_all().await;
+
+    let mut clears = inventory::iter::<TypeRegistration>
+        .into_iter()
+        .map(|registration| registration.clear)
+        .collect::<Vec<_>>();
+
+    clears.extend(
+        REGISTERED_TYPES
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .map(|registration| registration.clear),
+    );
+
+    clears.extend(
+        TYPED_REGISTRIES
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .map(|(_, clear)| *clear),
+    );
+
+    for clear in clears {
+        clear();
+    }
+
+    concurrency::clear();
+    live_keys::clear();
+}
+
+type ErasedTypedRegistry = (&'static (dyn Any + Send + Sync), fn());
+
+// Registries of generic actor types, which cannot declare a `static` of their own
+static TYPED_REGISTRIES: LazyLock<RwLock<HashMap<TypeId, ErasedTypedRegistry>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+fn clear_typed_registry<A: Actor>() {
+    typed_registry::<A>()
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .clear();
+}
+
+/// Return the registry of an actor type, creating it on first use.
+///
+/// Intended for generic persistent actors; the derive macro declares a `static` per type instead.
+pub fn typed_registry<A: Actor>() -> &'static TypedRegistry<A> {
+    let type_id = TypeId::of::<A>();
+
+    let existing = TYPED_REGISTRIES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&type_id)
+        .map(|(registry, _)| *registry);
+
+    let registry = match existing {
+        Some(registry) => registry,
+        None => {
+            TYPED_REGISTRIES
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+                .entry(type_id)
+                .or_insert_with(|| {
+                    (
+                        Box::leak(Box::new(TypedRegistry::<A>::default())),
+                        clear_typed_registry::<A>,
+                    )
+                })
+                .0
+        }
+    };
+
+    registry
+        .downcast_ref()
+        .expect("typed registry is keyed by its actor type")
+}
+
+/// Respawns a persistent actor of a given type from its persistence key.
+pub type Respawner = fn(Url) -> BoxFuture<'static, anyhow::Result<()>>;
+
+/// Respawns a persistent actor of a given type from its persistence key and the snapshot bytes
+/// read from it.
+pub type RespawnerFrom = fn(Url, Vec<u8>) -> BoxFuture<'static, anyhow::Result<()>>;
+
+/// Persistent actor type submitted to the type registry at compile time.
+///
+/// The derive macro submits one for every derived type.
+#[derive(Clone, Copy)]
+pub struct TypeRegistration {
+    pub type_tag: fn() -> &'static str,
+    pub schema_version: fn() -> u32,
+    pub respawn: Respawner,
+    pub respawn_from: RespawnerFrom,
+    pub clear: fn(),
+    /// Statistics of the registry of the type.
+    pub stats: fn() -> RegistryStats,
+    /// Decode stored snapshot bytes and encode them again for the key they are written to.
+    pub reencode: fn(&Url, &[u8]) -> anyhow::Result<Vec<u8>>,
+    /// Decode stored snapshot bytes, discarding the snapshot.
+    pub verify: fn(&[u8]) -> anyhow::Result<()>,
+    /// Decode stored snapshot bytes into JSON.
+    #[cfg(feature = "json")]
+    pub to_json: fn(&[u8]) -> anyhow::Result<serde_json::Value>,
+    /// Encode JSON into the snapshot bytes stored under a key.
+    #[cfg(feature = "json")]
+    pub from_json: fn(&Url, serde_json::Value) -> anyhow::Result<Vec<u8>>,
+}
+
+impl TypeRegistration {
+    pub const fn new<A: PersistentActor>() -> Self {
+        Self {
+            type_tag: A::type_tag,
+            schema_version: A::schema_version,
+            respawn: respawn_erased::<A>,
+            respawn_from: respawn_from_erased::<A>,
+            clear: A::clear_registry,
+            stats: registry_stats::<A>,
+            reencode: reencode_erased::<A>,
+            verify: verify_erased::<A>,
+            #[cfg(feature = "json")]
+            to_json: crate::json::to_json::<A>,
+            #[cfg(feature = "json")]
+            from_json: crate::json::from_json::<A>,
+        }
+    }
+}
+
+inventory::collect!(TypeRegistration);
+
+// Types registered at runtime, by type tag
+static REGISTERED_TYPES: LazyLock<RwLock<HashMap<&'static str, TypeRegistration>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+fn respawn_erased<A: PersistentActor>(
+    persistence_key: Url,
+) -> BoxFuture<'static, anyhow::Result<()>> {
+    Box::pin(async move { A::respawn_persistent(persistence_key).await.map(|_| ()) })
+}
+
+fn respawn_from_erased<A: PersistentActor>(
+    persistence_key: Url,
+    data: Vec<u8>,
+) -> BoxFuture<'static, anyhow::Result<()>> {
+    Box::pin(async move {
+        persistent_actor::respawn_from::<A>(persistence_key, RestoreContext::new(), Some(data))
+            .await
+            .map(|_| ())
+    })
+}
+
+fn reencode_erased<A: PersistentActor>(
+    persistence_key: &Url,
+    data: &[u8],
+) -> anyhow::Result<Vec<u8>> {
+    codec::encode::<A>(persistence_key, &codec::decode::<A>(data)?)
+}
+
+fn verify_erased<A: PersistentActor>(data: &[u8]) -> anyhow::Result<()> {
+    codec::decode::<A>(data).map(|_| ())
+}
+
+/// Make a persistent actor type known by its type tag.
+///
+/// Only needed for types which do not derive `PersistentActor`, such as generic actors.
+pub fn register_type<A: PersistentActor>() {
+    REGISTERED_TYPES
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(A::type_tag(), TypeRegistration::new::<A>());
+}
+
+/// Return the registration of an actor type tag.
+pub fn registration(type_tag: &str) -> Option<TypeRegistration> {
+    let registered = REGISTERED_TYPES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(type_tag)
+        .copied();
+
+    registered.or_else(|| {
+        inventory::iter::<TypeRegistration>
+            .into_iter()
+            .find(|registration| (registration.type_tag)() == type_tag)
+            .copied()
+    })
+}
+
+/// Return the respawner of an actor type tag.
+pub fn respawner(type_tag: &str) -> Option<Respawner> {
+    registration(type_tag).map(|registration| registration.respawn)
+}
+
+/// Respawn the persistent actor stored under a key, whatever its type.
+///
+/// The type is read from the snapshot header, so snapshots written before headers were
+/// introduced cannot be respawned this way.
+pub async fn respawn_any(persistence_key: Url) -> anyhow::Result<Arc<dyn ErasedPersistentActor>> {
+    respawn_any_admitted(persistence_key, None, |_| async { None }).await
+}
+
+/// Respawn as `respawn_any` does, holding the permit `admit` returns for the type of the actor
+/// until it finished starting, see `Recovery::max_starting`.
+///
+/// `data` is the snapshot if read already by `storage::read_many`, restored without reading
+/// it again.
+pub(crate) async fn respawn_any_admitted<F, P>(
+    persistence_key: Url,
+    data: Option<Vec<u8>>,
+    admit: F,
+) -> anyhow::Result<Arc<dyn ErasedPersistentActor>>
+where
+    F: FnOnce(&str) -> P,
+    P: Future<Output = Option<OwnedSemaphorePermit>>,
+{
+    if let Some(actor) = lookup(&persistence_key) {
+        return Ok(actor);
+    }
+
+    let prefetched = data.is_some();
+    let data = match data {
+        Some(data) => data,
+        None => storage::read(&persistence_key).await?,
+    };
+    let (Some(header), _) = codec::split(&data)? else {
+        anyhow::bail!(
+            "Snapshot stored under {} has no type tag",
+            redacted(&persistence_key)
+        );
+    };
+
+    let registration = registration(&header.type_tag)
+        .ok_or_else(|| anyhow!("No respawner registered for actor type {}", header.type_tag))?;
+
+    let permit = admit(&header.type_tag).await;
+    match prefetched {
+        true => (registration.respawn_from)(persistence_key.clone(), data).await?,
+        false => (registration.respawn)(persistence_key.clone()).await?,
+    }
+
+    let actor = lookup(&persistence_key).ok_or_else(|| {
+        anyhow!(
+            "Respawned actor for {} is not registered",
+            redacted(&persistence_key)
+        )
+    })?;
+
+    if permit.is_some() {
+        actor.wait_for_startup().await;
+    }
+
+    Ok(actor)
+}
@@ -0,0 +1,339 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, RwLock},
+};
+
+use anyhow::anyhow;
+use futures::future::BoxFuture;
+use kameo::{prelude::*, reply::Reply};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+#[cfg(feature = "tracing")]
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::{
+    PersistentActor,
+    redact::{redact, redacted},
+    registry,
+};
+
+/// Prefix of the names persistent actors are registered under in kameo's remote registry.
+const NAME_PREFIX: &str = "kameo-persistence:";
+
+/// Prefix of the names respawn services are registered under, followed by their node.
+const SERVICE_PREFIX: &str = "kameo-persistence-respawn:";
+
+/// Respawn an actor of a registered type here and register it remotely.
+type RemoteRespawner = fn(Url) -> BoxFuture<'static, anyhow::Result<()>>;
+
+// Types other nodes may ask this node to respawn, by type tag
+static RESPAWNERS: LazyLock<RwLock<HashMap<&'static str, RemoteRespawner>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Name a persistent actor is registered under in kameo's remote registry.
+///
+/// Derived from the key with its credentials redacted, so they never reach other nodes.
+pub fn remote_name(persistence_key: &Url) -> String {
+    format!("{NAME_PREFIX}{}", redact(persistence_key))
+}
+
+/// Persistent actor running in this process or on another node of the cluster.
+pub enum PersistentRef<A: Actor + RemoteActor> {
+    Local(ActorRef<A>),
+    Remote(RemoteActorRef<A>),
+}
+
+impl<A: Actor + RemoteActor> Clone for PersistentRef<A> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Local(actor_ref) => Self::Local(actor_ref.clone()),
+            Self::Remote(actor_ref) => Self::Remote(actor_ref.clone()),
+        }
+    }
+}
+
+impl<A: Actor + RemoteActor> PersistentRef<A> {
+    /// Return the reference if the actor runs in this process.
+    pub fn local(&self) -> Option<&ActorRef<A>> {
+        match self {
+            Self::Local(actor_ref) => Some(actor_ref),
+            Self::Remote(_) => None,
+        }
+    }
+
+    /// Send a message to the actor wherever it runs, without waiting for its reply.
+    pub async fn tell<M>(&self, msg: M) -> anyhow::Result<()>
+    where
+        A: Message<M> + RemoteMessage<M>,
+        M: Serialize + Send + 'static,
+    {
+        match self {
+            Self::Local(actor_ref) => actor_ref
+                .tell(msg)
+                .await
+                .map_err(|e| anyhow!("Failed to send message: {e}")),
+            Self::Remote(actor_ref) => actor_ref
+                .tell(&msg)
+                .await
+                .map_err(|e| anyhow!("Failed to send remote message: {e}")),
+        }
+    }
+
+    /// Send a message to the actor wherever it runs and wait for its reply.
+    pub async fn ask<M>(&self, msg: M) -> anyhow::Result<<A::Reply as Reply>::Ok>
+    where
+        A: Message<M> + RemoteMessage<M>,
+        M: Serialize + Send + 'static,
+        <A::Reply as Reply>::Ok: DeserializeOwned,
+        <A::Reply as Reply>::Error: DeserializeOwned,
+    {
+        match self {
+            Self::Local(actor_ref) => actor_ref
+                .ask(msg)
+                .await
+                .map_err(|e| anyhow!("Failed to ask: {e:?}")),
+            Self::Remote(actor_ref) => actor_ref
+                .ask(&msg)
+                .await
+                .map_err(|e| anyhow!("Failed to ask remote actor: {e:?}")),
+        }
+    }
+}
+
+/// Register a persistent actor in kameo's remote registry under `remote_name` of its key, so
+/// `lookup_or_respawn` on other nodes reaches it.
+pub async fn register_remote<A>(actor_ref: &ActorRef<A>) -> anyhow::Result<()>
+where
+    A: PersistentActor + RemoteActor,
+{
+    let persistence_key = A::persistence_key(actor_ref)
+        .ok_or_else(|| anyhow!("Actor {} is not persistent", std::any::type_name::<A>()))?;
+
+    actor_ref
+        .register(&remote_name(&persistence_key))
+        .await
+        .map_err(|e| {
+            anyhow!(
+                "Failed to register {} remotely: {e}",
+                redacted(&persistence_key)
+            )
+        })
+}
+
+/// Return the actor of a key from the local registry, else from kameo's remote registry, and
+/// only respawn it here if no node runs it, registering it remotely.
+///
+/// Requires the kameo swarm to be bootstrapped. Fails rather than respawning if the remote
+/// registry cannot be queried, so an unreachable cluster never leads to a divergent copy.
+pub async fn lookup_or_respawn<A>(persistence_key: Url) -> anyhow::Result<PersistentRef<A>>
+where
+    A: PersistentActor + RemoteActor,
+{
+    if let Some(actor_ref) = A::lookup_persistent(&persistence_key) {
+        return Ok(PersistentRef::Local(actor_ref));
+    }
+
+    let name = remote_name(&persistence_key);
+    if let Some(actor_ref) = lookup::<A>(&name).await? {
+        #[cfg(feature = "tracing")]
+        debug!("Found {name} running on another node");
+        return Ok(PersistentRef::Remote(actor_ref));
+    }
+
+    let actor_ref = A::respawn_persistent(persistence_key).await?;
+    register_remote(&actor_ref).await?;
+
+    Ok(PersistentRef::Local(actor_ref))
+}
+
+/// Let other nodes ask this node to respawn actors of type `A`, see `serve_respawns`.
+pub fn register_respawner<A>()
+where
+    A: PersistentActor + RemoteActor,
+{
+    RESPAWNERS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(A::type_tag(), respawn_here::<A>);
+}
+
+fn respawn_here<A>(persistence_key: Url) -> BoxFuture<'static, anyhow::Result<()>>
+where
+    A: PersistentActor + RemoteActor,
+{
+    Box::pin(async move {
+        let actor_ref = A::respawn_persistent(persistence_key).await?;
+        register_remote(&actor_ref).await
+    })
+}
+
+/// Actor respawning persistent actors on behalf of other nodes.
+pub struct RespawnService;
+
+impl Actor for RespawnService {
+    type Args = ();
+    type Error = anyhow::Error;
+
+    async fn on_start(_args: Self::Args, _actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        Ok(Self)
+    }
+}
+
+impl RemoteActor for RespawnService {
+    const REMOTE_ID: &'static str = "kameo_persistence::remote::RespawnService";
+}
+
+/// Ask a node to respawn the actor of a key and register it remotely.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Respawn {
+    pub type_tag: String,
+    pub persistence_key: Url,
+}
+
+#[remote_message("kameo_persistence::remote::Respawn")]
+impl Message<Respawn> for RespawnService {
+    type Reply = Result<(), String>;
+
+    async fn handle(&mut self, msg: Respawn, _ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        let respawner = RESPAWNERS
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(msg.type_tag.as_str())
+            .copied()
+            .ok_or_else(|| format!("No respawner registered for type {}", msg.type_tag))?;
+
+        respawner(msg.persistence_key.clone()).await.map_err(|e| {
+            #[cfg(feature = "tracing")]
+            warn!(
+                "Failed to respawn {} for another node: {e:#}",
+                redacted(&msg.persistence_key)
+            );
+            format!("{e:#}")
+        })
+    }
+}
+
+/// Ask a node how many live persistent actors it runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Load;
+
+#[remote_message("kameo_persistence::remote::Load")]
+impl Message<Load> for RespawnService {
+    type Reply = usize;
+
+    async fn handle(&mut self, _msg: Load, _ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        registry::live_keys().len()
+    }
+}
+
+/// Spawn the respawn service of this node and register it remotely as `node`, so other nodes
+/// can place actors here with `respawn_on`.
+pub async fn serve_respawns(node: &str) -> anyhow::Result<ActorRef<RespawnService>> {
+    let service = RespawnService::spawn(());
+    service
+        .register(&format!("{SERVICE_PREFIX}{node}"))
+        .await
+        .map_err(|e| anyhow!("Failed to register the respawn service of {node}: {e}"))?;
+
+    Ok(service)
+}
+
+/// How `respawn_on` picks the node to respawn an actor on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Placement {
+    /// The node the key hashes to, so every node places a key on the same node.
+    #[default]
+    Shard,
+    /// The node running the fewest persistent actors, asking every node for its load.
+    LeastLoaded,
+}
+
+/// Return the actor of a key wherever it runs, else have the node chosen by `placement` among
+/// `nodes` respawn it and return a reference to it there.
+///
+/// Every node in `nodes` must run `serve_respawns` and `register_respawner::<A>`. The actor
+/// is looked up remotely once respawned, so this fails if kameo's registry has not propagated
+/// its registration yet.
+pub async fn respawn_on<A>(
+    persistence_key: Url,
+    nodes: &[String],
+    placement: Placement,
+) -> anyhow::Result<PersistentRef<A>>
+where
+    A: PersistentActor + RemoteActor,
+{
+    if let Some(actor_ref) = A::lookup_persistent(&persistence_key) {
+        return Ok(PersistentRef::Local(actor_ref));
+    }
+
+    let name = remote_name(&persistence_key);
+    if let Some(actor_ref) = lookup::<A>(&name).await? {
+        return Ok(PersistentRef::Remote(actor_ref));
+    }
+
+    let node = match placement {
+        Placement::Shard => shard(&persistence_key, nodes)?,
+        Placement::LeastLoaded => least_loaded(nodes).await?,
+    };
+
+    #[cfg(feature = "tracing")]
+    debug!("Placing {name} on node {node}");
+
+    service(node)
+        .await?
+        .ask(&Respawn {
+            type_tag: A::type_tag().to_string(),
+            persistence_key,
+        })
+        .await
+        .map_err(|e| anyhow!("Node {node} failed to respawn {name}: {e:?}"))?;
+
+    lookup::<A>(&name)
+        .await?
+        .map(PersistentRef::Remote)
+        .ok_or_else(|| anyhow!("Node {node} respawned {name} but it is not registered yet"))
+}
+
+async fn lookup<A: Actor + RemoteActor>(name: &str) -> anyhow::Result<Option<RemoteActorRef<A>>> {
+    RemoteActorRef::<A>::lookup(name)
+        .await
+        .map_err(|e| anyhow!("Failed to look up {name} remotely: {e}"))
+}
+
+async fn service(node: &str) -> anyhow::Result<RemoteActorRef<RespawnService>> {
+    lookup(&format!("{SERVICE_PREFIX}{node}"))
+        .await?
+        .ok_or_else(|| anyhow!("No respawn service registered for node {node}"))
+}
+
+fn shard<'a>(persistence_key: &Url, nodes: &'a [String]) -> anyhow::Result<&'a str> {
+    anyhow::ensure!(!nodes.is_empty(), "No node to respawn on");
+
+    let digest = Sha256::digest(persistence_key.as_str().as_bytes());
+    let hash = u64::from_be_bytes(digest[..8].try_into()?);
+    Ok(&nodes[(hash % nodes.len() as u64) as usize])
+}
+
+async fn least_loaded(nodes: &[String]) -> anyhow::Result<&str> {
+    let mut best: Option<(&str, usize)> = None;
+
+    for node in nodes {
+        let load = match service(node).await {
+            Ok(service) => service.ask(&Load).await.map_err(|e| anyhow!("{e:?}")),
+            Err(e) => Err(e),
+        };
+
+        match load {
+            Ok(load) if best.is_none_or(|(_, best)| load < best) => best = Some((node, load)),
+            Ok(_) => {}
+            Err(_e) => {
+                #[cfg(feature = "tracing")]
+                warn!("Skipping node {node}, failed to get its load: {_e:#}");
+            }
+        }
+    }
+
+    best.map(|(node, _)| node)
+        .ok_or_else(|| anyhow!("No node reachable to respawn on"))
+}
@@ -0,0 +1,31 @@
+use anyhow::anyhow;
+use kameo::{error::SendError, prelude::*};
+
+use crate::PersistentActor;
+
+/// Message rolling an actor back to its latest saved snapshot, handled with
+/// `PersistentActor::on_rollback`; implemented by the derive macro.
+#[derive(Debug, Clone, Copy)]
+pub struct Rollback;
+
+/// Rollback of a running persistent actor.
+pub trait RollbackExt {
+    /// Discard the in-memory state of the actor and replace it with its latest saved snapshot,
+    /// such as to undo changes or recover from a bad mutation, without restarting it.
+    ///
+    /// Messages queued before the rollback are handled first, those after see the restored
+    /// state. Fails, keeping the current state, if the snapshot cannot be restored.
+    fn rollback_to_snapshot(&self) -> impl Future<Output = anyhow::Result<()>> + Send;
+}
+
+impl<A> RollbackExt for ActorRef<A>
+where
+    A: PersistentActor + Message<Rollback, Reply = anyhow::Result<()>>,
+{
+    async fn rollback_to_snapshot(&self) -> anyhow::Result<()> {
+        self.ask(Rollback).await.map_err(|e| match e {
+            SendError::HandlerError(e) => e,
+            e => anyhow!("Failed to send rollback: {e}"),
+        })
+    }
+}
@@ -0,0 +1,640 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+#[cfg(feature = "tracing")]
+use tracing::{debug, trace, warn};
+use url::Url;
+
+use crate::{
+    Checkpoint, PersistentActor,
+    checkpoint::{self, ActorGroup},
+    redact::redacted,
+    registry,
+};
+
+/// Build the `Args` of a new entity from its id.
+type NewEntityFn<A> = Box<dyn Fn(&str) -> <A as Actor>::Args + Send + Sync>;
+
+/// Actor routing messages to persistent entities of type `A` by id, one entity per id.
+///
+/// The key of an entity is its id appended to the router's root key. Entities not running are
+/// respawned from their snapshot, or spawned with `RouterArgs::new_entity` if they have none.
+///
+/// Routers given a `HashRing` with `RouterArgs::member` only start the entities the ring assigns
+/// to them, see `RouterPool`.
+pub struct EntityRouter<A: PersistentActor> {
+    root: Url,
+    new_entity: Option<NewEntityFn<A>>,
+    member: Option<(String, HashRing)>,
+    handoff_timeout: Duration,
+    // Entities started by this router, stopped when the ring assigns them elsewhere
+    started: BTreeMap<String, ActorRef<A>>,
+}
+
+/// Arguments of an `EntityRouter`.
+pub struct RouterArgs<A: PersistentActor> {
+    root: Url,
+    new_entity: Option<NewEntityFn<A>>,
+    member: Option<(String, HashRing)>,
+    handoff_timeout: Duration,
+}
+
+impl<A: PersistentActor> RouterArgs<A> {
+    /// Route to the entities below `root`, respawning only entities with a snapshot.
+    pub fn new(root: Url) -> Self {
+        Self {
+            root,
+            new_entity: None,
+            member: None,
+            handoff_timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Spawn entities without a snapshot with the `Args` built from their id.
+    pub fn new_entity(
+        mut self,
+        new_entity: impl Fn(&str) -> A::Args + Send + Sync + 'static,
+    ) -> Self {
+        self.new_entity = Some(Box::new(new_entity));
+        self
+    }
+
+    /// Only own the entities `ring` assigns to `name`, failing to route to the others.
+    pub fn member(mut self, name: &str, ring: HashRing) -> Self {
+        self.member = Some((name.to_string(), ring));
+        self
+    }
+
+    /// How long to wait for an entity handed off to another router to handle the messages it
+    /// already received and save its snapshot, 10 seconds by default.
+    pub fn handoff_timeout(mut self, timeout: Duration) -> Self {
+        self.handoff_timeout = timeout;
+        self
+    }
+}
+
+/// Consistent hash ring assigning entity ids to the members sharing them, such as the routers
+/// of several threads or nodes.
+///
+/// Every member is placed at `vnodes` points of the ring, and an id belongs to the member of the
+/// first point at or after its hash. Adding or removing a member only moves the ids of the
+/// ring's arcs it gains or loses, about `1 / members` of them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Reply)]
+pub struct HashRing {
+    vnodes: u32,
+    members: BTreeSet<String>,
+    points: BTreeMap<u64, String>,
+}
+
+impl Default for HashRing {
+    fn default() -> Self {
+        Self::new(160)
+    }
+}
+
+impl HashRing {
+    pub fn new(vnodes: u32) -> Self {
+        Self {
+            vnodes: vnodes.max(1),
+            members: BTreeSet::new(),
+            points: BTreeMap::new(),
+        }
+    }
+
+    /// Add a member, returning whether it was not in the ring yet.
+    pub fn add(&mut self, member: &str) -> bool {
+        if !self.members.insert(member.to_string()) {
+            return false;
+        }
+
+        for vnode in 0..self.vnodes {
+            // On the unlikely collision, the smallest member keeps the point on every member
+            let point = hash(&format!("{member}#{vnode}"));
+            let owner = self
+                .points
+                .entry(point)
+                .or_insert_with(|| member.to_string());
+            if member < owner.as_str() {
+                *owner = member.to_string();
+            }
+        }
+        true
+    }
+
+    /// Remove a member, returning whether it was in the ring.
+    pub fn remove(&mut self, member: &str) -> bool {
+        if !self.members.remove(member) {
+            return false;
+        }
+
+        // Rebuilt, so points the member won on collisions go back to the others
+        let members = std::mem::take(&mut self.members);
+        self.points.clear();
+        for member in &members {
+            self.add(member);
+        }
+        true
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = &str> {
+        self.members.iter().map(String::as_str)
+    }
+
+    pub fn contains(&self, member: &str) -> bool {
+        self.members.contains(member)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Member owning an entity id, `None` if the ring has no member.
+    pub fn owner(&self, entity_id: &str) -> Option<&str> {
+        let point = hash(entity_id);
+        self.points
+            .range(point..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .map(|(_, member)| member.as_str())
+    }
+}
+
+fn hash(value: &str) -> u64 {
+    let digest = Sha256::digest(value.as_bytes());
+    u64::from_be_bytes(
+        digest[..8]
+            .try_into()
+            .expect("SHA-256 digests are 32 bytes"),
+    )
+}
+
+/// Persistence key of the entity `entity_id` below `root`.
+pub fn entity_key(root: &Url, entity_id: &str) -> anyhow::Result<Url> {
+    if entity_id.is_empty() || entity_id == "." || entity_id == ".." {
+        anyhow::bail!("Invalid entity id: {entity_id:?}");
+    }
+
+    let mut key = root.clone();
+    key.path_segments_mut()
+        .map_err(|_| anyhow!("Persistence key cannot be a base: {}", redacted(root)))?
+        .pop_if_empty()
+        .push(entity_id);
+
+    Ok(key)
+}
+
+impl<A: PersistentActor> EntityRouter<A> {
+    /// Return the running entity of an id, respawning or spawning it if needed.
+    async fn entity(&mut self, entity_id: &str) -> anyhow::Result<ActorRef<A>> {
+        if let Some((member, ring)) = &self.member {
+            let owner = ring.owner(entity_id);
+            if owner != Some(member.as_str()) {
+                anyhow::bail!(
+                    "Entity {entity_id} is owned by router {}, not {member}",
+                    owner.unwrap_or("none")
+                );
+            }
+        }
+
+        let key = entity_key(&self.root, entity_id)?;
+        if let Some(entity) = A::lookup_persistent(&key) {
+            return Ok(entity);
+        }
+
+        #[cfg(feature = "tracing")]
+        trace!("Starting entity {entity_id} with key {}", redacted(&key));
+
+        let entity = match &self.new_entity {
+            Some(new_entity) => A::try_respawn_persistent(key, new_entity(entity_id)).await?,
+            None => A::respawn_persistent(key).await?,
+        };
+
+        if self.member.is_some() {
+            self.started.retain(|_, entity| entity.is_alive());
+            self.started.insert(entity_id.to_string(), entity.clone());
+        }
+        Ok(entity)
+    }
+
+    /// Drain, save and stop the started entities `ring` no longer assigns to this router,
+    /// returning their ids.
+    ///
+    /// The snapshot is taken at a barrier queued behind the messages the entity already
+    /// received, so its new owner respawns it with all of them handled. Entities failing to
+    /// save keep running here and are left out, so no message they handled is lost; the next
+    /// ring update tries to hand them off again.
+    async fn hand_off(&mut self) -> Vec<String> {
+        let Some((member, ring)) = &self.member else {
+            return Vec::new();
+        };
+
+        let leaving = self
+            .started
+            .keys()
+            .filter(|entity_id| ring.owner(entity_id) != Some(member.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut moved = Vec::new();
+        for entity_id in leaving {
+            let Some(entity) = self.started.get(&entity_id).cloned() else {
+                continue;
+            };
+
+            if entity.is_alive() {
+                #[cfg(feature = "tracing")]
+                debug!(
+                    "Handing entity {entity_id} off to router {:?}",
+                    ring.owner(&entity_id)
+                );
+
+                if let Err(_e) = self.save(&entity).await {
+                    #[cfg(feature = "tracing")]
+                    warn!("Keeping entity {entity_id}, which failed to save on hand-off: {_e:#}");
+                    continue;
+                }
+
+                if let Err(_e) = entity.stop_gracefully().await {
+                    #[cfg(feature = "tracing")]
+                    warn!("Failed to stop entity {entity_id} on hand-off: {_e}");
+                }
+                entity.wait_for_shutdown().await;
+            }
+
+            self.started.remove(&entity_id);
+            moved.push(entity_id);
+        }
+
+        moved
+    }
+
+    /// Save the snapshot of an entity at a barrier behind the messages it already received.
+    async fn save(&self, entity: &ActorRef<A>) -> anyhow::Result<()> {
+        let key = A::persistence_key(entity).ok_or_else(|| anyhow!("Entity is not persistent"))?;
+        let group = ActorGroup::new().with_key(&key)?;
+
+        checkpoint::save_group(&group, 1, self.handoff_timeout)
+            .await
+            .pop()
+            .unwrap_or_else(|| Err(anyhow!("Entity was not saved")))
+            .map(|_| ())
+    }
+}
+
+impl<A: PersistentActor> Actor for EntityRouter<A> {
+    type Args = RouterArgs<A>;
+    type Error = anyhow::Error;
+
+    async fn on_start(args: Self::Args, _actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            root: args.root,
+            new_entity: args.new_entity,
+            member: args.member,
+            handoff_timeout: args.handoff_timeout,
+            started: BTreeMap::new(),
+        })
+    }
+}
+
+/// Send `message` to the entity `entity_id`, starting it if needed, without waiting for it to
+/// be handled.
+pub struct Route<M> {
+    pub entity_id: String,
+    pub message: M,
+}
+
+impl<A, M> Message<Route<M>> for EntityRouter<A>
+where
+    A: PersistentActor + Message<M>,
+    M: Send + 'static,
+{
+    type Reply = anyhow::Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: Route<M>,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let entity = self.entity(&msg.entity_id).await?;
+        entity
+            .tell(msg.message)
+            .await
+            .map_err(|e| anyhow!("Failed to send message to entity {}: {e}", msg.entity_id))
+    }
+}
+
+/// Return the entity `entity_id`, starting it if needed, e.g. to ask it directly.
+pub struct GetEntity {
+    pub entity_id: String,
+}
+
+impl<A: PersistentActor> Message<GetEntity> for EntityRouter<A> {
+    type Reply = anyhow::Result<ActorRef<A>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetEntity,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.entity(&msg.entity_id).await
+    }
+}
+
+/// Replace the ring of a member router, stopping the entities it no longer owns, and return
+/// their ids.
+///
+/// Entities are stopped before the reply, so their new owner respawns them from their latest
+/// snapshot once every member moved to the new ring. Entities which could not be saved keep
+/// running on this router and are not returned; sending the ring again retries them.
+pub struct UpdateRing {
+    pub ring: HashRing,
+}
+
+impl<A: PersistentActor> Message<UpdateRing> for EntityRouter<A> {
+    type Reply = Vec<String>;
+
+    async fn handle(
+        &mut self,
+        msg: UpdateRing,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        match &mut self.member {
+            Some((_, ring)) => *ring = msg.ring,
+            None => return Vec::new(),
+        }
+        self.hand_off().await
+    }
+}
+
+/// Routers sharing the entities of a root key by consistent hashing, each entity owned by
+/// exactly one of them.
+///
+/// Pools created with `restore` keep their ring in a `ShardTable`, so ownership survives
+/// restarts.
+pub struct RouterPool<A: PersistentActor> {
+    ring: HashRing,
+    routers: BTreeMap<String, ActorRef<EntityRouter<A>>>,
+    table: Option<ActorRef<ShardTable>>,
+}
+
+impl<A: PersistentActor> RouterPool<A> {
+    pub fn new(vnodes: u32) -> Self {
+        Self {
+            ring: HashRing::new(vnodes),
+            routers: BTreeMap::new(),
+            table: None,
+        }
+    }
+
+    /// Respawn the pool whose ring is stored under `table_key`, spawning a router with
+    /// `args(name)` for every member, or start an empty pool of `vnodes` points per member.
+    pub async fn restore(
+        table_key: Url,
+        vnodes: u32,
+        args: impl Fn(&str) -> RouterArgs<A>,
+    ) -> anyhow::Result<Self> {
+        let table = ShardTable::try_respawn_persistent(
+            table_key,
+            ShardTableSnapshot {
+                ring: HashRing::new(vnodes),
+            },
+        )
+        .await?;
+        let ring = table
+            .ask(GetRing)
+            .await
+            .map_err(|e| anyhow!("Failed to read the shard table: {e}"))?;
+
+        let routers = ring
+            .members()
+            .map(|name| {
+                let router = EntityRouter::spawn(args(name).member(name, ring.clone()));
+                (name.to_string(), router)
+            })
+            .collect();
+
+        Ok(Self {
+            ring,
+            routers,
+            table: Some(table),
+        })
+    }
+
+    pub fn ring(&self) -> &HashRing {
+        &self.ring
+    }
+
+    /// Spawn a router named `name` and rebalance the entities, returning the ids handed off.
+    ///
+    /// `args` are completed with the pool's ring.
+    pub async fn add(&mut self, name: &str, args: RouterArgs<A>) -> anyhow::Result<Vec<String>> {
+        anyhow::ensure!(
+            !self.ring.contains(name),
+            "Router {name} is already in the pool"
+        );
+
+        let mut ring = self.ring.clone();
+        ring.add(name);
+
+        self.store(&ring).await?;
+        let moved = self.update(&ring).await?;
+
+        let router = EntityRouter::spawn(args.member(name, ring.clone()));
+        self.routers.insert(name.to_string(), router);
+        self.ring = ring;
+
+        Ok(moved)
+    }
+
+    /// Stop the router named `name` and its entities, returning the ids handed off.
+    pub async fn remove(&mut self, name: &str) -> anyhow::Result<Vec<String>> {
+        let mut ring = self.ring.clone();
+        if !ring.remove(name) {
+            return Ok(Vec::new());
+        }
+
+        self.store(&ring).await?;
+        let moved = self.update(&ring).await?;
+
+        if let Some(router) = self.routers.remove(name) {
+            router
+                .stop_gracefully()
+                .await
+                .map_err(|e| anyhow!("Failed to stop router {name}: {e}"))?;
+            router.wait_for_shutdown().await;
+        }
+        self.ring = ring;
+
+        Ok(moved)
+    }
+
+    /// Router owning an entity id.
+    pub fn router(&self, entity_id: &str) -> Option<&ActorRef<EntityRouter<A>>> {
+        self.routers.get(self.ring.owner(entity_id)?)
+    }
+
+    /// Send `message` to the entity `entity_id` through the router owning it.
+    pub async fn route<M>(&self, entity_id: &str, message: M) -> anyhow::Result<()>
+    where
+        A: Message<M>,
+        M: Send + 'static,
+    {
+        self.owner(entity_id)?
+            .ask(Route {
+                entity_id: entity_id.to_string(),
+                message,
+            })
+            .await
+            .map_err(|e| anyhow!("Failed to route to entity {entity_id}: {e}"))
+    }
+
+    /// Return the entity `entity_id` from the router owning it.
+    pub async fn entity(&self, entity_id: &str) -> anyhow::Result<ActorRef<A>> {
+        self.owner(entity_id)?
+            .ask(GetEntity {
+                entity_id: entity_id.to_string(),
+            })
+            .await
+            .map_err(|e| anyhow!("Failed to get entity {entity_id}: {e}"))
+    }
+
+    fn owner(&self, entity_id: &str) -> anyhow::Result<&ActorRef<EntityRouter<A>>> {
+        self.router(entity_id)
+            .ok_or_else(|| anyhow!("No router in the pool to route entity {entity_id} to"))
+    }
+
+    /// Save `ring` in the shard table before handing entities off, so a restart resumes from it.
+    async fn store(&self, ring: &HashRing) -> anyhow::Result<()> {
+        let Some(table) = &self.table else {
+            return Ok(());
+        };
+
+        table
+            .ask(SetRing { ring: ring.clone() })
+            .await
+            .map_err(|e| anyhow!("Failed to save the shard table: {e}"))
+    }
+
+    /// Move every router to `ring`, returning the ids they handed off.
+    async fn update(&self, ring: &HashRing) -> anyhow::Result<Vec<String>> {
+        let mut moved = Vec::new();
+        for (name, router) in &self.routers {
+            moved.extend(
+                router
+                    .ask(UpdateRing { ring: ring.clone() })
+                    .await
+                    .map_err(|e| anyhow!("Failed to update the ring of router {name}: {e}"))?,
+            );
+        }
+        Ok(moved)
+    }
+}
+
+/// Persistent actor storing the ring of a `RouterPool`.
+pub struct ShardTable {
+    ring: HashRing,
+}
+
+/// Snapshot and arguments of a `ShardTable`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ShardTableSnapshot {
+    pub ring: HashRing,
+}
+
+impl From<&ShardTable> for ShardTableSnapshot {
+    fn from(table: &ShardTable) -> Self {
+        Self {
+            ring: table.ring.clone(),
+        }
+    }
+}
+
+impl Actor for ShardTable {
+    type Args = ShardTableSnapshot;
+    type Error = anyhow::Error;
+
+    async fn on_start(args: Self::Args, _actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        Ok(Self { ring: args.ring })
+    }
+}
+
+impl PersistentActor for ShardTable {
+    type Snapshot = ShardTableSnapshot;
+
+    fn register_persistent(persistence_key: Url, actor_ref: &ActorRef<Self>) -> anyhow::Result<()> {
+        let Ok(mut registry) = registry::typed_registry::<Self>().write() else {
+            anyhow::bail!("Failed to acquire write lock on registry");
+        };
+        registry::register(persistence_key.clone(), actor_ref);
+        registry.insert(persistence_key, actor_ref.downgrade());
+        Ok(())
+    }
+
+    fn persistence_key(actor_ref: &ActorRef<Self>) -> Option<Url> {
+        let registry = registry::typed_registry::<Self>().read().ok()?;
+        registry.get_left(&actor_ref.downgrade()).cloned()
+    }
+
+    fn lookup_persistent(persistence_key: &Url) -> Option<ActorRef<Self>> {
+        let registry = registry::typed_registry::<Self>().read().ok()?;
+        registry
+            .get_right(persistence_key)
+            .and_then(|weak_ref| weak_ref.upgrade())
+            .filter(|actor_ref| actor_ref.is_alive())
+    }
+
+    fn unregister_persistent(persistence_key: &Url) {
+        registry::unregister(persistence_key);
+        registry::typed_registry::<Self>()
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove_left(persistence_key);
+    }
+}
+
+impl Message<Checkpoint> for ShardTable {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: Checkpoint,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.on_checkpoint(&ctx.actor_ref(), msg).await
+    }
+}
+
+/// Return the ring stored in the shard table.
+pub struct GetRing;
+
+impl Message<GetRing> for ShardTable {
+    type Reply = HashRing;
+
+    async fn handle(
+        &mut self,
+        _msg: GetRing,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.ring.clone()
+    }
+}
+
+/// Replace the ring stored in the shard table and save it.
+pub struct SetRing {
+    pub ring: HashRing,
+}
+
+impl Message<SetRing> for ShardTable {
+    type Reply = anyhow::Result<()>;
+
+    async fn handle(&mut self, msg: SetRing, ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        self.ring = msg.ring;
+        self.save_snapshot(&ctx.actor_ref()).await
+    }
+}
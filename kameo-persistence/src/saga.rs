@@ -0,0 +1,210 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+#[cfg(feature = "tracing")]
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::{redact::redacted, storage};
+
+type StepFn<C> = Arc<dyn Fn(C) -> BoxFuture<'static, anyhow::Result<C>> + Send + Sync>;
+
+struct Step<C> {
+    name: String,
+    action: StepFn<C>,
+    compensation: StepFn<C>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum Status {
+    Running,
+    /// A step failed with the error, and completed steps are being compensated.
+    Compensating(String),
+    Completed,
+    Compensated(String),
+}
+
+/// Progress of a saga, stored under its key after every step.
+#[derive(Serialize, Deserialize)]
+struct SagaState<C> {
+    context: C,
+    /// Names of the completed steps not compensated yet, in order.
+    completed: Vec<String>,
+    status: Status,
+}
+
+/// Final outcome of a saga.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SagaOutcome<C> {
+    /// Every step completed.
+    Completed(C),
+    /// A step failed and every completed step was compensated.
+    Compensated {
+        /// Context after the compensations.
+        context: C,
+        /// Error of the failed step.
+        error: String,
+    },
+}
+
+/// Workflow of steps with compensations, such as reserving stock, charging a payment and
+/// shipping an order, whose progress survives crashes.
+///
+/// Each step takes the context left by the previous one and returns it updated. Progress is
+/// stored under the saga's key after every step, so running the saga again after a crash
+/// resumes from the last completed step. If a step fails, the steps completed before it are
+/// compensated in reverse order, and the failure is stored too, so compensation also resumes.
+/// A step interrupted by a crash runs again, hence steps and compensations must be idempotent.
+/// A failed compensation fails `run`; running it again retries the compensation.
+pub struct PersistentSaga<C> {
+    persistence_key: Url,
+    steps: Vec<Step<C>>,
+}
+
+impl<C> PersistentSaga<C>
+where
+    C: Clone + Send + Serialize + DeserializeOwned + 'static,
+{
+    /// Create a saga without steps, storing its progress under `persistence_key`.
+    pub fn new(persistence_key: Url) -> Self {
+        Self {
+            persistence_key,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Add a step, run after the previous ones, and the compensation undoing it.
+    pub fn step<A, AF, U, UF>(mut self, name: &str, action: A, compensation: U) -> Self
+    where
+        A: Fn(C) -> AF + Send + Sync + 'static,
+        AF: Future<Output = anyhow::Result<C>> + Send + 'static,
+        U: Fn(C) -> UF + Send + Sync + 'static,
+        UF: Future<Output = anyhow::Result<C>> + Send + 'static,
+    {
+        self.steps.push(Step {
+            name: name.to_string(),
+            action: Arc::new(move |context| Box::pin(action(context))),
+            compensation: Arc::new(move |context| Box::pin(compensation(context))),
+        });
+        self
+    }
+
+    /// Run the saga from the start with `context`, or resume it from its stored progress, in
+    /// which case `context` is ignored.
+    ///
+    /// A saga already finished returns its stored outcome without running any step.
+    pub async fn run(&self, context: C) -> anyhow::Result<SagaOutcome<C>> {
+        let mut state = match self.load()? {
+            Some(state) => state,
+            None => SagaState {
+                context,
+                completed: Vec::new(),
+                status: Status::Running,
+            },
+        };
+
+        if state.status == Status::Running {
+            self.run_steps(&mut state).await?;
+        }
+
+        if let Status::Compensating(error) = &state.status {
+            let error = error.clone();
+            self.compensate(&mut state).await?;
+            state.status = Status::Compensated(error);
+            self.store(&state).await?;
+        }
+
+        match state.status {
+            Status::Completed => Ok(SagaOutcome::Completed(state.context)),
+            Status::Compensated(error) => Ok(SagaOutcome::Compensated {
+                context: state.context,
+                error,
+            }),
+            Status::Running | Status::Compensating(_) => unreachable!("saga did not finish"),
+        }
+    }
+
+    /// Delete the stored progress, so the next run starts over.
+    pub async fn clear(&self) -> anyhow::Result<()> {
+        storage::delete(&self.persistence_key).await
+    }
+
+    async fn run_steps(&self, state: &mut SagaState<C>) -> anyhow::Result<()> {
+        for step in &self.steps[state.completed.len()..] {
+            match (step.action)(state.context.clone()).await {
+                Ok(context) => {
+                    state.context = context;
+                    state.completed.push(step.name.clone());
+
+                    #[cfg(feature = "tracing")]
+                    debug!(
+                        "Saga {} completed step {}",
+                        redacted(&self.persistence_key),
+                        step.name
+                    );
+                }
+                Err(e) => {
+                    #[cfg(feature = "tracing")]
+                    warn!(
+                        "Saga {} failed at step {}, compensating: {e:#}",
+                        redacted(&self.persistence_key),
+                        step.name
+                    );
+
+                    state.status = Status::Compensating(format!("{}: {e:#}", step.name));
+                    return self.store(state).await;
+                }
+            }
+
+            self.store(state).await?;
+        }
+
+        state.status = Status::Completed;
+        self.store(state).await
+    }
+
+    async fn compensate(&self, state: &mut SagaState<C>) -> anyhow::Result<()> {
+        while let Some(name) = state.completed.last() {
+            let step = &self.steps[state.completed.len() - 1];
+
+            state.context = (step.compensation)(state.context.clone())
+                .await
+                .map_err(|e| e.context(format!("Failed to compensate step {name}")))?;
+            state.completed.pop();
+
+            self.store(state).await?;
+        }
+
+        Ok(())
+    }
+
+    fn load(&self) -> anyhow::Result<Option<SagaState<C>>> {
+        let Some(data) = storage::backend(&self.persistence_key)?
+            .read_file(&self.persistence_key, storage::snapshot_file())?
+        else {
+            return Ok(None);
+        };
+
+        let state: SagaState<C> = postcard::from_bytes(&data)?;
+
+        // Resuming with different steps would run or compensate the wrong ones
+        let defined = self.steps.iter().map(|step| &step.name);
+        if state.completed.len() > self.steps.len()
+            || !state.completed.iter().zip(defined).all(|(a, b)| a == b)
+        {
+            return Err(anyhow!(
+                "Saga {} was stored with steps {:?}, which this saga does not start with",
+                redacted(&self.persistence_key),
+                state.completed
+            ));
+        }
+
+        Ok(Some(state))
+    }
+
+    async fn store(&self, state: &SagaState<C>) -> anyhow::Result<()> {
+        storage::write_atomic(&self.persistence_key, &postcard::to_stdvec(state)?).await
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::anyhow;
+use serde::{Serialize, de::DeserializeOwned};
+#[cfg(feature = "tracing")]
+use tracing::debug;
+use url::Url;
+
+#[cfg(feature = "tracing")]
+use crate::redact::redacted;
+use crate::{PersistenceError, PersistentActor, codec, registry, storage};
+
+type Migration = Arc<dyn Fn(&[u8]) -> anyhow::Result<Vec<u8>> + Send + Sync>;
+
+// Migration steps by type tag and the schema version they upgrade from
+static MIGRATIONS: RwLock<Option<HashMap<(&'static str, u32), Migration>>> = RwLock::new(None);
+
+/// Register the step upgrading snapshots of `A` from schema version `from` to `from + 1`.
+///
+/// `Old` and `New` are the snapshot types of those versions. Steps are chained from the stored
+/// version up to `A::schema_version()` whenever a snapshot is decoded, and by `upgrade`.
+pub fn register_migration<A, Old, New>(
+    from: u32,
+    migrate: impl Fn(Old) -> New + Send + Sync + 'static,
+) where
+    A: PersistentActor,
+    Old: DeserializeOwned,
+    New: Serialize,
+{
+    let migration: Migration = Arc::new(move |payload| {
+        let old = postcard::from_bytes(payload)?;
+        Ok(postcard::to_allocvec(&migrate(old))?)
+    });
+
+    MIGRATIONS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_default()
+        .insert((A::type_tag(), from), migration);
+}
+
+/// Upgrade a decrypted payload of `A` from schema version `version` to the current one.
+pub(crate) fn upgrade_payload<A: PersistentActor>(
+    version: u32,
+    payload: Cow<'_, [u8]>,
+) -> anyhow::Result<Cow<'_, [u8]>> {
+    let current = A::schema_version();
+
+    if version > current {
+        return Err(PersistenceError::SchemaTooNew {
+            type_tag: A::type_tag().to_string(),
+            version,
+            current,
+        }
+        .into());
+    }
+
+    let mut payload = payload;
+    for from in version..current {
+        let migration = MIGRATIONS
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .and_then(|migrations| migrations.get(&(A::type_tag(), from)).cloned())
+            .ok_or_else(|| {
+                anyhow!(
+                    "No migration registered for {} from schema version {from}",
+                    A::type_tag()
+                )
+            })?;
+
+        payload = Cow::Owned(migration(&payload)?);
+    }
+
+    Ok(payload)
+}
+
+/// Outcome of a bulk schema upgrade.
+#[derive(Debug, Clone, Default)]
+pub struct UpgradeReport {
+    /// Keys whose snapshot was rewritten at the current schema version.
+    pub upgraded: Vec<Url>,
+    /// Keys whose snapshot already was at the current schema version.
+    pub current: Vec<Url>,
+}
+
+/// Rewrite every snapshot of `A` under `prefix` at the current schema version.
+///
+/// Runs the registered migrations ahead of deployment rather than lazily at restore time.
+/// Snapshots of other actor types, and snapshots without a header, are left alone.
+pub async fn upgrade<A: PersistentActor>(prefix: &Url) -> anyhow::Result<UpgradeReport> {
+    upgrade_type(prefix, A::type_tag()).await
+}
+
+/// Rewrite every snapshot of the actor type tagged `type_tag` under `prefix` at its current
+/// schema version.
+///
+/// The type must be registered in this process, by deriving `PersistentActor` or with
+/// `registry::register_type`. Actors running under the keys keep their state and overwrite the
+/// upgraded snapshot on their next save, which is harmless once they run the new schema.
+pub async fn upgrade_type(prefix: &Url, type_tag: &str) -> anyhow::Result<UpgradeReport> {
+    let registration = registry::registration(type_tag)
+        .ok_or_else(|| anyhow!("Actor type {type_tag} is not registered"))?;
+    let current = (registration.schema_version)();
+
+    let mut report = UpgradeReport::default();
+
+    for key in storage::list(prefix).await? {
+        let data = storage::read(&key).await?;
+        let Some(header) = codec::split(&data)?.0 else {
+            continue;
+        };
+
+        if header.type_tag != type_tag {
+            continue;
+        }
+
+        if header.schema_version == current {
+            report.current.push(key);
+            continue;
+        }
+
+        let upgraded = (registration.reencode)(&key, &data)?;
+        storage::write_atomic(&key, &upgraded).await?;
+
+        #[cfg(feature = "tracing")]
+        debug!(
+            "Upgraded {} from schema version {} to {current}",
+            redacted(&key),
+            header.schema_version
+        );
+
+        report.upgraded.push(key);
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,138 @@
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::parts::SegmentPart;
+
+// Versions of changed segments, unique so clones changed apart never share one
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(1);
+
+/// Field group of a snapshot stored as a part of its own, and only serialized and written when
+/// it changed, such as a multi-megabyte collection next to small counters.
+///
+/// Listed in `#[segments(...)]` of the derive macro, or split off in `split_parts` with
+/// `SnapshotParts::insert_segment`. Mutable access marks the segment dirty; clones share the
+/// value until one of them changes it, so capturing snapshots does not copy it.
+pub struct Segment<T> {
+    value: Arc<T>,
+    // Renewed on every mutable access, zero until then
+    version: u64,
+    // Stored in a part of its own rather than inside the snapshot
+    detached: bool,
+    // Version and file of the segment last written or restored, shared by clones
+    stored: Arc<Mutex<Option<(u64, String)>>>,
+}
+
+impl<T> Segment<T> {
+    pub fn new(value: T) -> Self {
+        Self::restored(value, None)
+    }
+
+    pub(crate) fn restored(value: T, file: Option<String>) -> Self {
+        Self {
+            value: Arc::new(value),
+            version: 0,
+            detached: false,
+            stored: Arc::new(Mutex::new(file.map(|file| (0, file)))),
+        }
+    }
+
+    /// Return true if the segment changed since it was last written or restored.
+    pub fn is_dirty(&self) -> bool {
+        self.stored
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .is_none_or(|(version, _)| *version != self.version)
+    }
+
+    /// Leave the segment out when serializing it, returning a clone to store on its own.
+    pub(crate) fn detach(&mut self) -> Self {
+        let segment = self.clone();
+        self.detached = true;
+        segment
+    }
+}
+
+impl<T> Clone for Segment<T> {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            version: self.version,
+            detached: false,
+            stored: self.stored.clone(),
+        }
+    }
+}
+
+impl<T> Deref for Segment<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Clone> DerefMut for Segment<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.version = NEXT_VERSION.fetch_add(1, Ordering::Relaxed);
+        Arc::make_mut(&mut self.value)
+    }
+}
+
+impl<T: Default> Default for Segment<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Segment<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+impl<T: Serialize> Serialize for Segment<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (!self.detached)
+            .then_some(&*self.value)
+            .serialize(serializer)
+    }
+}
+
+/// A detached segment is restored as `T::default()` until its part is joined, which `decode`
+/// without a key does not.
+impl<'de, T: Deserialize<'de> + Default> Deserialize<'de> for Segment<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self::new(
+            Option::deserialize(deserializer)?.unwrap_or_default(),
+        ))
+    }
+}
+
+impl<T: Serialize + Send + Sync> SegmentPart for Segment<T> {
+    fn stored(&self) -> Option<String> {
+        self.stored
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .filter(|(version, _)| *version == self.version)
+            .map(|(_, file)| file.clone())
+    }
+
+    fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(postcard::to_allocvec(&*self.value)?)
+    }
+
+    fn stored_in(&self, file: &str) {
+        *self.stored.lock().unwrap_or_else(|e| e.into_inner()) =
+            Some((self.version, file.to_string()));
+    }
+}
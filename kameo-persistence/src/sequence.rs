@@ -0,0 +1,188 @@
+use std::ops::Range;
+
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "tracing")]
+use tracing::trace;
+use url::Url;
+
+#[cfg(feature = "tracing")]
+use crate::redact::redacted;
+use crate::{Checkpoint, PersistentActor, registry};
+
+/// Ids reserved per save unless set otherwise.
+pub const DEFAULT_BLOCK: u64 = 1000;
+
+/// Actor issuing monotonically increasing ids, saving only once per block of ids.
+///
+/// The snapshot holds the high-water mark, the end of the block reserved last, and is saved
+/// before any id of a new block is issued. Ids are never issued twice, across restarts and
+/// crashes, but the rest of the block reserved before a restart is skipped.
+pub struct PersistentSequence {
+    next: u64,
+    high_water: u64,
+    block: u64,
+}
+
+/// Snapshot and arguments of a `PersistentSequence`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SequenceSnapshot {
+    /// Next id to issue after a restart.
+    pub high_water: u64,
+    /// Ids reserved per save.
+    pub block: u64,
+}
+
+impl SequenceSnapshot {
+    /// Issue ids from `first` on.
+    pub fn starting_at(first: u64) -> Self {
+        Self {
+            high_water: first,
+            block: DEFAULT_BLOCK,
+        }
+    }
+
+    /// Reserve `block` ids per save, trading ids skipped on restart for fewer saves.
+    pub fn block(mut self, block: u64) -> Self {
+        self.block = block.max(1);
+        self
+    }
+}
+
+impl Default for SequenceSnapshot {
+    fn default() -> Self {
+        Self::starting_at(0)
+    }
+}
+
+impl From<&PersistentSequence> for SequenceSnapshot {
+    fn from(sequence: &PersistentSequence) -> Self {
+        Self {
+            high_water: sequence.high_water,
+            block: sequence.block,
+        }
+    }
+}
+
+impl PersistentSequence {
+    /// Issue `count` ids, first saving a new high-water mark if they exceed the reserved block.
+    async fn issue(
+        &mut self,
+        actor_ref: &ActorRef<Self>,
+        count: u64,
+    ) -> anyhow::Result<Range<u64>> {
+        let end = self
+            .next
+            .checked_add(count)
+            .ok_or_else(|| anyhow::anyhow!("Sequence is exhausted"))?;
+
+        if end > self.high_water {
+            let previous = self.high_water;
+            self.high_water = end.saturating_add(self.block.max(1) - 1);
+
+            if let Err(e) = self.save_snapshot(actor_ref).await {
+                self.high_water = previous;
+                return Err(e);
+            }
+
+            #[cfg(feature = "tracing")]
+            if let Some(key) = Self::persistence_key(actor_ref) {
+                trace!(
+                    "Reserved ids up to {} of {}",
+                    self.high_water,
+                    redacted(&key)
+                );
+            }
+        }
+
+        let ids = self.next..end;
+        self.next = end;
+        Ok(ids)
+    }
+}
+
+impl Actor for PersistentSequence {
+    type Args = SequenceSnapshot;
+    type Error = anyhow::Error;
+
+    async fn on_start(args: Self::Args, _actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        // Ids up to the high-water mark may have been issued before a restart
+        Ok(Self {
+            next: args.high_water,
+            high_water: args.high_water,
+            block: args.block.max(1),
+        })
+    }
+}
+
+impl PersistentActor for PersistentSequence {
+    type Snapshot = SequenceSnapshot;
+
+    fn register_persistent(persistence_key: Url, actor_ref: &ActorRef<Self>) -> anyhow::Result<()> {
+        let Ok(mut registry) = registry::typed_registry::<Self>().write() else {
+            anyhow::bail!("Failed to acquire write lock on registry");
+        };
+        registry::register(persistence_key.clone(), actor_ref);
+        registry.insert(persistence_key, actor_ref.downgrade());
+        Ok(())
+    }
+
+    fn persistence_key(actor_ref: &ActorRef<Self>) -> Option<Url> {
+        let registry = registry::typed_registry::<Self>().read().ok()?;
+        registry.get_left(&actor_ref.downgrade()).cloned()
+    }
+
+    fn lookup_persistent(persistence_key: &Url) -> Option<ActorRef<Self>> {
+        let registry = registry::typed_registry::<Self>().read().ok()?;
+        registry
+            .get_right(persistence_key)
+            .and_then(|weak_ref| weak_ref.upgrade())
+            .filter(|actor_ref| actor_ref.is_alive())
+    }
+
+    fn unregister_persistent(persistence_key: &Url) {
+        registry::unregister(persistence_key);
+        registry::typed_registry::<Self>()
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove_left(persistence_key);
+    }
+}
+
+impl Message<Checkpoint> for PersistentSequence {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: Checkpoint,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.on_checkpoint(&ctx.actor_ref(), msg).await
+    }
+}
+
+/// Issue the next id.
+pub struct Next;
+
+impl Message<Next> for PersistentSequence {
+    type Reply = anyhow::Result<u64>;
+
+    async fn handle(&mut self, _msg: Next, ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        Ok(self.issue(&ctx.actor_ref(), 1).await?.start)
+    }
+}
+
+/// Issue a range of consecutive ids at once.
+pub struct NextBatch(pub u64);
+
+impl Message<NextBatch> for PersistentSequence {
+    type Reply = anyhow::Result<Range<u64>>;
+
+    async fn handle(
+        &mut self,
+        msg: NextBatch,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.issue(&ctx.actor_ref(), msg.0).await
+    }
+}
@@ -0,0 +1,184 @@
+use std::time::Duration;
+
+use kameo::prelude::*;
+#[cfg(feature = "signal")]
+use tokio::task::JoinHandle;
+#[cfg(feature = "tracing")]
+use tracing::{info, warn};
+use url::Url;
+
+#[cfg(feature = "tracing")]
+use crate::redact::redacted;
+use crate::{
+    PersistentActor,
+    checkpoint::{self, ActorGroup, Checkpoint},
+    registry,
+};
+
+/// Time `stop_persistent` waits for the final snapshot to be captured.
+pub const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Outcome of the final snapshots taken by `shutdown_all`.
+#[derive(Debug, Default)]
+pub struct ShutdownReport {
+    pub saved: Vec<Url>,
+    /// Keys whose snapshot could not be saved, with the error.
+    pub failed: Vec<(Url, String)>,
+}
+
+/// Save a final snapshot of every live persistent actor, at most `concurrency` at a time, then
+/// stop them all.
+///
+/// Snapshots not captured within `timeout` fail. Messages handled between an actor's final
+/// snapshot and its stop are not saved, so stop feeding actors before shutting down.
+pub async fn shutdown_all(concurrency: usize, timeout: Duration) -> ShutdownReport {
+    let mut group = ActorGroup::new();
+    let mut keys = Vec::new();
+    for key in registry::live_keys() {
+        // Actors stopped meanwhile are skipped
+        if group.push_key(&key) {
+            keys.push(key);
+        }
+    }
+
+    let results = checkpoint::save_group(&group, concurrency, timeout).await;
+
+    let mut report = ShutdownReport::default();
+    for (key, result) in keys.into_iter().zip(results) {
+        match result {
+            Ok(_) => report.saved.push(key),
+            Err(e) => report.failed.push((key, format!("{e:#}"))),
+        }
+    }
+
+    registry::stop_all().await;
+
+    #[cfg(feature = "tracing")]
+    info!(
+        "Saved {} actors on shutdown, {} failed",
+        report.saved.len(),
+        report.failed.len()
+    );
+
+    report
+}
+
+/// Save a final snapshot of a persistent actor, unregister its key, then stop it and wait until
+/// it has shut down, unlike `ActorRef::stop_gracefully` which loses the state since the last save.
+///
+/// The actor keeps running if the snapshot could not be saved, and the error is returned.
+/// Messages handled between the final snapshot and the stop are not saved.
+pub async fn stop_persistent<A>(actor_ref: &ActorRef<A>) -> anyhow::Result<()>
+where
+    A: PersistentActor + Message<Checkpoint, Reply = ()>,
+{
+    stop_persistent_within(actor_ref, DEFAULT_STOP_TIMEOUT).await
+}
+
+/// `stop_persistent`, failing if the final snapshot is not captured within `timeout`.
+pub async fn stop_persistent_within<A>(
+    actor_ref: &ActorRef<A>,
+    timeout: Duration,
+) -> anyhow::Result<()>
+where
+    A: PersistentActor + Message<Checkpoint, Reply = ()>,
+{
+    let Some(persistence_key) = A::persistence_key(actor_ref) else {
+        anyhow::bail!("Actor {} is not persistent", A::type_tag());
+    };
+
+    let group = ActorGroup::new().with(actor_ref);
+    for result in checkpoint::save_group(&group, 1, timeout).await {
+        result?;
+    }
+
+    A::unregister_persistent(&persistence_key);
+
+    // Already stopping if the stop signal cannot be delivered
+    let _ = actor_ref.stop_gracefully().await;
+    actor_ref.wait_for_shutdown().await;
+
+    #[cfg(feature = "tracing")]
+    info!("Saved and stopped {}", redacted(&persistence_key));
+
+    Ok(())
+}
+
+/// Handle of the handler installed by `shutdown_on_signal`; the handler is removed when it is
+/// dropped.
+#[cfg(feature = "signal")]
+pub struct SignalHandle {
+    task: JoinHandle<()>,
+}
+
+#[cfg(feature = "signal")]
+impl Drop for SignalHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// On SIGTERM or SIGINT (Ctrl-C, Ctrl-Break, console close or system shutdown on Windows), run
+/// `shutdown_all` then exit the process, with status 1 if a snapshot failed.
+///
+/// Keep `timeout` below the grace period of the orchestrator, such as the 30 seconds Kubernetes
+/// waits before killing a terminating pod.
+#[cfg(feature = "signal")]
+pub fn shutdown_on_signal(concurrency: usize, timeout: Duration) -> anyhow::Result<SignalHandle> {
+    let signal = signal()?;
+
+    let task = tokio::spawn(async move {
+        signal.await;
+
+        #[cfg(feature = "tracing")]
+        info!("Received shutdown signal, saving every persistent actor");
+
+        let report = shutdown_all(concurrency, timeout).await;
+
+        #[cfg(feature = "tracing")]
+        for (key, e) in &report.failed {
+            warn!("Failed to save {} on shutdown: {e}", redacted(key));
+        }
+
+        std::process::exit(if report.failed.is_empty() { 0 } else { 1 });
+    });
+
+    Ok(SignalHandle { task })
+}
+
+/// Future resolving on the first termination signal, with the handlers registered right away.
+#[cfg(all(feature = "signal", unix))]
+fn signal() -> anyhow::Result<impl Future<Output = ()> + Send> {
+    use std::pin::pin;
+
+    use futures::future;
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+
+    Ok(async move {
+        future::select(pin!(terminate.recv()), pin!(interrupt.recv())).await;
+    })
+}
+
+#[cfg(all(feature = "signal", windows))]
+fn signal() -> anyhow::Result<impl Future<Output = ()> + Send> {
+    use futures::{FutureExt, future};
+    use tokio::signal::windows;
+
+    let mut ctrl_c = windows::ctrl_c()?;
+    let mut ctrl_break = windows::ctrl_break()?;
+    let mut ctrl_close = windows::ctrl_close()?;
+    let mut ctrl_shutdown = windows::ctrl_shutdown()?;
+
+    Ok(async move {
+        future::select_all([
+            ctrl_c.recv().boxed(),
+            ctrl_break.recv().boxed(),
+            ctrl_close.recv().boxed(),
+            ctrl_shutdown.recv().boxed(),
+        ])
+        .await;
+    })
+}
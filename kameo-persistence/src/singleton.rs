@@ -0,0 +1,145 @@
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::{Arc, LazyLock, Mutex},
+    time::Duration,
+};
+
+use kameo::prelude::*;
+use url::Url;
+
+use crate::{
+    PersistentActor,
+    lease::{self, LeaseKeeper, Unkept},
+};
+
+// Serializes getting the actor of a key across every singleton of this process
+static LOCKS: LazyLock<Mutex<HashMap<Url, Arc<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Actor of a key running at most once in this process, and optionally in the cluster.
+///
+/// Concurrent callers share a single spawn, including with `get_or_spawn`, where a fresh actor
+/// is spawned if the respawn fails. Clones share the lease kept for `cluster_wide`.
+pub struct PersistentSingleton<A: PersistentActor> {
+    persistence_key: Url,
+    lease: Option<(String, Duration)>,
+    keeper: Arc<Mutex<Option<LeaseKeeper>>>,
+    _actor: PhantomData<fn() -> A>,
+}
+
+impl<A: PersistentActor> Clone for PersistentSingleton<A> {
+    fn clone(&self) -> Self {
+        Self {
+            persistence_key: self.persistence_key.clone(),
+            lease: self.lease.clone(),
+            keeper: self.keeper.clone(),
+            _actor: PhantomData,
+        }
+    }
+}
+
+impl<A: PersistentActor> PersistentSingleton<A> {
+    pub fn new(persistence_key: Url) -> Self {
+        Self {
+            persistence_key,
+            lease: None,
+            keeper: Arc::new(Mutex::new(None)),
+            _actor: PhantomData,
+        }
+    }
+
+    /// Also hold the lease of the key as `holder` while the actor runs here, so no other node
+    /// runs it, see `lease::respawn_single_writer`.
+    ///
+    /// Getting the actor fails with `PersistenceError::LeaseHeld` while another node holds it.
+    pub fn cluster_wide(mut self, holder: &str, ttl: Duration) -> Self {
+        self.lease = Some((holder.to_string(), ttl));
+        self
+    }
+
+    pub fn persistence_key(&self) -> &Url {
+        &self.persistence_key
+    }
+
+    /// Return the actor if it runs in this process.
+    pub fn get(&self) -> Option<ActorRef<A>> {
+        A::lookup_persistent(&self.persistence_key).filter(|actor_ref| actor_ref.is_alive())
+    }
+
+    /// Return the actor, respawning it from its snapshot if it does not run in this process.
+    pub async fn get_or_respawn(&self) -> anyhow::Result<ActorRef<A>> {
+        self.get_or(A::respawn_persistent).await
+    }
+
+    /// Return the actor, respawning it from its snapshot or else spawning it with `args` if it
+    /// does not run in this process.
+    pub async fn get_or_spawn(
+        &self,
+        args: impl FnOnce() -> <A as Actor>::Args,
+    ) -> anyhow::Result<ActorRef<A>> {
+        self.get_or(|persistence_key| A::try_respawn_persistent(persistence_key, args()))
+            .await
+    }
+
+    async fn get_or<F>(&self, spawn: impl FnOnce(Url) -> F) -> anyhow::Result<ActorRef<A>>
+    where
+        F: Future<Output = anyhow::Result<ActorRef<A>>>,
+    {
+        if let Some(actor_ref) = self.get() {
+            return Ok(actor_ref);
+        }
+
+        let lock = KeyLock::new(&self.persistence_key);
+        let _guard = lock.lock.lock().await;
+
+        // Spawned by another caller while waiting
+        if let Some(actor_ref) = self.get() {
+            return Ok(actor_ref);
+        }
+
+        let Some((holder, ttl)) = &self.lease else {
+            return spawn(self.persistence_key.clone()).await;
+        };
+
+        let lease = Unkept::fence(lease::acquire(&self.persistence_key, holder, *ttl)?);
+        let actor_ref = spawn(self.persistence_key.clone()).await?;
+
+        *self.keeper.lock().unwrap_or_else(|e| e.into_inner()) =
+            Some(lease::keep(lease.into_inner(), &actor_ref));
+
+        Ok(actor_ref)
+    }
+}
+
+/// Lock of a key, forgotten when its last user is done or dropped.
+struct KeyLock {
+    persistence_key: Url,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl KeyLock {
+    fn new(persistence_key: &Url) -> Self {
+        let lock = LOCKS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(persistence_key.clone())
+            .or_default()
+            .clone();
+
+        Self {
+            persistence_key: persistence_key.clone(),
+            lock,
+        }
+    }
+}
+
+impl Drop for KeyLock {
+    fn drop(&mut self) {
+        let mut locks = LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+        // Held by the map and by this user only
+        if Arc::strong_count(&self.lock) == 2 {
+            locks.remove(&self.persistence_key);
+        }
+    }
+}
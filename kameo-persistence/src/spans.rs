@@ -0,0 +1,47 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use tracing::{Span, debug, debug_span, field};
+use url::Url;
+
+use crate::redact::redacted;
+
+// Numbers the saves and restores of this process, to tell apart those of the same key
+static SEQ: AtomicU64 = AtomicU64::new(1);
+
+/// Span of a snapshot save, `persist.save`; `bytes` and `duration_ms` are recorded as known.
+pub(crate) fn save(actor_type: &'static str, key: &Url) -> Span {
+    debug_span!(
+        "persist.save",
+        key = %redacted(key),
+        actor_type,
+        seq = SEQ.fetch_add(1, Ordering::Relaxed),
+        bytes = field::Empty,
+        duration_ms = field::Empty,
+    )
+}
+
+/// Span of a restore from a snapshot, `persist.restore`, with the fields of `save`.
+pub(crate) fn restore(actor_type: &'static str, key: &Url) -> Span {
+    debug_span!(
+        "persist.restore",
+        key = %redacted(key),
+        actor_type,
+        seq = SEQ.fetch_add(1, Ordering::Relaxed),
+        bytes = field::Empty,
+        duration_ms = field::Empty,
+    )
+}
+
+/// Record the size of the snapshot on the current span.
+pub(crate) fn record_bytes(bytes: usize) {
+    Span::current().record("bytes", bytes);
+}
+
+/// Record the duration of the operation of `span` and report its outcome within it.
+pub(crate) fn finish(span: &Span, ok: bool, elapsed: Duration) {
+    span.record("duration_ms", elapsed.as_millis() as u64);
+    debug!(parent: span, ok, "Finished");
+}
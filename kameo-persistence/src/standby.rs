@@ -0,0 +1,201 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use kameo::prelude::*;
+use tokio::task::JoinHandle;
+#[cfg(feature = "tracing")]
+use tracing::{info, warn};
+use url::Url;
+
+#[cfg(feature = "tracing")]
+use crate::redact::redacted;
+use crate::{PersistentActor, RestoreContext, clock, codec, concurrency, storage};
+
+struct Warm<S> {
+    version: Option<String>,
+    snapshot: S,
+}
+
+/// Decoded snapshots of every actor of type `A` under a root, kept warm for failover.
+///
+/// A standby process refreshes the cache as snapshots change, comparing only their versions
+/// for unchanged keys, and on failover spawns the actors straight from memory instead of
+/// reading and decoding every snapshot from storage. State saved after the last refresh is not
+/// seen; with optimistic saves, see `concurrency::set_optimistic`, such actors fail their first
+/// save with a conflict instead of overwriting it.
+pub struct Standby<A: PersistentActor> {
+    root: Url,
+    warm: Arc<Mutex<HashMap<Url, Warm<A::Snapshot>>>>,
+}
+
+impl<A: PersistentActor> Clone for Standby<A> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            warm: self.warm.clone(),
+        }
+    }
+}
+
+/// Handle of a standby refreshing in the background; refreshing stops when it is dropped.
+pub struct StandbyHandle {
+    task: JoinHandle<()>,
+}
+
+impl Drop for StandbyHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl<A: PersistentActor> Standby<A> {
+    /// Create an empty standby for the actors of type `A` stored under `root`.
+    pub fn new(root: Url) -> Self {
+        Self {
+            root,
+            warm: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Number of snapshots kept warm.
+    pub fn len(&self) -> usize {
+        self.warm.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Return true if no snapshot is kept warm.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Load the snapshots of `A` changed since the last refresh and forget the deleted ones.
+    ///
+    /// Snapshots of other actor types under the root are ignored. Return the number of
+    /// snapshots loaded.
+    pub async fn refresh(&self) -> anyhow::Result<usize> {
+        let backend = storage::backend(&self.root)?;
+        let keys = storage::list(&self.root).await?;
+
+        let mut loaded = 0;
+        for key in &keys {
+            // Version first, so a snapshot replaced while reading is loaded again next time
+            let version = backend.file_version(key, storage::snapshot_file())?;
+
+            let unchanged = self
+                .warm
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(key)
+                .is_some_and(|warm| warm.version == version);
+            if unchanged {
+                continue;
+            }
+
+            let Some(data) = backend.read_file(key, storage::snapshot_file())? else {
+                continue;
+            };
+
+            if codec::split(&data)?
+                .0
+                .is_some_and(|header| header.type_tag != A::type_tag())
+            {
+                continue;
+            }
+
+            let snapshot = codec::decode_at::<A>(key, &data)?;
+            self.warm
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(key.clone(), Warm { version, snapshot });
+            loaded += 1;
+        }
+
+        let keys = keys.iter().collect::<HashSet<_>>();
+        self.warm
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|key, _| keys.contains(key));
+
+        Ok(loaded)
+    }
+
+    /// Refresh every `interval` until the returned handle is dropped.
+    pub fn start(&self, interval: Duration) -> StandbyHandle {
+        let standby = self.clone();
+
+        let task = tokio::spawn(async move {
+            loop {
+                let clock = clock::clock();
+                clock.sleep_until(clock.now() + interval).await;
+
+                if let Err(_e) = standby.refresh().await {
+                    #[cfg(feature = "tracing")]
+                    warn!(
+                        "Failed to refresh standby of {}: {_e:#}",
+                        redacted(&standby.root)
+                    );
+                }
+            }
+        });
+
+        StandbyHandle { task }
+    }
+
+    /// Spawn every actor kept warm, emptying the cache.
+    ///
+    /// Actors already alive in this process are returned as they are.
+    pub async fn failover(&self) -> anyhow::Result<Vec<ActorRef<A>>> {
+        let warm = std::mem::take(&mut *self.warm.lock().unwrap_or_else(|e| e.into_inner()));
+
+        #[cfg(feature = "tracing")]
+        info!(
+            "Failing over {} actors under {}",
+            warm.len(),
+            redacted(&self.root)
+        );
+
+        let mut actors = Vec::with_capacity(warm.len());
+        for (key, warm) in warm {
+            actors.push(spawn_warm::<A>(key, warm).await?);
+        }
+
+        Ok(actors)
+    }
+
+    /// Spawn the actor stored under a key, from memory if it is kept warm and from storage
+    /// otherwise.
+    pub async fn respawn(&self, persistence_key: Url) -> anyhow::Result<ActorRef<A>> {
+        let warm = self
+            .warm
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&persistence_key);
+
+        match warm {
+            Some(warm) => spawn_warm::<A>(persistence_key, warm).await,
+            None => A::respawn_persistent(persistence_key).await,
+        }
+    }
+}
+
+async fn spawn_warm<A: PersistentActor>(
+    persistence_key: Url,
+    warm: Warm<A::Snapshot>,
+) -> anyhow::Result<ActorRef<A>> {
+    if let Some(actor_ref) = A::lookup_persistent(&persistence_key) {
+        return Ok(actor_ref);
+    }
+
+    // The version the snapshot was loaded at, so saves conflict with anything written since
+    if concurrency::is_optimistic::<A>() {
+        concurrency::remember(&persistence_key, warm.version);
+    }
+
+    A::spawn_persistent(
+        persistence_key,
+        A::restore_args(warm.snapshot, &RestoreContext::global())?,
+    )
+    .await
+}
@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+
+use url::Url;
+
+use crate::{codec, storage};
+
+/// Group of snapshots without a header, whose actor type is unknown.
+pub const UNTAGGED: &str = "(untagged)";
+
+/// Storage used by a group of keys.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GroupStats {
+    /// Keys holding a snapshot.
+    pub snapshots: usize,
+    /// Bytes of every file stored under the keys, such as history and attachments, not only
+    /// their snapshots.
+    pub total_bytes: u64,
+    /// Largest keys by total bytes, largest first.
+    pub largest: Vec<(Url, u64)>,
+}
+
+impl GroupStats {
+    fn add(&mut self, key: &Url, bytes: u64, largest: usize) {
+        self.snapshots += 1;
+        self.total_bytes += bytes;
+
+        if largest > 0 {
+            let at = self.largest.partition_point(|(_, size)| *size >= bytes);
+            if at < largest {
+                self.largest.insert(at, (key.clone(), bytes));
+                self.largest.truncate(largest);
+            }
+        }
+    }
+}
+
+/// Storage used under a root, grouped by actor type and by key prefix.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StorageStats {
+    pub total: GroupStats,
+    /// By `PersistentActor::type_tag`, `UNTAGGED` for snapshots without a header.
+    pub by_type: BTreeMap<String, GroupStats>,
+    /// By the first path segments of the keys below the root, such as `accounts/eu`.
+    pub by_prefix: BTreeMap<String, GroupStats>,
+}
+
+/// Aggregate the storage used by the keys holding a snapshot under `root`, grouping keys by
+/// actor type and by their first `prefix_depth` path segments below the root, and keeping the
+/// `largest` keys of every group.
+///
+/// Every snapshot is read for its header, so this scans the whole root; run it off peak on
+/// large populations.
+pub async fn storage_stats(
+    root: &Url,
+    prefix_depth: usize,
+    largest: usize,
+) -> anyhow::Result<StorageStats> {
+    let backend = storage::backend(root)?;
+    let mut stats = StorageStats::default();
+
+    for key in storage::list(root).await? {
+        let Some(data) = backend.read_file(&key, storage::snapshot_file())? else {
+            continue;
+        };
+
+        let type_tag = match codec::split(&data) {
+            Ok((Some(header), _)) => header.type_tag,
+            _ => UNTAGGED.to_string(),
+        };
+
+        let mut bytes = 0;
+        for name in backend.list_files(&key)? {
+            bytes += backend.file_size(&key, &name)?.unwrap_or(0);
+        }
+
+        stats.total.add(&key, bytes, largest);
+        stats
+            .by_type
+            .entry(type_tag)
+            .or_default()
+            .add(&key, bytes, largest);
+        stats
+            .by_prefix
+            .entry(prefix(root, &key, prefix_depth))
+            .or_default()
+            .add(&key, bytes, largest);
+    }
+
+    Ok(stats)
+}
+
+/// First `depth` path segments of `key` below `root`, joined by `/`.
+fn prefix(root: &Url, key: &Url, depth: usize) -> String {
+    let relative = key
+        .path()
+        .strip_prefix(root.path().trim_end_matches('/'))
+        .unwrap_or(key.path());
+
+    relative
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .take(depth)
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,724 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, LazyLock, Mutex, RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::UNIX_EPOCH,
+};
+
+use anyhow::anyhow;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+#[cfg(feature = "audit")]
+use crate::audit::{self, AuditOperation};
+#[cfg(feature = "json")]
+use crate::manifest;
+use crate::{
+    clock, codec, concurrency, environment, generation, history, lease, options, rate_limit,
+    redact::redacted, transaction, watch, write_stats,
+};
+
+/// Default name of the snapshot file inside a persistence key directory, see `snapshot_file`.
+pub const SNAPSHOT_FILE: &str = "index.bin";
+
+static SNAPSHOT_FILE_NAME: RwLock<&str> = RwLock::new(SNAPSHOT_FILE);
+
+/// Snapshot being written, until it replaces the snapshot file.
+const SNAPSHOT_TMP_FILE: &str = "index.bin.tmp";
+
+/// Snapshot replaced by the current one, kept when enabled with `set_keep_previous`.
+pub const PREVIOUS_FILE: &str = "index.bin.prev";
+
+/// Suffix of the files conditional writes lock a file of a key with, left in place and never
+/// listed as files of the key.
+const LOCK_SUFFIX: &str = ".lock";
+
+static KEEP_PREVIOUS: AtomicBool = AtomicBool::new(false);
+
+/// Name the snapshot file of every key `name`, such as `state.bin`, instead of `index.bin`.
+///
+/// Set it once at startup, before any snapshot is read or written; snapshots stored under
+/// another name are not found.
+pub fn set_snapshot_file(name: &str) -> anyhow::Result<()> {
+    if name.is_empty()
+        || name.contains('/')
+        || name.starts_with('.')
+        || [SNAPSHOT_TMP_FILE, PREVIOUS_FILE].contains(&name)
+    {
+        anyhow::bail!("Invalid snapshot file name: {name:?}");
+    }
+
+    // Leaked, as names are set once and read without copying on every access
+    let name = match name {
+        SNAPSHOT_FILE => SNAPSHOT_FILE,
+        name => Box::leak(name.to_string().into_boxed_str()),
+    };
+    *SNAPSHOT_FILE_NAME
+        .write()
+        .unwrap_or_else(|e| e.into_inner()) = name;
+
+    Ok(())
+}
+
+/// Name of the snapshot file inside a persistence key directory, `SNAPSHOT_FILE` unless set
+/// with `set_snapshot_file`.
+pub fn snapshot_file() -> &'static str {
+    *SNAPSHOT_FILE_NAME.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Return true if `name` holds the snapshot of its key, one about to replace it or one moved
+/// aside, the files `options::KeyOptions` apply to.
+pub(crate) fn is_snapshot_file(name: &str) -> bool {
+    let snapshot_file = snapshot_file();
+    name == snapshot_file
+        || [SNAPSHOT_TMP_FILE, PREVIOUS_FILE, transaction::STAGED_FILE].contains(&name)
+        || name
+            .strip_prefix(snapshot_file)
+            .is_some_and(|rest| rest.starts_with(".corrupt-") || rest.starts_with(".suspect-"))
+}
+
+/// Storage of the files kept under persistence keys.
+///
+/// A key is a directory-like location holding named files, such as `index.bin`, and keys below
+/// it. Backends are selected by the scheme of the key.
+pub trait Backend: Send + Sync {
+    /// Read a file stored under a key, or `None` if it does not exist.
+    fn read_file(&self, key: &Url, name: &str) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Read a file stored under each of `keys`, in order, as `read_file` does.
+    ///
+    /// The default reads them one at a time; backends of remote storage should batch them,
+    /// such as with parallel GETs or a single `IN` query.
+    fn read_files(&self, keys: &[Url], name: &str) -> Vec<anyhow::Result<Option<Vec<u8>>>> {
+        keys.iter().map(|key| self.read_file(key, name)).collect()
+    }
+
+    /// Write a file under a key, creating the key if needed.
+    fn write_file(&self, key: &Url, name: &str, data: &[u8]) -> anyhow::Result<()>;
+
+    /// Atomically replace the file `to` with the file `from` of the same key.
+    fn rename_file(&self, key: &Url, from: &str, to: &str) -> anyhow::Result<()>;
+
+    /// Remove a file stored under a key, if it exists.
+    fn remove_file(&self, key: &Url, name: &str) -> anyhow::Result<()>;
+
+    /// Size of a file stored under a key, or `None` if it does not exist.
+    fn file_size(&self, key: &Url, name: &str) -> anyhow::Result<Option<u64>>;
+
+    /// Return true if anything is stored under the key.
+    fn exists(&self, key: &Url) -> anyhow::Result<bool>;
+
+    /// Delete a key with every file and key below it.
+    fn delete(&self, key: &Url) -> anyhow::Result<()>;
+
+    /// List `root`, if it exists, and every key below it, parents first.
+    fn list(&self, root: &Url) -> anyhow::Result<Vec<Url>>;
+
+    /// Names of the files stored directly under a key.
+    fn list_files(&self, key: &Url) -> anyhow::Result<Vec<String>>;
+
+    /// Version of a file stored under a key, or `None` if it does not exist.
+    ///
+    /// The version changes whenever the file is rewritten. Backends with native versions, such
+    /// as an ETag or a version column, should return those; the default hashes the content.
+    fn file_version(&self, key: &Url, name: &str) -> anyhow::Result<Option<String>> {
+        Ok(self
+            .read_file(key, name)?
+            .map(|data| content_version(&data)))
+    }
+
+    /// Write a file only if its version still is `expected`, `None` meaning it must not exist.
+    ///
+    /// Return the new version, or `None` without writing if the version differs. The default
+    /// compares then writes, which is only atomic among the writers of this process; backends
+    /// of shared storage should use a conditional write, such as `If-Match` or a version column.
+    fn write_file_if(
+        &self,
+        key: &Url,
+        name: &str,
+        expected: Option<&str>,
+        data: &[u8],
+    ) -> anyhow::Result<Option<String>> {
+        static LOCK: Mutex<()> = Mutex::new(());
+        let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        if self.file_version(key, name)?.as_deref() != expected {
+            return Ok(None);
+        }
+
+        self.write_file(key, name, data)?;
+        self.file_version(key, name)
+    }
+
+    /// Check that the backend serves `key`, failing if it is unavailable.
+    ///
+    /// The default writes, reads back and removes a probe file under the key; backends with a
+    /// cheaper check, such as a ping, should use it.
+    fn health_check(&self, key: &Url) -> anyhow::Result<()> {
+        const PROBE_FILE: &str = "health.probe";
+
+        let probe = clock::clock()
+            .system_time()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+            .to_le_bytes();
+
+        self.write_file(key, PROBE_FILE, &probe)?;
+        let read = self.read_file(key, PROBE_FILE)?;
+        self.remove_file(key, PROBE_FILE)?;
+
+        if read.as_deref() != Some(&probe[..]) {
+            anyhow::bail!("Probe file read back differs from the one written");
+        }
+        Ok(())
+    }
+}
+
+/// Version of a file derived from its content, as returned by default by `file_version`.
+pub fn content_version(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+static BACKENDS: LazyLock<RwLock<HashMap<String, Arc<dyn Backend>>>> = LazyLock::new(|| {
+    RwLock::new(HashMap::from([(
+        "file".to_string(),
+        Arc::new(FileBackend) as Arc<dyn Backend>,
+    )]))
+});
+
+/// Store keys of a URL scheme in `backend`, replacing any backend of that scheme.
+pub fn set_backend(scheme: &str, backend: Arc<dyn Backend>) {
+    BACKENDS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(scheme.to_string(), backend);
+}
+
+/// Return the backend storing a persistence key.
+///
+/// Fails with `PersistenceError::WrongEnvironment` for keys of another environment, see
+/// `environment::check`.
+pub fn backend(persistence_key: &Url) -> anyhow::Result<Arc<dyn Backend>> {
+    environment::check(persistence_key)?;
+
+    BACKENDS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(persistence_key.scheme())
+        .cloned()
+        .ok_or_else(|| {
+            anyhow!(
+                "Unsupported scheme for persistence key: {}",
+                persistence_key.scheme()
+            )
+        })
+}
+
+/// Return true if `persistence_key` is `root` or below it.
+pub fn is_within(root: &Url, persistence_key: &Url) -> bool {
+    if persistence_key.scheme() != root.scheme()
+        || persistence_key.host() != root.host()
+        || persistence_key.port() != root.port()
+    {
+        return false;
+    }
+
+    let (Some(root), Some(mut key)) = (root.path_segments(), persistence_key.path_segments())
+    else {
+        return false;
+    };
+
+    root.filter(|segment| !segment.is_empty())
+        .all(|segment| key.next() == Some(segment))
+}
+
+/// Map a key at or below `from` to the same relative location below `to`.
+///
+/// Return `None` if `persistence_key` is not within `from`.
+pub fn rebase(persistence_key: &Url, from: &Url, to: &Url) -> Option<Url> {
+    if !is_within(from, persistence_key) {
+        return None;
+    }
+
+    // Segments stay percent-encoded, so they are joined rather than pushed
+    let depth = from.path_segments()?.filter(|s| !s.is_empty()).count();
+    let relative = persistence_key
+        .path_segments()?
+        .skip(depth)
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+
+    let mut rebased = to.clone();
+    if !relative.is_empty() {
+        let path = format!("{}/{}", to.path().trim_end_matches('/'), relative.join("/"));
+        rebased.set_path(&path);
+    }
+
+    Some(rebased)
+}
+
+/// Keep the previous snapshot of a key whenever it is replaced.
+///
+/// Restores finding the current snapshot corrupt then fall back to the previous one. Costs an
+/// extra read and write per save; snapshots failing their checksum are never kept.
+pub fn set_keep_previous(enabled: bool) {
+    KEEP_PREVIOUS.store(enabled, Ordering::Relaxed);
+}
+
+/// Copy the current snapshot of a key to `PREVIOUS_FILE` before it is replaced, if enabled.
+///
+/// Nothing is copied if the current snapshot already is `replacement`, so retrying a write that
+/// failed after replacing the snapshot keeps the one before it.
+pub(crate) fn keep_previous(
+    persistence_key: &Url,
+    replacement: Option<&[u8]>,
+) -> anyhow::Result<()> {
+    match previous(persistence_key, replacement)? {
+        Some(data) => backend(persistence_key)?.write_file(persistence_key, PREVIOUS_FILE, &data),
+        None => Ok(()),
+    }
+}
+
+/// Current snapshot of a key which `keep_previous` would copy.
+fn previous(persistence_key: &Url, replacement: Option<&[u8]>) -> anyhow::Result<Option<Vec<u8>>> {
+    if !KEEP_PREVIOUS.load(Ordering::Relaxed) {
+        return Ok(None);
+    }
+
+    Ok(backend(persistence_key)?
+        .read_file(persistence_key, snapshot_file())?
+        .filter(|data| replacement != Some(data.as_slice()) && codec::verify(data).is_ok()))
+}
+
+/// Put the previous snapshot of a key back in place, returning it, or `None` if none was kept.
+pub(crate) async fn restore_previous(persistence_key: &Url) -> anyhow::Result<Option<Vec<u8>>> {
+    let Some(data) = backend(persistence_key)?.read_file(persistence_key, PREVIOUS_FILE)? else {
+        return Ok(None);
+    };
+
+    write_atomic(persistence_key, &data).await?;
+    Ok(Some(data))
+}
+
+/// Read the raw snapshot bytes stored under a persistence key.
+pub async fn read(persistence_key: &Url) -> anyhow::Result<Vec<u8>> {
+    let backend = backend(persistence_key)?;
+
+    if !backend.exists(persistence_key)? {
+        anyhow::bail!(
+            "persistence key does not exist: {}",
+            redacted(persistence_key)
+        );
+    }
+
+    // Complete a committed transaction interrupted before reaching this key
+    transaction::recover(persistence_key)?;
+
+    let data = backend
+        .read_file(persistence_key, snapshot_file())?
+        .ok_or_else(|| anyhow!("No snapshot stored under {}", redacted(persistence_key)))?;
+
+    #[cfg(feature = "audit")]
+    audit::record(AuditOperation::Restore, persistence_key, Some(&data))?;
+
+    Ok(data)
+}
+
+/// Read the raw snapshot bytes stored under each of `keys`, in order, as `read` does.
+///
+/// The keys of a backend are read together with `Backend::read_files`, in two batches, the
+/// second for the snapshots, so reading many snapshots from remote storage costs a couple of
+/// round trips rather than several per key.
+pub async fn read_many(keys: &[Url]) -> Vec<anyhow::Result<Vec<u8>>> {
+    let mut results = keys.iter().map(|_| None).collect::<Vec<_>>();
+
+    // Indices of the keys of each backend, by scheme
+    let mut groups = HashMap::<&str, (Arc<dyn Backend>, Vec<usize>)>::new();
+    for (i, key) in keys.iter().enumerate() {
+        match backend(key) {
+            Ok(backend) => groups
+                .entry(key.scheme())
+                .or_insert((backend, Vec::new()))
+                .1
+                .push(i),
+            Err(e) => results[i] = Some(Err(e)),
+        }
+    }
+
+    for (backend, indices) in groups.into_values() {
+        let mut group = indices.iter().map(|&i| keys[i].clone()).collect::<Vec<_>>();
+
+        // Complete committed transactions interrupted before reaching the keys
+        let staged_refs = backend.read_files(&group, transaction::STAGED_REF_FILE);
+        let mut pending = Vec::new();
+        for ((i, key), staged_ref) in indices.into_iter().zip(group.drain(..)).zip(staged_refs) {
+            match staged_ref.and_then(|staged_ref| transaction::recover_from(&key, staged_ref)) {
+                Ok(()) => pending.push((i, key)),
+                Err(e) => results[i] = Some(Err(e)),
+            }
+        }
+
+        let (indices, group): (Vec<_>, Vec<_>) = pending.into_iter().unzip();
+        let snapshots = backend.read_files(&group, snapshot_file());
+        for ((i, key), data) in indices.into_iter().zip(&group).zip(snapshots) {
+            results[i] = Some(data.and_then(|data| {
+                let data =
+                    data.ok_or_else(|| anyhow!("No snapshot stored under {}", redacted(key)))?;
+
+                #[cfg(feature = "audit")]
+                audit::record(AuditOperation::Restore, key, Some(&data))?;
+
+                Ok(data)
+            }));
+        }
+    }
+
+    results
+        .into_iter()
+        .map(|result| {
+            result.unwrap_or_else(|| Err(anyhow!("Backend returned fewer files than requested")))
+        })
+        .collect()
+}
+
+/// Write raw snapshot bytes under a persistence key.
+pub async fn write(persistence_key: &Url, data: &[u8]) -> anyhow::Result<()> {
+    rate_limit::acquire(1).await;
+    lease::check(persistence_key)?;
+    keep_previous(persistence_key, Some(data))?;
+    watch::note_write(persistence_key, data);
+    backend(persistence_key)?.write_file(persistence_key, snapshot_file(), data)?;
+    concurrency::note_write(persistence_key)?;
+    note_written(persistence_key, data)
+}
+
+/// Write raw snapshot bytes under a persistence key if the stored snapshot still has version
+/// `expected`, `None` meaning there must be none.
+///
+/// Return the new version, or `None` without writing if another writer replaced it.
+pub(crate) async fn write_if(
+    persistence_key: &Url,
+    expected: Option<&str>,
+    data: &[u8],
+) -> anyhow::Result<Option<String>> {
+    rate_limit::acquire(1).await;
+    lease::check(persistence_key)?;
+    let backend = backend(persistence_key)?;
+    // Read before the swap, which only succeeds if the snapshot is still the one read
+    let previous = previous(persistence_key, Some(data))?;
+    watch::note_write(persistence_key, data);
+    let version = backend
+        .write_file_if(persistence_key, snapshot_file(), expected, data)
+        .inspect_err(|_| watch::forget_write(persistence_key, data))?;
+    if version.is_none() {
+        watch::forget_write(persistence_key, data);
+        return Ok(None);
+    }
+
+    if let Some(previous) = previous {
+        backend.write_file(persistence_key, PREVIOUS_FILE, &previous)?;
+    }
+    note_written(persistence_key, data)?;
+
+    Ok(version)
+}
+
+/// Write raw snapshot bytes under a persistence key, replacing the previous snapshot atomically.
+pub(crate) async fn write_atomic(persistence_key: &Url, data: &[u8]) -> anyhow::Result<()> {
+    rate_limit::acquire(1).await;
+    lease::check(persistence_key)?;
+    let backend = backend(persistence_key)?;
+    keep_previous(persistence_key, Some(data))?;
+    watch::note_write(persistence_key, data);
+    backend.write_file(persistence_key, SNAPSHOT_TMP_FILE, data)?;
+    backend.rename_file(persistence_key, SNAPSHOT_TMP_FILE, snapshot_file())?;
+    concurrency::note_write(persistence_key)?;
+    note_written(persistence_key, data)
+}
+
+/// Update the history, counters and manifest of a key after its snapshot was replaced by `data`.
+///
+/// The snapshot is replaced first, so a failure here fails a write that did take place. Every
+/// update is idempotent, so the caller retrying the write with the same snapshot ends up with
+/// the same bookkeeping as a write that succeeded at once.
+fn note_written(persistence_key: &Url, data: &[u8]) -> anyhow::Result<()> {
+    history::note_write(persistence_key, data)?;
+    generation::note_write(persistence_key, data)?;
+    write_stats::note_write(persistence_key, data.len());
+    #[cfg(feature = "json")]
+    manifest::note_write(persistence_key, data)?;
+
+    #[cfg(feature = "audit")]
+    audit::record(AuditOperation::Save, persistence_key, Some(data))?;
+
+    Ok(())
+}
+
+/// List the persistence keys holding a snapshot under `root`, including `root` itself.
+pub async fn list(root: &Url) -> anyhow::Result<Vec<Url>> {
+    let backend = backend(root)?;
+
+    let mut keys = Vec::new();
+    for key in backend.list(root)? {
+        if backend.file_size(&key, snapshot_file())?.is_some() {
+            keys.push(key);
+        }
+    }
+
+    Ok(keys)
+}
+
+/// Copy every snapshot stored under `src_root` to the same relative key below `dst_root`.
+///
+/// The roots may be stored by different backends. Snapshots are copied as raw bytes, so they
+/// keep their header and encryption. Return the keys written.
+pub async fn copy(src_root: &Url, dst_root: &Url) -> anyhow::Result<Vec<Url>> {
+    let mut copied = Vec::new();
+
+    for key in list(src_root).await? {
+        let target = rebase(&key, src_root, dst_root)
+            .ok_or_else(|| anyhow!("Cannot copy {} to {}", redacted(&key), redacted(dst_root)))?;
+
+        write(&target, &read(&key).await?).await?;
+        copied.push(target);
+    }
+
+    Ok(copied)
+}
+
+/// Delete everything stored under a persistence key, including the keys below it.
+pub async fn delete(persistence_key: &Url) -> anyhow::Result<()> {
+    let backend = backend(persistence_key)?;
+
+    if !backend.exists(persistence_key)? {
+        return Ok(());
+    }
+
+    backend.delete(persistence_key)?;
+    write_stats::forget(persistence_key);
+
+    #[cfg(feature = "audit")]
+    audit::record(AuditOperation::Delete, persistence_key, None)?;
+
+    Ok(())
+}
+
+/// Move the snapshot stored under a persistence key aside, as `index.bin.corrupt-<unix ms>`.
+///
+/// The key then holds no snapshot, and the moved file is kept for inspection. Return its name.
+pub fn quarantine(persistence_key: &Url) -> anyhow::Result<String> {
+    let millis = clock::clock()
+        .system_time()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let name = format!("{}.corrupt-{millis}", snapshot_file());
+
+    backend(persistence_key)?.rename_file(persistence_key, snapshot_file(), &name)?;
+
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+        "Quarantined snapshot of {} as {name}",
+        redacted(persistence_key)
+    );
+
+    Ok(name)
+}
+
+/// Size of the snapshot stored under a persistence key, zero if none.
+pub(crate) fn snapshot_size(persistence_key: &Url) -> anyhow::Result<u64> {
+    Ok(backend(persistence_key)?
+        .file_size(persistence_key, snapshot_file())?
+        .unwrap_or(0))
+}
+
+/// Total size of the snapshots stored under `root`, including `root` itself.
+pub(crate) fn usage(root: &Url) -> anyhow::Result<u64> {
+    let backend = backend(root)?;
+
+    let mut total = 0;
+    for key in backend.list(root)? {
+        total += backend.file_size(&key, snapshot_file())?.unwrap_or(0);
+    }
+
+    Ok(total)
+}
+
+/// Stores each key as a directory of the local file system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileBackend;
+
+impl FileBackend {
+    pub(crate) fn key_dir(key: &Url) -> anyhow::Result<PathBuf> {
+        key.to_file_path()
+            .map_err(|_| anyhow!("Failed to convert Url to file path"))
+    }
+
+    pub(crate) fn create_key_dir(key: &Url) -> anyhow::Result<PathBuf> {
+        let dir = Self::key_dir(key)?;
+
+        if !dir.exists() {
+            std::fs::create_dir_all(&dir)?;
+        } else if !dir.is_dir() {
+            anyhow::bail!("persistence key exists but is not a directory: {:?}", dir);
+        }
+
+        Ok(dir)
+    }
+}
+
+impl Backend for FileBackend {
+    fn read_file(&self, key: &Url, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match std::fs::read(Self::key_dir(key)?.join(name)) {
+            Ok(data) => Ok(Some(options::decode_file(key, name, data)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write_file(&self, key: &Url, name: &str, data: &[u8]) -> anyhow::Result<()> {
+        let dir = Self::create_key_dir(key)?;
+        write_synced(
+            key,
+            &dir.join(name),
+            &options::encode_file(key, name, data)?,
+        )
+    }
+
+    fn rename_file(&self, key: &Url, from: &str, to: &str) -> anyhow::Result<()> {
+        let dir = Self::key_dir(key)?;
+        std::fs::rename(dir.join(from), dir.join(to))?;
+        sync_dir(key, &dir)
+    }
+
+    fn remove_file(&self, key: &Url, name: &str) -> anyhow::Result<()> {
+        match std::fs::remove_file(Self::key_dir(key)?.join(name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn file_size(&self, key: &Url, name: &str) -> anyhow::Result<Option<u64>> {
+        match std::fs::metadata(Self::key_dir(key)?.join(name)) {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn exists(&self, key: &Url) -> anyhow::Result<bool> {
+        Ok(Self::key_dir(key)?.exists())
+    }
+
+    fn delete(&self, key: &Url) -> anyhow::Result<()> {
+        let dir = Self::key_dir(key)?;
+        if dir.exists() {
+            std::fs::remove_dir_all(dir)?;
+        }
+        Ok(())
+    }
+
+    fn list(&self, root: &Url) -> anyhow::Result<Vec<Url>> {
+        let mut keys = Vec::new();
+
+        walk(&Self::key_dir(root)?, &mut |dir| {
+            keys.push(Url::from_file_path(dir).map_err(|_| anyhow!("Invalid path {dir:?}"))?);
+            Ok(())
+        })?;
+
+        Ok(keys)
+    }
+
+    fn list_files(&self, key: &Url) -> anyhow::Result<Vec<String>> {
+        let dir = Self::key_dir(key)?;
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type()?.is_file() && !name.ends_with(LOCK_SUFFIX) {
+                names.push(name);
+            }
+        }
+
+        Ok(names)
+    }
+
+    fn write_file_if(
+        &self,
+        key: &Url,
+        name: &str,
+        expected: Option<&str>,
+        data: &[u8],
+    ) -> anyhow::Result<Option<String>> {
+        let dir = Self::create_key_dir(key)?;
+
+        // Locked across processes, so the version check and the write are atomic
+        let lock = std::fs::File::create(dir.join(format!("{name}{LOCK_SUFFIX}")))?;
+        lock.lock()?;
+
+        if self.file_version(key, name)?.as_deref() != expected {
+            return Ok(None);
+        }
+
+        let stored = options::encode_file(key, name, data)?;
+        let tmp = dir.join(format!("{name}.swap"));
+        write_synced(key, &tmp, &stored)?;
+        std::fs::rename(tmp, dir.join(name))?;
+        sync_dir(key, &dir)?;
+
+        // Snapshots stored as JSON may not read back byte for byte
+        match options::KeyOptions::parse(key)?.format {
+            options::Format::Json => self.file_version(key, name),
+            options::Format::Postcard => Ok(Some(content_version(data))),
+        }
+    }
+}
+
+/// Write a file of a key, flushing it to disk if the key asks for `fsync`.
+pub(crate) fn write_synced(key: &Url, path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(data)?;
+    if options::KeyOptions::parse(key)?.fsync {
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Flush the entries of a key directory to disk if the key asks for `fsync`, so renames survive
+/// a crash.
+pub(crate) fn sync_dir(key: &Url, dir: &Path) -> anyhow::Result<()> {
+    #[cfg(unix)]
+    if options::KeyOptions::parse(key)?.fsync {
+        std::fs::File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = (key, dir);
+    Ok(())
+}
+
+/// Visit `dir` and every directory below it, parents first.
+fn walk(dir: &Path, visit: &mut impl FnMut(&Path) -> anyhow::Result<()>) -> anyhow::Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    visit(dir)?;
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            walk(&path, visit)?;
+        }
+    }
+
+    Ok(())
+}
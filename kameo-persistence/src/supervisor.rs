@@ -0,0 +1,269 @@
+use std::{collections::BTreeMap, ops::ControlFlow};
+
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "tracing")]
+use tracing::{debug, warn};
+use url::Url;
+
+#[cfg(feature = "tracing")]
+use crate::redact::redacted;
+use crate::{Checkpoint, PersistenceKeyExt, PersistentActor, registry};
+
+/// Actor owning a named set of persistent children of type `A`.
+///
+/// Children are respawned from storage when the supervisor starts, restarted from their latest
+/// snapshot when they die abnormally, and the supervisor persists its own list of children.
+pub struct PersistentSupervisor<A: PersistentActor> {
+    children: BTreeMap<String, Url>,
+    running: BTreeMap<String, ActorRef<A>>,
+}
+
+/// Snapshot and arguments of a `PersistentSupervisor`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SupervisorSnapshot {
+    pub children: BTreeMap<String, Url>,
+}
+
+impl<A: PersistentActor> From<&PersistentSupervisor<A>> for SupervisorSnapshot {
+    fn from(supervisor: &PersistentSupervisor<A>) -> Self {
+        Self {
+            children: supervisor.children.clone(),
+        }
+    }
+}
+
+impl<A: PersistentActor> PersistentSupervisor<A> {
+    /// Respawn a child from its snapshot and link it to the supervisor.
+    async fn start_child(supervisor_ref: &ActorRef<Self>, key: Url) -> anyhow::Result<ActorRef<A>> {
+        let child = A::respawn_persistent(key).await?;
+        supervisor_ref.link(&child).await;
+        Ok(child)
+    }
+}
+
+impl<A: PersistentActor> Actor for PersistentSupervisor<A> {
+    type Args = SupervisorSnapshot;
+    type Error = anyhow::Error;
+
+    async fn on_start(args: Self::Args, actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let mut running = BTreeMap::new();
+
+        for (name, key) in &args.children {
+            match Self::start_child(&actor_ref, key.clone()).await {
+                Ok(child) => {
+                    running.insert(name.clone(), child);
+                }
+                Err(_e) => {
+                    #[cfg(feature = "tracing")]
+                    warn!(
+                        "Failed to respawn child {name} with key {}: {_e}",
+                        redacted(key)
+                    );
+                }
+            }
+        }
+
+        Ok(Self {
+            children: args.children,
+            running,
+        })
+    }
+
+    async fn on_link_died(
+        &mut self,
+        actor_ref: WeakActorRef<Self>,
+        id: ActorId,
+        reason: ActorStopReason,
+    ) -> Result<ControlFlow<ActorStopReason>, Self::Error> {
+        let Some(name) = self
+            .running
+            .iter()
+            .find(|(_, child)| child.id() == id)
+            .map(|(name, _)| name.clone())
+        else {
+            return Ok(ControlFlow::Continue(()));
+        };
+
+        self.running.remove(&name);
+
+        // Stopped on purpose, nothing to restart
+        if matches!(reason, ActorStopReason::Normal) {
+            return Ok(ControlFlow::Continue(()));
+        }
+
+        let (Some(supervisor_ref), Some(key)) = (actor_ref.upgrade(), self.children.get(&name))
+        else {
+            return Ok(ControlFlow::Continue(()));
+        };
+
+        #[cfg(feature = "tracing")]
+        debug!(
+            "Restarting child {name} with key {} after {reason:?}",
+            redacted(key)
+        );
+
+        match Self::start_child(&supervisor_ref, key.clone()).await {
+            Ok(child) => {
+                self.running.insert(name, child);
+            }
+            Err(_e) => {
+                #[cfg(feature = "tracing")]
+                warn!(
+                    "Failed to restart child {name} with key {}: {_e}",
+                    redacted(key)
+                );
+            }
+        }
+
+        Ok(ControlFlow::Continue(()))
+    }
+}
+
+impl<A: PersistentActor> PersistentActor for PersistentSupervisor<A> {
+    type Snapshot = SupervisorSnapshot;
+
+    fn register_persistent(persistence_key: Url, actor_ref: &ActorRef<Self>) -> anyhow::Result<()> {
+        let Ok(mut registry) = registry::typed_registry::<Self>().write() else {
+            anyhow::bail!("Failed to acquire write lock on registry");
+        };
+        registry::register(persistence_key.clone(), actor_ref);
+        registry.insert(persistence_key, actor_ref.downgrade());
+        Ok(())
+    }
+
+    fn persistence_key(actor_ref: &ActorRef<Self>) -> Option<Url> {
+        let registry = registry::typed_registry::<Self>().read().ok()?;
+        registry.get_left(&actor_ref.downgrade()).cloned()
+    }
+
+    fn lookup_persistent(persistence_key: &Url) -> Option<ActorRef<Self>> {
+        let registry = registry::typed_registry::<Self>().read().ok()?;
+        registry
+            .get_right(persistence_key)
+            .and_then(|weak_ref| weak_ref.upgrade())
+            .filter(|actor_ref| actor_ref.is_alive())
+    }
+
+    fn unregister_persistent(persistence_key: &Url) {
+        registry::unregister(persistence_key);
+        registry::typed_registry::<Self>()
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove_left(persistence_key);
+    }
+
+    fn persistent_children(&self) -> Vec<Url> {
+        self.children.values().cloned().collect()
+    }
+}
+
+impl<A: PersistentActor> Message<Checkpoint> for PersistentSupervisor<A> {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: Checkpoint,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.on_checkpoint(&ctx.actor_ref(), msg).await
+    }
+}
+
+/// Spawn a persistent child named `name`, or return it if already running.
+pub struct SpawnChild<A: Actor> {
+    pub name: String,
+    pub args: A::Args,
+}
+
+impl<A: PersistentActor> Message<SpawnChild<A>> for PersistentSupervisor<A> {
+    type Reply = anyhow::Result<ActorRef<A>>;
+
+    async fn handle(
+        &mut self,
+        msg: SpawnChild<A>,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        if let Some(child) = self.running.get(&msg.name) {
+            return Ok(child.clone());
+        }
+
+        let supervisor_ref = ctx.actor_ref();
+        let Some(supervisor_key) = Self::persistence_key(&supervisor_ref) else {
+            anyhow::bail!("PersistentSupervisor must be persistent to spawn children");
+        };
+
+        let key = supervisor_key.child::<A>(&msg.name)?;
+        let child = A::spawn_persistent(key.clone(), msg.args).await?;
+        supervisor_ref.link(&child).await;
+
+        self.children.insert(msg.name.clone(), key);
+        self.running.insert(msg.name, child.clone());
+
+        self.save_snapshot(&supervisor_ref).await?;
+
+        Ok(child)
+    }
+}
+
+/// Return the running child named `name`.
+pub struct GetChild {
+    pub name: String,
+}
+
+impl<A: PersistentActor> Message<GetChild> for PersistentSupervisor<A> {
+    type Reply = Option<ActorRef<A>>;
+
+    async fn handle(
+        &mut self,
+        msg: GetChild,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.running.get(&msg.name).cloned()
+    }
+}
+
+/// Stop the child named `name` and remove it from the supervisor.
+pub struct StopChild {
+    pub name: String,
+}
+
+impl<A: PersistentActor> Message<StopChild> for PersistentSupervisor<A> {
+    type Reply = anyhow::Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: StopChild,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        if let Some(child) = self.running.remove(&msg.name) {
+            ctx.actor_ref().unlink(&child).await;
+            child
+                .stop_gracefully()
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to stop child {}: {e}", msg.name))?;
+        }
+
+        self.children.remove(&msg.name);
+
+        self.save_snapshot(&ctx.actor_ref()).await
+    }
+}
+
+/// List the children of the supervisor with their persistence keys.
+pub struct ListChildren;
+
+impl<A: PersistentActor> Message<ListChildren> for PersistentSupervisor<A> {
+    type Reply = Vec<(String, Url)>;
+
+    async fn handle(
+        &mut self,
+        _msg: ListChildren,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.children
+            .iter()
+            .map(|(name, key)| (name.clone(), key.clone()))
+            .collect()
+    }
+}
@@ -0,0 +1,243 @@
+use std::{collections::BTreeSet, time::Duration};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+#[cfg(feature = "tracing")]
+use tracing::{debug, info, warn};
+use url::Url;
+
+use crate::{clock, codec, concurrency, redact::redacted, storage};
+
+/// Versions of a key at its last sync, kept next to the local snapshot.
+const SYNC_FILE: &str = "sync.bin";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SyncState {
+    local: Option<String>,
+    central: Option<String>,
+}
+
+/// Outcome of a sync round, with keys under the local root.
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    /// Keys whose local snapshot was written to the central store.
+    pub pushed: Vec<Url>,
+    /// Keys whose central snapshot was written to the local store.
+    pub pulled: Vec<Url>,
+    /// Keys changed on both sides and reconciled by a conflict resolver.
+    pub resolved: Vec<Url>,
+    /// Keys changed on both sides without a resolver, or changed again while syncing; they are
+    /// retried on the next round.
+    pub conflicts: Vec<Url>,
+}
+
+/// Two-way sync between the local store of an edge process and a central store.
+///
+/// Actors persist under `local_root` as usual, so they keep working while disconnected. Each
+/// round compares every key with the versions recorded at its last sync: snapshots changed only
+/// locally are pushed, those changed only centrally are pulled, and those changed on both sides
+/// are handed to the conflict resolver of their actor type, see `concurrency::set_resolver` and
+/// `merge::set_merge_resolver`. Central writes are compare-and-swaps, so concurrent edges never
+/// silently overwrite each other. Deletions are not synced.
+pub struct EdgeSync {
+    local_root: Url,
+    central_root: Url,
+}
+
+/// Handle of a periodic sync; syncing stops when it is dropped.
+pub struct SyncHandle {
+    task: JoinHandle<()>,
+}
+
+impl Drop for SyncHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl EdgeSync {
+    /// Sync the keys under `local_root` with the same relative keys under `central_root`.
+    pub fn new(local_root: Url, central_root: Url) -> Self {
+        Self {
+            local_root,
+            central_root,
+        }
+    }
+
+    /// Sync every `interval` until the returned handle is dropped.
+    ///
+    /// A failed round, e.g. while the central store is unreachable, is retried on the next one.
+    pub fn spawn(self, interval: Duration) -> SyncHandle {
+        let task = tokio::spawn(async move {
+            loop {
+                let clock = clock::clock();
+                clock.sleep_until(clock.now() + interval).await;
+
+                match self.sync().await {
+                    Ok(_report) => {
+                        #[cfg(feature = "tracing")]
+                        debug!(
+                            "Synced {}: {} pushed, {} pulled, {} resolved, {} conflicts",
+                            redacted(&self.local_root),
+                            _report.pushed.len(),
+                            _report.pulled.len(),
+                            _report.resolved.len(),
+                            _report.conflicts.len()
+                        );
+                    }
+                    Err(_e) => {
+                        #[cfg(feature = "tracing")]
+                        warn!("Failed to sync {}: {_e:#}", redacted(&self.local_root));
+                    }
+                }
+            }
+        });
+
+        SyncHandle { task }
+    }
+
+    /// Run a single sync round.
+    pub async fn sync(&self) -> anyhow::Result<SyncReport> {
+        let mut keys = storage::list(&self.local_root)
+            .await?
+            .into_iter()
+            .collect::<BTreeSet<_>>();
+
+        for key in storage::list(&self.central_root).await? {
+            if let Some(local) = storage::rebase(&key, &self.central_root, &self.local_root) {
+                keys.insert(local);
+            }
+        }
+
+        let mut report = SyncReport::default();
+        for key in keys {
+            self.sync_key(key, &mut report).await?;
+        }
+
+        #[cfg(feature = "tracing")]
+        if !report.conflicts.is_empty() {
+            info!(
+                "{} snapshots under {} conflict with the central store",
+                report.conflicts.len(),
+                redacted(&self.local_root)
+            );
+        }
+
+        Ok(report)
+    }
+
+    async fn sync_key(&self, local: Url, report: &mut SyncReport) -> anyhow::Result<()> {
+        let central = storage::rebase(&local, &self.local_root, &self.central_root)
+            .ok_or_else(|| anyhow!("Cannot sync {}", redacted(&local)))?;
+
+        let local_backend = storage::backend(&local)?;
+        let central_backend = storage::backend(&central)?;
+
+        let state = match local_backend.read_file(&local, SYNC_FILE)? {
+            Some(bytes) => postcard::from_bytes(&bytes)?,
+            None => SyncState::default(),
+        };
+
+        // Versions first, so a snapshot replaced while reading fails its write
+        let versions = SyncState {
+            local: local_backend.file_version(&local, storage::snapshot_file())?,
+            central: central_backend.file_version(&central, storage::snapshot_file())?,
+        };
+
+        let local_changed = versions.local != state.local;
+        let central_changed = versions.central != state.central;
+        if !local_changed && !central_changed {
+            return Ok(());
+        }
+
+        let local_data = match local_changed {
+            true => local_backend.read_file(&local, storage::snapshot_file())?,
+            false => None,
+        };
+        let central_data = match central_changed {
+            true => central_backend.read_file(&central, storage::snapshot_file())?,
+            false => None,
+        };
+
+        let synced = match (local_data, central_data) {
+            (None, None) => return Ok(()),
+            (Some(data), None) => {
+                let synced = push(&central, &versions, &data).await?;
+                if synced.is_some() {
+                    report.pushed.push(local.clone());
+                }
+                synced
+            }
+            (None, Some(data)) => {
+                let synced = pull(&local, &versions, &data)?;
+                if synced.is_some() {
+                    report.pulled.push(local.clone());
+                }
+                synced
+            }
+            (Some(local_data), Some(central_data)) if local_data == central_data => Some(versions),
+            (Some(local_data), Some(central_data)) => {
+                let resolver = codec::split(&central_data)?
+                    .0
+                    .and_then(|header| concurrency::resolver(&header.type_tag));
+
+                match resolver {
+                    Some(resolver) => {
+                        let resolved =
+                            resolver(&central, &local_data, &central_data)?.unwrap_or(central_data);
+
+                        let synced = match push(&central, &versions, &resolved).await? {
+                            Some(pushed) => pull(&local, &pushed, &resolved)?,
+                            None => None,
+                        };
+                        if synced.is_some() {
+                            report.resolved.push(local.clone());
+                        }
+                        synced
+                    }
+                    None => None,
+                }
+            }
+        };
+
+        match synced {
+            Some(synced) => {
+                local_backend.write_file(&local, SYNC_FILE, &postcard::to_stdvec(&synced)?)?;
+            }
+            None => report.conflicts.push(local),
+        }
+
+        Ok(())
+    }
+}
+
+/// Write `data` to the central store, unless its snapshot changed since `versions` were read.
+async fn push(
+    central: &Url,
+    versions: &SyncState,
+    data: &[u8],
+) -> anyhow::Result<Option<SyncState>> {
+    let pushed = storage::write_if(central, versions.central.as_deref(), data).await?;
+
+    Ok(pushed.map(|central| SyncState {
+        local: versions.local.clone(),
+        central: Some(central),
+    }))
+}
+
+/// Write `data` to the local store, unless its snapshot changed since `versions` were read.
+fn pull(local: &Url, versions: &SyncState, data: &[u8]) -> anyhow::Result<Option<SyncState>> {
+    // Straight to the backend, so watchers see pulled snapshots as external changes
+    let pulled = storage::backend(local)?.write_file_if(
+        local,
+        storage::snapshot_file(),
+        versions.local.as_deref(),
+        data,
+    )?;
+
+    Ok(pulled.map(|local| SyncState {
+        local: Some(local),
+        central: versions.central.clone(),
+    }))
+}
@@ -0,0 +1,166 @@
+use std::sync::RwLock;
+
+use anyhow::anyhow;
+#[cfg(feature = "tracing")]
+use tracing::info;
+use url::Url;
+
+use crate::{protect, redact::redacted, storage};
+
+/// Isolated namespace of persistence keys belonging to one tenant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tenant {
+    id: String,
+    root: Url,
+    encrypted: bool,
+}
+
+// Tenants whose keys are resolved by `tenant_of`
+static TENANTS: RwLock<Vec<Tenant>> = RwLock::new(Vec::new());
+
+pub(crate) fn push_segments(url: &mut Url, path: &str) -> anyhow::Result<()> {
+    let segments = path.split('/').filter(|segment| !segment.is_empty());
+
+    if url.cannot_be_a_base() {
+        anyhow::bail!("Persistence key cannot be a base: {}", redacted(url));
+    }
+
+    let mut path_segments = url
+        .path_segments_mut()
+        .map_err(|_| anyhow!("Persistence key cannot be a base"))?;
+    path_segments.pop_if_empty();
+
+    for segment in segments {
+        if segment == "." || segment == ".." {
+            anyhow::bail!("Invalid key segment: {segment:?}");
+        }
+        path_segments.push(segment);
+    }
+
+    Ok(())
+}
+
+impl Tenant {
+    /// Tenant rooted at `base/<id>`.
+    pub fn new(id: impl Into<String>, base: &Url) -> anyhow::Result<Self> {
+        let id = id.into();
+        if id.is_empty() || id.contains('/') || id == "." || id == ".." {
+            anyhow::bail!("Invalid tenant id: {id:?}");
+        }
+
+        let mut root = base.clone();
+        push_segments(&mut root, &id)?;
+
+        Ok(Self::with_root(id, root))
+    }
+
+    /// Tenant with a root of its own, such as a dedicated volume.
+    pub fn with_root(id: impl Into<String>, root: Url) -> Self {
+        Self {
+            id: id.into(),
+            root,
+            encrypted: false,
+        }
+    }
+
+    /// Encrypt snapshots of the tenant with its own key from the installed `SubjectKeys`.
+    ///
+    /// An actor's `data_subject` takes precedence over the tenant key.
+    pub fn encrypted(mut self) -> Self {
+        self.encrypted = true;
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn root(&self) -> &Url {
+        &self.root
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// Subject whose key encrypts the snapshots of an encrypted tenant.
+    pub fn subject(&self) -> String {
+        format!("tenant:{}", self.id)
+    }
+
+    /// Persistence key at `path` below the tenant root.
+    pub fn key(&self, path: &str) -> anyhow::Result<Url> {
+        let mut key = self.root.clone();
+        push_segments(&mut key, path)?;
+        Ok(key)
+    }
+
+    /// Return true if `persistence_key` is the tenant root or below it.
+    pub fn contains(&self, persistence_key: &Url) -> bool {
+        storage::is_within(&self.root, persistence_key)
+    }
+
+    /// List every persistence key of the tenant holding a snapshot.
+    pub async fn keys(&self) -> anyhow::Result<Vec<Url>> {
+        storage::list(&self.root).await
+    }
+
+    /// Total size in bytes of the tenant's snapshots.
+    pub fn usage(&self) -> anyhow::Result<u64> {
+        storage::usage(&self.root)
+    }
+
+    /// Delete every snapshot of the tenant, and destroy its key if it is encrypted.
+    ///
+    /// Stop the tenant's actors first, or they write their snapshots again on their next save.
+    pub async fn wipe(&self) -> anyhow::Result<Vec<Url>> {
+        let keys = self.keys().await?;
+
+        // Children first, so each deletion is recorded on its own
+        for key in keys.iter().rev() {
+            storage::delete(key).await?;
+        }
+        storage::delete(&self.root).await?;
+
+        if self.encrypted {
+            protect::shred_subject(&self.subject())?;
+        }
+
+        #[cfg(feature = "tracing")]
+        info!("Wiped tenant {}: {} snapshots", self.id, keys.len());
+
+        Ok(keys)
+    }
+}
+
+/// Register a tenant, so keys below its root are attributed to it.
+pub fn register(tenant: Tenant) {
+    let mut tenants = TENANTS.write().unwrap_or_else(|e| e.into_inner());
+
+    tenants.retain(|existing| existing.id != tenant.id);
+    tenants.push(tenant);
+}
+
+/// Unregister a tenant by id.
+pub fn unregister(id: &str) -> Option<Tenant> {
+    let mut tenants = TENANTS.write().unwrap_or_else(|e| e.into_inner());
+
+    let index = tenants.iter().position(|tenant| tenant.id == id)?;
+    Some(tenants.remove(index))
+}
+
+/// Return every registered tenant.
+pub fn tenants() -> Vec<Tenant> {
+    TENANTS.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Return the registered tenant owning a persistence key, the most specific one if nested.
+pub fn tenant_of(persistence_key: &Url) -> Option<Tenant> {
+    TENANTS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .filter(|tenant| tenant.contains(persistence_key))
+        .max_by_key(|tenant| tenant.root.path().len())
+        .cloned()
+}
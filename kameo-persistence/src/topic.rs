@@ -0,0 +1,283 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    marker::PhantomData,
+};
+
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+#[cfg(feature = "tracing")]
+use tracing::debug;
+use url::Url;
+
+#[cfg(feature = "tracing")]
+use crate::redact::redacted;
+use crate::{Checkpoint, PersistentActor, registry};
+
+/// Broker actor publishing messages of type `M` to persistent subscribers of type `A`.
+///
+/// Subscribers are kept by persistence key, and every published message is first queued for
+/// each subscriber in the topic's snapshot, then delivered to the live ones and dropped from
+/// their queue. Messages for subscribers not alive stay queued, across restarts of the topic,
+/// until they are delivered by a later `Publish` or a `Redeliver`, e.g. once the subscriber was
+/// respawned. Delivery is at least once: a message delivered just before a crash is delivered
+/// again after the restart.
+pub struct PersistentTopic<A, M> {
+    subscribers: BTreeSet<Url>,
+    pending: BTreeMap<Url, VecDeque<M>>,
+    _subscriber: PhantomData<fn() -> A>,
+}
+
+/// Snapshot and arguments of a `PersistentTopic`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopicSnapshot<M> {
+    pub subscribers: BTreeSet<Url>,
+    /// Messages not delivered yet, per subscriber, oldest first.
+    pub pending: BTreeMap<Url, VecDeque<M>>,
+}
+
+impl<M> Default for TopicSnapshot<M> {
+    fn default() -> Self {
+        Self {
+            subscribers: BTreeSet::new(),
+            pending: BTreeMap::new(),
+        }
+    }
+}
+
+impl<A, M: Clone> From<&PersistentTopic<A, M>> for TopicSnapshot<M> {
+    fn from(topic: &PersistentTopic<A, M>) -> Self {
+        Self {
+            subscribers: topic.subscribers.clone(),
+            pending: topic.pending.clone(),
+        }
+    }
+}
+
+impl<A, M> From<TopicSnapshot<M>> for PersistentTopic<A, M> {
+    fn from(snapshot: TopicSnapshot<M>) -> Self {
+        Self {
+            subscribers: snapshot.subscribers,
+            pending: snapshot.pending,
+            _subscriber: PhantomData,
+        }
+    }
+}
+
+impl<A, M> PersistentTopic<A, M>
+where
+    A: PersistentActor + Message<M>,
+    M: Clone + Send + 'static,
+{
+    /// Deliver queued messages to the live subscribers, returning how many were delivered.
+    async fn deliver(&mut self) -> usize {
+        let mut delivered = 0;
+
+        for (key, queue) in &mut self.pending {
+            let Some(subscriber) = A::lookup_persistent(key) else {
+                continue;
+            };
+
+            while let Some(msg) = queue.front() {
+                if subscriber.tell(msg.clone()).await.is_err() {
+                    break;
+                }
+                queue.pop_front();
+                delivered += 1;
+            }
+        }
+
+        self.pending.retain(|_, queue| !queue.is_empty());
+        delivered
+    }
+}
+
+impl<A, M> Actor for PersistentTopic<A, M>
+where
+    A: PersistentActor + Message<M>,
+    M: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Args = TopicSnapshot<M>;
+    type Error = anyhow::Error;
+
+    async fn on_start(args: Self::Args, _actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        Ok(args.into())
+    }
+}
+
+impl<A, M> PersistentActor for PersistentTopic<A, M>
+where
+    A: PersistentActor + Message<M>,
+    M: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Snapshot = TopicSnapshot<M>;
+
+    fn register_persistent(persistence_key: Url, actor_ref: &ActorRef<Self>) -> anyhow::Result<()> {
+        let Ok(mut registry) = registry::typed_registry::<Self>().write() else {
+            anyhow::bail!("Failed to acquire write lock on registry");
+        };
+        registry::register(persistence_key.clone(), actor_ref);
+        registry.insert(persistence_key, actor_ref.downgrade());
+        Ok(())
+    }
+
+    fn persistence_key(actor_ref: &ActorRef<Self>) -> Option<Url> {
+        let registry = registry::typed_registry::<Self>().read().ok()?;
+        registry.get_left(&actor_ref.downgrade()).cloned()
+    }
+
+    fn lookup_persistent(persistence_key: &Url) -> Option<ActorRef<Self>> {
+        let registry = registry::typed_registry::<Self>().read().ok()?;
+        registry
+            .get_right(persistence_key)
+            .and_then(|weak_ref| weak_ref.upgrade())
+            .filter(|actor_ref| actor_ref.is_alive())
+    }
+
+    fn unregister_persistent(persistence_key: &Url) {
+        registry::unregister(persistence_key);
+        registry::typed_registry::<Self>()
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove_left(persistence_key);
+    }
+}
+
+impl<A, M> Message<Checkpoint> for PersistentTopic<A, M>
+where
+    A: PersistentActor + Message<M>,
+    M: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: Checkpoint,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.on_checkpoint(&ctx.actor_ref(), msg).await
+    }
+}
+
+/// Subscribe the persistent actor stored under `key` to the topic.
+pub struct Subscribe {
+    pub key: Url,
+}
+
+impl<A, M> Message<Subscribe> for PersistentTopic<A, M>
+where
+    A: PersistentActor + Message<M>,
+    M: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Reply = anyhow::Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: Subscribe,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        if self.subscribers.insert(msg.key) {
+            self.save_snapshot(&ctx.actor_ref()).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Unsubscribe the actor stored under `key`, dropping the messages queued for it.
+pub struct Unsubscribe {
+    pub key: Url,
+}
+
+impl<A, M> Message<Unsubscribe> for PersistentTopic<A, M>
+where
+    A: PersistentActor + Message<M>,
+    M: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Reply = anyhow::Result<()>;
+
+    async fn handle(
+        &mut self,
+        msg: Unsubscribe,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let removed = self.subscribers.remove(&msg.key);
+        let dropped = self.pending.remove(&msg.key).is_some();
+
+        if removed || dropped {
+            self.save_snapshot(&ctx.actor_ref()).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Publish a message to every subscriber, returning how many queued messages were delivered.
+pub struct Publish<M>(pub M);
+
+impl<A, M> Message<Publish<M>> for PersistentTopic<A, M>
+where
+    A: PersistentActor + Message<M>,
+    M: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Reply = anyhow::Result<usize>;
+
+    async fn handle(
+        &mut self,
+        msg: Publish<M>,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        if self.subscribers.is_empty() {
+            return Ok(0);
+        }
+
+        for key in &self.subscribers {
+            self.pending
+                .entry(key.clone())
+                .or_default()
+                .push_back(msg.0.clone());
+        }
+
+        // Queued durably before delivering, so a crash in between loses nothing
+        let actor_ref = ctx.actor_ref();
+        self.save_snapshot(&actor_ref).await?;
+
+        let delivered = self.deliver().await;
+        if delivered > 0 {
+            self.save_snapshot(&actor_ref).await?;
+        }
+
+        #[cfg(feature = "tracing")]
+        if let Some(key) = Self::persistence_key(&actor_ref) {
+            debug!(
+                "Published to {} subscribers of {}, {} messages queued",
+                self.subscribers.len(),
+                redacted(&key),
+                self.pending.values().map(VecDeque::len).sum::<usize>()
+            );
+        }
+
+        Ok(delivered)
+    }
+}
+
+/// Deliver the messages queued for subscribers which are alive again, returning how many were
+/// delivered.
+pub struct Redeliver;
+
+impl<A, M> Message<Redeliver> for PersistentTopic<A, M>
+where
+    A: PersistentActor + Message<M>,
+    M: Clone + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    type Reply = anyhow::Result<usize>;
+
+    async fn handle(
+        &mut self,
+        _msg: Redeliver,
+        ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let delivered = self.deliver().await;
+        if delivered > 0 {
+            self.save_snapshot(&ctx.actor_ref()).await?;
+        }
+        Ok(delivered)
+    }
+}
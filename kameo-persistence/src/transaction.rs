@@ -0,0 +1,390 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context as _;
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "tracing")]
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::{
+    PersistentActor, codec, concurrency, history, lease, rate_limit, redact::redacted, storage,
+    watch, write_stats,
+};
+
+/// Snapshot staged next to `index.bin` until its transaction is applied.
+pub(crate) const STAGED_FILE: &str = "index.bin.staged";
+
+/// Transaction the staged snapshot belongs to.
+pub(crate) const STAGED_REF_FILE: &str = "index.bin.staged.ref";
+
+// Serializes the transactions of each root key in this process, from staging to applying
+static ROOTS: LazyLock<Mutex<HashMap<Url, Arc<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Commit record of a transaction, written under the root key.
+fn manifest_file(transaction: &str) -> String {
+    format!("transaction-{transaction}.bin")
+}
+
+/// Commit record of a transaction being written under the root key.
+fn manifest_tmp_file(transaction: &str) -> String {
+    format!("transaction-{transaction}.bin.tmp")
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StagedRef {
+    transaction: String,
+    /// Key holding the commit record.
+    root_key: Url,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    transaction: String,
+    keys: Vec<Url>,
+}
+
+/// Group of snapshots written all together or not at all.
+///
+/// Snapshots are first staged next to their current `index.bin`. Once every snapshot is staged,
+/// a commit record named after the transaction is written under the root key, which is the
+/// commit point; staged snapshots are then moved into place. A reader finding a staged snapshot
+/// whose commit record exists completes the move itself, so a crash after the commit point
+/// never leaves the group half applied. Transactions of the same root key run one at a time
+/// within a process, so they never replace each other's staged snapshots.
+///
+/// Snapshots of actor types with optimistic saves, see `concurrency::set_optimistic`, are
+/// checked while staging: the transaction fails with `PersistenceError::Conflict` if another
+/// writer replaced one since it was last seen, without conflict resolution.
+pub struct SnapshotTransaction {
+    root_key: Url,
+    id: String,
+    staged: Vec<Staged>,
+}
+
+struct Staged {
+    key: Url,
+    data: Vec<u8>,
+    /// Type tag of the actor, if known, to check its snapshot for conflicts.
+    type_tag: Option<&'static str>,
+}
+
+impl SnapshotTransaction {
+    /// Create an empty transaction whose commit record is kept under `root_key`.
+    pub fn new(root_key: Url) -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+
+        Self {
+            root_key,
+            id: format!(
+                "{nanos:x}-{:x}-{:x}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ),
+            staged: Vec::new(),
+        }
+    }
+
+    /// Stage the current state of a persistent actor.
+    pub fn stage<A: PersistentActor>(
+        &mut self,
+        actor: &A,
+        actor_ref: &ActorRef<A>,
+    ) -> anyhow::Result<()> {
+        let Some(key) = A::persistence_key(actor_ref) else {
+            anyhow::bail!("Actor {} is not persistent", std::any::type_name::<A>());
+        };
+
+        self.stage_snapshot::<A>(key, &A::Snapshot::from(actor))
+    }
+
+    /// Stage a snapshot to be written under `persistence_key`.
+    pub fn stage_snapshot<A: PersistentActor>(
+        &mut self,
+        persistence_key: Url,
+        snapshot: &A::Snapshot,
+    ) -> anyhow::Result<()> {
+        let data = codec::encode::<A>(&persistence_key, snapshot)?;
+        self.push(persistence_key, data, Some(A::type_tag()));
+        Ok(())
+    }
+
+    /// Stage already encoded snapshot bytes to be written under `persistence_key`.
+    pub fn stage_bytes(&mut self, persistence_key: Url, data: Vec<u8>) {
+        self.push(persistence_key, data, None);
+    }
+
+    /// Stage encoded snapshot bytes of the actor type tagged `type_tag`.
+    pub(crate) fn stage_bytes_as(
+        &mut self,
+        type_tag: &'static str,
+        persistence_key: Url,
+        data: Vec<u8>,
+    ) {
+        self.push(persistence_key, data, Some(type_tag));
+    }
+
+    fn push(&mut self, key: Url, data: Vec<u8>, type_tag: Option<&'static str>) {
+        self.staged.retain(|staged| staged.key != key);
+        self.staged.push(Staged {
+            key,
+            data,
+            type_tag,
+        });
+    }
+
+    /// Persistence keys staged so far.
+    pub fn keys(&self) -> impl Iterator<Item = &Url> {
+        self.staged.iter().map(|staged| &staged.key)
+    }
+
+    /// Write every staged snapshot, or none of them if any could not be staged.
+    pub async fn commit(self) -> anyhow::Result<()> {
+        #[cfg(feature = "metrics")]
+        let (started, snapshots) = (std::time::Instant::now(), self.staged.len());
+
+        let result = self.commit_staged().await;
+
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_transaction(snapshots, result.is_ok(), started.elapsed());
+
+        result
+    }
+
+    async fn commit_staged(self) -> anyhow::Result<()> {
+        if self.staged.is_empty() {
+            return Ok(());
+        }
+
+        rate_limit::acquire(self.staged.len()).await;
+
+        let root = RootLock::new(&self.root_key);
+        let _guard = root.lock.lock().await;
+
+        let mut prepared = Vec::with_capacity(self.staged.len());
+        for staged in &self.staged {
+            match self.prepare(staged) {
+                Ok(()) => prepared.push(staged.key.clone()),
+                Err(e) => {
+                    Self::abort(&prepared);
+                    return Err(e.context(format!(
+                        "Failed to stage snapshot for {}",
+                        redacted(&staged.key)
+                    )));
+                }
+            }
+        }
+
+        // Commit point
+        if let Err(e) = self.write_manifest() {
+            Self::abort(&prepared);
+            return Err(e.context("Failed to write transaction commit record"));
+        }
+
+        for Staged { key, data, .. } in &self.staged {
+            apply(key).with_context(|| {
+                format!(
+                    "Transaction {} committed but not applied to {}; it completes on next read",
+                    self.id,
+                    redacted(key)
+                )
+            })?;
+            write_stats::note_write(key, data.len());
+        }
+
+        storage::backend(&self.root_key)?.remove_file(&self.root_key, &manifest_file(&self.id))?;
+
+        #[cfg(feature = "tracing")]
+        debug!(
+            "Committed transaction {} with {} snapshots",
+            self.id,
+            self.staged.len()
+        );
+
+        Ok(())
+    }
+
+    fn prepare(&self, staged: &Staged) -> anyhow::Result<()> {
+        let Staged {
+            key,
+            data,
+            type_tag,
+        } = staged;
+
+        lease::check(key)?;
+        if let Some(type_tag) = type_tag
+            && concurrency::is_optimistic_type(type_tag)
+        {
+            concurrency::check(type_tag, key)?;
+        }
+        let backend = storage::backend(key)?;
+
+        let staged_ref = StagedRef {
+            transaction: self.id.clone(),
+            root_key: self.root_key.clone(),
+        };
+
+        watch::note_write(key, data);
+        backend.write_file(key, STAGED_FILE, data)?;
+        backend.write_file(key, STAGED_REF_FILE, &postcard::to_stdvec(&staged_ref)?)?;
+
+        Ok(())
+    }
+
+    fn write_manifest(&self) -> anyhow::Result<()> {
+        let manifest = Manifest {
+            transaction: self.id.clone(),
+            keys: self.keys().cloned().collect(),
+        };
+
+        let backend = storage::backend(&self.root_key)?;
+
+        // Write then rename, so the commit record appears atomically
+        let tmp_file = manifest_tmp_file(&self.id);
+        backend.write_file(&self.root_key, &tmp_file, &postcard::to_stdvec(&manifest)?)?;
+        backend.rename_file(&self.root_key, &tmp_file, &manifest_file(&self.id))?;
+
+        Ok(())
+    }
+
+    fn abort(keys: &[Url]) {
+        for key in keys {
+            if let Ok(backend) = storage::backend(key) {
+                let _ = backend.remove_file(key, STAGED_REF_FILE);
+                let _ = backend.remove_file(key, STAGED_FILE);
+            }
+        }
+    }
+}
+
+/// Move a staged snapshot into place.
+fn apply(persistence_key: &Url) -> anyhow::Result<()> {
+    let backend = storage::backend(persistence_key)?;
+
+    // Already applied by a concurrent reader
+    if backend.file_size(persistence_key, STAGED_FILE)?.is_none() {
+        return Ok(());
+    }
+
+    storage::keep_previous(persistence_key, None)?;
+    backend.rename_file(persistence_key, STAGED_FILE, storage::snapshot_file())?;
+    backend.remove_file(persistence_key, STAGED_REF_FILE)?;
+    concurrency::note_write(persistence_key)?;
+    history::note_applied(persistence_key)?;
+
+    #[cfg(feature = "audit")]
+    {
+        let data = backend.read_file(persistence_key, storage::snapshot_file())?;
+        crate::audit::record(
+            crate::audit::AuditOperation::Save,
+            persistence_key,
+            data.as_deref(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Apply a staged snapshot left behind by a committed but interrupted transaction.
+///
+/// Snapshots staged by uncommitted transactions are left untouched.
+pub(crate) fn recover(persistence_key: &Url) -> anyhow::Result<()> {
+    let staged_ref =
+        storage::backend(persistence_key)?.read_file(persistence_key, STAGED_REF_FILE)?;
+    recover_from(persistence_key, staged_ref)
+}
+
+/// Recover as `recover` does, given the `STAGED_REF_FILE` of the key, if any, as read already.
+pub(crate) fn recover_from(
+    persistence_key: &Url,
+    staged_ref: Option<Vec<u8>>,
+) -> anyhow::Result<()> {
+    let Some(bytes) = staged_ref else {
+        return Ok(());
+    };
+
+    let staged_ref: StagedRef = postcard::from_bytes(&bytes)?;
+    let root_backend = storage::backend(&staged_ref.root_key)?;
+    let manifest_file = manifest_file(&staged_ref.transaction);
+
+    let manifest = root_backend
+        .read_file(&staged_ref.root_key, &manifest_file)
+        .ok()
+        .flatten()
+        .and_then(|bytes| postcard::from_bytes::<Manifest>(&bytes).ok())
+        .filter(|manifest| manifest.transaction == staged_ref.transaction);
+
+    let Some(manifest) = manifest else {
+        return Ok(());
+    };
+
+    #[cfg(feature = "tracing")]
+    warn!(
+        "Completing interrupted transaction {} in {}",
+        staged_ref.transaction,
+        redacted(persistence_key)
+    );
+
+    apply(persistence_key)?;
+
+    // The commit record is only needed until every snapshot of the transaction is in place
+    let pending = manifest.keys.iter().any(|key| {
+        match storage::backend(key).and_then(|backend| backend.read_file(key, STAGED_REF_FILE)) {
+            Ok(None) => false,
+            Ok(Some(bytes)) => postcard::from_bytes::<StagedRef>(&bytes)
+                .ok()
+                .is_none_or(|staged| staged.transaction == manifest.transaction),
+            Err(_) => true,
+        }
+    });
+    if !pending {
+        root_backend.remove_file(&staged_ref.root_key, &manifest_file)?;
+    }
+
+    Ok(())
+}
+
+/// Lock of a root key, forgotten when its last user is done or dropped.
+struct RootLock {
+    root_key: Url,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl RootLock {
+    fn new(root_key: &Url) -> Self {
+        let lock = ROOTS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(root_key.clone())
+            .or_default()
+            .clone();
+
+        Self {
+            root_key: root_key.clone(),
+            lock,
+        }
+    }
+}
+
+impl Drop for RootLock {
+    fn drop(&mut self) {
+        let mut roots = ROOTS.lock().unwrap_or_else(|e| e.into_inner());
+        // Held by the map and by this user only
+        if Arc::strong_count(&self.lock) == 2 {
+            roots.remove(&self.root_key);
+        }
+    }
+}
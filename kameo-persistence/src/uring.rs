@@ -0,0 +1,228 @@
+use std::{cell::RefCell, fs::File, io, os::fd::AsRawFd, sync::Arc};
+
+use io_uring::{IoUring, opcode, types};
+#[cfg(feature = "tracing")]
+use tracing::{info, warn};
+use url::Url;
+
+use crate::{
+    options,
+    storage::{self, Backend, FileBackend},
+};
+
+/// Entries of the ring of each thread; operations are submitted one at a time.
+const RING_ENTRIES: u32 = 8;
+
+/// Largest read or write submitted at once.
+const MAX_CHUNK: usize = 1 << 30;
+
+thread_local! {
+    /// Ring of the current thread, or `None` if the kernel refused to create one.
+    static RING: RefCell<Option<Option<IoUring>>> = const { RefCell::new(None) };
+}
+
+/// File backend reading and writing snapshots through io_uring on Linux.
+///
+/// Each thread submits to its own ring and waits for the completion in place, instead of
+/// handing blocking calls to a thread pool; directories, renames and locks go through the
+/// regular `FileBackend`. Threads which cannot create a ring, and kernels which do not support
+/// an operation, fall back to regular blocking calls.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UringFileBackend;
+
+impl UringFileBackend {
+    /// Store `file` keys in this backend if io_uring is available, returning whether it is;
+    /// keys stay with the regular `FileBackend` otherwise.
+    pub fn install() -> bool {
+        if let Err(_e) = IoUring::new(RING_ENTRIES) {
+            #[cfg(feature = "tracing")]
+            warn!("io_uring is unavailable, keeping the regular file backend: {_e}");
+            return false;
+        }
+
+        #[cfg(feature = "tracing")]
+        info!("Storing file keys through io_uring");
+
+        storage::set_backend("file", Arc::new(Self));
+        true
+    }
+}
+
+/// Run `op` on the ring of the current thread, or return `None` to fall back to blocking calls.
+fn with_ring<T>(op: impl FnOnce(&mut IoUring) -> io::Result<T>) -> Option<io::Result<T>> {
+    RING.with(|ring| {
+        let mut ring = ring.borrow_mut();
+        let ring = ring.get_or_insert_with(|| IoUring::new(RING_ENTRIES).ok());
+
+        match op(ring.as_mut()?) {
+            Err(e) if unsupported(&e) => None,
+            result => Some(result),
+        }
+    })
+}
+
+/// Return true for errors of kernels lacking an operation rather than of the operation itself.
+fn unsupported(e: &io::Error) -> bool {
+    // ENOSYS, EINVAL and EOPNOTSUPP
+    matches!(e.raw_os_error(), Some(38 | 22 | 95))
+}
+
+/// Submit a single entry and wait for its result.
+fn complete(ring: &mut IoUring, entry: io_uring::squeue::Entry) -> io::Result<usize> {
+    // Safety: buffers referenced by the entry outlive the call, which waits for the completion
+    unsafe {
+        ring.submission()
+            .push(&entry)
+            .map_err(|_| io::Error::other("io_uring submission queue is full"))?;
+    }
+    ring.submit_and_wait(1)?;
+
+    let cqe = ring
+        .completion()
+        .next()
+        .ok_or_else(|| io::Error::other("io_uring completion is missing"))?;
+
+    match cqe.result() {
+        result if result < 0 => Err(io::Error::from_raw_os_error(-result)),
+        result => Ok(result as usize),
+    }
+}
+
+fn write_all(ring: &mut IoUring, file: &File, data: &[u8]) -> io::Result<()> {
+    let mut written = 0;
+    while written < data.len() {
+        let chunk = &data[written..data.len().min(written + MAX_CHUNK)];
+        let entry = opcode::Write::new(
+            types::Fd(file.as_raw_fd()),
+            chunk.as_ptr(),
+            chunk.len() as u32,
+        )
+        .offset(written as u64)
+        .build();
+
+        match complete(ring, entry)? {
+            0 => return Err(io::ErrorKind::WriteZero.into()),
+            n => written += n,
+        }
+    }
+
+    Ok(())
+}
+
+fn read_to_end(ring: &mut IoUring, file: &File) -> io::Result<Vec<u8>> {
+    let mut data = vec![0; file.metadata()?.len() as usize];
+
+    let mut read = 0;
+    loop {
+        // The file may have grown since its size was read
+        if read == data.len() {
+            data.resize(data.len() * 2 + 4096, 0);
+        }
+
+        let end = (read + MAX_CHUNK).min(data.len());
+        let chunk = &mut data[read..end];
+        let entry = opcode::Read::new(
+            types::Fd(file.as_raw_fd()),
+            chunk.as_mut_ptr(),
+            chunk.len() as u32,
+        )
+        .offset(read as u64)
+        .build();
+
+        match complete(ring, entry)? {
+            0 => break,
+            n => read += n,
+        }
+    }
+
+    data.truncate(read);
+    Ok(data)
+}
+
+impl Backend for UringFileBackend {
+    fn read_file(&self, key: &Url, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let file = match File::open(FileBackend::key_dir(key)?.join(name)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        match with_ring(|ring| read_to_end(ring, &file)) {
+            Some(data) => Ok(Some(options::decode_file(key, name, data?)?)),
+            None => FileBackend.read_file(key, name),
+        }
+    }
+
+    fn write_file(&self, key: &Url, name: &str, data: &[u8]) -> anyhow::Result<()> {
+        let file = File::create(FileBackend::create_key_dir(key)?.join(name))?;
+        let stored = options::encode_file(key, name, data)?;
+
+        match with_ring(|ring| write_all(ring, &file, &stored)) {
+            Some(written) => {
+                written?;
+                if options::KeyOptions::parse(key)?.fsync {
+                    file.sync_all()?;
+                }
+                Ok(())
+            }
+            None => FileBackend.write_file(key, name, data),
+        }
+    }
+
+    fn rename_file(&self, key: &Url, from: &str, to: &str) -> anyhow::Result<()> {
+        FileBackend.rename_file(key, from, to)
+    }
+
+    fn remove_file(&self, key: &Url, name: &str) -> anyhow::Result<()> {
+        FileBackend.remove_file(key, name)
+    }
+
+    fn file_size(&self, key: &Url, name: &str) -> anyhow::Result<Option<u64>> {
+        FileBackend.file_size(key, name)
+    }
+
+    fn exists(&self, key: &Url) -> anyhow::Result<bool> {
+        FileBackend.exists(key)
+    }
+
+    fn delete(&self, key: &Url) -> anyhow::Result<()> {
+        FileBackend.delete(key)
+    }
+
+    fn list(&self, root: &Url) -> anyhow::Result<Vec<Url>> {
+        FileBackend.list(root)
+    }
+
+    fn list_files(&self, key: &Url) -> anyhow::Result<Vec<String>> {
+        FileBackend.list_files(key)
+    }
+
+    fn write_file_if(
+        &self,
+        key: &Url,
+        name: &str,
+        expected: Option<&str>,
+        data: &[u8],
+    ) -> anyhow::Result<Option<String>> {
+        let dir = FileBackend::create_key_dir(key)?;
+
+        // Same lock and swap file as `FileBackend`, so both can share a directory
+        let lock = File::create(dir.join(format!("{name}.lock")))?;
+        lock.lock()?;
+
+        if self.file_version(key, name)?.as_deref() != expected {
+            return Ok(None);
+        }
+
+        let swap = format!("{name}.swap");
+        self.write_file(key, &swap, data)?;
+        std::fs::rename(dir.join(swap), dir.join(name))?;
+        storage::sync_dir(key, &dir)?;
+
+        // Snapshots stored as JSON may not read back byte for byte
+        match options::KeyOptions::parse(key)?.format {
+            options::Format::Json => self.file_version(key, name),
+            options::Format::Postcard => Ok(Some(storage::content_version(data))),
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+use kameo::prelude::*;
+use sha2::{Digest, Sha256};
+use tokio::task::JoinHandle;
+#[cfg(feature = "tracing")]
+use tracing::{debug, warn};
+use url::Url;
+
+#[cfg(feature = "tracing")]
+use crate::redact::redacted;
+use crate::{PersistentActor, clock, codec, storage};
+
+/// Message delivered to a watched actor when its snapshot was changed outside of this process.
+#[derive(Debug, Clone)]
+pub struct SnapshotChanged<S>(pub S);
+
+// Own writes remembered per key, to tell them from external changes
+const OWN_WRITES: usize = 8;
+
+struct Fingerprints {
+    seen: Option<[u8; 32]>,
+    own: VecDeque<[u8; 32]>,
+}
+
+static WATCHED: LazyLock<Mutex<HashMap<Url, Fingerprints>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn fingerprint(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// Remember bytes written by this process under a watched key, so they are not reported back.
+pub(crate) fn note_write(persistence_key: &Url, data: &[u8]) {
+    let mut watched = WATCHED.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(fingerprints) = watched.get_mut(persistence_key) else {
+        return;
+    };
+
+    if fingerprints.own.len() == OWN_WRITES {
+        fingerprints.own.pop_front();
+    }
+    fingerprints.own.push_back(fingerprint(data));
+}
+
+/// Forget bytes noted with `note_write` which ended up not being written.
+pub(crate) fn forget_write(persistence_key: &Url, data: &[u8]) {
+    let mut watched = WATCHED.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(fingerprints) = watched.get_mut(persistence_key) else {
+        return;
+    };
+
+    let data = fingerprint(data);
+    if let Some(index) = fingerprints.own.iter().rposition(|own| *own == data) {
+        fingerprints.own.remove(index);
+    }
+}
+
+/// Handle of a snapshot watch; the watch stops when it is dropped.
+pub struct WatchHandle {
+    persistence_key: Url,
+    task: JoinHandle<()>,
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+        WATCHED
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.persistence_key);
+    }
+}
+
+/// Poll the snapshot stored under a key and send `SnapshotChanged` to its live actor whenever
+/// it is modified by anything but this process, such as an operator editing it on disk.
+///
+/// Polling goes through the storage backend, so it works for every backend. Changes made while
+/// no actor of type `A` is alive under the key are not delivered, as a respawn reads them anyway.
+/// Keep at most one watch per key.
+pub fn watch<A>(persistence_key: Url, interval: Duration) -> WatchHandle
+where
+    A: PersistentActor + Message<SnapshotChanged<A::Snapshot>>,
+    A::Snapshot: 'static,
+{
+    let initial = storage::backend(&persistence_key)
+        .and_then(|backend| backend.read_file(&persistence_key, storage::snapshot_file()))
+        .ok()
+        .flatten();
+
+    WATCHED.lock().unwrap_or_else(|e| e.into_inner()).insert(
+        persistence_key.clone(),
+        Fingerprints {
+            seen: initial.as_deref().map(fingerprint),
+            own: VecDeque::new(),
+        },
+    );
+
+    let key = persistence_key.clone();
+    let task = tokio::spawn(async move {
+        loop {
+            let clock = clock::clock();
+            clock.sleep_until(clock.now() + interval).await;
+
+            if let Err(_e) = poll::<A>(&key).await {
+                #[cfg(feature = "tracing")]
+                warn!("Failed to poll snapshot of {}: {_e:#}", redacted(&key));
+            }
+        }
+    });
+
+    WatchHandle {
+        persistence_key,
+        task,
+    }
+}
+
+async fn poll<A>(persistence_key: &Url) -> anyhow::Result<()>
+where
+    A: PersistentActor + Message<SnapshotChanged<A::Snapshot>>,
+    A::Snapshot: 'static,
+{
+    let Some(data) =
+        storage::backend(persistence_key)?.read_file(persistence_key, storage::snapshot_file())?
+    else {
+        return Ok(());
+    };
+
+    let current = fingerprint(&data);
+    {
+        let mut watched = WATCHED.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(fingerprints) = watched.get_mut(persistence_key) else {
+            return Ok(());
+        };
+
+        if fingerprints.seen == Some(current) {
+            return Ok(());
+        }
+        fingerprints.seen = Some(current);
+
+        if fingerprints.own.contains(&current) {
+            return Ok(());
+        }
+    }
+
+    let Some(actor_ref) = A::lookup_persistent(persistence_key) else {
+        return Ok(());
+    };
+
+    let snapshot = codec::decode_at::<A>(persistence_key, &data)?;
+
+    #[cfg(feature = "tracing")]
+    debug!(
+        "Snapshot of {} changed externally, reloading",
+        redacted(persistence_key)
+    );
+
+    actor_ref
+        .tell(SnapshotChanged(snapshot))
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to deliver snapshot change: {e}"))
+}
@@ -0,0 +1,97 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        LazyLock, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use url::Url;
+
+use crate::{clock, storage};
+
+static MAX_WRITES: AtomicUsize = AtomicUsize::new(0);
+
+// Last writes of every key, oldest first
+static WRITES: LazyLock<Mutex<HashMap<Url, VecDeque<WriteRecord>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Snapshot write, as remembered by `set_write_tracking`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteRecord {
+    pub at: SystemTime,
+    /// Size of the snapshot written in bytes.
+    pub bytes: usize,
+}
+
+/// Remember the time and size of the last `max_writes` snapshot writes of every key in memory,
+/// for `recent_writes` and `busiest`; 0 remembers none, which is the default.
+///
+/// Costs `max_writes` records per key written since; keys are forgotten when deleted.
+pub fn set_write_tracking(max_writes: usize) {
+    MAX_WRITES.store(max_writes, Ordering::Relaxed);
+
+    if max_writes == 0 {
+        writes().clear();
+    }
+}
+
+/// Last writes of a key remembered in this process, oldest first.
+pub fn recent_writes(persistence_key: &Url) -> Vec<WriteRecord> {
+    writes()
+        .get(persistence_key)
+        .map(|records| records.iter().copied().collect())
+        .unwrap_or_default()
+}
+
+/// Keys written most often within the last `window`, with their count of writes, most written
+/// first, to spot actors saving far more often than intended.
+///
+/// Counts are capped by `max_writes` of `set_write_tracking`.
+pub fn busiest(window: Duration, limit: usize) -> Vec<(Url, usize)> {
+    let since = clock::clock()
+        .system_time()
+        .checked_sub(window)
+        .unwrap_or(UNIX_EPOCH);
+
+    let mut busiest = writes()
+        .iter()
+        .map(|(key, records)| {
+            let count = records.iter().filter(|record| record.at >= since).count();
+            (key.clone(), count)
+        })
+        .filter(|(_, count)| *count > 0)
+        .collect::<Vec<_>>();
+
+    busiest.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    busiest.truncate(limit);
+    busiest
+}
+
+/// Remember a snapshot write, if enabled.
+pub(crate) fn note_write(persistence_key: &Url, bytes: usize) {
+    let max_writes = MAX_WRITES.load(Ordering::Relaxed);
+    if max_writes == 0 {
+        return;
+    }
+
+    let mut writes = writes();
+    let records = writes.entry(persistence_key.clone()).or_default();
+    records.push_back(WriteRecord {
+        at: clock::clock().system_time(),
+        bytes,
+    });
+    while records.len() > max_writes {
+        records.pop_front();
+    }
+}
+
+/// Forget the writes of a deleted key and of the keys below it.
+pub(crate) fn forget(persistence_key: &Url) {
+    writes().retain(|key, _| !storage::is_within(persistence_key, key));
+}
+
+fn writes() -> std::sync::MutexGuard<'static, HashMap<Url, VecDeque<WriteRecord>>> {
+    WRITES.lock().unwrap_or_else(|e| e.into_inner())
+}
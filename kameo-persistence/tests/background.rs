@@ -0,0 +1,89 @@
+use std::sync::{
+    Condvar, Mutex,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use kameo_persistence::{
+    PersistentActor, codec,
+    observer::{self, PersistenceObserver},
+    storage,
+};
+
+// Saves started so far, and whether they may go on
+static STARTED: AtomicUsize = AtomicUsize::new(0);
+static GATE: (Mutex<bool>, Condvar) = (Mutex::new(false), Condvar::new());
+
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+pub struct Counter {
+    pub count: u64,
+}
+
+impl From<&Counter> for Counter {
+    fn from(actor: &Counter) -> Self {
+        actor.clone()
+    }
+}
+
+/// Count the saves written, holding each back until the gate opens.
+struct Gate;
+
+impl PersistenceObserver for Gate {
+    fn on_save_start(&self, _actor_type: &'static str, _key: &Url) {
+        STARTED.fetch_add(1, Ordering::SeqCst);
+        let (open, opened) = &GATE;
+        let mut open = open.lock().unwrap();
+        while !*open {
+            open = opened.wait(open).unwrap();
+        }
+    }
+}
+
+fn open_gate() {
+    let (open, opened) = &GATE;
+    *open.lock().unwrap() = true;
+    opened.notify_all();
+}
+
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() {
+    let dir = std::env::temp_dir().join(format!("background-{}", uuid::Uuid::new_v4()));
+    let key = Url::from_directory_path(&dir).unwrap();
+
+    observer::add_observer(Gate);
+    let actor = Counter::spawn_persistent(key.clone(), Counter { count: 0 })
+        .await
+        .unwrap();
+
+    // The first save is held while being written, the others queue up behind it
+    let first = Counter { count: 1 }
+        .save_snapshot_in_background(&actor)
+        .unwrap();
+    while STARTED.load(Ordering::SeqCst) == 0 {
+        tokio::task::yield_now().await;
+    }
+    let queued = (2..=4)
+        .map(|count| {
+            Counter { count }
+                .save_snapshot_in_background(&actor)
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+
+    // Only the newest of the queued saves is written, the ones it superseded succeed unwritten
+    open_gate();
+    first.wait().await.unwrap();
+    for save in queued {
+        save.wait().await.unwrap();
+    }
+    assert_eq!(STARTED.load(Ordering::SeqCst), 2);
+
+    let stored = codec::decode::<Counter>(&storage::read(&key).await.unwrap()).unwrap();
+    assert_eq!(stored.count, 4);
+
+    observer::clear_observers();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,82 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use futures::future::BoxFuture;
+use kameo_persistence::{
+    SnapshotTransaction,
+    clock::{self, Clock},
+    rate_limit, storage,
+};
+use tokio::time::timeout;
+use url::Url;
+
+/// Clock following the tokio timer, so pausing it pauses the save rate limit as well.
+struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep_until(deadline.into()))
+    }
+}
+
+fn files(key: &Url) -> Vec<String> {
+    std::fs::read_dir(key.to_file_path().unwrap())
+        .map(|entries| {
+            entries
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[tokio::main(flavor = "current_thread", start_paused = true)]
+async fn main() {
+    clock::set_clock(Arc::new(TokioClock));
+
+    let dir = std::env::temp_dir().join(format!("cancellation-{}", uuid::Uuid::new_v4()));
+    let a = Url::from_directory_path(dir.join("a")).unwrap();
+    let b = Url::from_directory_path(dir.join("b")).unwrap();
+
+    rate_limit::set_save_rate(1.0, 1);
+    storage::write(&a, b"kept").await.unwrap();
+
+    // A write dropped while waiting for the rate limit leaves the snapshot untouched
+    let dropped = timeout(Duration::from_millis(50), storage::write(&a, b"dropped")).await;
+    assert!(dropped.is_err());
+    assert_eq!(storage::read(&a).await.unwrap(), b"kept");
+
+    // A dropped transaction stages nothing
+    let mut transaction = SnapshotTransaction::new(a.clone());
+    transaction.stage_bytes(a.clone(), b"dropped".to_vec());
+    transaction.stage_bytes(b.clone(), b"dropped".to_vec());
+    assert!(
+        timeout(Duration::from_millis(50), transaction.commit())
+            .await
+            .is_err()
+    );
+    assert_eq!(storage::read(&a).await.unwrap(), b"kept");
+    assert!(storage::read(&b).await.is_err());
+    assert!(files(&a).iter().all(|file| !file.contains("staged")));
+    assert!(files(&b).is_empty());
+
+    // Dropped writes give their turn back, so the next write only waits for the refill
+    tokio::time::advance(Duration::from_secs(1)).await;
+    timeout(Duration::from_millis(50), storage::write(&a, b"next"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(storage::read(&a).await.unwrap(), b"next");
+
+    rate_limit::clear_save_rate();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
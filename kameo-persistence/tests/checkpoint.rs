@@ -0,0 +1,200 @@
+use std::time::Duration;
+
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use url::Url;
+
+use kameo_persistence::{PersistenceKeyExt, PersistentActor, checkpoint, codec, save_all, storage};
+
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+pub struct Counter {
+    pub count: u64,
+}
+
+impl From<&Counter> for Counter {
+    fn from(actor: &Counter) -> Self {
+        actor.clone()
+    }
+}
+
+#[derive(Debug, Clone, PersistentActor)]
+pub struct Parent {
+    pub count: u64,
+    #[child]
+    pub child: ActorRef<Counter>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParentArgs {
+    pub count: u64,
+    pub child: Url,
+}
+
+impl From<&Parent> for ParentArgs {
+    fn from(actor: &Parent) -> Self {
+        Self {
+            count: actor.count,
+            child: Counter::persistence_key(&actor.child).expect("child is persistent"),
+        }
+    }
+}
+
+impl Actor for Parent {
+    type Args = ParentArgs;
+    type Error = anyhow::Error;
+
+    async fn on_start(args: Self::Args, _actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let child = Counter::lookup_persistent(&args.child)
+            .ok_or_else(|| anyhow::anyhow!("Child {} is not running", args.child))?;
+
+        Ok(Self {
+            count: args.count,
+            child,
+        })
+    }
+}
+
+/// Current count.
+#[derive(Debug)]
+pub struct Count;
+
+impl Message<Count> for Counter {
+    type Reply = u64;
+
+    async fn handle(&mut self, _msg: Count, _ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        self.count
+    }
+}
+
+impl Message<Count> for Parent {
+    type Reply = u64;
+
+    async fn handle(&mut self, _msg: Count, _ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        self.count
+    }
+}
+
+/// Increment the counter.
+#[derive(Debug)]
+pub struct Increment;
+
+impl Message<Increment> for Counter {
+    type Reply = u64;
+
+    async fn handle(
+        &mut self,
+        _msg: Increment,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.count += 1;
+        self.count
+    }
+}
+
+/// Increment the parent, then its child, keeping both counts equal.
+impl Message<Increment> for Parent {
+    type Reply = u64;
+
+    async fn handle(
+        &mut self,
+        _msg: Increment,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.count += 1;
+        self.child.ask(Increment).await.unwrap();
+        self.count
+    }
+}
+
+/// Keep the counter busy until the sender is dropped.
+#[derive(Debug)]
+pub struct Block(oneshot::Receiver<()>);
+
+impl Message<Block> for Counter {
+    type Reply = ();
+
+    async fn handle(&mut self, msg: Block, _ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        let _ = msg.0.await;
+    }
+}
+
+async fn stored_count(key: &Url) -> u64 {
+    codec::decode::<Counter>(&storage::read(key).await.unwrap())
+        .unwrap()
+        .count
+}
+
+#[tokio::main]
+async fn main() {
+    let dir = std::env::temp_dir().join(format!("checkpoint-{}", uuid::Uuid::new_v4()));
+    let parent_key = Url::from_directory_path(&dir).unwrap();
+    let child_key = parent_key.child::<Counter>("child").unwrap();
+
+    let child = Counter::spawn_persistent(child_key.clone(), Counter { count: 0 })
+        .await
+        .unwrap();
+    let parent = Parent::spawn_persistent(
+        parent_key.clone(),
+        ParentArgs {
+            count: 0,
+            child: child_key.clone(),
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(parent.ask(Increment).await.unwrap(), 1);
+
+    // The child is busy when the checkpoint starts, so the parent is captured first and held
+    let (unblock, blocked) = oneshot::channel();
+    child.tell(Block(blocked)).await.unwrap();
+    let barrier = tokio::spawn({
+        let parent_key = parent_key.clone();
+        async move { checkpoint(&parent_key, Duration::from_secs(5)).await }
+    });
+    while tokio::time::timeout(Duration::from_millis(20), parent.ask(Count))
+        .await
+        .is_ok()
+    {}
+
+    // Handled by the parent only once the child is captured too, so neither snapshot has it
+    parent.tell(Increment).await.unwrap();
+    drop(unblock);
+
+    let mut saved = barrier.await.unwrap().unwrap();
+    saved.sort();
+    let mut keys = vec![parent_key.clone(), child_key.clone()];
+    keys.sort();
+    assert_eq!(saved, keys);
+
+    let stored_parent = codec::decode::<Parent>(&storage::read(&parent_key).await.unwrap())
+        .unwrap()
+        .count;
+    assert_eq!(stored_parent, 1);
+    assert_eq!(stored_count(&child_key).await, 1);
+
+    assert_eq!(parent.ask(Count).await.unwrap(), 2);
+    assert_eq!(child.ask(Count).await.unwrap(), 2);
+
+    // Batch saves report each actor in order, a non-persistent one failing alone
+    let other_key = parent_key.child::<Counter>("other").unwrap();
+    let other = Counter::spawn_persistent(other_key.clone(), Counter { count: 5 })
+        .await
+        .unwrap();
+    let unregistered = Counter::spawn(Counter { count: 9 });
+
+    let results = save_all(
+        &[child.clone(), unregistered, other],
+        2,
+        Duration::from_secs(5),
+    )
+    .await;
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().unwrap(), &child_key);
+    assert!(results[1].is_err());
+    assert_eq!(results[2].as_ref().unwrap(), &other_key);
+    assert_eq!(stored_count(&child_key).await, 2);
+    assert_eq!(stored_count(&other_key).await, 5);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
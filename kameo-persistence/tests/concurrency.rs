@@ -0,0 +1,143 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use kameo_persistence::{
+    PersistenceError, PersistentActor, codec,
+    concurrency::{self, Resolution},
+    storage,
+    transaction::SnapshotTransaction,
+};
+
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+pub struct Counter {
+    pub count: u64,
+}
+
+impl From<&Counter> for Counter {
+    fn from(actor: &Counter) -> Self {
+        actor.clone()
+    }
+}
+
+/// Replace the snapshot as another node sharing the storage would, unseen by this process.
+fn write_elsewhere(key: &Url, count: u64) {
+    let data = codec::encode::<Counter>(key, &Counter { count }).unwrap();
+    storage::backend(key)
+        .unwrap()
+        .write_file(key, storage::snapshot_file(), &data)
+        .unwrap();
+}
+
+async fn stored(key: &Url) -> u64 {
+    codec::decode::<Counter>(&storage::read(key).await.unwrap())
+        .unwrap()
+        .count
+}
+
+fn is_conflict(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<PersistenceError>(),
+        Some(PersistenceError::Conflict { .. })
+    )
+}
+
+#[tokio::main]
+async fn main() {
+    let dir = std::env::temp_dir().join(format!("concurrency-{}", uuid::Uuid::new_v4()));
+    let key = Url::from_directory_path(&dir).unwrap();
+
+    concurrency::set_optimistic::<Counter>(true);
+    Counter::try_write(&key, Counter { count: 1 })
+        .await
+        .unwrap();
+    Counter::try_write(&key, Counter { count: 2 })
+        .await
+        .unwrap();
+
+    // A save over a snapshot replaced in between fails and leaves the other write in place
+    write_elsewhere(&key, 10);
+    let error = Counter::try_write(&key, Counter { count: 3 })
+        .await
+        .unwrap_err();
+    assert!(is_conflict(&error));
+    assert_eq!(stored(&key).await, 10);
+
+    // A merge is written over the stored snapshot, after which saves go through unchecked again
+    let calls = Arc::new(AtomicUsize::new(0));
+    let merges = calls.clone();
+    concurrency::set_resolver::<Counter>(move |local, stored| {
+        merges.fetch_add(1, Ordering::SeqCst);
+        Resolution::Merged(Counter {
+            count: local.count + stored.count,
+        })
+    });
+    Counter::try_write(&key, Counter { count: 3 })
+        .await
+        .unwrap();
+    assert_eq!(stored(&key).await, 13);
+    Counter::try_write(&key, Counter { count: 14 })
+        .await
+        .unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // Keeping theirs drops the local snapshot, and the next save is checked against theirs
+    write_elsewhere(&key, 20);
+    concurrency::set_resolver::<Counter>(|_, _| Resolution::Theirs);
+    Counter::try_write(&key, Counter { count: 15 })
+        .await
+        .unwrap();
+    assert_eq!(stored(&key).await, 20);
+    Counter::try_write(&key, Counter { count: 21 })
+        .await
+        .unwrap();
+    assert_eq!(stored(&key).await, 21);
+
+    // A writer getting in before every retry wears the resolver out
+    let calls = Arc::new(AtomicUsize::new(0));
+    let attempts = calls.clone();
+    let contended = key.clone();
+    concurrency::set_resolver::<Counter>(move |_, _| {
+        let attempt = attempts.fetch_add(1, Ordering::SeqCst) as u64;
+        write_elsewhere(&contended, 100 + attempt);
+        Resolution::Ours
+    });
+    write_elsewhere(&key, 30);
+    let error = Counter::try_write(&key, Counter { count: 22 })
+        .await
+        .unwrap_err();
+    assert!(is_conflict(&error));
+    assert!(calls.load(Ordering::SeqCst) > 1);
+    assert!(stored(&key).await >= 100);
+
+    // Without a resolver, conflicts fail again, keeping nothing as the previous snapshot
+    concurrency::remove_resolver::<Counter>();
+    storage::set_keep_previous(true);
+    write_elsewhere(&key, 40);
+    let error = Counter::try_write(&key, Counter { count: 23 })
+        .await
+        .unwrap_err();
+    assert!(is_conflict(&error));
+    let previous = storage::backend(&key)
+        .unwrap()
+        .read_file(&key, storage::PREVIOUS_FILE)
+        .unwrap();
+    assert!(previous.is_none());
+    storage::set_keep_previous(false);
+
+    // Transactions cannot resolve conflicts, and fail before writing anything
+    let mut transaction = SnapshotTransaction::new(key.clone());
+    transaction
+        .stage_snapshot::<Counter>(key.clone(), &Counter { count: 24 })
+        .unwrap();
+    let error = transaction.commit().await.unwrap_err();
+    assert!(is_conflict(&error));
+    assert_eq!(stored(&key).await, 40);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,67 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use kameo_persistence::PersistentActor;
+
+static STARTS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PersistentActor)]
+pub struct Session {
+    pub user: String,
+}
+
+impl From<&Session> for Session {
+    fn from(actor: &Session) -> Self {
+        actor.clone()
+    }
+}
+
+impl Actor for Session {
+    type Args = Self;
+    type Error = anyhow::Error;
+
+    async fn on_start(args: Self::Args, _actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        STARTS.fetch_add(1, Ordering::SeqCst);
+        // Leave the other respawns time to race this one
+        tokio::task::yield_now().await;
+        Ok(args)
+    }
+}
+
+#[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+async fn main() {
+    let dir = std::env::temp_dir().join(format!("concurrent-respawn-{}", uuid::Uuid::new_v4()));
+    let root = Url::from_directory_path(&dir).unwrap();
+
+    for round in 0..8 {
+        let key = root.join(&format!("session-{round}/")).unwrap();
+        Session::try_write(
+            &key,
+            Session {
+                user: format!("user {round}"),
+            },
+        )
+        .await
+        .unwrap();
+
+        let starts = STARTS.load(Ordering::SeqCst);
+        let respawns = (0..16)
+            .map(|_| tokio::spawn(Session::respawn_persistent(key.clone())))
+            .collect::<Vec<_>>();
+
+        let mut ids = Vec::new();
+        for respawn in respawns {
+            ids.push(respawn.await.unwrap().unwrap().id());
+        }
+
+        // Every caller shares the one actor started for the key
+        assert!(ids.iter().all(|id| *id == ids[0]));
+        assert_eq!(STARTS.load(Ordering::SeqCst), starts + 1);
+        assert_eq!(Session::lookup_persistent(&key).unwrap().id(), ids[0]);
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
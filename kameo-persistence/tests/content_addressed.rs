@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use url::Url;
+
+use kameo_persistence::{
+    cas::ContentAddressedBackend,
+    storage::{Backend, FileBackend},
+};
+
+#[tokio::main]
+async fn main() {
+    let dir = std::env::temp_dir().join(format!("content-addressed-{}", uuid::Uuid::new_v4()));
+    let root = Url::from_directory_path(&dir).unwrap();
+    let actors = root.join("actors/").unwrap();
+    let alice = actors.join("alice/").unwrap();
+    let bob = actors.join("bob/").unwrap();
+    let carol = actors.join("carol/").unwrap();
+    let roots = std::slice::from_ref(&actors);
+
+    let store = ContentAddressedBackend::new(Arc::new(FileBackend), root.join("blobs/").unwrap());
+    let template = vec![7; 4096];
+    let edited = vec![8; 4096];
+
+    // Identical contents share one blob, and keys only hold a pointer to it
+    store.write_file(&alice, "index.bin", &template).unwrap();
+    store.write_file(&bob, "index.bin", &template).unwrap();
+    assert_eq!(
+        store.read_file(&alice, "index.bin").unwrap(),
+        Some(template.clone())
+    );
+    assert_eq!(
+        store.file_size(&bob, "index.bin").unwrap(),
+        Some(template.len() as u64)
+    );
+    let pointer = FileBackend.file_size(&bob, "index.bin").unwrap().unwrap();
+    assert!(pointer < 64);
+    assert_eq!(store.collect_blobs(roots).unwrap(), 0);
+
+    // Files written before the store was enabled are read as they are
+    FileBackend
+        .write_file(&carol, "index.bin", b"legacy")
+        .unwrap();
+    let read = store.read_files(&[alice.clone(), carol.clone()], "index.bin");
+    assert_eq!(read[0].as_ref().unwrap().as_deref(), Some(&template[..]));
+    assert_eq!(read[1].as_ref().unwrap().as_deref(), Some(&b"legacy"[..]));
+
+    // A blob still referenced by one key survives the overwrite of another
+    store.write_file(&alice, "index.bin", &edited).unwrap();
+    assert_eq!(store.collect_blobs(roots).unwrap(), 0);
+    assert_eq!(
+        store.read_file(&bob, "index.bin").unwrap(),
+        Some(template.clone())
+    );
+
+    // Once every key moved on, the old blob is collected and the new one kept
+    store.write_file(&bob, "index.bin", &edited).unwrap();
+    assert_eq!(store.collect_blobs(roots).unwrap(), 1);
+    for key in [&alice, &bob] {
+        assert_eq!(
+            store.read_file(key, "index.bin").unwrap(),
+            Some(edited.clone())
+        );
+    }
+
+    store.delete(&alice).unwrap();
+    store.delete(&bob).unwrap();
+    assert_eq!(store.collect_blobs(roots).unwrap(), 1);
+    assert_eq!(store.collect_blobs(roots).unwrap(), 0);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
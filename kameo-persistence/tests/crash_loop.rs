@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use kameo_persistence::{
+    PersistentActor, codec,
+    crash_loop::{self, CrashLoopPolicy, SuspectFallback},
+    storage,
+};
+
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+pub struct Counter {
+    pub count: u64,
+}
+
+impl From<&Counter> for Counter {
+    fn from(actor: &Counter) -> Self {
+        actor.clone()
+    }
+}
+
+/// Current count.
+#[derive(Debug)]
+pub struct Count;
+
+impl Message<Count> for Counter {
+    type Reply = u64;
+
+    async fn handle(&mut self, _msg: Count, _ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        self.count
+    }
+}
+
+/// Restore the actor and have it crash right away, `times` times.
+async fn crash(key: &Url, times: usize) {
+    for _ in 0..times {
+        let actor = Counter::respawn_persistent(key.clone()).await.unwrap();
+        actor.kill();
+        actor.wait_for_shutdown().await;
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let dir = std::env::temp_dir().join(format!("crash-loop-{}", uuid::Uuid::new_v4()));
+    let key = Url::from_directory_path(&dir).unwrap();
+
+    storage::set_keep_previous(true);
+    Counter::try_write(&key, Counter { count: 1 })
+        .await
+        .unwrap();
+    Counter::try_write(&key, Counter { count: 2 })
+        .await
+        .unwrap();
+
+    // Crashing twice in a row shortly after restoring flags the snapshot and fails the next restore
+    crash_loop::set_crash_loop_policy(Some(CrashLoopPolicy::new(2, Duration::from_secs(3600))));
+    crash(&key, 2).await;
+    assert!(crash_loop::suspicion(&key).unwrap().is_none());
+    assert!(Counter::respawn_persistent(key.clone()).await.is_err());
+
+    let suspicion = crash_loop::suspicion(&key).unwrap().unwrap();
+    assert_eq!(suspicion.crashes, 2);
+    let suspect = storage::backend(&key)
+        .unwrap()
+        .read_file(&key, &suspicion.moved_to)
+        .unwrap()
+        .unwrap();
+    assert_eq!(codec::decode::<Counter>(&suspect).unwrap().count, 2);
+
+    // Without a snapshot in place, the actor starts over from fresh args
+    let fresh = Counter::try_respawn_persistent(key.clone(), Counter { count: 0 })
+        .await
+        .unwrap();
+    assert_eq!(fresh.ask(Count).await.unwrap(), 0);
+    fresh.kill();
+    fresh.wait_for_shutdown().await;
+
+    // Falling back to the previous snapshot restores it in place of the suspect one
+    crash_loop::clear(&key).unwrap();
+    assert!(crash_loop::suspicion(&key).unwrap().is_none());
+    for count in [3, 4] {
+        Counter::try_write(&key, Counter { count }).await.unwrap();
+    }
+    crash_loop::set_crash_loop_policy(Some(
+        CrashLoopPolicy::new(2, Duration::from_secs(3600)).fallback(SuspectFallback::Previous),
+    ));
+    crash(&key, 2).await;
+    let restored = Counter::respawn_persistent(key.clone()).await.unwrap();
+    assert_eq!(restored.ask(Count).await.unwrap(), 3);
+    assert_eq!(crash_loop::suspicion(&key).unwrap().unwrap().crashes, 2);
+
+    crash_loop::set_crash_loop_policy(None);
+    storage::set_keep_previous(false);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,185 @@
+use std::{collections::HashMap, time::Duration};
+
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use kameo_persistence::{
+    PersistenceKeyExt, PersistentActor, checkpoint, hierarchy, respawn_tree,
+    shutdown::stop_persistent,
+};
+
+#[derive(Debug, Clone, PersistentActor)]
+pub struct ManagerActor {
+    pub regular_config: String,
+    #[child]
+    pub sub_actors: HashMap<String, ActorRef<SubActor>>,
+    #[child]
+    pub backup: Option<ActorRef<SubActor>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManagerActorArgs {
+    pub regular_config: String,
+    pub sub_actors: HashMap<String, Url>,
+    pub backup: Option<Url>,
+}
+
+impl From<&ManagerActor> for ManagerActorArgs {
+    fn from(actor: &ManagerActor) -> Self {
+        Self {
+            regular_config: actor.regular_config.clone(),
+            sub_actors: actor
+                .sub_actors
+                .iter()
+                .filter_map(|(name, actor_ref)| {
+                    PersistentActor::persistence_key(actor_ref).map(|url| (name.clone(), url))
+                })
+                .collect(),
+            backup: actor.backup.as_ref().and_then(SubActor::persistence_key),
+        }
+    }
+}
+
+impl Actor for ManagerActor {
+    type Args = ManagerActorArgs;
+    type Error = anyhow::Error;
+
+    async fn on_start(args: Self::Args, _actor_ref: ActorRef<Self>) -> Result<Self, Self::Error> {
+        let mut sub_actors = HashMap::new();
+
+        // Already running when respawned by `respawn_tree`, which restores children first
+        for (name, url) in args.sub_actors {
+            if let Ok(sub_actor) = SubActor::respawn_persistent(url).await {
+                sub_actors.insert(name, sub_actor);
+            }
+        }
+
+        let backup = match args.backup {
+            Some(url) => SubActor::respawn_persistent(url).await.ok(),
+            None => None,
+        };
+
+        Ok(Self {
+            regular_config: args.regular_config,
+            sub_actors,
+            backup,
+        })
+    }
+}
+
+/// Keys of the children the manager holds.
+#[derive(Debug)]
+pub struct Children;
+
+impl Message<Children> for ManagerActor {
+    type Reply = Vec<Url>;
+
+    async fn handle(
+        &mut self,
+        _msg: Children,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        let mut children = self.persistent_children();
+        children.sort();
+        children
+    }
+}
+
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+pub struct SubActor {
+    pub config: String,
+}
+
+impl From<&SubActor> for SubActor {
+    fn from(actor: &SubActor) -> Self {
+        actor.clone()
+    }
+}
+
+/// Configuration of a sub actor.
+#[derive(Debug)]
+pub struct Config;
+
+impl Message<Config> for SubActor {
+    type Reply = String;
+
+    async fn handle(&mut self, _msg: Config, _ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        self.config.clone()
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let dir = std::env::temp_dir().join(format!("children-{}", uuid::Uuid::new_v4()));
+    let manager_key = Url::from_directory_path(&dir).unwrap();
+
+    let mut sub_actors = Vec::new();
+    for name in ["alice", "bob", "spare"] {
+        let key = manager_key.child::<SubActor>(name).unwrap();
+        let sub_actor = SubActor::spawn_persistent(
+            key.clone(),
+            SubActor {
+                config: format!("{name} config"),
+            },
+        )
+        .await
+        .unwrap();
+        sub_actors.push((name, key, sub_actor));
+    }
+
+    let manager = ManagerActor::spawn_persistent(
+        manager_key.clone(),
+        ManagerActorArgs {
+            regular_config: "manager config".to_string(),
+            sub_actors: sub_actors[..2]
+                .iter()
+                .map(|(name, key, _)| (name.to_string(), key.clone()))
+                .collect(),
+            backup: Some(sub_actors[2].1.clone()),
+        },
+    )
+    .await
+    .unwrap();
+
+    // The whole tree is saved, and the children the manager holds recorded as referenced
+    let saved = checkpoint(&manager_key, Duration::from_secs(5))
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 4);
+    let manifest = hierarchy::read_manifest(&manager_key).unwrap();
+    assert_eq!(manifest.children.len(), 3);
+    assert_eq!(manifest.referenced.len(), 3);
+    assert_eq!(manifest.unreferenced().count(), 0);
+
+    // Simulate a restart
+    stop_persistent(&manager).await.unwrap();
+    for (_, _, sub_actor) in &sub_actors {
+        stop_persistent(sub_actor).await.unwrap();
+    }
+    assert!(SubActor::lookup_persistent(&sub_actors[0].1).is_none());
+
+    let tree = respawn_tree::<ManagerActor>(manager_key.clone())
+        .await
+        .unwrap();
+    assert!(tree.failed.is_empty());
+    assert_eq!(tree.restored.len(), 3);
+
+    let mut keys = sub_actors
+        .iter()
+        .map(|(_, key, _)| key.clone())
+        .collect::<Vec<_>>();
+    keys.sort();
+    assert_eq!(tree.root.ask(Children).await.unwrap(), keys);
+
+    for (name, key, _) in &sub_actors {
+        assert!(tree.restored.contains(key));
+        let sub_actor = SubActor::lookup_persistent(key).unwrap();
+        assert_eq!(
+            sub_actor.ask(Config).await.unwrap(),
+            format!("{name} config")
+        );
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
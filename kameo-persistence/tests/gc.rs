@@ -0,0 +1,111 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use futures::future::BoxFuture;
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use kameo_persistence::{
+    PersistenceKeyExt, PersistentActor,
+    clock::{self, Clock},
+    gc, hierarchy, storage,
+};
+
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+pub struct Note {
+    pub text: String,
+}
+
+impl From<&Note> for Note {
+    fn from(actor: &Note) -> Self {
+        actor.clone()
+    }
+}
+
+/// Wall clock moved by hand.
+struct ManualClock(Mutex<SystemTime>);
+
+impl ManualClock {
+    fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep_until(deadline.into()))
+    }
+}
+
+async fn derive(parent: &Url, name: &str) -> Url {
+    let key = parent.child::<Note>(name).unwrap();
+    storage::write(&key, name.as_bytes()).await.unwrap();
+    key
+}
+
+#[tokio::main]
+async fn main() {
+    let time = Arc::new(ManualClock(Mutex::new(SystemTime::now())));
+    clock::set_clock(time.clone());
+
+    let dir = std::env::temp_dir().join(format!("gc-{}", uuid::Uuid::new_v4()));
+    let root = Url::from_directory_path(&dir).unwrap();
+    let parent = root.join("parent/").unwrap();
+    storage::write(&parent, b"parent").await.unwrap();
+
+    let kept = derive(&parent, "kept").await;
+    let dropped = derive(&parent, "dropped").await;
+
+    // Children derived since the parent's latest snapshot are never orphans
+    time.advance(gc::DEFAULT_MIN_AGE * 2);
+    let report = gc::collect_garbage(&root, false).await.unwrap();
+    assert!(report.orphans.is_empty());
+
+    // The parent saves without `dropped`, then derives `late` and saves again shortly after
+    hierarchy::record_references(&parent, std::slice::from_ref(&kept)).unwrap();
+    let late = derive(&parent, "late").await;
+    time.advance(Duration::from_secs(10));
+    hierarchy::record_references(&parent, std::slice::from_ref(&kept)).unwrap();
+    let created = derive(&parent, "created").await;
+
+    // Nothing is unreferenced for long enough yet
+    let report = gc::collect_garbage(&root, false).await.unwrap();
+    assert!(report.orphans.is_empty());
+
+    time.advance(gc::DEFAULT_MIN_AGE - Duration::from_secs(5));
+    let report = gc::collect_garbage(&root, true).await.unwrap();
+    assert_eq!(report.orphans, std::slice::from_ref(&dropped));
+    assert!(report.deleted.is_empty());
+    assert!(storage::read(&dropped).await.is_ok());
+
+    let report = gc::collect_garbage(&root, false).await.unwrap();
+    assert_eq!(report.deleted, std::slice::from_ref(&dropped));
+    assert!(storage::read(&dropped).await.is_err());
+    for key in [&kept, &late, &created] {
+        assert!(storage::read(key).await.is_ok());
+    }
+
+    let manifest = hierarchy::read_manifest(&parent).unwrap();
+    assert!(!manifest.children.contains_key(&dropped));
+    assert_eq!(manifest.unreferenced().count(), 1);
+
+    // A shorter minimum age collects the child left out 10 seconds before the latest save
+    let report = gc::collect_garbage_with(&root, Duration::from_secs(1), false)
+        .await
+        .unwrap();
+    assert_eq!(report.deleted, [late]);
+    assert!(storage::read(&created).await.is_ok());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
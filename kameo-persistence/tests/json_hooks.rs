@@ -0,0 +1,62 @@
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use kameo_persistence::{
+    PersistentActor, codec,
+    hooks::{self, SnapshotHook},
+    storage,
+};
+
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+pub struct Note {
+    pub text: String,
+}
+
+impl From<&Note> for Note {
+    fn from(actor: &Note) -> Self {
+        actor.clone()
+    }
+}
+
+/// Stands in for proprietary encryption.
+struct Xor;
+
+impl SnapshotHook for Xor {
+    fn name(&self) -> &str {
+        "xor"
+    }
+
+    fn before_write(&self, data: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        Ok(data.into_iter().map(|byte| byte ^ 0x5a).collect())
+    }
+
+    fn after_read(&self, data: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        self.before_write(data)
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let dir = std::env::temp_dir().join(format!("json-hooks-{}", uuid::Uuid::new_v4()));
+    let key = Url::from_directory_path(&dir).unwrap();
+    #[cfg(feature = "json")]
+    let key = Url::parse(&format!("{key}?format=json")).unwrap();
+
+    hooks::add_snapshot_hook(Xor).unwrap();
+    let note = Note {
+        text: "meet at noon".to_string(),
+    };
+    Note::try_write(&key, Note::from(&note)).await.unwrap();
+
+    // A snapshot run through a hook is stored as the hook wrote it, never as JSON
+    let stored = std::fs::read(dir.join(storage::snapshot_file())).unwrap();
+    assert!(stored.starts_with(codec::MAGIC));
+    assert!(!stored.windows(4).any(|window| window == b"noon"));
+
+    let data = storage::read(&key).await.unwrap();
+    assert_eq!(codec::decode::<Note>(&data).unwrap().text, note.text);
+
+    hooks::clear_snapshot_hooks();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,129 @@
+use std::{io::Write, time::Duration};
+
+use url::Url;
+
+use kameo_persistence::{
+    log_store::{CompactionSchedule, LogBackend},
+    storage::Backend,
+};
+
+fn segments(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+    let mut segments = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "seg"))
+        .collect::<Vec<_>>();
+    segments.sort();
+    segments
+}
+
+#[tokio::main]
+async fn main() {
+    let dir = std::env::temp_dir().join(format!("log-store-{}", uuid::Uuid::new_v4()));
+    let root = Url::parse("log:///accounts/").unwrap();
+    let alice = root.join("alice/").unwrap();
+    let bob = root.join("bob/").unwrap();
+    let carol = Url::parse("log:///accounts/carol/?fsync=true").unwrap();
+
+    let store = LogBackend::open(&dir).unwrap().segment_size(256);
+
+    // Append, read and list
+    store.write_file(&alice, "index.bin", b"alice 0").unwrap();
+    store.write_file(&bob, "index.bin", b"bob 0").unwrap();
+    store.write_file(&bob, "notes", b"hello").unwrap();
+    store.write_file(&carol, "index.bin", b"carol 0").unwrap();
+    assert_eq!(
+        store.read_file(&alice, "index.bin").unwrap().as_deref(),
+        Some(&b"alice 0"[..])
+    );
+    assert_eq!(store.read_file(&alice, "notes").unwrap(), None);
+    assert_eq!(store.list(&root).unwrap().len(), 3);
+    assert_eq!(store.list_files(&bob).unwrap(), ["index.bin", "notes"]);
+
+    store.rename_file(&bob, "notes", "greeting").unwrap();
+    store.remove_file(&bob, "index.bin").unwrap();
+    assert_eq!(store.list_files(&bob).unwrap(), ["greeting"]);
+
+    // Conditional writes see the version in place
+    assert_eq!(
+        store
+            .write_file_if(&alice, "index.bin", None, b"alice 1")
+            .unwrap(),
+        None
+    );
+
+    // Overwrites leave garbage behind across sealed segments
+    for i in 1..=50 {
+        let data = format!("alice {i}");
+        store
+            .write_file(&alice, "index.bin", data.as_bytes())
+            .unwrap();
+    }
+    assert!(segments(&dir).len() > 1);
+    assert!(store.garbage_ratio() > 0.5);
+
+    let report = store.compact().unwrap();
+    assert!(report.after < report.before);
+    assert!(store.garbage_ratio() < 0.01);
+    assert_eq!(
+        store.read_file(&alice, "index.bin").unwrap().as_deref(),
+        Some(&b"alice 50"[..])
+    );
+    assert_eq!(
+        store.read_file(&bob, "greeting").unwrap().as_deref(),
+        Some(&b"hello"[..])
+    );
+
+    store.delete(&carol).unwrap();
+    drop(store);
+
+    // A torn record at the end of the active segment is dropped on restart
+    let last = segments(&dir).pop().unwrap();
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(&last)
+        .unwrap()
+        .write_all(&[0xde, 0xad, 0xbe, 0xef, 1, 2, 3])
+        .unwrap();
+
+    let store = LogBackend::open(&dir).unwrap();
+    assert_eq!(
+        store.read_file(&alice, "index.bin").unwrap().as_deref(),
+        Some(&b"alice 50"[..])
+    );
+    assert_eq!(store.list_files(&bob).unwrap(), ["greeting"]);
+    assert!(!store.exists(&carol).unwrap());
+
+    store.write_file(&bob, "index.bin", b"bob 1").unwrap();
+    drop(store);
+    let store = LogBackend::open(&dir).unwrap().segment_size(256);
+    assert_eq!(
+        store.read_file(&bob, "index.bin").unwrap().as_deref(),
+        Some(&b"bob 1"[..])
+    );
+
+    // Background compaction reports its progress segment by segment
+    for i in 51..=100 {
+        let data = format!("alice {i}");
+        store
+            .write_file(&alice, "index.bin", data.as_bytes())
+            .unwrap();
+    }
+    let garbage_ratio = store.garbage_ratio();
+    let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel();
+    let handle = store.schedule_compaction(
+        CompactionSchedule::new(Duration::from_millis(10))
+            .min_garbage_ratio(0.2)
+            .on_progress(move |progress| {
+                let _ = progress_tx.send(progress.clone());
+            }),
+    );
+    let progress = progress_rx.recv().await.unwrap();
+    drop(handle);
+    assert_eq!(progress.segments_done, 1);
+    assert!(progress.segments_total >= 1);
+    assert!(progress.bytes_reclaimed > progress.bytes_copied);
+    assert!(progress.garbage_ratio < garbage_ratio);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
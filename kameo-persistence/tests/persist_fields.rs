@@ -0,0 +1,102 @@
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use kameo_persistence::{FieldCipher, PersistentActor, persist_fields, set_cipher, storage};
+
+#[derive(Debug, Clone, Actor, PersistentActor)]
+#[snapshot(PatientSnapshot)]
+#[data_subject(name)]
+pub struct Patient {
+    pub name: String,
+    pub session_token: String,
+    pub email: String,
+    pub diagnosis: String,
+}
+
+#[persist_fields]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatientSnapshot {
+    pub name: String,
+    #[persist(redact)]
+    pub session_token: String,
+    #[persist(hash)]
+    pub email: String,
+    #[persist(encrypt)]
+    pub diagnosis: String,
+}
+
+impl From<&Patient> for PatientSnapshot {
+    fn from(actor: &Patient) -> Self {
+        Self {
+            name: actor.name.clone(),
+            session_token: actor.session_token.clone(),
+            email: actor.email.clone(),
+            diagnosis: actor.diagnosis.clone(),
+        }
+    }
+}
+
+impl From<PatientSnapshot> for Patient {
+    fn from(snapshot: PatientSnapshot) -> Self {
+        Self {
+            name: snapshot.name,
+            session_token: snapshot.session_token,
+            email: snapshot.email,
+            diagnosis: snapshot.diagnosis,
+        }
+    }
+}
+
+/// Reversible stand-in for an AEAD, tagging its output.
+struct XorCipher;
+
+impl FieldCipher for XorCipher {
+    fn encrypt(&self, plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Ok(b"xor:"
+            .iter()
+            .copied()
+            .chain(plaintext.iter().map(|byte| byte ^ 0x5a))
+            .collect())
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let body = ciphertext
+            .strip_prefix(b"xor:")
+            .ok_or_else(|| anyhow::anyhow!("Not encrypted with XorCipher"))?;
+        Ok(body.iter().map(|byte| byte ^ 0x5a).collect())
+    }
+}
+
+fn contains(data: &[u8], text: &str) -> bool {
+    data.windows(text.len())
+        .any(|window| window == text.as_bytes())
+}
+
+fn main() {
+    let snapshot = PatientSnapshot {
+        name: "Ada".to_string(),
+        session_token: "token-1234".to_string(),
+        email: "ada@example.com".to_string(),
+        diagnosis: "influenza".to_string(),
+    };
+
+    // Encrypted fields are not stored in plaintext without a cipher, failing the save instead
+    assert!(postcard::to_allocvec(&snapshot).is_err());
+
+    set_cipher(XorCipher);
+    let stored = postcard::to_allocvec(&snapshot).unwrap();
+    assert!(contains(&stored, "Ada"));
+    assert!(!contains(&stored, "token-1234"));
+    assert!(!contains(&stored, "ada@example.com"));
+    assert!(!contains(&stored, "influenza"));
+    assert!(contains(&stored, "xor:"));
+
+    let restored: PatientSnapshot = postcard::from_bytes(&stored).unwrap();
+    assert_eq!(restored.name, snapshot.name);
+    assert_eq!(restored.session_token, "");
+    assert_eq!(
+        restored.email,
+        storage::content_version(&postcard::to_allocvec(&snapshot.email).unwrap())
+    );
+    assert_eq!(restored.diagnosis, snapshot.diagnosis);
+}
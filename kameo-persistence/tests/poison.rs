@@ -0,0 +1,91 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use kameo_persistence::{PersistentActor, poison, recording::Replay};
+
+// Set once the bug crashing on negative amounts is fixed
+static FIXED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+pub struct Ledger {
+    pub total: i64,
+}
+
+impl From<&Ledger> for Ledger {
+    fn from(actor: &Ledger) -> Self {
+        actor.clone()
+    }
+}
+
+/// Add an amount to the total, crashing on negative ones until fixed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Apply(i64);
+
+impl Message<Apply> for Ledger {
+    type Reply = Option<i64>;
+
+    async fn handle(&mut self, msg: Apply, ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        let amount = msg.0;
+        poison::guard(&ctx.actor_ref(), &msg, move || async move {
+            if amount < 0 && !FIXED.load(Ordering::SeqCst) {
+                panic!("negative amount");
+            }
+            self.total += amount;
+            self.total
+        })
+        .await
+        .unwrap()
+    }
+}
+
+/// Deliver `msg` to the ledger of `key`, respawned as a durable mailbox would after a crash.
+async fn deliver(key: &Url, msg: Apply) -> (ActorRef<Ledger>, Option<Option<i64>>) {
+    let ledger = match Ledger::lookup_persistent(key) {
+        Some(ledger) => ledger,
+        None => Ledger::respawn_persistent(key.clone()).await.unwrap(),
+    };
+    let reply = ledger.ask(msg).await.ok();
+    (ledger, reply)
+}
+
+#[tokio::main]
+async fn main() {
+    let dir = std::env::temp_dir().join(format!("poison-{}", uuid::Uuid::new_v4()));
+    let key = Url::from_directory_path(&dir).unwrap();
+
+    poison::set_max_attempts(2);
+    Ledger::try_write(&key, Ledger { total: 10 }).await.unwrap();
+
+    // The message crashes its handler on every delivery until it exhausted its attempts
+    for _ in 0..2 {
+        let (ledger, reply) = deliver(&key, Apply(-1)).await;
+        assert!(reply.is_none());
+        ledger.wait_for_shutdown().await;
+    }
+    assert!(poison::quarantined(&key).unwrap().is_empty());
+
+    // Delivered a third time, it is quarantined and skipped, the actor handling the next ones
+    let (ledger, reply) = deliver(&key, Apply(-1)).await;
+    assert_eq!(reply, Some(None));
+    assert_eq!(ledger.ask(Apply(5)).await.unwrap(), Some(15));
+
+    let quarantined = poison::quarantined(&key).unwrap();
+    assert_eq!(quarantined.len(), 1);
+    assert_eq!(quarantined[0].attempts, 2);
+    assert_eq!(quarantined[0].actor_type, Ledger::type_tag());
+    assert!(quarantined[0].message.is::<Apply>());
+    assert_eq!(quarantined[0].message.decode::<Apply>().unwrap().0, -1);
+
+    // Once fixed, the quarantined message is handled again and leaves the quarantine
+    FIXED.store(true, Ordering::SeqCst);
+    let replay = Replay::new().message::<Apply>();
+    assert_eq!(poison::reprocess(&key, &replay, &ledger).await.unwrap(), 1);
+    assert!(poison::quarantined(&key).unwrap().is_empty());
+    assert_eq!(ledger.ask(Apply(0)).await.unwrap(), Some(14));
+
+    poison::set_max_attempts(0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,96 @@
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use kameo_persistence::{
+    PersistenceError, PersistentActor, codec,
+    limits::{self, LimitAction},
+    storage,
+    tenant::{self, Tenant},
+};
+
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+pub struct Blob {
+    pub data: Vec<u8>,
+}
+
+impl From<&Blob> for Blob {
+    fn from(actor: &Blob) -> Self {
+        actor.clone()
+    }
+}
+
+fn blob(len: usize) -> Blob {
+    Blob { data: vec![7; len] }
+}
+
+fn is_too_large(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<PersistenceError>(),
+        Some(PersistenceError::SnapshotTooLarge { .. })
+    )
+}
+
+fn is_over_quota(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<PersistenceError>(),
+        Some(PersistenceError::QuotaExceeded { .. })
+    )
+}
+
+#[tokio::main]
+async fn main() {
+    let dir = std::env::temp_dir().join(format!("quota-{}", uuid::Uuid::new_v4()));
+    let base = Url::from_directory_path(&dir).unwrap();
+    let acme = Tenant::new("acme", &base).unwrap();
+    let globex = Tenant::new("globex", &base).unwrap();
+    tenant::register(acme.clone());
+    tenant::register(globex.clone());
+
+    let size = codec::encode::<Blob>(&acme.key("a").unwrap(), &blob(1000))
+        .unwrap()
+        .len() as u64;
+
+    // Snapshots over the size limit of their type are rejected before anything is written
+    limits::set_max_snapshot_size::<Blob>(size, LimitAction::Reject);
+    let large = acme.key("large").unwrap();
+    let error = Blob::try_write(&large, blob(2000)).await.unwrap_err();
+    assert!(is_too_large(&error));
+    assert!(storage::read(&large).await.is_err());
+
+    // A tenant fits two blobs in its quota, and a third is rejected
+    limits::set_tenant_quota("acme", size * 5 / 2, LimitAction::Reject);
+    for name in ["a", "b"] {
+        Blob::try_write(&acme.key(name).unwrap(), blob(1000))
+            .await
+            .unwrap();
+    }
+    let error = Blob::try_write(&acme.key("c").unwrap(), blob(1000))
+        .await
+        .unwrap_err();
+    assert!(is_over_quota(&error));
+    assert_eq!(acme.usage().unwrap(), size * 2);
+
+    // Replacing a snapshot only counts the difference, and other tenants are not limited
+    Blob::try_write(&acme.key("a").unwrap(), blob(1000))
+        .await
+        .unwrap();
+    for name in ["a", "b", "c"] {
+        Blob::try_write(&globex.key(name).unwrap(), blob(1000))
+            .await
+            .unwrap();
+    }
+    assert_eq!(globex.usage().unwrap(), size * 3);
+
+    // Warning only lets writes over the quota through
+    limits::set_tenant_quota("acme", size * 5 / 2, LimitAction::Warn);
+    Blob::try_write(&acme.key("c").unwrap(), blob(1000))
+        .await
+        .unwrap();
+    assert_eq!(acme.usage().unwrap(), size * 3);
+
+    limits::clear_limits();
+    tenant::unregister("acme");
+    tenant::unregister("globex");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
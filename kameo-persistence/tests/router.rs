@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use url::Url;
+
+use kameo_persistence::{
+    PersistentActor, codec,
+    router::{RouterArgs, RouterPool, UpdateRing, entity_key},
+    storage,
+};
+
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+pub struct Account {
+    pub balance: u64,
+}
+
+impl From<&Account> for Account {
+    fn from(actor: &Account) -> Self {
+        actor.clone()
+    }
+}
+
+/// Add to the balance.
+#[derive(Debug)]
+pub struct Deposit(u64);
+
+impl Message<Deposit> for Account {
+    type Reply = ();
+
+    async fn handle(&mut self, msg: Deposit, _ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        self.balance += msg.0;
+    }
+}
+
+/// Current balance.
+#[derive(Debug)]
+pub struct Balance;
+
+impl Message<Balance> for Account {
+    type Reply = u64;
+
+    async fn handle(
+        &mut self,
+        _msg: Balance,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        self.balance
+    }
+}
+
+/// Keep the account busy until the sender is dropped.
+#[derive(Debug)]
+pub struct Block(oneshot::Receiver<()>);
+
+impl Message<Block> for Account {
+    type Reply = ();
+
+    async fn handle(&mut self, msg: Block, _ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        let _ = msg.0.await;
+    }
+}
+
+fn args(root: &Url) -> RouterArgs<Account> {
+    RouterArgs::new(root.clone())
+        .new_entity(|_| Account { balance: 0 })
+        .handoff_timeout(Duration::from_secs(1))
+}
+
+async fn stored_balance(root: &Url, entity_id: &str) -> u64 {
+    let data = storage::read(&entity_key(root, entity_id).unwrap())
+        .await
+        .unwrap();
+    codec::decode::<Account>(&data).unwrap().balance
+}
+
+#[tokio::main]
+async fn main() {
+    let dir = std::env::temp_dir().join(format!("router-{}", uuid::Uuid::new_v4()));
+    let root = Url::from_directory_path(dir.join("accounts")).unwrap();
+    let table_key = Url::from_directory_path(dir.join("table")).unwrap();
+
+    let mut pool = RouterPool::restore(table_key.clone(), 16, |_| args(&root))
+        .await
+        .unwrap();
+    assert!(pool.add("a", args(&root)).await.unwrap().is_empty());
+
+    // Every entity is started by the only router, and messages reach it through the pool
+    let ids = (0..20).map(|i| format!("account-{i}")).collect::<Vec<_>>();
+    for (i, id) in ids.iter().enumerate() {
+        pool.route(id, Deposit(i as u64 + 1)).await.unwrap();
+    }
+    for (i, id) in ids.iter().enumerate() {
+        let entity = pool.entity(id).await.unwrap();
+        assert_eq!(entity.ask(Balance).await.unwrap(), i as u64 + 1);
+    }
+
+    let mut ring = pool.ring().clone();
+    ring.add("b");
+    let (leaving, staying): (Vec<_>, Vec<_>) = ids
+        .iter()
+        .enumerate()
+        .partition(|(_, id)| ring.owner(id) == Some("b"));
+    assert!(leaving.len() > 1);
+    assert!(!staying.is_empty());
+
+    // An entity busy past the hand-off timeout cannot be saved, so it keeps running
+    let (blocked_index, blocked_id) = leaving[0];
+    let blocked = pool.entity(blocked_id).await.unwrap();
+    let (unblock, busy) = oneshot::channel();
+    blocked.tell(Block(busy)).await.unwrap();
+
+    let mut moved = pool.add("b", args(&root)).await.unwrap();
+    moved.sort();
+    let mut expected = leaving[1..]
+        .iter()
+        .map(|(_, id)| id.to_string())
+        .collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(moved, expected);
+    assert!(blocked.is_alive());
+
+    // Entities handed off were saved with every message handled, and restart on their new owner
+    for (i, id) in &leaving[1..] {
+        assert_eq!(stored_balance(&root, id).await, *i as u64 + 1);
+        let entity = pool.entity(id).await.unwrap();
+        assert_eq!(entity.ask(Balance).await.unwrap(), *i as u64 + 1);
+    }
+
+    // Sending the ring again hands the entity off once it can be saved
+    drop(unblock);
+    let (_, staying_id) = staying[0];
+    let router_a = pool.router(staying_id).unwrap();
+    let moved = router_a
+        .ask(UpdateRing {
+            ring: pool.ring().clone(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(moved, [blocked_id.to_string()]);
+    assert!(!blocked.is_alive());
+    assert_eq!(
+        stored_balance(&root, blocked_id).await,
+        blocked_index as u64 + 1
+    );
+
+    // The ring is kept in the shard table, so a restored pool has the same members
+    let restored = RouterPool::<Account>::restore(table_key, 16, |_| args(&root))
+        .await
+        .unwrap();
+    assert_eq!(restored.ring(), pool.ring());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
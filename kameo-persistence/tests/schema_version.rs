@@ -0,0 +1,107 @@
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use kameo_persistence::{PersistenceError, PersistentActor, codec, registry, schema, storage};
+
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+#[persistent(type_tag = "counter")]
+#[schema_version(2)]
+pub struct Counter {
+    pub count: u64,
+    pub label: String,
+    pub step: u64,
+}
+
+impl From<&Counter> for Counter {
+    fn from(actor: &Counter) -> Self {
+        actor.clone()
+    }
+}
+
+/// `Counter` as released with schema version 1.
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+#[persistent(type_tag = "counter")]
+#[schema_version(1)]
+pub struct CounterV1 {
+    pub count: u64,
+    pub label: String,
+}
+
+impl From<&CounterV1> for CounterV1 {
+    fn from(actor: &CounterV1) -> Self {
+        actor.clone()
+    }
+}
+
+/// `Counter` as first released, before snapshots had headers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CounterV0 {
+    pub count: u32,
+}
+
+#[tokio::main]
+async fn main() {
+    assert_eq!(Counter::schema_version(), 2);
+
+    schema::register_migration::<Counter, CounterV0, CounterV1>(0, |old| CounterV1 {
+        count: old.count.into(),
+        label: String::new(),
+    });
+    schema::register_migration::<Counter, CounterV1, Counter>(1, |old| Counter {
+        count: old.count,
+        label: old.label,
+        step: 1,
+    });
+
+    let dir = std::env::temp_dir().join(format!("schema-version-{}", uuid::Uuid::new_v4()));
+    let root = Url::from_directory_path(&dir).unwrap();
+    let v0 = root.join("v0/").unwrap();
+    let v1 = root.join("v1/").unwrap();
+
+    // Snapshots without a header are at version 0 and go through every step
+    let payload = postcard::to_allocvec(&CounterV0 { count: 7 }).unwrap();
+    storage::write(&v0, &payload).await.unwrap();
+    let counter = codec::decode::<Counter>(&storage::read(&v0).await.unwrap()).unwrap();
+    assert_eq!(counter.count, 7);
+    assert_eq!(counter.label, "");
+    assert_eq!(counter.step, 1);
+
+    // Snapshots at version 1 only go through the last step
+    CounterV1::try_write(
+        &v1,
+        CounterV1 {
+            count: 3,
+            label: "visits".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let data = storage::read(&v1).await.unwrap();
+    let counter = codec::decode::<Counter>(&data).unwrap();
+    assert_eq!(counter.count, 3);
+    assert_eq!(counter.label, "visits");
+    assert_eq!(counter.step, 1);
+
+    // Upgrading ahead of time rewrites the snapshot with a header at the current version
+    registry::register_type::<Counter>();
+    let report = schema::upgrade::<Counter>(&root).await.unwrap();
+    assert_eq!(report.upgraded.len(), 1);
+    assert!(report.current.is_empty());
+    let data = storage::read(&v1).await.unwrap();
+    assert_eq!(codec::split(&data).unwrap().0.unwrap().schema_version, 2);
+    assert_eq!(codec::decode::<Counter>(&data).unwrap().label, "visits");
+
+    // Binaries still at version 1 refuse the upgraded snapshot rather than misreading it
+    let error = codec::decode::<CounterV1>(&data).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<PersistenceError>(),
+        Some(PersistenceError::SchemaTooNew {
+            version: 2,
+            current: 1,
+            ..
+        })
+    ));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
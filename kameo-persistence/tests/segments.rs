@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use kameo_persistence::{PersistentActor, codec, segment::Segment};
+
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+#[snapshot(Inventory)]
+#[segments(items)]
+pub struct Inventory {
+    pub updates: u64,
+    pub items: Segment<BTreeMap<u32, String>>,
+}
+
+impl From<&Inventory> for Inventory {
+    fn from(actor: &Inventory) -> Self {
+        actor.clone()
+    }
+}
+
+fn part_files(key: &Url) -> Vec<String> {
+    std::fs::read_dir(key.to_file_path().unwrap())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|file| file.starts_with("part-"))
+        .collect()
+}
+
+#[tokio::main]
+async fn main() {
+    let dir = std::env::temp_dir().join(format!("segments-{}", uuid::Uuid::new_v4()));
+    let key = Url::from_directory_path(&dir).unwrap();
+
+    let mut inventory = Inventory {
+        updates: 0,
+        items: Segment::new(BTreeMap::from([(1, "one".to_string())])),
+    };
+    Inventory::try_write(&key, Inventory::from(&inventory))
+        .await
+        .unwrap();
+    assert!(!inventory.items.is_dirty());
+    let written = part_files(&key);
+    assert_eq!(written.len(), 1);
+
+    // Saving a change of another field leaves the segment as written
+    inventory.updates += 1;
+    Inventory::try_write(&key, Inventory::from(&inventory))
+        .await
+        .unwrap();
+    assert_eq!(part_files(&key), written);
+
+    inventory.items.insert(2, "two".to_string());
+    assert!(inventory.items.is_dirty());
+    Inventory::try_write(&key, Inventory::from(&inventory))
+        .await
+        .unwrap();
+    assert!(!inventory.items.is_dirty());
+    assert_ne!(part_files(&key), written);
+
+    let data = Inventory::try_read(&key).await.unwrap();
+    let restored = codec::decode_at::<Inventory>(&key, &data).unwrap();
+    assert_eq!(restored.updates, 1);
+    assert_eq!(*restored.items, *inventory.items);
+    assert!(!restored.items.is_dirty());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,98 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use kameo_persistence::{
+    PersistenceError, PersistentActor, codec,
+    protect::{self, SubjectKeys},
+    storage,
+};
+
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+#[data_subject(user)]
+pub struct Profile {
+    pub user: String,
+    pub email: String,
+}
+
+impl From<&Profile> for Profile {
+    fn from(actor: &Profile) -> Self {
+        actor.clone()
+    }
+}
+
+/// Key store XOR-ing with a byte per subject, enough to tell ciphertext from plaintext.
+#[derive(Default)]
+struct XorKeys(Mutex<HashMap<String, u8>>);
+
+impl SubjectKeys for XorKeys {
+    fn encrypt(&self, subject: &str, plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut keys = self.0.lock().unwrap();
+        let next = keys.len() as u8 + 1;
+        let key = *keys.entry(subject.to_string()).or_insert(next);
+        Ok(plaintext.iter().map(|byte| byte ^ key).collect())
+    }
+
+    fn decrypt(&self, subject: &str, ciphertext: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let keys = self.0.lock().unwrap();
+        Ok(keys
+            .get(subject)
+            .map(|key| ciphertext.iter().map(|byte| byte ^ key).collect()))
+    }
+
+    fn destroy(&self, subject: &str) -> anyhow::Result<()> {
+        self.0.lock().unwrap().remove(subject);
+        Ok(())
+    }
+}
+
+fn is_shredded(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<PersistenceError>(),
+        Some(PersistenceError::SubjectShredded { .. })
+    )
+}
+
+#[tokio::main]
+async fn main() {
+    protect::set_subject_keys(XorKeys::default());
+
+    let dir = std::env::temp_dir().join(format!("shred-{}", uuid::Uuid::new_v4()));
+    let root = Url::from_directory_path(&dir).unwrap();
+    let alice = root.join("alice/").unwrap();
+    let bob = root.join("bob/").unwrap();
+
+    for (key, user) in [(&alice, "alice"), (&bob, "bob")] {
+        let profile = Profile {
+            user: user.to_string(),
+            email: format!("{user}@example.com"),
+        };
+        Profile::try_write(key, profile).await.unwrap();
+    }
+
+    // Snapshots are stored encrypted for their subject, and read back as long as its key exists
+    let stored = storage::read(&alice).await.unwrap();
+    assert!(
+        !stored
+            .windows(17)
+            .any(|bytes| bytes == b"alice@example.com")
+    );
+    let profile = codec::decode::<Profile>(&stored).unwrap();
+    assert_eq!(profile.email, "alice@example.com");
+
+    // Once shredded, every copy of the subject's snapshots is unreadable, others are untouched
+    protect::shred_subject("alice").unwrap();
+    let error = codec::decode::<Profile>(&stored).unwrap_err();
+    assert!(is_shredded(&error));
+    let error = Profile::respawn_persistent(alice.clone())
+        .await
+        .unwrap_err();
+    assert!(is_shredded(&error));
+
+    let profile = codec::decode::<Profile>(&storage::read(&bob).await.unwrap()).unwrap();
+    assert_eq!(profile.email, "bob@example.com");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,163 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use futures::future::BoxFuture;
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use url::Url;
+
+use kameo_persistence::{
+    PersistenceError, PersistentActor,
+    clock::{self, Clock},
+    lease, storage,
+};
+
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+pub struct Counter {
+    pub count: u64,
+}
+
+impl From<&Counter> for Counter {
+    fn from(actor: &Counter) -> Self {
+        actor.clone()
+    }
+}
+
+/// Clock moved by hand, waking the sleeps it passes.
+struct ManualClock {
+    start: Instant,
+    time: SystemTime,
+    elapsed: watch::Sender<Duration>,
+}
+
+impl ManualClock {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            time: SystemTime::now(),
+            elapsed: watch::Sender::new(Duration::ZERO),
+        }
+    }
+
+    fn advance(&self, by: Duration) {
+        self.elapsed.send_modify(|elapsed| *elapsed += by);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed.borrow()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.time + *self.elapsed.borrow()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        let start = self.start;
+        let mut elapsed = self.elapsed.subscribe();
+        Box::pin(async move {
+            let _ = elapsed
+                .wait_for(|elapsed| start + *elapsed >= deadline)
+                .await;
+        })
+    }
+}
+
+/// Let the tasks woken by the clock run.
+async fn settle() {
+    for _ in 0..16 {
+        tokio::task::yield_now().await;
+    }
+}
+
+fn is_fenced(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<PersistenceError>(),
+        Some(PersistenceError::Fenced { .. })
+    )
+}
+
+fn is_held(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<PersistenceError>(),
+        Some(PersistenceError::LeaseHeld { .. })
+    )
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let time = Arc::new(ManualClock::new());
+    clock::set_clock(time.clone());
+
+    let dir = std::env::temp_dir().join(format!("single-writer-{}", uuid::Uuid::new_v4()));
+    let key = Url::from_directory_path(dir.join("lease")).unwrap();
+    let ttl = Duration::from_secs(30);
+
+    // Node a holds the lease, node b cannot take it over
+    let mut a = lease::acquire(&key, "a", ttl).unwrap();
+    lease::fence(&a);
+    assert!(is_held(&lease::acquire(&key, "b", ttl).unwrap_err()));
+    storage::write(&key, b"from a").await.unwrap();
+    a.renew().unwrap();
+
+    // Node a is partitioned and stops renewing: its writes fail before its lease expires
+    time.advance(ttl * 4 / 5);
+    let error = storage::write(&key, b"stale a").await.unwrap_err();
+    assert!(is_fenced(&error));
+
+    // Once expired, node b takes over with a larger fencing token
+    time.advance(ttl / 2);
+    let b = lease::acquire(&key, "b", ttl).unwrap();
+    assert!(b.token() > a.token());
+
+    // Node a comes back: its renewal and its writes are fenced out
+    assert!(is_fenced(&a.renew().unwrap_err()));
+    let error = storage::write(&key, b"stale a").await.unwrap_err();
+    assert!(is_fenced(&error));
+    assert!(is_held(&lease::acquire(&key, "a", ttl).unwrap_err()));
+    assert_eq!(storage::read(&key).await.unwrap(), b"from a");
+
+    // Node b writes under its own token
+    lease::fence(&b);
+    storage::write(&key, b"from b").await.unwrap();
+    assert_eq!(storage::read(&key).await.unwrap(), b"from b");
+
+    // Released leases are granted right away, with a larger token still
+    let token = b.token();
+    b.release().unwrap();
+    let a = lease::acquire(&key, "a", ttl).unwrap();
+    assert!(a.token() > token);
+
+    // A single-writer actor keeps its lease past the ttl while renewals go through
+    let actor_key = Url::from_directory_path(dir.join("actor")).unwrap();
+    Counter::try_write(&actor_key, Counter { count: 1 })
+        .await
+        .unwrap();
+    let (actor, _keeper) = lease::respawn_single_writer::<Counter>(actor_key.clone(), "a", ttl)
+        .await
+        .unwrap();
+    for _ in 0..6 {
+        time.advance(ttl / 3);
+        settle().await;
+    }
+    assert!(is_held(&lease::acquire(&actor_key, "b", ttl).unwrap_err()));
+    assert!(actor.is_alive());
+
+    // Partitioned past the ttl, node a loses the lease to node b and its actor is killed
+    time.advance(ttl * 2);
+    let b = lease::acquire(&actor_key, "b", ttl).unwrap();
+    settle().await;
+    actor.wait_for_shutdown().await;
+    assert!(!actor.is_alive());
+    let error = Counter::try_write(&actor_key, Counter { count: 2 })
+        .await
+        .unwrap_err();
+    assert!(is_fenced(&error));
+    b.release().unwrap();
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,163 @@
+use std::sync::{Arc, Mutex};
+
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use kameo_persistence::{
+    PersistentActor, codec,
+    storage::{self, Backend, FileBackend},
+    transaction::SnapshotTransaction,
+};
+
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+pub struct Counter {
+    pub count: u64,
+}
+
+impl From<&Counter> for Counter {
+    fn from(actor: &Counter) -> Self {
+        actor.clone()
+    }
+}
+
+/// File backend failing to move a staged snapshot into place under one key, as if the process
+/// died right before.
+#[derive(Default)]
+struct CrashingBackend {
+    crash_on: Mutex<Option<Url>>,
+}
+
+impl CrashingBackend {
+    fn crash_on(&self, key: Option<&Url>) {
+        *self.crash_on.lock().unwrap() = key.cloned();
+    }
+}
+
+impl Backend for CrashingBackend {
+    fn read_file(&self, key: &Url, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        FileBackend.read_file(key, name)
+    }
+
+    fn write_file(&self, key: &Url, name: &str, data: &[u8]) -> anyhow::Result<()> {
+        FileBackend.write_file(key, name, data)
+    }
+
+    fn rename_file(&self, key: &Url, from: &str, to: &str) -> anyhow::Result<()> {
+        if to == storage::snapshot_file() && self.crash_on.lock().unwrap().as_ref() == Some(key) {
+            anyhow::bail!("crashed");
+        }
+        FileBackend.rename_file(key, from, to)
+    }
+
+    fn remove_file(&self, key: &Url, name: &str) -> anyhow::Result<()> {
+        FileBackend.remove_file(key, name)
+    }
+
+    fn file_size(&self, key: &Url, name: &str) -> anyhow::Result<Option<u64>> {
+        FileBackend.file_size(key, name)
+    }
+
+    fn exists(&self, key: &Url) -> anyhow::Result<bool> {
+        FileBackend.exists(key)
+    }
+
+    fn delete(&self, key: &Url) -> anyhow::Result<()> {
+        FileBackend.delete(key)
+    }
+
+    fn list(&self, root: &Url) -> anyhow::Result<Vec<Url>> {
+        FileBackend.list(root)
+    }
+
+    fn list_files(&self, key: &Url) -> anyhow::Result<Vec<String>> {
+        FileBackend.list_files(key)
+    }
+}
+
+fn count(data: &[u8]) -> u64 {
+    codec::decode::<Counter>(data).unwrap().count
+}
+
+fn transaction(root: &Url, keys: &[&Url], count: u64) -> SnapshotTransaction {
+    let mut transaction = SnapshotTransaction::new(root.clone());
+    for key in keys {
+        transaction
+            .stage_snapshot::<Counter>((*key).clone(), &Counter { count })
+            .unwrap();
+    }
+    transaction
+}
+
+#[tokio::main]
+async fn main() {
+    let backend = Arc::new(CrashingBackend::default());
+    storage::set_backend("file", backend.clone());
+
+    let dir = std::env::temp_dir().join(format!("transaction-{}", uuid::Uuid::new_v4()));
+    let root = Url::from_directory_path(&dir).unwrap();
+    let alice = root.join("alice/").unwrap();
+    let bob = root.join("bob/").unwrap();
+
+    transaction(&root, &[&alice, &bob], 1)
+        .commit()
+        .await
+        .unwrap();
+    for key in [&alice, &bob] {
+        assert_eq!(count(&storage::read(key).await.unwrap()), 1);
+        assert_eq!(
+            FileBackend.list_files(key).unwrap(),
+            [storage::snapshot_file()]
+        );
+    }
+
+    // A group with a key that cannot be staged writes nothing
+    let mut failing = transaction(&root, &[&alice], 2);
+    failing.stage_bytes(Url::parse("unknown:///carol/").unwrap(), Vec::new());
+    assert!(failing.commit().await.is_err());
+    assert_eq!(count(&storage::read(&alice).await.unwrap()), 1);
+    assert_eq!(
+        FileBackend.list_files(&alice).unwrap(),
+        [storage::snapshot_file()]
+    );
+
+    // A crash past the commit record leaves `bob` staged but unapplied
+    backend.crash_on(Some(&bob));
+    assert!(
+        transaction(&root, &[&alice, &bob], 3)
+            .commit()
+            .await
+            .is_err()
+    );
+    backend.crash_on(None);
+
+    let stored = FileBackend
+        .read_file(&bob, storage::snapshot_file())
+        .unwrap()
+        .unwrap();
+    assert_eq!(count(&stored), 1);
+    assert_eq!(FileBackend.list_files(&bob).unwrap().len(), 3);
+    assert_eq!(FileBackend.list_files(&root).unwrap().len(), 1);
+
+    // Reading either key sees the whole group, the staged snapshot moved into place on read
+    assert_eq!(count(&storage::read(&alice).await.unwrap()), 3);
+    assert_eq!(count(&storage::read(&bob).await.unwrap()), 3);
+    assert_eq!(
+        FileBackend.list_files(&bob).unwrap(),
+        [storage::snapshot_file()]
+    );
+    assert!(FileBackend.list_files(&root).unwrap().is_empty());
+
+    // Transactions of the same root never mix their snapshots nor remove each other's records
+    let (first, second) = tokio::join!(
+        transaction(&root, &[&alice, &bob], 4).commit(),
+        transaction(&root, &[&bob, &alice], 5).commit()
+    );
+    first.unwrap();
+    second.unwrap();
+    let stored = count(&storage::read(&alice).await.unwrap());
+    assert_eq!(count(&storage::read(&bob).await.unwrap()), stored);
+    assert!(FileBackend.list_files(&root).unwrap().is_empty());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
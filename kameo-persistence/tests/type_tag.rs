@@ -0,0 +1,69 @@
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use kameo_persistence::{PersistenceError, PersistentActor, codec, storage};
+
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+#[persistent(type_tag = "billing::Account")]
+pub struct Account {
+    pub balance: u64,
+}
+
+impl From<&Account> for Account {
+    fn from(actor: &Account) -> Self {
+        actor.clone()
+    }
+}
+
+/// `Account` after a rename, keeping its former tag.
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+#[persistent(type_tag = "billing::Account")]
+pub struct Ledger {
+    pub balance: u64,
+}
+
+impl From<&Ledger> for Ledger {
+    fn from(actor: &Ledger) -> Self {
+        actor.clone()
+    }
+}
+
+/// `Account` after a rename, tagged with its new name.
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+pub struct Wallet {
+    pub balance: u64,
+}
+
+impl From<&Wallet> for Wallet {
+    fn from(actor: &Wallet) -> Self {
+        actor.clone()
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    assert_eq!(Account::type_tag(), "billing::Account");
+    assert!(Wallet::type_tag().ends_with("::Wallet"));
+
+    let dir = std::env::temp_dir().join(format!("type-tag-{}", uuid::Uuid::new_v4()));
+    let key = Url::from_directory_path(&dir).unwrap();
+
+    Account::try_write(&key, Account { balance: 42 })
+        .await
+        .unwrap();
+    let data = storage::read(&key).await.unwrap();
+
+    assert_eq!(codec::decode::<Ledger>(&data).unwrap().balance, 42);
+
+    let error = codec::decode::<Wallet>(&data).unwrap_err();
+    match error.downcast_ref::<PersistenceError>() {
+        Some(PersistenceError::TypeMismatch { expected, found }) => {
+            assert_eq!(expected, Wallet::type_tag());
+            assert_eq!(found, "billing::Account");
+        }
+        other => panic!("expected a type mismatch, got {other:?}"),
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,148 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use futures::future::BoxFuture;
+use kameo::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch as channel;
+use url::Url;
+
+use kameo_persistence::{
+    PersistentActor,
+    clock::{self, Clock},
+    codec, storage,
+    watch::{self, SnapshotChanged},
+};
+
+#[derive(Debug, Clone, Actor, Serialize, Deserialize, PersistentActor)]
+pub struct Config {
+    pub value: u64,
+}
+
+impl From<&Config> for Config {
+    fn from(actor: &Config) -> Self {
+        actor.clone()
+    }
+}
+
+impl Message<SnapshotChanged<Config>> for Config {
+    type Reply = ();
+
+    async fn handle(
+        &mut self,
+        msg: SnapshotChanged<Config>,
+        _ctx: &mut Context<Self, Self::Reply>,
+    ) -> Self::Reply {
+        *self = msg.0;
+    }
+}
+
+/// Current value.
+#[derive(Debug)]
+pub struct Value;
+
+impl Message<Value> for Config {
+    type Reply = u64;
+
+    async fn handle(&mut self, _msg: Value, _ctx: &mut Context<Self, Self::Reply>) -> Self::Reply {
+        self.value
+    }
+}
+
+/// Clock moved by hand, waking the sleeps it passes.
+struct ManualClock {
+    start: Instant,
+    time: SystemTime,
+    elapsed: channel::Sender<Duration>,
+}
+
+impl ManualClock {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            time: SystemTime::now(),
+            elapsed: channel::Sender::new(Duration::ZERO),
+        }
+    }
+
+    fn advance(&self, by: Duration) {
+        self.elapsed.send_modify(|elapsed| *elapsed += by);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed.borrow()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.time + *self.elapsed.borrow()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        let start = self.start;
+        let mut elapsed = self.elapsed.subscribe();
+        Box::pin(async move {
+            let _ = elapsed
+                .wait_for(|elapsed| start + *elapsed >= deadline)
+                .await;
+        })
+    }
+}
+
+/// Replace the snapshot as an operator editing it would, outside of this process.
+fn write_elsewhere(key: &Url, value: u64) {
+    let data = codec::encode::<Config>(key, &Config { value }).unwrap();
+    storage::backend(key)
+        .unwrap()
+        .write_file(key, storage::snapshot_file(), &data)
+        .unwrap();
+}
+
+/// Let the watch poll once more.
+async fn poll(time: &ManualClock, interval: Duration) {
+    time.advance(interval);
+    for _ in 0..16 {
+        tokio::task::yield_now().await;
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let time = Arc::new(ManualClock::new());
+    clock::set_clock(time.clone());
+
+    let dir = std::env::temp_dir().join(format!("watch-{}", uuid::Uuid::new_v4()));
+    let key = Url::from_directory_path(&dir).unwrap();
+    let interval = Duration::from_secs(1);
+
+    Config::try_write(&key, Config { value: 1 }).await.unwrap();
+    let config = Config::respawn_persistent(key.clone()).await.unwrap();
+    let handle = watch::watch::<Config>(key.clone(), interval);
+
+    // Saves of this process are not reported back to the actor
+    Config::try_write(&key, Config { value: 2 }).await.unwrap();
+    poll(&time, interval).await;
+    assert_eq!(config.ask(Value).await.unwrap(), 1);
+
+    // Changes made elsewhere are delivered once
+    write_elsewhere(&key, 3);
+    poll(&time, interval).await;
+    assert_eq!(config.ask(Value).await.unwrap(), 3);
+    config
+        .tell(SnapshotChanged(Config { value: 4 }))
+        .await
+        .unwrap();
+    poll(&time, interval).await;
+    assert_eq!(config.ask(Value).await.unwrap(), 4);
+
+    // Dropping the handle stops the watch
+    drop(handle);
+    write_elsewhere(&key, 5);
+    poll(&time, interval).await;
+    assert_eq!(config.ask(Value).await.unwrap(), 4);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}